@@ -9,15 +9,19 @@ use aes_gcm_siv::aead::Generate;
 use aes_gcm_siv::aead::KeyInit;
 use aes_gcm_siv::aead::array::Array;
 use argon2::Argon2;
+use argon2::PasswordHash;
+use argon2::PasswordHasher;
+use argon2::PasswordVerifier;
 use argon2::password_hash::SaltString;
 use axum_extra::extract::CookieJar;
 use axum_extra::extract::cookie::Cookie;
-use chrono::NaiveDate;
 use chrono::Utc;
 use serde::Deserialize;
 use serde::Serialize;
 use subtle::ConstantTimeEq;
 
+pub mod totp;
+
 fn constant_time_str_eq(a: &str, b: &str) -> bool {
     a.as_bytes().ct_eq(b.as_bytes()).into()
 }
@@ -28,34 +32,112 @@ pub struct Login {
     pub password: Option<String>,
 }
 
-fn verify_login(actual: &Login, received: &Login) -> bool {
-    let username_eq = match (&actual.username, &received.username) {
-        (Some(a), Some(e)) => constant_time_str_eq(a, e),
-        _ => false,
+/// Password of the admin as configured on the server.
+#[derive(Clone, Debug)]
+pub enum Password {
+    /// Plaintext password such as from `FX_PASSWORD`.
+    Plain(String),
+    /// Argon2 hash in the PHC string format such as from `FX_PASSWORD_HASH`.
+    Hash(String),
+}
+
+impl Password {
+    /// Whether `received` matches this password.
+    pub fn verify(&self, received: &str) -> bool {
+        match self {
+            Password::Plain(password) => constant_time_str_eq(password, received),
+            Password::Hash(hash) => verify_password_hash(hash, received),
+        }
+    }
+}
+
+/// Credentials of the admin as configured on the server.
+#[derive(Clone, Debug)]
+pub struct Admin {
+    pub username: String,
+    pub password: Password,
+}
+
+pub fn verify_login(actual: &Admin, received: &Login) -> bool {
+    let username_eq = match &received.username {
+        Some(username) => constant_time_str_eq(&actual.username, username),
+        None => false,
     };
-    let password_eq = match (&actual.password, &received.password) {
-        (Some(a), Some(e)) => constant_time_str_eq(a, e),
-        _ => false,
+    // Always verify the password so that the response time does not reveal
+    // whether the username was correct.
+    let password_eq = match &received.password {
+        Some(password) => actual.password.verify(password),
+        None => false,
     };
     username_eq && password_eq
 }
 
-struct FxKey {
+/// Hash a password into a PHC string for `FX_PASSWORD_HASH`.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate();
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
+
+fn verify_password_hash(hash: &str, password: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(e) => {
+            tracing::error!("invalid password hash: {e}");
+            false
+        }
+    }
+}
+
+/// Parse the password hash so that a typo is reported at startup.
+pub fn parse_password_hash(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    match PasswordHash::new(s) {
+        Ok(hash) if hash.algorithm.as_str().starts_with("argon2") => Ok(s.to_string()),
+        Ok(hash) => Err(format!(
+            "expected an Argon2 hash but got {}",
+            hash.algorithm
+        )),
+        Err(e) => Err(format!("invalid PHC string: {e}")),
+    }
+}
+
+#[test]
+fn password_hash_roundtrip() {
+    let hash = hash_password("password");
+    assert!(hash.starts_with("$argon2id$"));
+    assert_eq!(parse_password_hash(&hash), Ok(hash.clone()));
+    let password = Password::Hash(hash);
+    assert!(password.verify("password"));
+    assert!(!password.verify("Password"));
+    assert!(parse_password_hash("password").is_err());
+}
+
+/// Key that encrypts the cookies.
+///
+/// Derived from a server secret and not from the admin password so that the
+/// password does not have to be known to the server.
+#[derive(Clone)]
+pub struct CookieKey {
     key: Aes256GcmSiv,
 }
 
 pub type Salt = [u8; 22];
 
-impl FxKey {
-    fn new(salt: &Salt, password: &str) -> Self {
+impl CookieKey {
+    pub fn new(salt: &Salt, secret: &str) -> Self {
         // Salt can be public because it does not help the attacker.
         // It is only used to defend against rainbow tables.
         let argon2 = Argon2::default();
         let mut key = [0u8; 32];
         argon2
-            .hash_password_into(password.as_bytes(), salt, &mut key)
+            .hash_password_into(secret.as_bytes(), salt, &mut key)
             .unwrap();
-        FxKey {
+        CookieKey {
             key: Aes256GcmSiv::new_from_slice(&key).unwrap(),
         }
     }
@@ -67,17 +149,10 @@ struct Ciphertext {
     ciphertext: Vec<u8>,
 }
 
-fn today() -> NaiveDate {
-    Utc::now().date_naive()
-}
-
-fn encrypt_login(salt: &Salt, password: &str) -> Ciphertext {
-    let plaintext = today();
-    let key = FxKey::new(salt, password);
+fn encrypt(key: &CookieKey, plaintext: &str) -> Ciphertext {
     // Nonce should be unique per message.
     // let nonce = Aes256GcmSiv::generate_nonce().unwrap();
     let nonce = Nonce::generate();
-    let plaintext = plaintext.to_string();
     let ciphertext = key.key.encrypt(&nonce, plaintext.as_bytes()).unwrap();
     Ciphertext {
         nonce: nonce.into(),
@@ -85,15 +160,13 @@ fn encrypt_login(salt: &Salt, password: &str) -> Ciphertext {
     }
 }
 
-fn decrypt_login(salt: &Salt, password: &str, auth: &Ciphertext) -> Option<String> {
-    let key = FxKey::new(salt, password);
+fn decrypt_login(key: &CookieKey, auth: &Ciphertext) -> Option<String> {
     let nonce: aead::Nonce<Aes256GcmSiv> = Array(auth.nonce);
     let ciphertext = auth.ciphertext.as_slice();
     let plaintext = match key.key.decrypt(&nonce, ciphertext) {
         Ok(plaintext) => plaintext,
         Err(e) => {
-            // This can occur when the salt is incorrect. Should not happen in
-            // production I think. Time will tell.
+            // This can occur when the salt or secret changed.
             tracing::warn!("failed to decrypt login: {}", e);
             return None;
         }
@@ -104,52 +177,57 @@ fn decrypt_login(salt: &Salt, password: &str, auth: &Ciphertext) -> Option<Strin
 #[test]
 fn encryption_roundtrip() {
     let salt = b"nblVMlxYtvt0rxo3BML3zw";
-    let password = "password";
-    let auth = encrypt_login(salt, password);
-    let plaintext = decrypt_login(salt, password, &auth).unwrap();
-    let today = today().to_string();
-    assert_eq!(plaintext, today);
+    let key = CookieKey::new(salt, "secret");
+    let auth = encrypt(&key, "plaintext");
+    let plaintext = decrypt_login(&key, &auth).unwrap();
+    assert_eq!(plaintext, "plaintext");
+    let other = CookieKey::new(salt, "other secret");
+    assert_eq!(decrypt_login(&other, &auth), None);
 }
 
 pub fn handle_logout(jar: CookieJar) -> CookieJar {
     jar.remove(Cookie::from("auth"))
 }
 
-const MAX_AGE_SEC: i64 = 2 * 60 * 60 * 24 * 7; // 2 weeks.
+/// Time after which an unused session expires.
+pub const SESSION_MAX_AGE_SEC: i64 = 2 * 60 * 60 * 24 * 7; // 2 weeks.
 
-pub fn is_logged_in(salt: &Salt, login: &Login, jar: &CookieJar) -> bool {
-    let cookie = jar.get("auth");
+/// Decrypt the cookie with the given name.
+fn decrypt_cookie(key: &CookieKey, jar: &CookieJar, name: &str) -> Option<String> {
+    let cookie = jar.get(name);
     match cookie {
         Some(cookie) => {
             let ciphertext = match serde_json::from_str(cookie.value()) {
                 Ok(ciphertext) => ciphertext,
                 Err(_) => {
-                    return false;
-                }
-            };
-            let key = match &login.password {
-                Some(key) => key,
-                None => {
-                    tracing::warn!("admin password not set");
-                    return false;
+                    return None;
                 }
             };
-            let plaintext = match decrypt_login(salt, key, &ciphertext) {
-                Some(plaintext) => plaintext,
+            match decrypt_login(key, &ciphertext) {
+                Some(plaintext) => Some(plaintext),
                 None => {
                     tracing::warn!(
-                        "failed to decrypt login; probably a cookie that belongs to another salt"
+                        "failed to decrypt login; probably a cookie that belongs to another key"
                     );
-                    return false;
+                    None
                 }
-            };
-            let date = NaiveDate::parse_from_str(&plaintext, "%Y-%m-%d").unwrap();
-            today() <= date + chrono::Duration::days(MAX_AGE_SEC)
+            }
         }
-        None => false,
+        None => None,
     }
 }
 
+/// Session token from the `auth` cookie.
+///
+/// Whether the session is still active is up to the caller to check.
+pub fn session(key: &CookieKey, jar: &CookieJar) -> Option<String> {
+    let plaintext = decrypt_cookie(key, jar, "auth")?;
+    // Other cookies, such as the pending login, contain no session.
+    plaintext
+        .strip_prefix("session ")
+        .map(|session| session.to_string())
+}
+
 pub fn generate_salt() -> Salt {
     SaltString::generate()
         .as_str()
@@ -158,34 +236,95 @@ pub fn generate_salt() -> Salt {
         .unwrap()
 }
 
-pub fn handle_login(
-    salt: &Salt,
-    actual: &Login,
-    received: &Login,
-    jar: CookieJar,
-) -> Option<CookieJar> {
-    if verify_login(actual, received) {
-        let password = match &received.password {
-            Some(password) => password,
-            None => {
-                tracing::warn!("admin password not set");
-                return None;
-            }
-        };
-        let ciphertext = encrypt_login(salt, password);
-        let ciphertext = serde_json::to_string(&ciphertext).unwrap();
-        // Secure ensures only HTTPS scheme (except on localhost).
-        // Without secure, a man-in-the-middle could steal the cookie.
-        // HttpOnly prevents the cookie from being accessed by JavaScript.
-        // SameSite=Strict prevents the cookie from being sent in a cross-site request.
-        let cookie = format!(
-            "auth={ciphertext}; Max-Age={MAX_AGE_SEC}; \
-            Secure; HttpOnly; SameSite=Strict;"
-        );
-        let cookie = Cookie::parse(cookie).unwrap();
-        let updated_jar = jar.add(cookie);
-        Some(updated_jar)
-    } else {
-        None
+/// Generate a random token such as an access token or authorization code.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).unwrap();
+    hex::encode(bytes)
+}
+
+/// Hash a token before storing it in the database.
+///
+/// A plain SHA-256 is enough here since the tokens are random and long. Unlike
+/// passwords, they cannot be guessed via a dictionary.
+pub fn hash_token(token: &str) -> String {
+    use sha2::Digest;
+    let hash = sha2::Sha256::digest(token.as_bytes());
+    hex::encode(hash)
+}
+
+#[test]
+fn token_hash_differs() {
+    let token = generate_token();
+    assert_eq!(token.len(), 64);
+    assert_ne!(token, generate_token());
+    assert_eq!(hash_token(&token), hash_token(&token));
+    assert_ne!(hash_token(&token), token);
+}
+
+fn cookie(name: &str, ciphertext: &Ciphertext, max_age_sec: i64) -> Cookie<'static> {
+    let ciphertext = serde_json::to_string(ciphertext).unwrap();
+    // Secure ensures only HTTPS scheme (except on localhost).
+    // Without secure, a man-in-the-middle could steal the cookie.
+    // HttpOnly prevents the cookie from being accessed by JavaScript.
+    // SameSite=Strict prevents the cookie from being sent in a cross-site request.
+    let cookie = format!(
+        "{name}={ciphertext}; Max-Age={max_age_sec}; \
+        Secure; HttpOnly; SameSite=Strict;"
+    );
+    Cookie::parse(cookie).unwrap()
+}
+
+/// Store the session token in the `auth` cookie.
+///
+/// Also used to extend the cookie lifetime when the session is renewed.
+pub fn login(key: &CookieKey, session: &str, jar: CookieJar) -> CookieJar {
+    let ciphertext = encrypt(key, &format!("session {session}"));
+    jar.remove(Cookie::from("pending"))
+        .add(cookie("auth", &ciphertext, SESSION_MAX_AGE_SEC))
+}
+
+/// Time that the user has to complete the second step of the login.
+const PENDING_MAX_AGE_SEC: i64 = 5 * 60;
+
+/// Mark the password step of the login as completed.
+///
+/// Call this instead of [login] when a second factor is enabled. The
+/// short-lived `pending` cookie only proves that the password was correct.
+/// The `auth` cookie is set by [login] once the second factor is verified.
+pub fn require_second_factor(key: &CookieKey, jar: CookieJar) -> CookieJar {
+    let plaintext = format!("pending {}", Utc::now().timestamp());
+    let ciphertext = encrypt(key, &plaintext);
+    jar.remove(Cookie::from("auth"))
+        .add(cookie("pending", &ciphertext, PENDING_MAX_AGE_SEC))
+}
+
+/// Whether the password step of the login was completed recently.
+pub fn is_login_pending(key: &CookieKey, jar: &CookieJar) -> bool {
+    let plaintext = match decrypt_cookie(key, jar, "pending") {
+        Some(plaintext) => plaintext,
+        None => return false,
+    };
+    let created = plaintext
+        .strip_prefix("pending ")
+        .and_then(|created| created.parse::<i64>().ok());
+    match created {
+        Some(created) => Utc::now().timestamp() - created <= PENDING_MAX_AGE_SEC,
+        None => false,
     }
 }
+
+#[test]
+fn pending_login_is_not_a_login() {
+    let key = CookieKey::new(b"nblVMlxYtvt0rxo3BML3zw", "secret");
+    let jar = require_second_factor(&key, CookieJar::new());
+    assert_eq!(session(&key, &jar), None);
+    assert!(is_login_pending(&key, &jar));
+    // Moving the pending cookie into the auth cookie does not help.
+    let pending = jar.get("pending").unwrap().value().to_string();
+    let forged = jar.clone().add(Cookie::new("auth", pending));
+    assert_eq!(session(&key, &forged), None);
+    let jar = login(&key, "token", jar);
+    assert_eq!(session(&key, &jar), Some("token".to_string()));
+    assert!(!is_login_pending(&key, &jar));
+}
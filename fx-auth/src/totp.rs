@@ -0,0 +1,169 @@
+//! Time-based one-time passwords (RFC 6238).
+//!
+//! Uses the defaults that authenticator apps expect: HMAC-SHA1, 30 second
+//! steps, and 6 digits.
+use crate::constant_time_str_eq;
+use hmac::Hmac;
+use hmac::KeyInit;
+use hmac::Mac;
+use sha1::Sha1;
+
+const STEP_SEC: i64 = 30;
+const DIGITS: u32 = 6;
+/// Number of steps before and after the current step that are accepted.
+///
+/// Allows for clock drift between the server and the phone.
+const SKEW: i64 = 1;
+
+/// Generate a random 160-bit secret as recommended by RFC 4226.
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    getrandom::fill(&mut secret).unwrap();
+    secret
+}
+
+/// Encode the secret in the base32 form that authenticator apps accept.
+pub fn encode_secret(secret: &[u8]) -> String {
+    data_encoding::BASE32_NOPAD.encode(secret)
+}
+
+pub fn decode_secret(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.trim().to_ascii_uppercase().replace(' ', "");
+    data_encoding::BASE32_NOPAD.decode(encoded.as_bytes()).ok()
+}
+
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // Dynamic truncation from RFC 4226 section 5.3.
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    let code = binary % 10u32.pow(digits);
+    format!("{code:0width$}", width = digits as usize)
+}
+
+/// Time step for the given Unix time.
+pub fn step(unix_sec: i64) -> i64 {
+    unix_sec.div_euclid(STEP_SEC)
+}
+
+/// Code for the given Unix time.
+pub fn code(secret: &[u8], unix_sec: i64) -> String {
+    hotp(secret, step(unix_sec) as u64, DIGITS)
+}
+
+/// Verify `code` and return the step that it belongs to.
+///
+/// Codes from steps up to and including `last_used` are rejected so that an
+/// observed code cannot be used a second time.
+pub fn verify(secret: &[u8], code: &str, unix_sec: i64, last_used: Option<i64>) -> Option<i64> {
+    let code = code.trim().replace(' ', "");
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let current = step(unix_sec);
+    (current - SKEW..=current + SKEW)
+        .filter(|step| last_used.is_none_or(|last| last < *step))
+        .find(|step| constant_time_str_eq(&hotp(secret, *step as u64, DIGITS), &code))
+}
+
+/// URI that authenticator apps read from the QR code.
+pub fn otpauth_uri(secret: &[u8], issuer: &str, account: &str) -> String {
+    let encode = |s: &str| {
+        s.bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{b:02X}"),
+            })
+            .collect::<String>()
+    };
+    let issuer = encode(issuer);
+    let account = encode(account);
+    let secret = encode_secret(secret);
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}\
+        &algorithm=SHA1&digits={DIGITS}&period={STEP_SEC}"
+    )
+}
+
+/// Generate single-use recovery codes such as `3f9a-c01b-77de`.
+pub fn generate_recovery_codes(n: usize) -> Vec<String> {
+    (0..n)
+        .map(|_| {
+            let mut bytes = [0u8; 6];
+            getrandom::fill(&mut bytes).unwrap();
+            let code = hex::encode(bytes);
+            format!("{}-{}-{}", &code[0..4], &code[4..8], &code[8..12])
+        })
+        .collect()
+}
+
+/// Normalize a recovery code before hashing it so that formatting does not
+/// matter.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[test]
+fn test_rfc6238_vectors() {
+    let secret = b"12345678901234567890";
+    let cases = [
+        (59, "94287082"),
+        (1111111109, "07081804"),
+        (1111111111, "14050471"),
+        (1234567890, "89005924"),
+        (2000000000, "69279037"),
+        (20000000000, "65353130"),
+    ];
+    for (time, expected) in cases {
+        assert_eq!(hotp(secret, step(time) as u64, 8), expected);
+    }
+}
+
+#[test]
+fn test_verify() {
+    let secret = generate_secret();
+    let now = 1_700_000_000;
+    let current = code(&secret, now);
+    assert_eq!(verify(&secret, &current, now, None), Some(step(now)));
+    // Replay of the same code.
+    assert_eq!(verify(&secret, &current, now, Some(step(now))), None);
+    // Clock drift of one step is accepted but two steps are not.
+    let previous = code(&secret, now - STEP_SEC);
+    assert_eq!(verify(&secret, &previous, now, None), Some(step(now) - 1));
+    let old = code(&secret, now - 2 * STEP_SEC);
+    assert_eq!(verify(&secret, &old, now, None), None);
+    assert_eq!(verify(&secret, "12345", now, None), None);
+}
+
+#[test]
+fn test_secret_roundtrip() {
+    let secret = generate_secret();
+    let encoded = encode_secret(&secret);
+    assert_eq!(encoded.len(), 32);
+    assert_eq!(decode_secret(&encoded.to_lowercase()), Some(secret));
+}
+
+#[test]
+fn test_otpauth_uri() {
+    let uri = otpauth_uri(b"12345678901234567890", "John's Weblog", "admin");
+    assert_eq!(
+        uri,
+        "otpauth://totp/John%27s%20Weblog:admin?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+        &issuer=John%27s%20Weblog&algorithm=SHA1&digits=6&period=30"
+    );
+}
+
+#[test]
+fn test_recovery_codes() {
+    let codes = generate_recovery_codes(10);
+    assert_eq!(codes.len(), 10);
+    assert_eq!(codes[0].len(), 14);
+    assert_eq!(normalize_recovery_code(" 3F9A-c01b-77de "), "3f9ac01b77de");
+}
@@ -1,74 +1,193 @@
 //! API endpoints at `/api`.
+use crate::ServeArgs;
 use crate::data::Post;
 use crate::files::File;
+use crate::files::FileInfo;
 use crate::serve::ServerContext;
 use crate::serve::response;
 use crate::serve::response_json;
 use crate::settings::Settings;
+use crate::tokens::ApiAuth;
+use crate::tokens::Scope;
 use axum::Router;
 use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::Query;
 use axum::extract::State;
 use axum::http::Response;
 use axum::http::StatusCode;
 use axum::http::header::HeaderMap;
 use axum::http::header::HeaderValue;
+use axum::routing::delete;
 use axum::routing::get;
+use axum::routing::post;
 use axum::routing::put;
-use serde_json::json;
+use bytes::Bytes;
+use chrono::DateTime;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::TransactionBehavior;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
 use std::io::Read;
-use subtle::ConstantTimeEq;
 use tar::Builder;
 use tar::Header;
+use utoipa::IntoParams;
+use utoipa::OpenApi;
+use utoipa::ToSchema;
+use utoipa::openapi::KnownFormat;
+use utoipa::openapi::ObjectBuilder;
+use utoipa::openapi::RefOr;
+use utoipa::openapi::Schema;
+use utoipa::openapi::SchemaFormat;
+use utoipa::openapi::Type;
+use utoipa::openapi::security::HttpAuthScheme;
+use utoipa::openapi::security::HttpBuilder;
+use utoipa::openapi::security::SecurityScheme;
+use utoipa::openapi::server::Server;
 use xz2::read::XzEncoder;
 
+/// Description of the API.
+///
+/// The routes below are annotated with `utoipa::path` so that the document is
+/// generated from the same types that the handlers use.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "fx",
+        description = "API for reading and writing the posts, files, and settings of an fx site."
+    ),
+    paths(
+        get_api,
+        get_openapi,
+        get_download_all,
+        list_posts,
+        create_post,
+        get_post,
+        update_post,
+        delete_post,
+        list_files,
+        upload_file,
+        get_file,
+        delete_file,
+        get_settings,
+        update_settings,
+        update_about,
+    ),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "meta", description = "Information about the API"),
+        (name = "posts", description = "Blog posts in Markdown"),
+        (name = "files", description = "Uploaded files such as images"),
+        (name = "settings", description = "Site settings"),
+        (name = "backup", description = "Archive of the full site"),
+    )
+)]
+struct ApiDoc;
+
+struct BearerAuth;
+
+impl utoipa::Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let scheme = HttpBuilder::new()
+            .scheme(HttpAuthScheme::Bearer)
+            .description(Some(
+                "API token created at `/settings/tokens`. \
+                The scopes that an operation requires are listed in its security requirement.",
+            ))
+            .build();
+        components.add_security_scheme("bearer", SecurityScheme::Http(scheme));
+    }
+}
+
+pub fn openapi(ctx: &ServerContext) -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    let base_url = ctx.base_url();
+    if !base_url.is_empty() {
+        doc.servers = Some(vec![Server::new(base_url)]);
+    }
+    doc
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ApiIndex {
+    pub openapi_url: String,
+    pub download_all_url: String,
+    pub posts_url: String,
+    pub files_url: String,
+    pub settings_url: String,
+}
+
+/// List the API entry points.
+#[utoipa::path(
+    get,
+    path = "/api",
+    tag = "meta",
+    responses((status = 200, description = "URLs of the API resources", body = ApiIndex))
+)]
 async fn get_api(State(ctx): State<ServerContext>) -> Response<Body> {
     let domain = ctx.base_url();
-    let body = json!({
-        "download_all_url": format!("{domain}/api/download/all.tar.xz"),
-    })
-    .to_string();
+    let index = ApiIndex {
+        openapi_url: format!("{domain}/api/openapi.json"),
+        download_all_url: format!("{domain}/api/download/all.tar.xz"),
+        posts_url: format!("{domain}/api/posts"),
+        files_url: format!("{domain}/api/files"),
+        settings_url: format!("{domain}/api/settings"),
+    };
+    json_with_headers(&ctx, StatusCode::OK, HeaderMap::new(), &index)
+}
+
+/// Get this OpenAPI document.
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "meta",
+    responses((status = 200, description = "OpenAPI 3.1 document"))
+)]
+async fn get_openapi(State(ctx): State<ServerContext>) -> Response<Body> {
+    let body = openapi(&ctx).to_json().unwrap();
     response_json(StatusCode::OK, body, &ctx)
 }
 
-fn is_authenticated(ctx: &ServerContext, headers: &HeaderMap) -> bool {
-    let password = &ctx.args.password;
-    let password = if let Some(password) = password {
-        password
-    } else {
-        tracing::warn!("admin password not set");
-        return false;
-    };
-    let header = if let Some(cookie) = headers.get("Authorization") {
-        cookie
-    } else {
-        return false;
-    };
-    let parts = header
-        .to_str()
-        .unwrap()
-        .split_ascii_whitespace()
-        .collect::<Vec<&str>>();
-    if parts.len() != 2 {
-        return false;
-    }
-    if parts[0] != "Bearer" {
-        return false;
+/// Raw bytes such as an uploaded file or the backup archive.
+struct Binary;
+
+impl utoipa::PartialSchema for Binary {
+    fn schema() -> RefOr<Schema> {
+        let format = SchemaFormat::KnownFormat(KnownFormat::Binary);
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .format(Some(format))
+            .into()
     }
-    let token = parts[1];
-    token.as_bytes().ct_eq(password.as_bytes()).into()
 }
 
-fn error(ctx: &ServerContext, status: StatusCode, message: &str) -> Response<Body> {
-    let body = json!({
-        "status": status.as_u16(),
-        "message": message,
-    })
-    .to_string();
+impl ToSchema for Binary {}
+
+/// Body of all error responses.
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ApiError {
+    /// HTTP status code.
+    #[schema(example = 404)]
+    pub status: u16,
+    #[schema(example = "not found")]
+    pub message: String,
+}
+
+pub fn error(ctx: &ServerContext, status: StatusCode, message: &str) -> Response<Body> {
+    let body = ApiError {
+        status: status.as_u16(),
+        message: message.to_string(),
+    };
+    let body = serde_json::to_string(&body).unwrap();
     response_json(status, body, ctx)
 }
 
-fn unauthorized(ctx: &ServerContext) -> Response<Body> {
-    error(ctx, StatusCode::UNAUTHORIZED, "unauthorized")
+fn forbidden(ctx: &ServerContext, scope: Scope) -> Response<Body> {
+    let message = format!("token lacks the {} scope", scope.as_str());
+    error(ctx, StatusCode::FORBIDDEN, &message)
 }
 
 struct SiteData<'a> {
@@ -158,7 +277,7 @@ fn download_all(ctx: &ServerContext) -> Response<Body> {
             "failed to get settings",
         );
     };
-    let files = File::list(&conn);
+    let files = File::list_with_data(&conn);
     drop(conn);
     let files = if let Ok(files) = files {
         files
@@ -184,9 +303,21 @@ fn download_all(ctx: &ServerContext) -> Response<Body> {
     response::<Vec<u8>>(StatusCode::OK, headers, body, ctx)
 }
 
-async fn get_download_all(State(ctx): State<ServerContext>, headers: HeaderMap) -> Response<Body> {
-    if !is_authenticated(&ctx, &headers) {
-        return unauthorized(&ctx);
+/// Download the full site as a `.tar.xz` archive.
+#[utoipa::path(
+    get,
+    path = "/api/download/all.tar.xz",
+    tag = "backup",
+    responses(
+        (status = 200, description = "Archive with the posts, files, and settings", content_type = "application/x-xz", body = Binary),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+    ),
+    security(("bearer" = ["backup"]))
+)]
+async fn get_download_all(State(ctx): State<ServerContext>, auth: ApiAuth) -> Response<Body> {
+    if !auth.allows(Scope::Backup) {
+        return forbidden(&ctx, Scope::Backup);
     }
     tokio::task::spawn_blocking({
         let ctx = ctx.clone();
@@ -196,13 +327,26 @@ async fn get_download_all(State(ctx): State<ServerContext>, headers: HeaderMap)
     .unwrap()
 }
 
+/// Replace the about text.
+#[utoipa::path(
+    put,
+    path = "/api/settings/about",
+    tag = "settings",
+    request_body(content = String, content_type = "text/plain"),
+    responses(
+        (status = 200, description = "About text was updated"),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+    ),
+    security(("bearer" = ["settings"]))
+)]
 async fn update_about(
     State(ctx): State<ServerContext>,
-    headers: HeaderMap,
+    auth: ApiAuth,
     body: String,
 ) -> Response<Body> {
-    if !is_authenticated(&ctx, &headers) {
-        return unauthorized(&ctx);
+    if !auth.allows(Scope::Settings) {
+        return forbidden(&ctx, Scope::Settings);
     }
     let settings = Settings::from_db(&ctx.conn());
     if let Ok(settings) = settings {
@@ -224,10 +368,779 @@ async fn update_about(
     response_json(StatusCode::OK, "ok", &ctx)
 }
 
-pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+fn not_found(ctx: &ServerContext) -> Response<Body> {
+    error(ctx, StatusCode::NOT_FOUND, "not found")
+}
+
+fn bad_request(ctx: &ServerContext, message: &str) -> Response<Body> {
+    error(ctx, StatusCode::BAD_REQUEST, message)
+}
+
+/// Strong ETag over the given parts.
+fn etag(parts: &[&[u8]]) -> String {
+    let mut hasher = sha2::Sha256::new();
+    for part in parts {
+        hasher.update(part);
+        // Separator to avoid that different parts produce the same hash.
+        hasher.update([0]);
+    }
+    let hash = hasher.finalize();
+    format!("\"{}\"", hex::encode(&hash[..16]))
+}
+
+/// Check the `If-Match` header for optimistic concurrency.
+///
+/// Updates without `If-Match` are allowed so that simple scripts keep working.
+/// Clients that want to avoid overwriting changes made by someone else should
+/// send the ETag that they received when fetching the resource.
+fn if_match(headers: &HeaderMap, current: &str) -> bool {
+    let header = match headers.get("If-Match") {
+        Some(header) => header,
+        None => return true,
+    };
+    let header = match header.to_str() {
+        Ok(header) => header,
+        Err(_) => return false,
+    };
+    header
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag == current)
+}
+
+#[test]
+fn test_if_match() {
+    let mut headers = HeaderMap::new();
+    assert!(if_match(&headers, "\"a\""));
+    headers.insert("If-Match", HeaderValue::from_static("\"b\", \"a\""));
+    assert!(if_match(&headers, "\"a\""));
+    assert!(!if_match(&headers, "\"c\""));
+    headers.insert("If-Match", HeaderValue::from_static("*"));
+    assert!(if_match(&headers, "\"c\""));
+}
+
+fn precondition_failed(ctx: &ServerContext) -> Response<Body> {
+    let message = "resource was modified; fetch it again to get the current ETag";
+    error(ctx, StatusCode::PRECONDITION_FAILED, message)
+}
+
+/// Why a write with an `If-Match` check did not happen.
+enum WriteError {
+    NotFound,
+    PreconditionFailed,
+    Database(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for WriteError {
+    fn from(e: rusqlite::Error) -> Self {
+        WriteError::Database(e)
+    }
+}
+
+impl WriteError {
+    /// Response for the error, where `action` describes the write.
+    fn response(self, ctx: &ServerContext, action: &str) -> Response<Body> {
+        match self {
+            WriteError::NotFound => not_found(ctx),
+            WriteError::PreconditionFailed => precondition_failed(ctx),
+            WriteError::Database(e) => {
+                let message = format!("failed to {action}: {e}");
+                error(ctx, StatusCode::INTERNAL_SERVER_ERROR, &message)
+            }
+        }
+    }
+}
+
+/// Run `write` in a transaction if `headers` match the current ETag.
+///
+/// `current` reads the resource and its ETag. Reading, comparing, and writing
+/// in one immediate transaction ensures that two clients that send the same
+/// `If-Match` cannot both succeed, where the second would overwrite the first.
+fn write_if_match<C, T>(
+    conn: &mut Connection,
+    headers: &HeaderMap,
+    current: impl FnOnce(&Connection) -> Result<(C, String), WriteError>,
+    write: impl FnOnce(&Connection, C) -> Result<T, WriteError>,
+) -> Result<T, WriteError> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let (current, etag) = current(&tx)?;
+    if !if_match(headers, &etag) {
+        return Err(WriteError::PreconditionFailed);
+    }
+    let written = write(&tx, current)?;
+    tx.commit()?;
+    Ok(written)
+}
+
+#[test]
+fn test_write_if_match() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    crate::data::Kv::create_table(&conn).unwrap();
+    crate::data::Kv::insert(&conn, "key", b"a").unwrap();
+    let current = |conn: &Connection| {
+        let value = crate::data::Kv::get(conn, "key")?;
+        Ok(((), etag(&[&value])))
+    };
+    let write = |value: &'static [u8]| {
+        move |conn: &Connection, _| Ok(crate::data::Kv::insert(conn, "key", value)?)
+    };
+    let mut headers = HeaderMap::new();
+    let version = HeaderValue::from_str(&etag(&[b"a"])).unwrap();
+    headers.insert("If-Match", version);
+    assert!(write_if_match(&mut conn, &headers, current, write(b"b")).is_ok());
+    // The second write with the same version is rejected and changes nothing.
+    let result = write_if_match(&mut conn, &headers, current, write(b"c"));
+    assert!(matches!(result, Err(WriteError::PreconditionFailed)));
+    assert_eq!(crate::data::Kv::get(&conn, "key").unwrap(), b"b");
+}
+
+/// Parse a JSON request body into `T`.
+fn parse_json<T: serde::de::DeserializeOwned>(
+    ctx: &ServerContext,
+    body: &str,
+) -> Result<T, Box<Response<Body>>> {
+    serde_json::from_str(body)
+        .map_err(|e| Box::new(bad_request(ctx, &format!("invalid JSON body: {e}"))))
+}
+
+/// Respond with a JSON body and extra headers such as `ETag` or `Location`.
+fn json_with_headers<T: Serialize>(
+    ctx: &ServerContext,
+    status: StatusCode,
+    mut headers: HeaderMap,
+    body: &T,
+) -> Response<Body> {
+    let body = serde_json::to_string(body).unwrap();
+    crate::serve::content_type(&mut headers, "application/json");
+    response(status, headers, body, ctx)
+}
+
+fn etag_header(etag: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("ETag", HeaderValue::from_str(etag).unwrap());
+    headers
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ApiPost {
+    pub id: i64,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub content: String,
+    pub url: String,
+}
+
+impl ApiPost {
+    fn new(ctx: &ServerContext, post: &Post) -> Self {
+        let slug = crate::md::extract_slug(post);
+        let url = crate::html::post_link(post, &slug);
+        Self {
+            id: post.id,
+            created: post.created,
+            updated: post.updated,
+            content: post.content.clone(),
+            url: format!("{}{url}", ctx.base_url()),
+        }
+    }
+}
+
+fn post_etag(post: &Post) -> String {
+    let id = post.id.to_string();
+    let updated = post.updated.to_rfc3339();
+    etag(&[id.as_bytes(), updated.as_bytes(), post.content.as_bytes()])
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListQuery {
+    /// One-based page number.
+    page: Option<usize>,
+    /// Number of posts per page, at most 100.
+    per_page: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct PostList {
+    pub posts: Vec<ApiPost>,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize,
+    /// URL of the next page or `None` when this is the last page.
+    pub next: Option<String>,
+}
+
+/// List posts, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/posts",
+    tag = "posts",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "One page of posts", body = PostList),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+    ),
+    security(("bearer" = ["read"]))
+)]
+async fn list_posts(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    Query(query): Query<ListQuery>,
+) -> Response<Body> {
+    if !auth.allows(Scope::Read) {
+        return forbidden(&ctx, Scope::Read);
+    }
+    let posts = match Post::list(&ctx.conn()) {
+        Ok(posts) => posts,
+        Err(_) => {
+            return error(
+                &ctx,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to get posts",
+            );
+        }
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(20).clamp(1, 100);
+    let total = posts.len();
+    let start = (page - 1).saturating_mul(per_page).min(total);
+    let end = (start + per_page).min(total);
+    let next = if end < total {
+        let base = ctx.base_url();
+        let next_page = page + 1;
+        Some(format!(
+            "{base}/api/posts?page={next_page}&per_page={per_page}"
+        ))
+    } else {
+        None
+    };
+    let list = PostList {
+        posts: posts[start..end]
+            .iter()
+            .map(|post| ApiPost::new(&ctx, post))
+            .collect(),
+        page,
+        per_page,
+        total,
+        next,
+    };
+    json_with_headers(&ctx, StatusCode::OK, HeaderMap::new(), &list)
+}
+
+/// Return the post unless it does not exist or was deleted.
+fn get_existing_post(ctx: &ServerContext, id: i64) -> Option<Post> {
+    match Post::get(&ctx.conn(), id) {
+        Ok(post) if post.content != "<DELETED>" => Some(post),
+        _ => None,
+    }
+}
+
+/// The post with its ETag, for [write_if_match].
+fn current_post(conn: &Connection, id: i64) -> Result<(Post, String), WriteError> {
+    match Post::get(conn, id) {
+        Ok(post) if post.content != "<DELETED>" => {
+            let etag = post_etag(&post);
+            Ok((post, etag))
+        }
+        Ok(_) | Err(rusqlite::Error::QueryReturnedNoRows) => Err(WriteError::NotFound),
+        Err(e) => Err(WriteError::Database(e)),
+    }
+}
+
+/// Get a post.
+#[utoipa::path(
+    get,
+    path = "/api/posts/{id}",
+    tag = "posts",
+    params(("id" = i64, Path, description = "Post id")),
+    responses(
+        (status = 200, description = "The post", body = ApiPost, headers(("ETag" = String, description = "Version of the post"))),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+        (status = 404, description = "Post does not exist", body = ApiError),
+    ),
+    security(("bearer" = ["read"]))
+)]
+async fn get_post(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    Path(id): Path<i64>,
+) -> Response<Body> {
+    if !auth.allows(Scope::Read) {
+        return forbidden(&ctx, Scope::Read);
+    }
+    let post = match get_existing_post(&ctx, id) {
+        Some(post) => post,
+        None => return not_found(&ctx),
+    };
+    let headers = etag_header(&post_etag(&post));
+    json_with_headers(&ctx, StatusCode::OK, headers, &ApiPost::new(&ctx, &post))
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct PostContent {
+    pub content: String,
+}
+
+/// Create a post.
+#[utoipa::path(
+    post,
+    path = "/api/posts",
+    tag = "posts",
+    request_body(content = PostContent, content_type = "application/json"),
+    responses(
+        (status = 201, description = "The created post", body = ApiPost, headers(("ETag" = String, description = "Version of the post"), ("Location" = String, description = "URL of the post in the API"))),
+        (status = 400, description = "Invalid request body", body = ApiError),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+    ),
+    security(("bearer" = ["write_posts"]))
+)]
+async fn create_post(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    body: String,
+) -> Response<Body> {
+    if !auth.allows(Scope::WritePosts) {
+        return forbidden(&ctx, Scope::WritePosts);
+    }
+    let input = match parse_json::<PostContent>(&ctx, &body) {
+        Ok(input) => input,
+        Err(response) => return *response,
+    };
+    if input.content.trim().is_empty() {
+        return bad_request(&ctx, "content must not be empty");
+    }
+    let now = Utc::now();
+    let id = match Post::insert(&ctx.conn(), now, now, &input.content) {
+        Ok(id) => id,
+        Err(e) => {
+            let message = format!("failed to insert post: {e}");
+            return error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, &message);
+        }
+    };
+    let post = match get_existing_post(&ctx, id) {
+        Some(post) => post,
+        None => return not_found(&ctx),
+    };
+    crate::trigger::trigger_github_backup(&ctx).await;
+    let api_post = ApiPost::new(&ctx, &post);
+    let mut headers = etag_header(&post_etag(&post));
+    let location = format!("{}/api/posts/{id}", ctx.base_url());
+    headers.insert("Location", HeaderValue::from_str(&location).unwrap());
+    tracing::info!("\"POST /api/posts HTTP/1.1\" 201");
+    json_with_headers(&ctx, StatusCode::CREATED, headers, &api_post)
+}
+
+/// Replace the content of a post.
+#[utoipa::path(
+    put,
+    path = "/api/posts/{id}",
+    tag = "posts",
+    params(("id" = i64, Path, description = "Post id"), ("If-Match" = Option<String>, Header, description = "Only update when the `ETag` matches")),
+    request_body(content = PostContent, content_type = "application/json"),
+    responses(
+        (status = 200, description = "The updated post", body = ApiPost, headers(("ETag" = String, description = "Version of the post"))),
+        (status = 400, description = "Invalid request body", body = ApiError),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+        (status = 404, description = "Post does not exist", body = ApiError),
+        (status = 412, description = "`If-Match` does not match the current `ETag`", body = ApiError),
+    ),
+    security(("bearer" = ["write_posts"]))
+)]
+async fn update_post(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    body: String,
+) -> Response<Body> {
+    if !auth.allows(Scope::WritePosts) {
+        return forbidden(&ctx, Scope::WritePosts);
+    }
+    let input = match parse_json::<PostContent>(&ctx, &body) {
+        Ok(input) => input,
+        Err(response) => return *response,
+    };
+    if input.content.trim().is_empty() {
+        return bad_request(&ctx, "content must not be empty");
+    }
+    let update = |conn: &Connection, post: Post| {
+        let post = Post {
+            id,
+            created: post.created,
+            updated: Utc::now(),
+            content: input.content,
+        };
+        post.update(conn)?;
+        // Read back to return the cleaned up content.
+        Ok(Post::get(conn, id)?)
+    };
+    let current = |conn: &Connection| current_post(conn, id);
+    let post = match write_if_match(&mut ctx.conn(), &headers, current, update) {
+        Ok(post) => post,
+        Err(e) => return e.response(&ctx, "update post"),
+    };
+    crate::trigger::trigger_github_backup(&ctx).await;
+    let headers = etag_header(&post_etag(&post));
+    tracing::info!("\"PUT /api/posts/{id} HTTP/1.1\" 200");
+    json_with_headers(&ctx, StatusCode::OK, headers, &ApiPost::new(&ctx, &post))
+}
+
+/// Delete a post.
+#[utoipa::path(
+    delete,
+    path = "/api/posts/{id}",
+    tag = "posts",
+    params(("id" = i64, Path, description = "Post id"), ("If-Match" = Option<String>, Header, description = "Only update when the `ETag` matches")),
+    responses(
+        (status = 204, description = "Post was deleted"),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+        (status = 404, description = "Post does not exist", body = ApiError),
+        (status = 412, description = "`If-Match` does not match the current `ETag`", body = ApiError),
+    ),
+    security(("bearer" = ["write_posts"]))
+)]
+async fn delete_post(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Response<Body> {
+    if !auth.allows(Scope::WritePosts) {
+        return forbidden(&ctx, Scope::WritePosts);
+    }
+    let delete = |conn: &Connection, _post: Post| Ok(Post::delete(conn, id)?);
+    let current = |conn: &Connection| current_post(conn, id);
+    if let Err(e) = write_if_match(&mut ctx.conn(), &headers, current, delete) {
+        return e.response(&ctx, "delete post");
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    tracing::info!("\"DELETE /api/posts/{id} HTTP/1.1\" 204");
+    response(StatusCode::NO_CONTENT, HeaderMap::new(), "", &ctx)
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ApiFile {
+    pub sha: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub size: usize,
+    pub url: String,
+    /// Width in pixels for images.
+    pub width: Option<u32>,
+    /// Height in pixels for images.
+    pub height: Option<u32>,
+    /// Alternative text for images.
+    pub alt: String,
+    pub caption: String,
+    pub title: String,
+}
+
+impl ApiFile {
+    fn new(ctx: &ServerContext, file: &FileInfo) -> Self {
+        let filename = crate::html::url_encode(&file.filename_without_prefix());
+        Self {
+            sha: file.sha.clone(),
+            filename: file.filename.clone(),
+            mime_type: file.mime_type.clone(),
+            size: file.size as usize,
+            url: format!("{}/files/{}/{filename}", ctx.base_url(), file.sha),
+            width: file.width,
+            height: file.height,
+            alt: file.alt.clone(),
+            caption: file.caption.clone(),
+            title: file.title.clone(),
+        }
+    }
+}
+
+/// List files.
+#[utoipa::path(
+    get,
+    path = "/api/files",
+    tag = "files",
+    responses(
+        (status = 200, description = "All files", body = Vec<ApiFile>),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+    ),
+    security(("bearer" = ["read"]))
+)]
+async fn list_files(State(ctx): State<ServerContext>, auth: ApiAuth) -> Response<Body> {
+    if !auth.allows(Scope::Read) {
+        return forbidden(&ctx, Scope::Read);
+    }
+    let files = match File::list(&ctx.conn()) {
+        Ok(files) => files,
+        Err(_) => {
+            return error(
+                &ctx,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to get files",
+            );
+        }
+    };
+    let files = files
+        .iter()
+        .map(|file| ApiFile::new(&ctx, file))
+        .collect::<Vec<_>>();
+    json_with_headers(&ctx, StatusCode::OK, HeaderMap::new(), &files)
+}
+
+/// Get the metadata of a file.
+#[utoipa::path(
+    get,
+    path = "/api/files/{sha}",
+    tag = "files",
+    params(("sha" = String, Path, description = "Hash of the file")),
+    responses(
+        (status = 200, description = "The file metadata", body = ApiFile),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+        (status = 404, description = "File does not exist", body = ApiError),
+    ),
+    security(("bearer" = ["read"]))
+)]
+async fn get_file(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    Path(sha): Path<String>,
+) -> Response<Body> {
+    if !auth.allows(Scope::Read) {
+        return forbidden(&ctx, Scope::Read);
+    }
+    let file = match File::info(&ctx.conn(), &sha) {
+        Ok(file) => file,
+        Err(_) => return not_found(&ctx),
+    };
+    json_with_headers(
+        &ctx,
+        StatusCode::OK,
+        HeaderMap::new(),
+        &ApiFile::new(&ctx, &file),
+    )
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UploadQuery {
+    /// Name of the file, such as `image.png`.
+    filename: Option<String>,
+}
+
+/// Upload the request body as a file.
+///
+/// The body is the raw file content and the `Content-Type` header is used as
+/// the mime type. This is easier to use from scripts than a multipart form.
+/// Without a `Content-Type` header, the mime type is detected from the
+/// content.
+#[utoipa::path(
+    post,
+    path = "/api/files",
+    tag = "files",
+    params(UploadQuery),
+    request_body(content = Binary, content_type = "application/octet-stream"),
+    responses(
+        (status = 201, description = "The stored file", body = ApiFile, headers(("Location" = String, description = "URL of the file in the API"))),
+        (status = 400, description = "Missing filename, empty body, or image whose metadata cannot be removed", body = ApiError),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+        (status = 413, description = "The file is larger than `FX_MAX_UPLOAD_SIZE`"),
+    ),
+    security(("bearer" = ["write_files"]))
+)]
+async fn upload_file(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    headers: HeaderMap,
+    Query(query): Query<UploadQuery>,
+    body: Bytes,
+) -> Response<Body> {
+    if !auth.allows(Scope::WriteFiles) {
+        return forbidden(&ctx, Scope::WriteFiles);
+    }
+    let filename = match query.filename {
+        Some(filename) if !filename.trim().is_empty() => filename,
+        _ => return bad_request(&ctx, "missing filename query parameter"),
+    };
+    if body.is_empty() {
+        return bad_request(&ctx, "empty body");
+    }
+    let mime_type = headers
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok());
+    let mime_type = crate::upload::content_type(mime_type, &body);
+    let file = File::new(&mime_type, filename.trim(), body);
+    let file = match crate::files::store_upload(&ctx, file).await {
+        Ok(file) => file,
+        Err(e) => return error(&ctx, e.status(), &e.to_string()),
+    };
+    let file = match File::info(&ctx.conn(), &file.sha) {
+        Ok(file) => file,
+        Err(e) => {
+            let message = format!("failed to get stored file: {e}");
+            return error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, &message);
+        }
+    };
+    crate::trigger::trigger_github_backup(&ctx).await;
+    let mut headers = HeaderMap::new();
+    let location = format!("{}/api/files/{}", ctx.base_url(), file.sha);
+    headers.insert("Location", HeaderValue::from_str(&location).unwrap());
+    tracing::info!("\"POST /api/files HTTP/1.1\" 201");
+    json_with_headers(
+        &ctx,
+        StatusCode::CREATED,
+        headers,
+        &ApiFile::new(&ctx, &file),
+    )
+}
+
+/// Delete a file.
+#[utoipa::path(
+    delete,
+    path = "/api/files/{sha}",
+    tag = "files",
+    params(("sha" = String, Path, description = "Hash of the file")),
+    responses(
+        (status = 204, description = "File was deleted"),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+        (status = 404, description = "File does not exist", body = ApiError),
+    ),
+    security(("bearer" = ["write_files"]))
+)]
+async fn delete_file(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    Path(sha): Path<String>,
+) -> Response<Body> {
+    if !auth.allows(Scope::WriteFiles) {
+        return forbidden(&ctx, Scope::WriteFiles);
+    }
+    match File::delete(&ctx.conn(), &ctx.file_index, &sha) {
+        Ok(0) => return not_found(&ctx),
+        Ok(_) => (),
+        Err(e) => {
+            let message = format!("failed to delete file: {e}");
+            return error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, &message);
+        }
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    tracing::info!("\"DELETE /api/files/{sha} HTTP/1.1\" 204");
+    response(StatusCode::NO_CONTENT, HeaderMap::new(), "", &ctx)
+}
+
+fn settings_etag(settings: &Settings) -> String {
+    let data = serde_json::to_string(settings).unwrap();
+    etag(&[data.as_bytes()])
+}
+
+/// Get the settings.
+#[utoipa::path(
+    get,
+    path = "/api/settings",
+    tag = "settings",
+    responses(
+        (status = 200, description = "The settings", body = Settings, headers(("ETag" = String, description = "Version of the settings"))),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+    ),
+    security(("bearer" = ["read"]))
+)]
+async fn get_settings(State(ctx): State<ServerContext>, auth: ApiAuth) -> Response<Body> {
+    if !auth.allows(Scope::Read) {
+        return forbidden(&ctx, Scope::Read);
+    }
+    let settings = match Settings::from_db(&ctx.conn()) {
+        Ok(settings) => settings,
+        Err(_) => {
+            return error(
+                &ctx,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to get settings",
+            );
+        }
+    };
+    let headers = etag_header(&settings_etag(&settings));
+    json_with_headers(&ctx, StatusCode::OK, headers, &settings)
+}
+
+/// Replace the settings.
+#[utoipa::path(
+    put,
+    path = "/api/settings",
+    tag = "settings",
+    params(("If-Match" = Option<String>, Header, description = "Only update when the `ETag` matches")),
+    request_body(content = Settings, content_type = "application/json"),
+    responses(
+        (status = 200, description = "The updated settings", body = Settings, headers(("ETag" = String, description = "Version of the settings"))),
+        (status = 400, description = "Invalid request body", body = ApiError),
+        (status = 401, description = "Missing, unknown, or expired token", body = ApiError),
+        (status = 403, description = "Token lacks the required scope", body = ApiError),
+        (status = 412, description = "`If-Match` does not match the current `ETag`", body = ApiError),
+    ),
+    security(("bearer" = ["settings"]))
+)]
+async fn update_settings(
+    State(ctx): State<ServerContext>,
+    auth: ApiAuth,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    if !auth.allows(Scope::Settings) {
+        return forbidden(&ctx, Scope::Settings);
+    }
+    let input = match parse_json::<Settings>(&ctx, &body) {
+        Ok(input) => input,
+        Err(response) => return *response,
+    };
+    let current = |conn: &Connection| {
+        let settings = Settings::from_db(conn)?;
+        let etag = settings_etag(&settings);
+        Ok((settings, etag))
+    };
+    let save = |conn: &Connection, current: Settings| {
+        input.save(conn)?;
+        Ok((current, Settings::from_db(conn)?))
+    };
+    let (current, settings) = match write_if_match(&mut ctx.conn(), &headers, current, save) {
+        Ok(settings) => settings,
+        Err(e) => return e.response(&ctx, "update settings"),
+    };
+    if current.blogroll_feeds != settings.blogroll_feeds {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            crate::settings::update_feeds(&ctx).await;
+        });
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    let headers = etag_header(&settings_etag(&settings));
+    tracing::info!("\"PUT /api/settings HTTP/1.1\" 200");
+    json_with_headers(&ctx, StatusCode::OK, headers, &settings)
+}
+
+pub fn routes(router: &Router<ServerContext>, args: &ServeArgs) -> Router<ServerContext> {
     router
         .clone()
         .route("/api", get(get_api))
+        .route("/api/openapi.json", get(get_openapi))
         .route("/api/download/all.tar.xz", get(get_download_all))
+        .route("/api/posts", get(list_posts))
+        .route("/api/posts", post(create_post))
+        .route("/api/posts/{id}", get(get_post))
+        .route("/api/posts/{id}", put(update_post))
+        .route("/api/posts/{id}", delete(delete_post))
+        .route("/api/files", get(list_files))
+        .route(
+            "/api/files",
+            post(upload_file).layer(crate::upload::body_limit(args)),
+        )
+        .route("/api/files/{sha}", get(get_file))
+        .route("/api/files/{sha}", delete(delete_file))
+        .route("/api/settings", get(get_settings))
+        .route("/api/settings", put(update_settings))
         .route("/api/settings/about", put(update_about))
 }
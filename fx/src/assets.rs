@@ -0,0 +1,329 @@
+//! Stylesheets and scripts.
+//!
+//! Assets are minified, fingerprinted, and compressed once instead of on every
+//! request. Pages refer to the fingerprinted URLs, such as
+//! `/static/style.0123456789abcdef.css`, which never change content and can
+//! thus be cached forever by browsers. The plain URLs, such as
+//! `/static/style.css`, keep working with a short cache lifetime.
+//!
+//! Uploaded files with a filename like `theme/custom.css` or `theme/custom.js`
+//! go through the same pipeline and are added to every page. This allows
+//! changing the look of the site without touching the extra HTML head.
+use crate::compression;
+use crate::compression::Encoding;
+use crate::serve::ServerContext;
+use crate::serve::content_type;
+use crate::serve::enable_caching;
+use crate::serve::response;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::get;
+use bytes::Bytes;
+use rusqlite::Connection;
+use sha2::Digest;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+/// An asset with its precompressed variants.
+struct Asset {
+    content_type: &'static str,
+    body: Bytes,
+    variants: Vec<(Encoding, Bytes)>,
+}
+
+impl Asset {
+    /// Minify and compress the source.
+    ///
+    /// `best` compresses as small as possible, which takes seconds for large
+    /// files, so it is only used for the built-in assets.
+    fn new(content_type: &'static str, source: &str, best: bool) -> Self {
+        let body = Bytes::from(crate::html::minify(source));
+        let variants = [Encoding::Br, Encoding::Zstd, Encoding::Gzip]
+            .into_iter()
+            .map(|encoding| {
+                let compressed = compression::compress(encoding, &body, best);
+                (encoding, Bytes::from(compressed))
+            })
+            .collect();
+        Self {
+            content_type,
+            body,
+            variants,
+        }
+    }
+}
+
+fn fingerprint(data: &[u8]) -> String {
+    let sha = sha2::Sha256::digest(data);
+    hex::encode(&sha[..8])
+}
+
+/// Name with the fingerprint before the extension.
+fn fingerprinted(name: &str, fingerprint: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{stem}.{fingerprint}.{extension}"),
+        None => format!("{name}.{fingerprint}"),
+    }
+}
+
+#[test]
+fn test_fingerprinted() {
+    assert_eq!(fingerprinted("style.css", "abc"), "style.abc.css");
+    assert_eq!(fingerprinted("LICENSE", "abc"), "LICENSE.abc");
+}
+
+struct Builtin {
+    name: &'static str,
+    fingerprinted: String,
+    asset: Asset,
+}
+
+/// The assets that are embedded in the binary.
+static BUILTIN: LazyLock<Vec<Builtin>> = LazyLock::new(|| {
+    let css = "text/css; charset=utf-8";
+    let js = "text/javascript; charset=utf-8";
+    let sources = [
+        (
+            "style.css",
+            css,
+            include_str!("static/style.css").to_string(),
+        ),
+        ("highlight.css", css, crate::highlight::stylesheet()),
+        (
+            "script.js",
+            js,
+            include_str!("static/script.js").to_string(),
+        ),
+        ("katex.js", js, include_str!("static/katex.js").to_string()),
+        (
+            "nodefer.js",
+            js,
+            include_str!("static/nodefer.js").to_string(),
+        ),
+        (
+            "passkeys.js",
+            js,
+            include_str!("static/passkeys.js").to_string(),
+        ),
+        (
+            "upload.js",
+            js,
+            include_str!("static/upload.js").to_string(),
+        ),
+    ];
+    sources
+        .into_iter()
+        .map(|(name, content_type, source)| {
+            let asset = Asset::new(content_type, &source, true);
+            Builtin {
+                name,
+                fingerprinted: fingerprinted(name, &fingerprint(&asset.body)),
+                asset,
+            }
+        })
+        .collect()
+});
+
+/// Prepare the assets so that the first requests do not have to wait.
+pub fn init() {
+    LazyLock::force(&BUILTIN);
+}
+
+/// Fingerprinted URL of a built-in asset such as `style.css`.
+pub fn url(name: &str) -> String {
+    let builtin = BUILTIN.iter().find(|builtin| builtin.name == name).unwrap();
+    format!("/static/{}", builtin.fingerprinted)
+}
+
+fn serve(
+    ctx: &ServerContext,
+    request_headers: &HeaderMap,
+    asset: &Asset,
+    immutable: bool,
+) -> Response<Body> {
+    let mut headers = HeaderMap::new();
+    content_type(&mut headers, asset.content_type);
+    if immutable {
+        let value = HeaderValue::from_static("public, max-age=31536000, immutable");
+        headers.insert("Cache-Control", value);
+    } else {
+        enable_caching(&mut headers, 600);
+    }
+    headers.insert("Vary", HeaderValue::from_static("Accept-Encoding"));
+    let encoding = compression::negotiate(request_headers, &ctx.args.compression);
+    let variant = asset
+        .variants
+        .iter()
+        .find(|(variant, _)| Some(*variant) == encoding);
+    let body = match variant {
+        Some((encoding, body)) => {
+            let value = HeaderValue::from_static(encoding.name());
+            headers.insert("Content-Encoding", value);
+            body.clone()
+        }
+        None => asset.body.clone(),
+    };
+    response(StatusCode::OK, headers, body, ctx)
+}
+
+async fn get_static(
+    State(ctx): State<ServerContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let builtin = BUILTIN.iter().find_map(|builtin| {
+        if builtin.fingerprinted == name {
+            Some((builtin, true))
+        } else if builtin.name == name {
+            Some((builtin, false))
+        } else {
+            None
+        }
+    });
+    match builtin {
+        Some((builtin, immutable)) => serve(&ctx, &headers, &builtin.asset, immutable),
+        None => crate::serve::not_found(State(ctx)).await,
+    }
+}
+
+/// Filename prefix of uploaded files that are used as theme.
+const THEME_PREFIX: &str = "theme/";
+
+struct ThemeFile {
+    sha: String,
+    extension: &'static str,
+}
+
+fn theme_extension(filename: &str) -> Option<&'static str> {
+    let name = filename.strip_prefix(THEME_PREFIX)?;
+    if name.ends_with(".css") {
+        Some("css")
+    } else if name.ends_with(".js") {
+        Some("js")
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_theme_extension() {
+    assert_eq!(theme_extension("theme/dark.css"), Some("css"));
+    assert_eq!(theme_extension("theme/a/b.js"), Some("js"));
+    assert_eq!(theme_extension("theme/logo.png"), None);
+    assert_eq!(theme_extension("style.css"), None);
+}
+
+fn theme_files(conn: &Connection) -> rusqlite::Result<Vec<ThemeFile>> {
+    let stmt = "
+        SELECT sha, filename
+        FROM files
+        WHERE filename LIKE 'theme/%'
+        ORDER BY filename;
+        ";
+    let mut stmt = conn.prepare(stmt)?;
+    let files = stmt.query_map([], |row| {
+        let sha: String = row.get("sha")?;
+        let filename: String = row.get("filename")?;
+        Ok((sha, filename))
+    })?;
+    let files = files
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .into_iter()
+        .filter_map(|(sha, filename)| {
+            let extension = theme_extension(&filename)?;
+            Some(ThemeFile { sha, extension })
+        })
+        .collect();
+    Ok(files)
+}
+
+/// Stylesheets and scripts from the uploaded theme files for the page head.
+pub fn theme_head(ctx: &ServerContext, nonce: &str) -> String {
+    let files = match theme_files(&ctx.conn()) {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::error!("failed to list theme files: {e}");
+            return String::new();
+        }
+    };
+    files
+        .iter()
+        .map(|file| {
+            let sha = &file.sha;
+            match file.extension {
+                "css" => format!("<link rel='stylesheet' href='/static/theme/{sha}.css'>"),
+                _ => {
+                    format!("<script nonce='{nonce}' src='/static/theme/{sha}.js' defer></script>")
+                }
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Processed theme files by sha.
+///
+/// The sha of an uploaded file already changes with the content, so it is
+/// used as the fingerprint.
+static THEME: LazyLock<Mutex<HashMap<String, Arc<Asset>>>> = LazyLock::new(Default::default);
+
+/// Drop the processed theme file, such as when the file is deleted.
+pub fn forget(sha: &str) {
+    THEME.lock().unwrap().remove(sha);
+}
+
+async fn get_theme_asset(
+    State(ctx): State<ServerContext>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let Some((sha, extension)) = name.split_once('.') else {
+        return crate::serve::not_found(State(ctx)).await;
+    };
+    // Files that were removed or renamed are no longer part of the theme.
+    let in_theme = match theme_files(&ctx.conn()) {
+        Ok(files) => files
+            .iter()
+            .any(|file| file.sha == sha && file.extension == extension),
+        Err(_) => false,
+    };
+    if !in_theme {
+        return crate::serve::not_found(State(ctx)).await;
+    }
+    let cached = THEME.lock().unwrap().get(sha).cloned();
+    let asset = match cached {
+        Some(asset) => asset,
+        None => {
+            let Ok(file) = crate::files::File::get(&ctx.conn(), sha) else {
+                return crate::serve::not_found(State(ctx)).await;
+            };
+            let content_type = match extension {
+                "css" => "text/css; charset=utf-8",
+                _ => "text/javascript; charset=utf-8",
+            };
+            let process = move || {
+                let source = String::from_utf8_lossy(&file.data);
+                Asset::new(content_type, &source, false)
+            };
+            let asset = Arc::new(tokio::task::spawn_blocking(process).await.unwrap());
+            THEME.lock().unwrap().insert(sha.to_string(), asset.clone());
+            asset
+        }
+    };
+    serve(&ctx, &headers, &asset, true)
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/static/{name}", get(get_static))
+        .route("/static/theme/{name}", get(get_theme_asset))
+}
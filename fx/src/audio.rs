@@ -0,0 +1,124 @@
+//! Audio files in posts.
+//!
+//! Links to uploaded audio files that are on a line of their own are shown as
+//! an audio player, and the first audio file of a post is added to the feeds
+//! as enclosure. This makes the posts with audio a podcast.
+use crate::files::FileIndex;
+use regex::Regex;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// An uploaded audio file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Audio {
+    pub mime_type: String,
+    pub filename: String,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+impl Audio {
+    /// URL of the file with the filename, so that downloads get a useful name.
+    pub fn url(&self, sha: &str) -> String {
+        let filename = self.filename.rsplit('/').next().unwrap_or_default();
+        if filename.is_empty() {
+            format!("/files/{sha}")
+        } else {
+            format!("/files/{sha}/{}", crate::html::url_encode(filename))
+        }
+    }
+}
+
+/// Links to uploaded files.
+static FILE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"/files/([0-9a-f]{16})").unwrap());
+
+/// Paragraphs that contain only a link to an uploaded file.
+static LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<p><a href=["'](/files/([0-9a-f]{16})(?:/[^"']*)?)["']>([^<]*)</a></p>"#).unwrap()
+});
+
+/// Read the audio files from the database, or only the file with `sha`.
+pub(crate) fn read(
+    conn: &Connection,
+    sha: Option<&str>,
+) -> rusqlite::Result<HashMap<String, Audio>> {
+    let stmt = "
+        SELECT sha, mime_type, filename, length(data) AS size
+        FROM files
+        WHERE mime_type LIKE 'audio/%' AND (?1 IS NULL OR sha = ?1);
+        ";
+    let mut stmt = conn.prepare(stmt)?;
+    let rows = stmt.query_map([sha], |row| {
+        let audio = Audio {
+            mime_type: row.get("mime_type")?,
+            filename: row.get("filename")?,
+            size: row.get::<_, i64>("size")? as u64,
+        };
+        Ok((row.get::<_, String>("sha")?, audio))
+    })?;
+    rows.collect()
+}
+
+/// The first audio file that the content links to.
+pub fn first(content: &str, index: &FileIndex) -> Option<(String, Audio)> {
+    FILE.captures_iter(content).find_map(|caps| {
+        let sha = caps[1].to_string();
+        index.audio(&sha).map(|audio| (sha, audio))
+    })
+}
+
+/// Replace links to audio files that are alone in a paragraph with a player.
+///
+/// The link stays below the player for downloading the file.
+pub fn render_html(html: &str, index: &FileIndex) -> String {
+    LINK.replace_all(html, |caps: &regex::Captures| {
+        let (url, sha, text) = (&caps[1], &caps[2], &caps[3]);
+        match index.audio(sha) {
+            Some(audio) => {
+                let mime_type = crate::html::escape_single_quote(&audio.mime_type);
+                format!(
+                    "<figure class='audio'>\
+                    <audio controls preload='metadata'>\
+                    <source src='{url}' type='{mime_type}'>\
+                    </audio>\
+                    <figcaption><a href='{url}'>{text}</a></figcaption>\
+                    </figure>"
+                )
+            }
+            None => caps[0].to_string(),
+        }
+    })
+    .to_string()
+}
+
+#[test]
+fn test_render_html() {
+    let sha = "00000000000000ab";
+    let audio = Audio {
+        mime_type: "audio/mpeg".to_string(),
+        filename: "notes/episode 1.mp3".to_string(),
+        size: 1234,
+    };
+    let index = FileIndex::default();
+    index.insert_audio(sha, audio.clone());
+    assert_eq!(audio.url(sha), format!("/files/{sha}/episode%201.mp3"));
+    let html = format!(r#"<p><a href="/files/{sha}/episode.mp3">Episode 1</a></p>"#);
+    let expected = format!(
+        "<figure class='audio'><audio controls preload='metadata'>\
+        <source src='/files/{sha}/episode.mp3' type='audio/mpeg'></audio>\
+        <figcaption><a href='/files/{sha}/episode.mp3'>Episode 1</a></figcaption>\
+        </figure>"
+    );
+    assert_eq!(render_html(&html, &index), expected);
+
+    // Links inside a sentence and links to other files are kept.
+    let html = format!(r#"<p>Listen to <a href="/files/{sha}">this</a>.</p>"#);
+    assert_eq!(render_html(&html, &index), html);
+    let html = "<p><a href='/files/1111111111111111'>a.txt</a></p>";
+    assert_eq!(render_html(html, &index), html);
+
+    let content = format!("See [a](/files/1111111111111111) and [b](/files/{sha}).");
+    assert_eq!(first(&content, &index), Some((sha.to_string(), audio)));
+    assert_eq!(first("no audio", &index), None);
+}
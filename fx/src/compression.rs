@@ -0,0 +1,306 @@
+//! Compression of responses.
+//!
+//! Pages and feeds are mostly text, which compresses well. The encoding is
+//! negotiated with the `Accept-Encoding` header of the client and the order of
+//! `FX_COMPRESSION`.
+use crate::serve::ServerContext;
+use axum::body::Body;
+use axum::body::HttpBody;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use http_body_util::BodyExt;
+use std::io::Write;
+
+/// A content coding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// Brotli.
+    Br,
+    /// Zstandard.
+    Zstd,
+    /// Gzip.
+    Gzip,
+    /// No compression. Encodings after this one are never used.
+    Identity,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Br => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+            Encoding::Identity => "identity",
+        }
+    }
+}
+
+/// Compress the data.
+///
+/// The best compression is slow, so it is only used for data that is
+/// compressed once, such as the static assets.
+pub fn compress(encoding: Encoding, data: &[u8], best: bool) -> Vec<u8> {
+    match encoding {
+        Encoding::Br => {
+            let params = brotli::enc::BrotliEncoderParams {
+                quality: if best { 11 } else { 5 },
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            brotli::BrotliCompress(&mut &data[..], &mut out, &params).unwrap();
+            out
+        }
+        Encoding::Zstd => {
+            let level = if best { 19 } else { 3 };
+            zstd::encode_all(data, level).unwrap()
+        }
+        Encoding::Gzip => {
+            let level = if best {
+                flate2::Compression::best()
+            } else {
+                flate2::Compression::default()
+            };
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+        Encoding::Identity => data.to_vec(),
+    }
+}
+
+#[test]
+fn test_compress() {
+    let data = "lorem ipsum ".repeat(100);
+    for encoding in [Encoding::Br, Encoding::Zstd, Encoding::Gzip] {
+        let compressed = compress(encoding, data.as_bytes(), false);
+        assert!(compressed.len() < data.len() / 10, "{encoding:?}");
+    }
+    let decompressed = zstd::decode_all(&compress(Encoding::Zstd, b"abc", true)[..]).unwrap();
+    assert_eq!(decompressed, b"abc");
+}
+
+/// The first encoding from `preferred` that the client accepts.
+///
+/// Quality values are only used to exclude encodings since clients rarely set
+/// them otherwise. Returns `None` when the response should not be compressed.
+pub fn negotiate(headers: &HeaderMap, preferred: &[Encoding]) -> Option<Encoding> {
+    let accept = headers.get("Accept-Encoding")?.to_str().ok()?;
+    let accepted = accept
+        .split(',')
+        .filter_map(|part| {
+            let mut parts = part.split(';');
+            let coding = parts.next()?.trim().to_lowercase();
+            let rejected = parts.any(|param| {
+                let param = param.trim().replace(' ', "");
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            if rejected { None } else { Some(coding) }
+        })
+        .collect::<Vec<_>>();
+    let encoding = preferred.iter().find(|encoding| {
+        **encoding == Encoding::Identity
+            || accepted
+                .iter()
+                .any(|coding| coding == encoding.name() || coding == "*")
+    })?;
+    match encoding {
+        Encoding::Identity => None,
+        encoding => Some(*encoding),
+    }
+}
+
+#[test]
+fn test_negotiate() {
+    let preferred = [Encoding::Br, Encoding::Zstd, Encoding::Gzip];
+    let mut headers = HeaderMap::new();
+    assert_eq!(negotiate(&headers, &preferred), None);
+    headers.insert(
+        "Accept-Encoding",
+        "gzip, deflate, br, zstd".parse().unwrap(),
+    );
+    assert_eq!(negotiate(&headers, &preferred), Some(Encoding::Br));
+    headers.insert("Accept-Encoding", "gzip;q=1.0, br; q=0".parse().unwrap());
+    assert_eq!(negotiate(&headers, &preferred), Some(Encoding::Gzip));
+    headers.insert("Accept-Encoding", "identity".parse().unwrap());
+    assert_eq!(negotiate(&headers, &preferred), None);
+    headers.insert("Accept-Encoding", "gzip, zstd".parse().unwrap());
+    let preferred = [Encoding::Identity, Encoding::Gzip];
+    assert_eq!(negotiate(&headers, &preferred), None);
+}
+
+/// Whether compressing the content type is worth it.
+///
+/// Images, audio, video, and archives are already compressed.
+pub fn is_compressible(content_type: &str) -> bool {
+    let content_type = content_type.split(';').next().unwrap_or("").trim();
+    content_type.starts_with("text/")
+        || content_type.ends_with("json")
+        || content_type.ends_with("xml")
+        || content_type.ends_with("javascript")
+        || content_type == "image/svg+xml"
+}
+
+#[test]
+fn test_is_compressible() {
+    assert!(is_compressible("text/html; charset=utf-8"));
+    assert!(is_compressible("application/rss+xml; charset=utf-8"));
+    assert!(is_compressible("application/feed+json"));
+    assert!(is_compressible("image/svg+xml"));
+    assert!(!is_compressible("image/png"));
+    assert!(!is_compressible("application/zip"));
+}
+
+/// Whether the data starts with the magic bytes of a compressed format.
+///
+/// Uploads are sometimes stored with a text content type, such as a
+/// `notes.txt.gz` file that was uploaded as `text/plain`.
+pub fn is_compressed(data: &[u8]) -> bool {
+    let magic: [&[u8]; 7] = [
+        b"\x1f\x8b",           // gzip
+        b"\x28\xb5\x2f\xfd",   // zstd
+        b"PK\x03\x04",         // zip
+        b"\xfd7zXZ\x00",       // xz
+        b"BZh",                // bzip2
+        b"7z\xbc\xaf\x27\x1c", // 7z
+        b"\x89PNG\r\n\x1a\n",  // png
+    ];
+    magic.iter().any(|magic| data.starts_with(magic))
+}
+
+#[test]
+fn test_is_compressed() {
+    assert!(is_compressed(&compress(Encoding::Gzip, b"a", false)));
+    assert!(is_compressed(&compress(Encoding::Zstd, b"a", false)));
+    assert!(!is_compressed(b"hello"));
+}
+
+/// Entity tag of the compressed representation.
+///
+/// Strong entity tags must differ between encodings, so the encoding is added
+/// to the tag in the same way as Apache does.
+fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match etag.strip_suffix('"') {
+        Some(etag) => format!("{etag}-{}\"", encoding.name()),
+        None => etag.to_string(),
+    }
+}
+
+/// Remove the encodings from the tags in `If-None-Match` so that the inner
+/// layers can compare them with the tags of the uncompressed responses.
+fn decoded_etags(if_none_match: &str) -> String {
+    let suffixes = [Encoding::Br, Encoding::Zstd, Encoding::Gzip]
+        .map(|encoding| format!("-{}\"", encoding.name()));
+    if_none_match
+        .split(',')
+        .map(|tag| {
+            let tag = tag.trim();
+            match suffixes
+                .iter()
+                .find(|suffix| tag.ends_with(suffix.as_str()))
+            {
+                Some(suffix) => format!("{}\"", &tag[..tag.len() - suffix.len()]),
+                None => tag.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[test]
+fn test_etags() {
+    let etag = encoded_etag("\"abc\"", Encoding::Br);
+    assert_eq!(etag, "\"abc-br\"");
+    assert_eq!(decoded_etags(&format!("{etag}, \"d\"")), "\"abc\", \"d\"");
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+}
+
+/// Middleware that compresses responses.
+///
+/// Runs outside the conditional layer, which thus sees the entity tags of the
+/// uncompressed responses.
+pub async fn layer(
+    State(ctx): State<ServerContext>,
+    mut req: Request,
+    next: Next,
+) -> Response<Body> {
+    let encoding = negotiate(req.headers(), &ctx.args.compression);
+    let if_none_match = header(req.headers(), "If-None-Match").to_string();
+    if !if_none_match.is_empty() {
+        let decoded = HeaderValue::from_str(&decoded_etags(&if_none_match)).unwrap();
+        req.headers_mut().insert("If-None-Match", decoded);
+    }
+    let mut response = next.run(req).await;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        // The client has to keep the tag of the representation that it has.
+        let etag = header(response.headers(), "ETag").to_string();
+        let encoded = encoding.map(|encoding| encoded_etag(&etag, encoding));
+        if let Some(encoded) = encoded.filter(|encoded| if_none_match.contains(encoded.as_str())) {
+            let value = HeaderValue::from_str(&encoded).unwrap();
+            response.headers_mut().insert("ETag", value);
+        }
+        return response;
+    }
+    let headers = response.headers();
+    let compressible = is_compressible(header(headers, "Content-Type"))
+        && !headers.contains_key("Content-Encoding")
+        && !headers.contains_key("Content-Range")
+        && !header(headers, "Cache-Control").contains("no-transform");
+    if !compressible {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .append("Vary", HeaderValue::from_static("Accept-Encoding"));
+    let Some(encoding) = encoding else {
+        return Response::from_parts(parts, body);
+    };
+    // Streamed bodies without a `Content-Length` have no known size and are
+    // left alone. Compressing reads the whole body into memory, so large
+    // bodies, such as big text files, are also sent as they are.
+    let size = HttpBody::size_hint(&body)
+        .exact()
+        .or_else(|| header(&parts.headers, "Content-Length").parse().ok());
+    let args = &ctx.args;
+    let in_range = size.is_some_and(|size| {
+        let size = size as usize;
+        args.compression_min_size <= size && size <= args.compression_max_size
+    });
+    if !in_range {
+        return Response::from_parts(parts, body);
+    }
+    let etag = header(&parts.headers, "ETag").to_string();
+    if !etag.is_empty() {
+        let etag = HeaderValue::from_str(&encoded_etag(&etag, encoding)).unwrap();
+        parts.headers.insert("ETag", etag);
+    }
+    let data = match body.collect().await {
+        Ok(data) => data.to_bytes(),
+        Err(e) => {
+            tracing::error!("failed to read body for compression: {e}");
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let compressed = tokio::task::spawn_blocking(move || compress(encoding, &data, false))
+        .await
+        .unwrap();
+    parts.headers.remove("Content-Length");
+    let value = HeaderValue::from_static(encoding.name());
+    parts.headers.insert("Content-Encoding", value);
+    Response::from_parts(parts, Body::from(compressed))
+}
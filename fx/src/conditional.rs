@@ -0,0 +1,191 @@
+//! Conditional requests with `ETag` and `Last-Modified`.
+//!
+//! Feed readers poll the feed regularly and browsers revalidate pages and
+//! files. With validators, unchanged responses are answered with a short
+//! `304 Not Modified` instead of the full body.
+//!
+//! Pages and feeds get no `Last-Modified` since they also change with the
+//! settings, the navigation, and deleted posts, which have no date. The `ETag`
+//! of the body covers all of these.
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Method;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use chrono::DateTime;
+use chrono::Utc;
+use sha2::Digest;
+
+/// Parse a date as sent by HTTP, such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+#[test]
+fn test_http_date() {
+    let dt = DateTime::parse_from_rfc3339("1994-11-06T08:49:37Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    let formatted = "Sun, 06 Nov 1994 08:49:37 GMT";
+    assert_eq!(parse_http_date(formatted), Some(dt));
+    assert_eq!(parse_http_date("yesterday"), None);
+}
+
+/// Set a strong `ETag` for a response with the given tag.
+pub fn set_etag(headers: &mut HeaderMap, tag: &str) {
+    let value = HeaderValue::from_str(&format!("\"{tag}\"")).unwrap();
+    headers.insert("ETag", value);
+}
+
+/// Whether `If-None-Match` lists the entity tag.
+///
+/// Uses the weak comparison, which RFC 9110 requires for `If-None-Match`.
+fn none_match(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+#[test]
+fn test_none_match() {
+    assert!(none_match("\"a\"", "\"a\""));
+    assert!(none_match("\"b\", W/\"a\"", "\"a\""));
+    assert!(none_match("*", "\"a\""));
+    assert!(!none_match("\"b\"", "\"a\""));
+}
+
+/// Whether the response that the client has is still up to date.
+fn is_fresh(request: &HeaderMap, response: &HeaderMap) -> bool {
+    let header = |headers: &HeaderMap, name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    // `If-None-Match` takes precedence since the page can also change when,
+    // for example, the settings change while the post stays the same.
+    if let Some(if_none_match) = header(request, "If-None-Match") {
+        return match header(response, "ETag") {
+            Some(etag) => none_match(&if_none_match, &etag),
+            None => false,
+        };
+    }
+    let since = header(request, "If-Modified-Since").and_then(|v| parse_http_date(&v));
+    let modified = header(response, "Last-Modified").and_then(|v| parse_http_date(&v));
+    match (since, modified) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+#[test]
+fn test_is_fresh() {
+    let headers = |pairs: &[(&'static str, &str)]| {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    };
+    let modified = "Sun, 06 Nov 1994 08:49:37 GMT";
+    let response = headers(&[("ETag", "\"a\""), ("Last-Modified", modified)]);
+    assert!(is_fresh(&headers(&[("If-None-Match", "\"a\"")]), &response));
+    assert!(is_fresh(
+        &headers(&[("If-Modified-Since", modified)]),
+        &response
+    ));
+    let later = "Mon, 07 Nov 1994 08:49:37 GMT";
+    assert!(is_fresh(
+        &headers(&[("If-Modified-Since", later)]),
+        &response
+    ));
+    let earlier = "Sat, 05 Nov 1994 08:49:37 GMT";
+    assert!(!is_fresh(
+        &headers(&[("If-Modified-Since", earlier)]),
+        &response
+    ));
+    // `If-None-Match` takes precedence.
+    let request = headers(&[("If-None-Match", "\"b\""), ("If-Modified-Since", later)]);
+    assert!(!is_fresh(&request, &response));
+    // Without a validator in the response, nothing is fresh.
+    let request = headers(&[("If-Modified-Since", later)]);
+    assert!(!is_fresh(&request, &HeaderMap::new()));
+}
+
+/// Hash of a generated body.
+///
+/// The nonce of the Content-Security-Policy is different for each request, so
+/// it is left out. Otherwise, pages would never match. When a cached page is
+/// reused after a `304`, the browser also keeps the policy that was sent with
+/// the page, so the nonces in the page and policy still belong together.
+fn body_tag(body: &[u8], nonce: &str) -> String {
+    let sha = if nonce.is_empty() {
+        sha2::Sha256::digest(body)
+    } else {
+        let body = String::from_utf8_lossy(body).replace(nonce, "");
+        sha2::Sha256::digest(body.as_bytes())
+    };
+    hex::encode(&sha[..16])
+}
+
+#[test]
+fn test_body_tag() {
+    let a = body_tag(b"<script nonce='abc'>", "abc");
+    let b = body_tag(b"<script nonce='def'>", "def");
+    assert_eq!(a, b);
+    assert_ne!(a, body_tag(b"<script nonce='abc'>", ""));
+}
+
+fn not_modified(response: Response<Body>) -> Response<Body> {
+    let (mut parts, _body) = response.into_parts();
+    parts.status = StatusCode::NOT_MODIFIED;
+    // Without a content type, the security headers middleware does not send a
+    // new policy that would not match the nonces in the cached page.
+    for name in ["Content-Type", "Content-Length", "Content-Encoding"] {
+        parts.headers.remove(name);
+    }
+    Response::from_parts(parts, Body::empty())
+}
+
+/// Middleware that adds an `ETag` to generated responses and answers
+/// conditional requests.
+///
+/// Handlers can set their own `ETag` or `Last-Modified`, which avoids reading
+/// large bodies such as files.
+pub async fn layer(req: Request, next: Next) -> Response<Body> {
+    let is_get = req.method() == Method::GET || req.method() == Method::HEAD;
+    let request_headers = req.headers().clone();
+    let response = next.run(req).await;
+    if !is_get || response.status() != StatusCode::OK {
+        return response;
+    }
+    let response = if response.headers().contains_key("ETag") {
+        response
+    } else {
+        let (mut parts, body) = response.into_parts();
+        let body = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("failed to read body for ETag: {e}");
+                let body = Body::from("failed to read body");
+                parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+                return Response::from_parts(parts, body);
+            }
+        };
+        let tag = body_tag(&body, &crate::csp::nonce());
+        set_etag(&mut parts.headers, &tag);
+        Response::from_parts(parts, Body::from(body))
+    };
+    if is_fresh(&request_headers, response.headers()) {
+        not_modified(response)
+    } else {
+        response
+    }
+}
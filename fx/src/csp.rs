@@ -0,0 +1,206 @@
+//! Content-Security-Policy and other security headers.
+//!
+//! Each request gets a random nonce. Only scripts that carry this nonce are
+//! allowed to run, so a script that ends up in a page via some injection is
+//! blocked by the browser.
+use crate::serve::ServerContext;
+use axum::Router;
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::routing::post;
+use regex::Regex;
+use serde_json::Value;
+use std::sync::LazyLock;
+
+/// How the Content-Security-Policy is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// Block everything that violates the policy.
+    Enforce,
+    /// Only report violations, which is useful for testing the policy.
+    ReportOnly,
+    /// Do not send a policy.
+    Off,
+}
+
+tokio::task_local! {
+    static NONCE: String;
+}
+
+/// Nonce of the current request.
+///
+/// Empty outside a request, for example in unit tests.
+pub fn nonce() -> String {
+    NONCE.try_with(|nonce| nonce.clone()).unwrap_or_default()
+}
+
+/// Start tags of script elements.
+static SCRIPT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<script(\s|>)").unwrap());
+
+/// Add the nonce to the script elements in HTML written by the admin.
+///
+/// Used for the extra HTML head so that scripts, such as analytics, keep
+/// working. Scripts in posts do not get the nonce.
+pub fn add_nonce(html: &str, nonce: &str) -> String {
+    SCRIPT
+        .replace_all(html, |caps: &regex::Captures| {
+            format!("<script nonce='{nonce}'{}", &caps[1])
+        })
+        .to_string()
+}
+
+#[test]
+fn test_add_nonce() {
+    let html = "<script src='a.js'></script><SCRIPT>x()</SCRIPT><scripts>";
+    let expected = "<script nonce='n' src='a.js'></script>\
+        <script nonce='n'>x()</SCRIPT><scripts>";
+    assert_eq!(add_nonce(html, "n"), expected);
+}
+
+pub fn policy(nonce: &str, report: bool) -> String {
+    // `https:` and `'unsafe-inline'` are ignored by browsers that understand
+    // nonces and only serve as fallback for older browsers. Styles are not
+    // restricted since the pages use inline `style` attributes everywhere.
+    let mut directives = vec![
+        "default-src 'self'".to_string(),
+        format!("script-src 'nonce-{nonce}' 'strict-dynamic' https: 'unsafe-inline'"),
+        "style-src 'self' 'unsafe-inline' https:".to_string(),
+        "img-src 'self' https: data:".to_string(),
+        "font-src 'self' https: data:".to_string(),
+        "media-src 'self' https:".to_string(),
+        "frame-src https:".to_string(),
+        "object-src 'none'".to_string(),
+        "base-uri 'self'".to_string(),
+        "frame-ancestors 'self'".to_string(),
+    ];
+    if report {
+        directives.push("report-uri /csp-report".to_string());
+    }
+    directives.join("; ")
+}
+
+#[test]
+fn test_policy() {
+    let policy = policy("abc", false);
+    assert!(policy.contains("script-src 'nonce-abc' 'strict-dynamic'"));
+    assert!(policy.contains("object-src 'none'"));
+    assert!(!policy.contains("report-uri"));
+}
+
+fn is_html(headers: &HeaderMap) -> bool {
+    headers
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"))
+}
+
+/// Middleware that creates the nonce and sets the security headers.
+pub async fn headers(State(ctx): State<ServerContext>, req: Request, next: Next) -> Response<Body> {
+    let nonce = fx_auth::generate_token();
+    let mut response = NONCE.scope(nonce.clone(), next.run(req)).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        "X-Content-Type-Options",
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        "Referrer-Policy",
+        HeaderValue::from_static("strict-origin-when-cross-origin"),
+    );
+    headers.insert(
+        "Permissions-Policy",
+        HeaderValue::from_static(
+            "camera=(), microphone=(), geolocation=(), payment=(), usb=(), browsing-topics=()",
+        ),
+    );
+    // The policy only has an effect on documents.
+    if is_html(headers) {
+        let name = match ctx.args.csp {
+            Mode::Enforce => Some("Content-Security-Policy"),
+            Mode::ReportOnly => Some("Content-Security-Policy-Report-Only"),
+            Mode::Off => None,
+        };
+        if let Some(name) = name {
+            let policy = policy(&nonce, ctx.args.csp_report);
+            headers.insert(name, HeaderValue::from_str(&policy).unwrap());
+        }
+    }
+    response
+}
+
+/// Shorten a value from the report before it ends up in the log.
+fn field(report: &Value, names: &[&str]) -> String {
+    let value = names
+        .iter()
+        .find_map(|name| report.get(name).and_then(|value| value.as_str()))
+        .unwrap_or("");
+    value.chars().take(200).collect()
+}
+
+/// Violations in a report.
+///
+/// Browsers send either the older `application/csp-report` format or an array
+/// of reports from the Reporting API.
+fn violations(report: &Value) -> Vec<&Value> {
+    match report {
+        Value::Array(reports) => reports
+            .iter()
+            .filter_map(|report| report.get("body"))
+            .collect(),
+        Value::Object(_) => report.get("csp-report").into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+#[test]
+fn test_violations() {
+    let old = serde_json::json!({
+        "csp-report": {"document-uri": "https://example.com/", "blocked-uri": "inline"}
+    });
+    let violations_old = violations(&old);
+    assert_eq!(violations_old.len(), 1);
+    assert_eq!(field(violations_old[0], &["blocked-uri"]), "inline");
+    let new = serde_json::json!([
+        {"type": "csp-violation", "body": {"blockedURL": "eval"}}
+    ]);
+    let violations_new = violations(&new);
+    assert_eq!(
+        field(violations_new[0], &["blocked-uri", "blockedURL"]),
+        "eval"
+    );
+}
+
+async fn post_csp_report(State(ctx): State<ServerContext>, body: Bytes) -> Response<Body> {
+    if !ctx.args.csp_report {
+        return crate::serve::not_found(State(ctx)).await;
+    }
+    let report = serde_json::from_slice::<Value>(&body).unwrap_or(Value::Null);
+    for violation in violations(&report) {
+        // Debug formatting escapes control characters such as newlines.
+        tracing::warn!(
+            "CSP violation on {:?}: {:?} blocked {:?}",
+            field(violation, &["document-uri", "documentURL"]),
+            field(violation, &["violated-directive", "effectiveDirective"]),
+            field(violation, &["blocked-uri", "blockedURL"]),
+        );
+    }
+    crate::serve::response(StatusCode::NO_CONTENT, HeaderMap::new(), "", &ctx)
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        // Reports are small, so there is no need to accept large bodies.
+        .route(
+            "/csp-report",
+            post(post_csp_report).layer(DefaultBodyLimit::max(64 * 1024)),
+        )
+}
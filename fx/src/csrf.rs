@@ -0,0 +1,187 @@
+//! Protection against cross-site request forgery.
+//!
+//! `SameSite=Strict` cookies already stop most forged requests, but not all
+//! browsers enforce it and it does not help against sibling subdomains. So
+//! each form also contains a token that is tied to the session, and requests
+//! that the browser marks as cross-site are rejected.
+use crate::serve::ServerContext;
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::extract::FromRequest;
+use axum::extract::FromRequestParts;
+use axum::extract::Request;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum_extra::extract::CookieJar;
+use serde::de::DeserializeOwned;
+
+/// Name of the form field that contains the token.
+pub const FIELD: &str = "csrf_token";
+
+/// Name of the header that contains the token for requests from scripts.
+pub const HEADER: &str = "X-CSRF-Token";
+
+/// Token for the forms of the current session.
+///
+/// Derived from the session token so that nothing has to be stored. The hash
+/// does not reveal the session token, so the token can be put in the page.
+pub fn token(ctx: &ServerContext, jar: &CookieJar) -> String {
+    match fx_auth::session(&ctx.key, jar) {
+        Some(session) => fx_auth::hash_token(&format!("csrf {session}")),
+        None => "".to_string(),
+    }
+}
+
+/// Hidden input that has to be placed inside each form.
+pub fn input(ctx: &ServerContext, jar: &CookieJar) -> String {
+    let token = token(ctx, jar);
+    format!("<input type='hidden' name='{FIELD}' value='{token}'/>")
+}
+
+/// Whether the token belongs to the current session.
+pub fn verify(ctx: &ServerContext, jar: &CookieJar, received: &str) -> bool {
+    let expected = token(ctx, jar);
+    !expected.is_empty() && crate::serve::constant_time_eq(&expected, received)
+}
+
+/// Origins that the browser may report for this site.
+///
+/// Plain HTTP is only expected outside production.
+fn expected_origins(base_url: &str, production: bool, headers: &HeaderMap) -> Vec<String> {
+    let mut origins = Vec::new();
+    if !base_url.is_empty() {
+        origins.push(base_url.to_string());
+    }
+    if let Some(host) = headers.get("Host").and_then(|host| host.to_str().ok()) {
+        origins.push(format!("https://{host}"));
+        if !production {
+            origins.push(format!("http://{host}"));
+        }
+    }
+    origins
+}
+
+/// Whether the browser indicates that the request comes from this site.
+///
+/// Requests without `Sec-Fetch-Site` and `Origin`, such as from old browsers
+/// or scripts, are allowed since they cannot carry a forged form token.
+fn is_same_origin(base_url: &str, production: bool, headers: &HeaderMap) -> bool {
+    if let Some(site) = headers.get("Sec-Fetch-Site") {
+        // `none` means that the user initiated the request, for example via a
+        // bookmark.
+        return site == "same-origin" || site == "none";
+    }
+    match headers
+        .get("Origin")
+        .and_then(|origin| origin.to_str().ok())
+    {
+        Some(origin) => expected_origins(base_url, production, headers)
+            .iter()
+            .any(|expected| expected == origin),
+        None => true,
+    }
+}
+
+#[test]
+fn test_is_same_origin() {
+    let base_url = "https://example.com";
+    let mut headers = HeaderMap::new();
+    assert!(is_same_origin(base_url, true, &headers));
+    headers.insert("Origin", "https://evil.example".parse().unwrap());
+    assert!(!is_same_origin(base_url, true, &headers));
+    headers.insert("Origin", "null".parse().unwrap());
+    assert!(!is_same_origin(base_url, true, &headers));
+    headers.insert("Origin", "https://example.com".parse().unwrap());
+    assert!(is_same_origin(base_url, true, &headers));
+    headers.insert("Sec-Fetch-Site", "cross-site".parse().unwrap());
+    assert!(!is_same_origin(base_url, true, &headers));
+    headers.insert("Sec-Fetch-Site", "same-site".parse().unwrap());
+    assert!(!is_same_origin(base_url, true, &headers));
+    headers.insert("Sec-Fetch-Site", "same-origin".parse().unwrap());
+    assert!(is_same_origin(base_url, true, &headers));
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Host", "localhost:3000".parse().unwrap());
+    headers.insert("Origin", "http://localhost:3000".parse().unwrap());
+    assert!(is_same_origin("", false, &headers));
+    assert!(!is_same_origin("", true, &headers));
+}
+
+/// Response for a request that failed the check.
+pub async fn rejected(ctx: &ServerContext) -> Response<Body> {
+    tracing::warn!("rejected request that failed the CSRF check");
+    let msg = "The form could not be verified. This happens when the page was \
+        open for a long time, when you logged out in the meantime, or when the \
+        form was submitted from another website. Go back, reload the page, \
+        and try again.";
+    crate::serve::error(ctx, StatusCode::FORBIDDEN, "Forbidden", msg).await
+}
+
+/// Rejects requests that the browser marks as cross-site.
+///
+/// For forms that are used before logging in. Use [CsrfForm] for forms that
+/// require a login.
+pub struct SameOrigin;
+
+impl FromRequestParts<ServerContext> for SameOrigin {
+    type Rejection = Response<Body>;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        ctx: &ServerContext,
+    ) -> Result<Self, Self::Rejection> {
+        let base_url = ctx.base_url();
+        if is_same_origin(&base_url, ctx.args.production, &parts.headers) {
+            Ok(SameOrigin)
+        } else {
+            Err(rejected(ctx).await)
+        }
+    }
+}
+
+/// CSRF check for forms without other fields.
+pub type Csrf = CsrfForm<serde::de::IgnoredAny>;
+
+/// URL-encoded form from a logged in user that passed the CSRF checks.
+///
+/// Use this instead of `Form` in admin handlers. Responds with the login error
+/// when the user is not logged in so that handlers behave as before.
+pub struct CsrfForm<T>(pub T);
+
+impl<T: DeserializeOwned + Send> FromRequest<ServerContext> for CsrfForm<T> {
+    type Rejection = Response<Body>;
+
+    async fn from_request(req: Request, ctx: &ServerContext) -> Result<Self, Self::Rejection> {
+        let (mut parts, body) = req.into_parts();
+        SameOrigin::from_request_parts(&mut parts, ctx).await?;
+        let jar = CookieJar::from_headers(&parts.headers);
+        if !crate::serve::is_logged_in(ctx, &jar) {
+            return Err(crate::serve::unauthorized(ctx).await);
+        }
+        let req = Request::from_parts(parts, body);
+        let bytes = match Bytes::from_request(req, ctx).await {
+            Ok(bytes) => bytes,
+            Err(e) => return Err(axum::response::IntoResponse::into_response(e)),
+        };
+        let pairs = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&bytes);
+        let received = pairs
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(key, _)| key == FIELD)
+            .map(|(_, value)| value)
+            .unwrap_or_default();
+        if !verify(ctx, &jar, &received) {
+            return Err(rejected(ctx).await);
+        }
+        match serde_urlencoded::from_bytes::<T>(&bytes) {
+            Ok(form) => Ok(CsrfForm(form)),
+            Err(e) => {
+                let msg = format!("Invalid form: {e}");
+                let status = StatusCode::UNPROCESSABLE_ENTITY;
+                Err(crate::serve::error(ctx, status, "Invalid Form", &msg).await)
+            }
+        }
+    }
+}
@@ -63,6 +63,10 @@ impl Kv {
             .query_row([key], |row| row.get("value"))?;
         Ok(value)
     }
+    pub fn delete(conn: &Connection, key: &str) -> Result<usize> {
+        let stmt = "DELETE FROM kv WHERE key = ?";
+        conn.execute(stmt, [key])
+    }
     pub fn get_or_empty_string(conn: &Connection, key: &str) -> String {
         match Kv::get(conn, key) {
             Ok(value) => String::from_utf8(value).unwrap(),
@@ -131,8 +135,9 @@ impl Post {
         let created = created.to_sqlite();
         let updated = updated.to_sqlite();
         let content = cleanup_content(content);
-        conn.execute(stmt, [created, updated, content])?;
+        conn.execute(stmt, [created, updated, content.clone()])?;
         let id = conn.last_insert_rowid();
+        crate::usage::index_post(conn, id, &content)?;
         Ok(id)
     }
     pub fn list(conn: &Connection) -> Result<Vec<Post>> {
@@ -187,11 +192,16 @@ impl Post {
         let updated = self.updated.to_sqlite();
         let content = cleanup_content(&self.content);
         let id = self.id.to_string();
-        conn.execute(stmt, [created, updated, content, id])
+        let changed = conn.execute(stmt, [created, updated, content.clone(), id])?;
+        crate::usage::index_post(conn, self.id, &content)?;
+        Ok(changed)
     }
     pub fn delete(conn: &Connection, id: i64) -> Result<usize> {
         let stmt = "UPDATE posts SET content = '<DELETED>' WHERE id = ?";
-        conn.execute(stmt, [id])
+        let changed = conn.execute(stmt, [id])?;
+        crate::usage::index_post(conn, id, "")?;
+        crate::og::delete(conn, id)?;
+        Ok(changed)
     }
 }
 
@@ -229,10 +239,40 @@ pub fn connect(args: &ServeArgs) -> Result<DbPool> {
     Ok(pool)
 }
 
+/// Add a column to a table that was created by an older version of fx.
+pub fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let stmt = format!("SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?");
+    let exists = conn.prepare(&stmt)?.exists([column])?;
+    if !exists {
+        let stmt = format!("ALTER TABLE {table} ADD COLUMN {column} {definition}");
+        conn.execute(&stmt, [])?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_add_column() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute("CREATE TABLE t (a TEXT)", []).unwrap();
+    add_column(&conn, "t", "b", "INTEGER").unwrap();
+    // Running it again does nothing.
+    add_column(&conn, "t", "b", "INTEGER").unwrap();
+    conn.execute("INSERT INTO t (a, b) VALUES ('a', 1)", [])
+        .unwrap();
+}
+
 fn init_tables(conn: &Connection) {
     Post::create_table(conn).expect("Failed to create posts table");
     Kv::create_table(conn).expect("Failed to create kv table");
     File::create_table(conn).expect("Failed to create files table");
+    crate::images::Variant::create_table(conn).expect("Failed to create file_variants table");
+    crate::usage::create_table(conn).expect("Failed to create file_usage table");
+    crate::upload::create_table(conn).expect("Failed to create uploads table");
+    crate::og::create_table(conn).expect("Failed to create og_images table");
+    crate::indieauth::create_tables(conn).expect("Failed to create indieauth tables");
+    crate::tokens::ApiToken::create_table(conn).expect("Failed to create api_tokens table");
+    crate::passkeys::create_tables(conn).expect("Failed to create passkey tables");
+    crate::sessions::Session::create_table(conn).expect("Failed to create sessions table");
 }
 
 pub const BLOGROLL_SETTINGS_KEY: &str = "blogroll_settings";
@@ -314,6 +354,11 @@ fn init_data(args: &ServeArgs, conn: &Connection) {
             mime_type: "text/plain".to_string(),
             filename: "example.txt".to_string(),
             data: Bytes::from_static(b"example"),
+            width: None,
+            height: None,
+            alt: String::new(),
+            caption: String::new(),
+            title: String::new(),
         };
         File::insert(conn, &file).unwrap();
 
@@ -325,4 +370,5 @@ fn init_data(args: &ServeArgs, conn: &Connection) {
 pub fn init(args: &ServeArgs, conn: &Connection) {
     init_tables(conn);
     init_data(args, conn);
+    crate::usage::rebuild(conn).expect("Failed to index file usage");
 }
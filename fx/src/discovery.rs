@@ -1,17 +1,35 @@
-//! Discovery protocols such as sitemap.xml, rss and robots.
+//! Discovery protocols such as sitemap.xml, feeds and robots.
+//!
+//! The posts are published as RSS 2.0, Atom, and JSON Feed 1.1. All three are
+//! rendered from the same [Feed] so that they contain the same entries.
+//!
+//! The feeds contain the full posts, but only the newest `FX_FEED_SIZE`. Older
+//! posts are in archive pages, such as `/atom.xml?page=1` for the oldest
+//! posts, which are linked as described in RFC 5005 so that readers can fetch
+//! the history. Pages are numbered from the oldest posts, so an archive page
+//! does not change when new posts are published.
+//!
+//! Posts that link to an uploaded audio file contain it as enclosure and are
+//! also published as podcast at `/podcast.xml`.
 use crate::data::Post;
 use crate::files::File;
+use crate::files::FileIndex;
 use crate::serve::ServerContext;
 use crate::serve::content_type;
 use crate::serve::response;
 use crate::settings::Settings;
 use axum::Router;
 use axum::body::Body;
+use axum::extract::Query;
 use axum::extract::State;
 use axum::http::HeaderMap;
 use axum::http::Response;
 use axum::http::StatusCode;
 use axum::routing::get;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
 
 fn rfc822_datetime(dt: &chrono::DateTime<chrono::Utc>) -> String {
     dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
@@ -40,56 +58,602 @@ fn test_escape_xml() {
     assert_eq!(escape_xml("foo&bar"), "foo&amp;bar");
 }
 
-async fn rss(ctx: &ServerContext, posts: &[Post]) -> String {
-    let settings = Settings::from_db(&ctx.conn()).unwrap();
-    let site_name = escape_xml(&settings.site_name);
-    let author_name = escape_xml(&settings.author_name);
-    let base = ctx.base_url();
+/// A post in the feeds.
+struct Entry {
+    url: String,
+    title: String,
+    /// The post as HTML.
+    content: String,
+    published: DateTime<Utc>,
+    updated: DateTime<Utc>,
+    enclosure: Option<Enclosure>,
+}
+
+/// A media file that is attached to a post, such as the audio of a podcast
+/// episode.
+struct Enclosure {
+    url: String,
+    mime_type: String,
+    /// Size in bytes.
+    size: u64,
+}
+
+impl Entry {
+    fn new(base: &str, post: &Post, index: &FileIndex) -> Self {
+        let enclosure = crate::audio::first(&post.content, index).map(|(sha, audio)| Enclosure {
+            url: format!("{base}{}", audio.url(&sha)),
+            mime_type: audio.mime_type,
+            size: audio.size,
+        });
+        Entry {
+            url: format!("{base}/posts/{}", post.id),
+            title: crate::md::extract_html_title(post),
+            content: crate::md::extract_rss_description(post, index),
+            published: post.created,
+            updated: post.updated,
+            enclosure,
+        }
+    }
+}
+
+/// Number of archive pages when each feed document has `size` items.
+///
+/// The newest items are always in the subscription feed, so they are only
+/// archived once a newer item exists.
+fn archive_pages(total: usize, size: usize) -> usize {
+    total.saturating_sub(1) / size
+}
+
+/// The items on a page, where `None` is the subscription feed.
+///
+/// The items are sorted with the newest first.
+fn page_items<T>(items: &[T], size: usize, page: Option<usize>) -> Option<&[T]> {
+    let total = items.len();
+    match page {
+        None => Some(&items[..size.min(total)]),
+        Some(page) if 1 <= page && page <= archive_pages(total, size) => {
+            Some(&items[total - page * size..total - (page - 1) * size])
+        }
+        Some(_) => None,
+    }
+}
+
+#[test]
+fn test_page_items() {
+    let items = [7, 6, 5, 4, 3, 2, 1];
+    assert_eq!(archive_pages(items.len(), 3), 2);
+    assert_eq!(page_items(&items, 3, None), Some(&[7, 6, 5][..]));
+    assert_eq!(page_items(&items, 3, Some(1)), Some(&[3, 2, 1][..]));
+    assert_eq!(page_items(&items, 3, Some(2)), Some(&[6, 5, 4][..]));
+    assert_eq!(page_items(&items, 3, Some(3)), None);
+    assert_eq!(page_items(&items, 3, Some(0)), None);
+    assert_eq!(archive_pages(6, 3), 1);
+    assert_eq!(archive_pages(3, 3), 0);
+    assert_eq!(page_items(&items[..0], 3, None), Some(&[][..]));
+}
+
+/// What the feeds contain, independent of the format.
+struct Feed {
+    title: String,
+    author: String,
+    description: String,
+    /// URL of the site without a trailing slash.
+    base: String,
+    language: String,
+    /// When any of the entries was last changed.
+    updated: Option<DateTime<Utc>>,
+    entries: Vec<Entry>,
+    /// The archive page, or `None` for the subscription feed.
+    page: Option<usize>,
+    /// Number of archive pages.
+    pages: usize,
+}
+
+impl Feed {
+    /// The feed for the page, or `None` when the page does not exist.
+    ///
+    /// The posts are sorted with the newest first.
+    fn new(ctx: &ServerContext, posts: &[Post], page: Option<usize>) -> Option<Self> {
+        let size = ctx.args.feed_size.max(1);
+        let pages = archive_pages(posts.len(), size);
+        let posts = page_items(posts, size, page)?;
+        let settings = Settings::from_db(&ctx.conn()).unwrap();
+        Some(Feed::with_posts(ctx, &settings, posts, page, pages))
+    }
+
+    fn with_posts(
+        ctx: &ServerContext,
+        settings: &Settings,
+        posts: &[Post],
+        page: Option<usize>,
+        pages: usize,
+    ) -> Self {
+        let base = ctx.base_url();
+        let entries = posts
+            .iter()
+            .map(|post| Entry::new(&base, post, &ctx.file_index))
+            .collect();
+        Feed {
+            title: settings.site_name.clone(),
+            description: format!("Posts by {}", settings.author_name),
+            author: settings.author_name.clone(),
+            base,
+            language: ctx.args.html_lang.clone(),
+            updated: posts.iter().map(|post| post.updated).max(),
+            entries,
+            page,
+            pages,
+        }
+    }
+
+    fn url(&self, path: &str, page: Option<usize>) -> String {
+        let base = &self.base;
+        match page {
+            Some(page) => format!("{base}{path}?page={page}"),
+            None => format!("{base}{path}"),
+        }
+    }
+
+    /// The link relations of RFC 5005 for the feed at `path`.
+    ///
+    /// The subscription feed links to the newest archive page and each archive
+    /// page links to its neighbors and back to the subscription feed.
+    fn links(&self, path: &str) -> Vec<(&'static str, String)> {
+        let mut links = vec![("self", self.url(path, self.page))];
+        match self.page {
+            None => {
+                if 0 < self.pages {
+                    links.push(("prev-archive", self.url(path, Some(self.pages))));
+                }
+            }
+            Some(page) => {
+                links.push(("current", self.url(path, None)));
+                if 1 < page {
+                    links.push(("prev-archive", self.url(path, Some(page - 1))));
+                }
+                if page < self.pages {
+                    links.push(("next-archive", self.url(path, Some(page + 1))));
+                }
+            }
+        }
+        links
+    }
+
+    /// The page with the next older entries for JSON Feed's `next_url`.
+    fn next_url(&self, path: &str) -> Option<String> {
+        let older = match self.page {
+            None => self.pages,
+            Some(page) => page - 1,
+        };
+        (0 < older).then(|| self.url(path, Some(older)))
+    }
+}
+
+/// Namespace of the `archive` element from RFC 5005.
+const HISTORY_NS: &str = "http://purl.org/syndication/history/1.0";
+
+/// The `archive` element that marks archive pages, which do not change.
+fn archive_marker(feed: &Feed) -> &'static str {
+    if feed.page.is_some() {
+        "<fh:archive/>\n"
+    } else {
+        ""
+    }
+}
+
+/// Wrap HTML in a CDATA section.
+///
+/// The end marker can occur in posts, such as in code blocks, so it is split
+/// over two sections.
+fn cdata(html: &str) -> String {
+    format!("<![CDATA[{}]]>", html.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+#[test]
+fn test_cdata() {
+    assert_eq!(cdata("<p>a</p>"), "<![CDATA[<p>a</p>]]>");
+    assert_eq!(cdata("a]]>b"), "<![CDATA[a]]]]><![CDATA[>b]]>");
+}
+
+/// An RSS item with `extra` elements, such as the tags of podcasts.
+fn rss_item(entry: &Entry, extra: &str) -> String {
+    let title = escape_xml(&entry.title);
+    let description = cdata(&entry.content);
+    let url = &entry.url;
+    let created = rfc822_datetime(&entry.published);
+    let enclosure = match &entry.enclosure {
+        Some(enclosure) => format!(
+            "<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+            escape_xml(&enclosure.url),
+            enclosure.size,
+            escape_xml(&enclosure.mime_type)
+        ),
+        None => String::new(),
+    };
+    indoc::formatdoc! {"
+        <item>
+        <title>{title}</title>
+        <link>{url}</link>
+        <guid>{url}</guid>
+        <pubDate>{created}</pubDate>
+        <description>{description}</description>
+        {enclosure}{extra}</item>
+        "
+    }
+}
+
+fn rss(feed: &Feed) -> String {
+    let site_name = escape_xml(&feed.title);
+    let description = escape_xml(&feed.description);
+    let base = &feed.base;
     let mut body = String::new();
     body.push_str(xml_header());
-    body.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n");
+    body.push_str(&format!(
+        "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+        xmlns:fh=\"{HISTORY_NS}\">\n"
+    ));
     body.push_str("<channel>\n");
     body.push_str(&format!("<title>{site_name}</title>\n"));
     body.push_str(&format!("<link>{base}</link>\n"));
+    body.push_str(&format!("<description>{description}</description>\n"));
+    for (rel, href) in feed.links("/feed.xml") {
+        body.push_str(&format!(
+            "<atom:link rel=\"{rel}\" href=\"{href}\" type=\"application/rss+xml\"/>\n"
+        ));
+    }
+    body.push_str(archive_marker(feed));
+    for entry in &feed.entries {
+        body.push_str(&rss_item(entry, ""));
+    }
+    // Not minified since that would remove the indentation of code blocks.
+    body.push_str("</channel>\n");
+    body.push_str("</rss>\n");
+    body
+}
+
+fn atom(feed: &Feed) -> String {
+    let base = &feed.base;
+    // Atom requires an update time, so a feed without entries falls back to
+    // the Unix epoch.
+    let updated = w3_datetime(&feed.updated.unwrap_or_default());
+    let mut body = String::new();
+    body.push_str(xml_header());
     body.push_str(&format!(
-        "<description>Posts by {author_name}</description>\n"
+        "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:fh=\"{HISTORY_NS}\" \
+        xml:lang=\"{}\">\n",
+        escape_xml(&feed.language)
     ));
-    let atom_link = format!("{base}/feed.xml");
+    body.push_str(&format!("<title>{}</title>\n", escape_xml(&feed.title)));
     body.push_str(&format!(
-        "<atom:link rel=\"self\" href=\"{atom_link}\" \
-      type=\"application/rss+xml\"/>\n"
+        "<subtitle>{}</subtitle>\n",
+        escape_xml(&feed.description)
     ));
-    for post in posts {
-        let title = escape_xml(&crate::md::extract_html_title(post));
-        let description = &crate::md::extract_rss_description(post);
-        let url = format!("{base}/posts/{}", post.id);
-        let created = rfc822_datetime(&post.created);
-        let entry = format!(
-            "
-            <item>
+    body.push_str(&format!("<id>{base}/</id>\n"));
+    body.push_str(&format!(
+        "<link rel=\"alternate\" type=\"text/html\" href=\"{base}/\"/>\n"
+    ));
+    for (rel, href) in feed.links("/atom.xml") {
+        body.push_str(&format!(
+            "<link rel=\"{rel}\" type=\"application/atom+xml\" href=\"{href}\"/>\n"
+        ));
+    }
+    body.push_str(archive_marker(feed));
+    body.push_str(&format!("<updated>{updated}</updated>\n"));
+    body.push_str(&format!(
+        "<author><name>{}</name></author>\n",
+        escape_xml(&feed.author)
+    ));
+    for entry in &feed.entries {
+        let title = escape_xml(&entry.title);
+        let url = &entry.url;
+        let published = w3_datetime(&entry.published);
+        let updated = w3_datetime(&entry.updated);
+        let content = escape_xml(&entry.content);
+        let enclosure = match &entry.enclosure {
+            Some(enclosure) => format!(
+                "<link rel=\"enclosure\" type=\"{}\" length=\"{}\" href=\"{}\"/>\n",
+                escape_xml(&enclosure.mime_type),
+                enclosure.size,
+                escape_xml(&enclosure.url)
+            ),
+            None => String::new(),
+        };
+        let entry = indoc::formatdoc! {"
+            <entry>
             <title>{title}</title>
-            <link>{url}</link>
-            <guid>{url}</guid>
-            <pubDate>{created}</pubDate>
-            <description><![CDATA[{description}]]</description>
-            </item>
+            <link rel=\"alternate\" type=\"text/html\" href=\"{url}\"/>
+            {enclosure}<id>{url}</id>
+            <published>{published}</published>
+            <updated>{updated}</updated>
+            <content type=\"html\">{content}</content>
+            </entry>
             "
-        );
+        };
         body.push_str(&entry);
     }
+    // Not minified since that would remove the indentation of code blocks in
+    // the escaped content.
+    body.push_str("</feed>\n");
+    body
+}
+
+#[derive(Serialize)]
+struct JsonFeedAuthor<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonFeedItem<'a> {
+    id: &'a str,
+    url: &'a str,
+    title: &'a str,
+    content_html: &'a str,
+    date_published: String,
+    date_modified: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<JsonFeedAttachment<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedAttachment<'a> {
+    url: &'a str,
+    mime_type: &'a str,
+    size_in_bytes: u64,
+}
+
+/// A feed according to <https://www.jsonfeed.org/version/1.1/>.
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: String,
+    feed_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_url: Option<String>,
+    description: &'a str,
+    language: &'a str,
+    authors: Vec<JsonFeedAuthor<'a>>,
+    items: Vec<JsonFeedItem<'a>>,
+}
+
+fn json_feed(feed: &Feed) -> String {
+    let base = &feed.base;
+    let items = feed
+        .entries
+        .iter()
+        .map(|entry| JsonFeedItem {
+            id: &entry.url,
+            url: &entry.url,
+            title: &entry.title,
+            content_html: &entry.content,
+            date_published: w3_datetime(&entry.published),
+            date_modified: w3_datetime(&entry.updated),
+            attachments: entry
+                .enclosure
+                .iter()
+                .map(|enclosure| JsonFeedAttachment {
+                    url: &enclosure.url,
+                    mime_type: &enclosure.mime_type,
+                    size_in_bytes: enclosure.size,
+                })
+                .collect(),
+        })
+        .collect();
+    let json = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: &feed.title,
+        home_page_url: format!("{base}/"),
+        feed_url: feed.url("/feed.json", feed.page),
+        next_url: feed.next_url("/feed.json"),
+        description: &feed.description,
+        language: &feed.language,
+        authors: vec![JsonFeedAuthor { name: &feed.author }],
+        items,
+    };
+    serde_json::to_string_pretty(&json).unwrap()
+}
+
+/// Namespace of the tags of Apple Podcasts.
+const ITUNES_NS: &str = "http://www.itunes.com/dtds/podcast-1.0.dtd";
+
+/// Namespace of the tags of Podcasting 2.0.
+const PODCAST_NS: &str = "https://podcastindex.org/namespace/1.0";
+
+/// The `podcast:guid` of the feed at `url`.
+///
+/// According to the Podcasting 2.0 namespace, this is a UUIDv5 of the URL
+/// without the scheme and trailing slashes, so that the podcast keeps its
+/// identity when it moves to another URL later.
+fn podcast_guid(url: &str) -> String {
+    use sha1::Digest;
+    let namespace = hex::decode("ead4c236bf5858c6a2c6a6b28d128cb6").unwrap();
+    let name = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let name = name.trim_end_matches('/');
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(&namespace);
+    hasher.update(name.as_bytes());
+    let mut bytes = hasher.finalize()[..16].to_vec();
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[test]
+fn test_podcast_guid() {
+    let expected = "9b024349-ccf0-5f69-a609-6b82873eab3c";
+    assert_eq!(podcast_guid("https://podnews.net/rss"), expected);
+    assert_eq!(podcast_guid("podnews.net/rss/"), expected);
+}
+
+/// The `itunes:category` element for a category such as `Technology` or
+/// `Society & Culture > Documentary`.
+fn itunes_category(category: &str) -> String {
+    let mut names = category
+        .split('>')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(escape_xml);
+    match (names.next(), names.next()) {
+        (Some(category), Some(subcategory)) => format!(
+            "<itunes:category text=\"{category}\">\
+            <itunes:category text=\"{subcategory}\"/>\
+            </itunes:category>\n"
+        ),
+        (Some(category), None) => format!("<itunes:category text=\"{category}\"/>\n"),
+        _ => String::new(),
+    }
+}
+
+#[test]
+fn test_itunes_category() {
+    assert_eq!(itunes_category(""), "");
+    assert_eq!(
+        itunes_category("Technology"),
+        "<itunes:category text=\"Technology\"/>\n"
+    );
+    assert_eq!(
+        itunes_category("Society & Culture > Documentary"),
+        "<itunes:category text=\"Society &amp; Culture\">\
+        <itunes:category text=\"Documentary\"/></itunes:category>\n"
+    );
+}
+
+/// The podcast with the posts that contain audio.
+///
+/// Podcast apps expect all episodes in one document, so this feed is not
+/// paged.
+fn podcast(feed: &Feed, settings: &Settings) -> String {
+    let base = &feed.base;
+    let url = format!("{base}/podcast.xml");
+    let mut body = String::new();
+    body.push_str(xml_header());
+    body.push_str(&format!(
+        "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+        xmlns:itunes=\"{ITUNES_NS}\" xmlns:podcast=\"{PODCAST_NS}\">\n"
+    ));
+    body.push_str("<channel>\n");
+    body.push_str(&format!("<title>{}</title>\n", escape_xml(&feed.title)));
+    body.push_str(&format!("<link>{base}</link>\n"));
+    let description = if settings.site_description.trim().is_empty() {
+        &feed.description
+    } else {
+        &settings.site_description
+    };
+    body.push_str(&format!(
+        "<description>{}</description>\n",
+        escape_xml(description)
+    ));
+    body.push_str(&format!(
+        "<language>{}</language>\n",
+        escape_xml(&feed.language)
+    ));
+    body.push_str(&format!(
+        "<atom:link rel=\"self\" href=\"{url}\" type=\"application/rss+xml\"/>\n"
+    ));
+    body.push_str(&format!(
+        "<itunes:author>{}</itunes:author>\n",
+        escape_xml(&feed.author)
+    ));
+    let artwork = settings.podcast_artwork.trim();
+    if !artwork.is_empty() {
+        let artwork = if artwork.starts_with('/') {
+            format!("{base}{artwork}")
+        } else {
+            artwork.to_string()
+        };
+        body.push_str(&format!(
+            "<itunes:image href=\"{}\"/>\n",
+            escape_xml(&artwork)
+        ));
+    }
+    body.push_str(&itunes_category(&settings.podcast_category));
+    let explicit = settings.podcast_explicit.is_some();
+    body.push_str(&format!("<itunes:explicit>{explicit}</itunes:explicit>\n"));
+    body.push_str("<itunes:type>episodic</itunes:type>\n");
+    body.push_str(&format!(
+        "<podcast:guid>{}</podcast:guid>\n",
+        podcast_guid(&url)
+    ));
+    body.push_str("<podcast:medium>podcast</podcast:medium>\n");
+    for entry in &feed.entries {
+        let extra = "<itunes:episodeType>full</itunes:episodeType>\n";
+        body.push_str(&rss_item(entry, extra));
+    }
     body.push_str("</channel>\n");
     body.push_str("</rss>\n");
-    crate::html::minify(&body)
+    body
 }
 
-async fn get_rss(State(ctx): State<ServerContext>) -> Response<Body> {
-    let posts = Post::list(&ctx.conn()).unwrap();
-    let body = rss(&ctx, &posts).await;
+fn feed_response(ctx: &ServerContext, mime: &str, body: String) -> Response<Body> {
     let mut headers = HeaderMap::new();
+    content_type(&mut headers, mime);
+    response(StatusCode::OK, headers, body, ctx)
+}
+
+#[derive(Deserialize)]
+struct FeedQuery {
+    page: Option<usize>,
+}
+
+/// Respond with the page of the feed in the format of `render`.
+async fn serve_feed(
+    ctx: ServerContext,
+    query: FeedQuery,
+    mime: &str,
+    render: fn(&Feed) -> String,
+) -> Response<Body> {
+    let posts = Post::list(&ctx.conn()).unwrap();
+    let Some(feed) = Feed::new(&ctx, &posts, query.page) else {
+        return crate::serve::not_found(State(ctx)).await;
+    };
+    feed_response(&ctx, mime, render(&feed))
+}
+
+async fn get_rss(
+    State(ctx): State<ServerContext>,
+    Query(query): Query<FeedQuery>,
+) -> Response<Body> {
     // Forces download in Firefox unfortunately:
     // https://www.petefreitag.com/blog/content-type-xml-feeds/
-    content_type(&mut headers, "application/rss+xml; charset=utf-8");
-    response(StatusCode::OK, headers, body, &ctx)
+    let mime = "application/rss+xml; charset=utf-8";
+    serve_feed(ctx, query, mime, rss).await
+}
+
+async fn get_atom(
+    State(ctx): State<ServerContext>,
+    Query(query): Query<FeedQuery>,
+) -> Response<Body> {
+    let mime = "application/atom+xml; charset=utf-8";
+    serve_feed(ctx, query, mime, atom).await
+}
+
+async fn get_json_feed(
+    State(ctx): State<ServerContext>,
+    Query(query): Query<FeedQuery>,
+) -> Response<Body> {
+    let mime = "application/feed+json; charset=utf-8";
+    serve_feed(ctx, query, mime, json_feed).await
+}
+
+async fn get_podcast(State(ctx): State<ServerContext>) -> Response<Body> {
+    let conn = ctx.conn();
+    let posts = Post::list(&conn).unwrap();
+    let episodes = posts
+        .into_iter()
+        .filter(|post| crate::audio::first(&post.content, &ctx.file_index).is_some())
+        .collect::<Vec<_>>();
+    let settings = Settings::from_db(&conn).unwrap();
+    let feed = Feed::with_posts(&ctx, &settings, &episodes, None, 0);
+    let mime = "application/rss+xml; charset=utf-8";
+    feed_response(&ctx, mime, podcast(&feed, &settings))
 }
 
 async fn get_robots(State(ctx): State<ServerContext>) -> Response<Body> {
@@ -108,7 +672,7 @@ async fn get_robots(State(ctx): State<ServerContext>) -> Response<Body> {
     response(StatusCode::OK, headers, body, &ctx)
 }
 
-fn w3_datetime(dt: &chrono::DateTime<chrono::Utc>) -> String {
+fn w3_datetime(dt: &DateTime<Utc>) -> String {
     dt.format("%Y-%m-%dT%H:%M:%SZ").to_string()
 }
 
@@ -163,6 +727,9 @@ pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
     router
         .clone()
         .route("/feed.xml", get(get_rss))
+        .route("/atom.xml", get(get_atom))
+        .route("/feed.json", get(get_json_feed))
+        .route("/podcast.xml", get(get_podcast))
         .route("/robots.txt", get(get_robots))
         .route("/sitemap.xml", get(get_sitemap))
 }
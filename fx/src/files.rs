@@ -1,19 +1,33 @@
 //! File upload and download at `/files`.
+use crate::ServeArgs;
+use crate::audio::Audio;
+use crate::csrf::Csrf;
+use crate::csrf::CsrfForm;
+use crate::csrf::SameOrigin;
+use crate::data::DbPool;
 use crate::data::Kv;
 use crate::html::PageSettings;
 use crate::html::Top;
 use crate::html::page;
+use crate::images::Image;
+use crate::images::Variant;
+use crate::range;
+use crate::range::ByteRange;
+use crate::range::Ranges;
 use crate::serve::ServerContext;
 use crate::serve::is_logged_in;
 use crate::serve::not_found;
 use crate::serve::response;
+use crate::upload::TempUpload;
+use crate::upload::UploadError;
 use axum::Router;
 use axum::body::Body;
-use axum::extract::Form;
 use axum::extract::Multipart;
 use axum::extract::Path;
 use axum::extract::State;
+use axum::extract::multipart::MultipartError;
 use axum::http::HeaderMap;
+use axum::http::HeaderValue;
 use axum::http::Response;
 use axum::http::StatusCode;
 use axum::routing::get;
@@ -26,6 +40,10 @@ use rusqlite::params;
 use serde::Deserialize;
 use serde::Serialize;
 use sha2::Digest;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::RwLock;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct File {
@@ -39,25 +57,153 @@ pub struct File {
     /// Filename is shown for easier identification.
     pub filename: String,
     pub data: Bytes,
+    /// Width in pixels for images.
+    pub width: Option<u32>,
+    /// Height in pixels for images.
+    pub height: Option<u32>,
+    /// Alternative text for images, used when a post does not set its own.
+    pub alt: String,
+    /// Caption that is shown below images that are alone in a paragraph.
+    pub caption: String,
+    pub title: String,
+}
+
+/// The sha of a file from the SHA-256 digest of its data.
+pub fn short_sha(digest: &[u8]) -> String {
+    // Turning the 256 bit hash into a 64 bit hash. The probability of a
+    // collision is roughly 1 in 2^(n/2) which means 1 in 2^32=4 billion to get
+    // a collision. Collisions are not a security risk here, because the site
+    // owner is the only one who can upload files. If my math is right, it
+    // would take 1 million sites with 1000 files each before a collision is
+    // likely to occur. This risk is worth it since a shorter hash is much
+    // easier to work with.
+    hex::encode(&digest[..8])
 }
 
 impl File {
-    fn new(mime_type: &str, filename: &str, data: Bytes) -> Self {
-        let sha = sha2::Sha256::digest(&data);
-        // Turning the 256 bit hash into a 64 bit hash. The probability of a
-        // collision is roughly 1 in 2^(n/2) which means 1 in 2^32=4 billion to
-        // get a collision. Collisions are not a security risk here, because the
-        // site owner is the only one who can upload files. If my math is right,
-        // it would take 1 million sites with 1000 files each before a collision
-        // is likely to occur. This risk is worth it since a shorter hash is
-        // much easier to work with.
-        let sha = sha[..8].to_vec();
-        let sha = hex::encode(sha);
+    pub fn new(mime_type: &str, filename: &str, data: Bytes) -> Self {
+        let sha = short_sha(&sha2::Sha256::digest(&data));
         Self {
             sha,
             mime_type: mime_type.to_string(),
             filename: filename.to_string(),
             data,
+            width: None,
+            height: None,
+            alt: String::new(),
+            caption: String::new(),
+            title: String::new(),
+        }
+    }
+}
+
+/// What rendering posts needs to know about the uploaded files.
+///
+/// Rendering Markdown does not have access to the database, so the dimensions,
+/// variants, and descriptions of images, and the names and sizes of audio
+/// files are kept in memory. This is small since it contains no file data.
+#[derive(Clone, Default)]
+pub struct FileIndex {
+    files: Arc<RwLock<IndexedFiles>>,
+}
+
+#[derive(Default)]
+struct IndexedFiles {
+    images: HashMap<String, Image>,
+    audio: HashMap<String, Audio>,
+}
+
+impl FileIndex {
+    /// Read the index from the database.
+    pub fn load(conn: &Connection) -> rusqlite::Result<Self> {
+        let files = IndexedFiles {
+            images: crate::images::read(conn, None)?,
+            audio: crate::audio::read(conn, None)?,
+        };
+        Ok(Self {
+            files: Arc::new(RwLock::new(files)),
+        })
+    }
+    /// Update the index after the file with `sha` was stored or changed.
+    pub fn refresh(&self, conn: &Connection, sha: &str) -> rusqlite::Result<()> {
+        let images = crate::images::read(conn, Some(sha))?;
+        let audio = crate::audio::read(conn, Some(sha))?;
+        let mut files = self.files.write().unwrap();
+        files.images.remove(sha);
+        files.images.extend(images);
+        files.audio.remove(sha);
+        files.audio.extend(audio);
+        Ok(())
+    }
+    pub fn forget(&self, sha: &str) {
+        let mut files = self.files.write().unwrap();
+        files.images.remove(sha);
+        files.audio.remove(sha);
+    }
+    pub fn image(&self, sha: &str) -> Option<Image> {
+        self.files.read().unwrap().images.get(sha).cloned()
+    }
+    pub fn audio(&self, sha: &str) -> Option<Audio> {
+        self.files.read().unwrap().audio.get(sha).cloned()
+    }
+    #[cfg(test)]
+    pub fn insert_image(&self, sha: &str, image: Image) {
+        let mut files = self.files.write().unwrap();
+        files.images.insert(sha.to_string(), image);
+    }
+    #[cfg(test)]
+    pub fn insert_audio(&self, sha: &str, audio: Audio) {
+        let mut files = self.files.write().unwrap();
+        files.audio.insert(sha.to_string(), audio);
+    }
+}
+
+/// A file without the data, which is read in chunks when serving the file.
+pub struct FileInfo {
+    rowid: i64,
+    pub sha: String,
+    pub mime_type: String,
+    pub filename: String,
+    /// Size in bytes.
+    pub size: u64,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub alt: String,
+    pub caption: String,
+    pub title: String,
+}
+
+impl FileInfo {
+    /// Columns that `from_row` reads, where `size` is `length(data)`.
+    const COLUMNS: &str = "
+        rowid, sha, mime_type, filename, length(data) AS size,
+        width, height, alt, caption, title
+        ";
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(FileInfo {
+            rowid: row.get("rowid")?,
+            sha: row.get("sha")?,
+            mime_type: row.get("mime_type")?,
+            filename: row.get("filename")?,
+            size: row.get::<_, i64>("size")? as u64,
+            width: row.get("width")?,
+            height: row.get("height")?,
+            alt: row.get("alt")?,
+            caption: row.get("caption")?,
+            title: row.get("title")?,
+        })
+    }
+    // If the filename contains a forward slash, return the part after the
+    // forward slash; otherwise, return the filename. When the prefix would be
+    // kept, the forward slash would turn the url into
+    // /files/sha/prefix/filename, which is not an endpoint that exists since
+    // only /files/sha and /files/sha/filename are valid.
+    pub fn filename_without_prefix(&self) -> String {
+        let filename = &self.filename;
+        if let Some(index) = filename.find('/') {
+            filename[index + 1..].to_string()
+        } else {
+            filename.to_string()
         }
     }
 }
@@ -80,11 +226,25 @@ impl File {
                 data BLOB NOT NULL
             );
         ";
-        conn.execute(stmt, [])
+        let changed = conn.execute(stmt, [])?;
+        crate::data::add_column(conn, "files", "width", "INTEGER")?;
+        crate::data::add_column(conn, "files", "height", "INTEGER")?;
+        for column in ["alt", "caption", "title"] {
+            crate::data::add_column(conn, "files", column, "TEXT NOT NULL DEFAULT ''")?;
+        }
+        Ok(changed)
+    }
+    /// All files without their data.
+    pub fn list(conn: &Connection) -> rusqlite::Result<Vec<FileInfo>> {
+        let stmt = format!("SELECT {} FROM files ORDER BY filename", FileInfo::COLUMNS);
+        let mut stmt = conn.prepare(&stmt)?;
+        let files = stmt.query_map([], FileInfo::from_row)?;
+        files.collect()
     }
-    pub fn list(conn: &Connection) -> rusqlite::Result<Vec<Self>> {
+    /// All files including their data, such as for a backup.
+    pub fn list_with_data(conn: &Connection) -> rusqlite::Result<Vec<Self>> {
         let stmt = "
-            SELECT sha, mime_type, filename, data
+            SELECT sha, mime_type, filename, data, width, height, alt, caption, title
             FROM files
             ORDER BY filename;
             ";
@@ -95,28 +255,92 @@ impl File {
                 mime_type: row.get("mime_type")?,
                 filename: row.get("filename")?,
                 data: blob_to_bytes(row.get("data")?),
+                width: row.get("width")?,
+                height: row.get("height")?,
+                alt: row.get("alt")?,
+                caption: row.get("caption")?,
+                title: row.get("title")?,
             })
         })?;
         files.collect::<Result<Vec<_>, _>>()
     }
     pub fn insert(conn: &Connection, file: &Self) -> rusqlite::Result<usize> {
-        // We can safely use `INSERT OR REPLACE` because the sha is the primary
+        // We can safely replace existing files because the sha is the primary
         // key. In the "worse" case, it will only rename the filename. If the
         // mime type is different, then it means that the extension was changed
         // locally which probably is good to reflect in the database. Also
         // having the need for two identical files with different mimetypes is a
-        // very unlikely scenario.
+        // very unlikely scenario. The description is kept since uploading the
+        // same file again should not remove the alt text.
         let sql = "
-            INSERT OR REPLACE INTO files (sha, mime_type, filename, data)
-            VALUES (?, ?, ?, ?);
+            INSERT INTO files (sha, mime_type, filename, data, width, height, alt, caption, title)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (sha) DO UPDATE SET
+                mime_type = excluded.mime_type,
+                filename = excluded.filename,
+                data = excluded.data,
+                width = excluded.width,
+                height = excluded.height;
             ";
         let data = bytes_to_blob(&file.data);
-        let params = params![file.sha, file.mime_type, file.filename, data];
+        let params = params![
+            file.sha,
+            file.mime_type,
+            file.filename,
+            data,
+            file.width,
+            file.height,
+            file.alt,
+            file.caption,
+            file.title
+        ];
         conn.execute(sql, params)
     }
+    /// Insert a file whose data is read from `reader`.
+    ///
+    /// The data is copied into a blob of `size` bytes in chunks, so large
+    /// uploads do not have to fit in memory. Like [File::insert], this keeps
+    /// the description of an existing file.
+    pub fn insert_reader(
+        conn: &Connection,
+        sha: &str,
+        mime_type: &str,
+        filename: &str,
+        size: u64,
+        reader: &mut impl std::io::Read,
+    ) -> std::io::Result<()> {
+        let sql = "
+            INSERT INTO files (sha, mime_type, filename, data)
+            VALUES (?, ?, ?, zeroblob(?))
+            ON CONFLICT (sha) DO UPDATE SET
+                mime_type = excluded.mime_type,
+                filename = excluded.filename,
+                data = excluded.data;
+            ";
+        let tx = conn
+            .unchecked_transaction()
+            .map_err(std::io::Error::other)?;
+        tx.execute(sql, params![sha, mime_type, filename, size as i64])
+            .map_err(std::io::Error::other)?;
+        let rowid: i64 = tx
+            .query_row("SELECT rowid FROM files WHERE sha = ?", [sha], |row| {
+                row.get(0)
+            })
+            .map_err(std::io::Error::other)?;
+        let mut blob = tx
+            .blob_open(rusqlite::MAIN_DB, "files", "data", rowid, false)
+            .map_err(std::io::Error::other)?;
+        let copied = std::io::copy(reader, &mut blob)?;
+        drop(blob);
+        if copied != size {
+            let msg = format!("expected {size} bytes but read {copied}");
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, msg));
+        }
+        tx.commit().map_err(std::io::Error::other)
+    }
     pub fn get(conn: &Connection, name: &str) -> rusqlite::Result<Self> {
         let stmt = "
-            SELECT sha, mime_type, filename, data
+            SELECT sha, mime_type, filename, data, width, height, alt, caption, title
             FROM files
             WHERE sha = ?;
             ";
@@ -129,37 +353,99 @@ impl File {
                 mime_type: row.get("mime_type")?,
                 filename: row.get("filename")?,
                 data: blob_to_bytes(row.get("data")?),
+                width: row.get("width")?,
+                height: row.get("height")?,
+                alt: row.get("alt")?,
+                caption: row.get("caption")?,
+                title: row.get("title")?,
             })
         })
     }
-    pub fn delete(conn: &Connection, sha: &str) -> rusqlite::Result<usize> {
+    /// Information about the file without loading the data.
+    pub fn info(conn: &Connection, sha: &str) -> rusqlite::Result<FileInfo> {
+        let stmt = format!("SELECT {} FROM files WHERE sha = ?", FileInfo::COLUMNS);
+        let mut stmt = conn.prepare(&stmt)?;
+        stmt.query_row([sha], FileInfo::from_row)
+    }
+    pub fn delete(conn: &Connection, index: &FileIndex, sha: &str) -> rusqlite::Result<usize> {
+        Variant::delete(conn, sha)?;
+        index.forget(sha);
+        crate::assets::forget(sha);
         let sql = "DELETE FROM files WHERE sha = ?";
         conn.execute(sql, [sha])
     }
-    pub fn rename(conn: &Connection, sha: &str, filename: &str) -> rusqlite::Result<usize> {
+    pub fn rename(
+        conn: &Connection,
+        index: &FileIndex,
+        sha: &str,
+        filename: &str,
+    ) -> rusqlite::Result<usize> {
         let sql = "UPDATE files SET filename = ? WHERE sha = ?";
-        conn.execute(sql, [filename, sha])
+        let changed = conn.execute(sql, [filename, sha])?;
+        index.refresh(conn, sha)?;
+        // The file may no longer be part of the theme.
+        crate::assets::forget(sha);
+        Ok(changed)
     }
-    // If the filename contains a forward slash, return the part after the
-    // forward slash; otherwise, return the filename. When the prefix would be
-    // kept, the forward slash would turn the url into
-    // /files/sha/prefix/filename, which is not an endpoint that exists since
-    // only /files/sha and /files/sha/filename are valid.
-    pub fn filename_without_prefix(&self) -> String {
-        let filename = &self.filename;
-        if let Some(index) = filename.find('/') {
-            filename[index + 1..].to_string()
-        } else {
-            filename.to_string()
-        }
+    pub fn describe(
+        conn: &Connection,
+        index: &FileIndex,
+        sha: &str,
+        alt: &str,
+        caption: &str,
+        title: &str,
+    ) -> rusqlite::Result<usize> {
+        let sql = "UPDATE files SET alt = ?, caption = ?, title = ? WHERE sha = ?";
+        let changed = conn.execute(sql, [alt, caption, title, sha])?;
+        index.refresh(conn, sha)?;
+        Ok(changed)
+    }
+}
+
+/// Store an uploaded file.
+///
+/// Images are stored without metadata and with resized variants. Since this
+/// changes the data, the returned file can have a different sha than the
+/// upload. Images whose metadata cannot be removed are rejected.
+pub async fn store_upload(ctx: &ServerContext, file: File) -> Result<File, UploadError> {
+    let mime_type = file.mime_type.clone();
+    let data = file.data.clone();
+    let process = move || crate::images::process(&mime_type, data);
+    // The decoders see untrusted data, so a panic is treated like an image
+    // that cannot be read.
+    let processed = tokio::task::spawn_blocking(process)
+        .await
+        .map_err(|e| UploadError::Invalid(format!("Failed to process the image: {e}")))?;
+    let Some(processed) = processed.map_err(UploadError::Invalid)? else {
+        let conn = ctx.conn();
+        File::insert(&conn, &file)?;
+        ctx.file_index.refresh(&conn, &file.sha)?;
+        return Ok(file);
+    };
+    let mut stored = File::new(&file.mime_type, &file.filename, processed.data);
+    stored.width = processed.width;
+    stored.height = processed.height;
+    // In one transaction so that the file is never stored with only some of
+    // its variants.
+    let mut conn = ctx.conn();
+    let tx = conn.transaction()?;
+    File::insert(&tx, &stored)?;
+    Variant::delete(&tx, &stored.sha)?;
+    for variant in &processed.variants {
+        Variant::insert(&tx, &stored.sha, variant)?;
     }
+    tx.commit()?;
+    ctx.file_index.refresh(&conn, &stored.sha)?;
+    Ok(stored)
 }
 
-fn md_link(file: &File) -> String {
+fn md_link(file: &FileInfo) -> String {
     let sha = &file.sha;
-    let filename = &file.filename;
     if file.mime_type.starts_with("image/") {
-        format!("![{filename}](/files/{sha})")
+        // Links without alt text get the stored alt text when rendering, so
+        // the alt text can still be added later.
+        let alt = file.alt.replace('[', "\\[").replace(']', "\\]");
+        format!("![{alt}](/files/{sha})")
     } else {
         let filename = file.filename_without_prefix();
         let filename = crate::html::url_encode(&filename);
@@ -167,9 +453,56 @@ fn md_link(file: &File) -> String {
     }
 }
 
-fn show_file(file: &File) -> String {
+/// Titles of the posts by id.
+fn post_titles(conn: &Connection) -> HashMap<i64, String> {
+    let posts = crate::data::Post::list(conn).unwrap_or_default();
+    posts
+        .iter()
+        .map(|post| (post.id, crate::md::extract_html_title(post)))
+        .collect()
+}
+
+/// Links to the posts that use a file.
+fn post_links(ids: &[i64], titles: &HashMap<i64, String>) -> String {
+    ids.iter()
+        .map(|id| {
+            let title = titles.get(id).cloned().unwrap_or_default();
+            let title = if title.is_empty() {
+                format!("post {id}")
+            } else {
+                crate::html::escape_html(&title)
+            };
+            format!("<a href='/posts/{id}'>{title}</a>")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn show_file(file: &FileInfo, used_in: &[i64], titles: &HashMap<i64, String>) -> String {
     let sha = &file.sha;
+    let usage = if used_in.is_empty() {
+        "Not used in any post".to_string()
+    } else {
+        format!("Used in: {}", post_links(used_in, titles))
+    };
     let link = md_link(file);
+    let describe = if file.mime_type.starts_with("image/") {
+        let alt = if file.alt.is_empty() {
+            "no alt text".to_string()
+        } else {
+            format!("alt text: {}", crate::html::escape_html(&file.alt))
+        };
+        format!(
+            "&nbsp;
+            <a class='unstyled-link' href='/files/describe/{sha}' \
+              style='font-size: 0.8rem; padding-top: 0.1rem;'>
+                📝 Describe
+            </a>
+            <span style='font-size: var(--ui-font-size);'>({alt})</span>"
+        )
+    } else {
+        String::new()
+    };
     let filename = file.filename_without_prefix();
     let filename = crate::html::url_encode(&filename);
     format!(
@@ -184,11 +517,11 @@ fn show_file(file: &File) -> String {
             <a class='unstyled-link' href='/files/delete/{sha}' \
               style='font-size: 0.8rem; padding-top: 0.1rem;'>
                 🗑️ Delete
-            </a><br>
+            </a>{describe}<br>
+            <span style='font-size: var(--ui-font-size);'>{usage}</span><br>
             <span style='font-size: var(--ui-font-size);'>
                 Markdown link
-                (<a id='copy-{sha}' href='javascript:void(0)' \
-                  onclick='copyCode(\"{sha}\")'>copy</a>):
+                (<a id='copy-{sha}' href='#' data-copy-code='{sha}'>copy</a>):
             </span><br>
             <pre style='margin-top: 6px; margin-bottom: 0px;'>
                 <code id='code-{sha}' class='language-md'>{link}</code>
@@ -204,21 +537,32 @@ async fn get_files(State(ctx): State<ServerContext>, jar: CookieJar) -> Response
     if !is_logged_in {
         return crate::serve::unauthorized(&ctx).await;
     }
-    let files = File::list(&ctx.conn()).unwrap();
+    let conn = ctx.conn();
+    let files = File::list(&conn).unwrap();
+    let usage = crate::usage::all(&conn).unwrap_or_default();
+    let titles = post_titles(&conn);
     let files = files
         .iter()
-        .map(show_file)
+        .map(|file| {
+            let used_in = usage.get(&file.sha).map(Vec::as_slice).unwrap_or_default();
+            show_file(file, used_in, &titles)
+        })
         .collect::<Vec<String>>()
         .join("");
+    let csrf = crate::csrf::input(&ctx, &jar);
+    let max_size = ctx.args.max_upload_size;
+    let nonce = crate::csp::nonce();
+    let script = crate::assets::url("upload.js");
     let body = format!(
         "
         <div style='border-bottom: 2px solid var(--border);'>
-            <form method='post' action='/files/add' \
+            <form method='post' action='/files/add' id='upload-form' \
               class='margin-auto' \
               enctype='multipart/form-data' \
               style='margin-top: 5vh; width: 80%;'>
+                {csrf}
                 <div>
-                    <label for='file'>Choose file(s) to upload (max 15 MB)</label><br>
+                    <label for='file'>Choose file(s) to upload (max {max_size} MB)</label><br>
                     <input type='file' id='file' name='file' multiple />
                 </div>
                 <br>
@@ -237,9 +581,11 @@ async fn get_files(State(ctx): State<ServerContext>, jar: CookieJar) -> Response
                 <div>
                     <input style='margin-left: 0;' type='submit' value='Upload'/>
                 </div>
+                <div id='upload-status'></div>
                 <br>
                 <br>
             </form>
+            <script nonce='{nonce}' src='{script}' defer></script>
         </div>
         <div style='font-size: 0.8rem; padding: 6px; padding-bottom: 10px;'>
             Each file will get an unique SHA identifier, such as <code>69b83ddf8f65695f</code>.
@@ -247,6 +593,8 @@ async fn get_files(State(ctx): State<ServerContext>, jar: CookieJar) -> Response
             <code>/files/69b83ddf8f65695f</code>,
             <code>/files/69b83ddf8f65695f/example.txt</code>, or
             <code>/files/69b83ddf8f65695f/something-else.txt</code>.
+            Files that are not used in any post are listed at
+            <a href='/files/orphaned'>orphaned files</a>.
         </div>
         <div>
             {files}
@@ -260,7 +608,106 @@ async fn get_files(State(ctx): State<ServerContext>, jar: CookieJar) -> Response
     response(StatusCode::OK, HeaderMap::new(), body, &ctx)
 }
 
-async fn get_file(State(ctx): State<ServerContext>, Path(sha): Path<String>) -> Response<Body> {
+/// Value for the `Content-Disposition` header with the stored filename.
+///
+/// The `filename*` parameter keeps non-ASCII characters while `filename` is a
+/// fallback for old clients.
+fn content_disposition(filename: &str) -> String {
+    let name = filename.rsplit('/').next().unwrap_or(filename);
+    let fallback = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let encoded = percent_encoding::utf8_percent_encode(name, percent_encoding::NON_ALPHANUMERIC);
+    format!("inline; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+#[test]
+fn test_content_disposition() {
+    assert_eq!(
+        content_disposition("example/a b.txt"),
+        "inline; filename=\"a b.txt\"; filename*=UTF-8''a%20b%2Etxt"
+    );
+    assert_eq!(
+        content_disposition("é\".txt"),
+        "inline; filename=\"__.txt\"; filename*=UTF-8''%C3%A9%22%2Etxt"
+    );
+}
+
+/// Size of the chunks in which files are read from the database.
+const CHUNK_SIZE: u64 = 256 * 1024;
+
+fn read_blob(
+    pool: &DbPool,
+    table: &str,
+    rowid: i64,
+    start: u64,
+    len: u64,
+) -> Result<Vec<u8>, String> {
+    let conn = pool.get().map_err(|e| e.to_string())?;
+    let blob = conn
+        .blob_open(rusqlite::MAIN_DB, table, "data", rowid, true)
+        .map_err(|e| e.to_string())?;
+    let mut buf = vec![0; len as usize];
+    blob.read_at_exact(&mut buf, start as usize)
+        .map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+/// Part of a streamed response body.
+enum Part {
+    Bytes(Bytes),
+    Blob(ByteRange),
+}
+
+/// Stream the parts without loading the whole file into memory.
+///
+/// Each chunk is read with a short-lived connection, so that slow clients do
+/// not hold on to a connection from the pool.
+fn stream(pool: DbPool, table: &'static str, rowid: i64, parts: Vec<Part>) -> Body {
+    let parts = VecDeque::from(parts);
+    let stream = futures_util::stream::unfold(parts, move |mut parts| {
+        let pool = pool.clone();
+        async move {
+            let chunk = match parts.pop_front()? {
+                Part::Bytes(bytes) => Ok(bytes),
+                Part::Blob(range) => {
+                    let len = range.len().min(CHUNK_SIZE);
+                    if len < range.len() {
+                        let rest = ByteRange {
+                            start: range.start + len,
+                            end: range.end,
+                        };
+                        parts.push_front(Part::Blob(rest));
+                    }
+                    let read = move || read_blob(&pool, table, rowid, range.start, len);
+                    match tokio::task::spawn_blocking(read).await.unwrap() {
+                        Ok(data) => Ok(Bytes::from(data)),
+                        Err(e) => {
+                            tracing::error!("failed to read file: {e}");
+                            parts.clear();
+                            Err(std::io::Error::other(e))
+                        }
+                    }
+                }
+            };
+            Some((chunk, parts))
+        }
+    });
+    Body::from_stream(stream)
+}
+
+async fn get_file(
+    State(ctx): State<ServerContext>,
+    Path(sha): Path<String>,
+    request_headers: HeaderMap,
+) -> Response<Body> {
     // Anything after the sha is allowed. This allows anyone to decide the
     // filename. For security purposes, this should be okay since the only
     // person that can upload files is the site owner. The benefit of this is
@@ -271,8 +718,8 @@ async fn get_file(State(ctx): State<ServerContext>, Path(sha): Path<String>) ->
     } else {
         sha.clone()
     };
-    let file = match File::get(&ctx.conn(), &name) {
-        Ok(file) => file,
+    let info = match File::info(&ctx.conn(), &name) {
+        Ok(info) => info,
         Err(_) => {
             return {
                 let body = "not found";
@@ -280,65 +727,287 @@ async fn get_file(State(ctx): State<ServerContext>, Path(sha): Path<String>) ->
             };
         }
     };
+    // The sha changes with the content, so there is no need to hash the data.
+    let etag = info.sha.clone();
+    let response = serve_blob(&ctx, &request_headers, "files", &info, &etag).await;
+    let status_code = response.status().as_u16();
+    tracing::info!("\"GET /files/{sha} HTTP/1.1\" {status_code}");
+    response
+}
+
+/// Serve a blob from `table` with support for range requests.
+async fn serve_blob(
+    ctx: &ServerContext,
+    request_headers: &HeaderMap,
+    table: &'static str,
+    info: &FileInfo,
+    etag: &str,
+) -> Response<Body> {
+    let size = info.size;
     let mut headers = HeaderMap::new();
-    crate::serve::content_type(&mut headers, &file.mime_type);
+    crate::serve::content_type(&mut headers, &info.mime_type);
     // Setting this too high might make deleted files accessible for too long
     // which could be confusing for the user.
     let max_age = 300;
     crate::serve::enable_caching(&mut headers, max_age);
-    tracing::info!("\"GET /files/{sha} HTTP/1.1\" 200");
-    response(StatusCode::OK, headers, file.data, &ctx)
+    crate::conditional::set_etag(&mut headers, etag);
+    headers.insert("Accept-Ranges", HeaderValue::from_static("bytes"));
+    let disposition = content_disposition(&info.filename);
+    headers.insert(
+        "Content-Disposition",
+        HeaderValue::from_str(&disposition).unwrap(),
+    );
+    let pool = ctx.pool.clone();
+    let rowid = info.rowid;
+    let magic = move || read_blob(&pool, table, rowid, 0, size.min(8));
+    let magic = tokio::task::spawn_blocking(magic).await.unwrap();
+    if magic.is_ok_and(|magic| crate::compression::is_compressed(&magic)) {
+        // Compressing again only costs time.
+        let cache_control = headers.get("Cache-Control").unwrap().to_str().unwrap();
+        let cache_control = format!("{cache_control}, no-transform");
+        let value = HeaderValue::from_str(&cache_control).unwrap();
+        headers.insert("Cache-Control", value);
+    }
+
+    let etag = format!("\"{etag}\"");
+    let header = |name: &str| {
+        request_headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let ranges = match (header("Range"), header("If-Range")) {
+        (Some(range), None) => range::parse(range, size),
+        (Some(range), Some(if_range)) if range::if_range_matches(if_range, &etag) => {
+            range::parse(range, size)
+        }
+        _ => Ranges::Full,
+    };
+    let (status, parts) = match ranges {
+        Ranges::Full => {
+            let parts = if size == 0 {
+                vec![]
+            } else {
+                vec![Part::Blob(ByteRange {
+                    start: 0,
+                    end: size - 1,
+                })]
+            };
+            (StatusCode::OK, parts)
+        }
+        Ranges::Unsatisfiable => {
+            let value = HeaderValue::from_str(&format!("bytes */{size}")).unwrap();
+            headers.insert("Content-Range", value);
+            (StatusCode::RANGE_NOT_SATISFIABLE, vec![])
+        }
+        Ranges::Satisfiable(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            let value = HeaderValue::from_str(&range.content_range(size)).unwrap();
+            headers.insert("Content-Range", value);
+            (StatusCode::PARTIAL_CONTENT, vec![Part::Blob(range)])
+        }
+        Ranges::Satisfiable(ranges) => {
+            let boundary = fx_auth::generate_token();
+            let mut parts = Vec::new();
+            for (i, range) in ranges.iter().enumerate() {
+                let separator = if i == 0 { "" } else { "\r\n" };
+                let head = format!(
+                    "{separator}--{boundary}\r\n\
+                    Content-Type: {}\r\n\
+                    Content-Range: {}\r\n\r\n",
+                    info.mime_type,
+                    range.content_range(size)
+                );
+                parts.push(Part::Bytes(Bytes::from(head)));
+                parts.push(Part::Blob(*range));
+            }
+            let tail = format!("\r\n--{boundary}--\r\n");
+            parts.push(Part::Bytes(Bytes::from(tail)));
+            let content_type = format!("multipart/byteranges; boundary={boundary}");
+            crate::serve::content_type(&mut headers, &content_type);
+            (StatusCode::PARTIAL_CONTENT, parts)
+        }
+    };
+    let length = parts
+        .iter()
+        .map(|part| match part {
+            Part::Bytes(bytes) => bytes.len() as u64,
+            Part::Blob(range) => range.len(),
+        })
+        .sum::<u64>();
+    headers.insert("Content-Length", HeaderValue::from(length));
+    let body = stream(ctx.pool.clone(), table, rowid, parts);
+    response(status, headers, body, ctx)
 }
 
 async fn get_file_with_filename(
     State(ctx): State<ServerContext>,
     Path((sha, _filename)): Path<(String, String)>,
+    headers: HeaderMap,
 ) -> Response<Body> {
-    get_file(State(ctx), Path(sha)).await
+    get_file(State(ctx), Path(sha), headers).await
+}
+
+/// Information about a variant of an image.
+///
+/// The filename is derived from the original, such as `cat-480.webp` for
+/// `cat.jpg`.
+fn variant_info(
+    conn: &Connection,
+    sha: &str,
+    width: u32,
+    mime_type: &str,
+) -> rusqlite::Result<FileInfo> {
+    let stmt = "
+        SELECT v.rowid AS rowid, f.filename AS filename, length(v.data) AS size,
+            v.height AS height, f.alt AS alt, f.caption AS caption, f.title AS title
+        FROM file_variants v
+        JOIN files f ON f.sha = v.sha
+        WHERE v.sha = ? AND v.width = ? AND v.mime_type = ?;
+        ";
+    let mut stmt = conn.prepare(stmt)?;
+    stmt.query_row(params![sha, width, mime_type], |row| {
+        let filename: String = row.get("filename")?;
+        let stem = match filename.rsplit_once('.') {
+            Some((stem, _)) => stem,
+            None => &filename,
+        };
+        let extension = crate::images::extension(mime_type).unwrap_or("bin");
+        Ok(FileInfo {
+            rowid: row.get("rowid")?,
+            sha: sha.to_string(),
+            mime_type: mime_type.to_string(),
+            filename: format!("{stem}-{width}.{extension}"),
+            size: row.get::<_, i64>("size")? as u64,
+            width: Some(width),
+            height: row.get("height")?,
+            alt: row.get("alt")?,
+            caption: row.get("caption")?,
+            title: row.get("title")?,
+        })
+    })
+}
+
+async fn get_variant(
+    State(ctx): State<ServerContext>,
+    Path((sha, name)): Path<(String, String)>,
+    request_headers: HeaderMap,
+) -> Response<Body> {
+    let mime_type = match name.rsplit_once('.') {
+        Some((_, "jpg")) => "image/jpeg",
+        Some((_, "png")) => "image/png",
+        Some((_, "webp")) => "image/webp",
+        _ => return not_found(State(ctx)).await,
+    };
+    let width = name.split('.').next().and_then(|w| w.parse::<u32>().ok());
+    let info = width.and_then(|width| variant_info(&ctx.conn(), &sha, width, mime_type).ok());
+    let Some(info) = info else {
+        return not_found(State(ctx)).await;
+    };
+    let etag = format!("{sha}-{name}");
+    let response = serve_blob(&ctx, &request_headers, "file_variants", &info, &etag).await;
+    let status_code = response.status().as_u16();
+    tracing::info!("\"GET /files/variants/{sha}/{name} HTTP/1.1\" {status_code}");
+    response
+}
+
+/// A file from the upload form that is not stored yet.
+struct Received {
+    upload: TempUpload,
+    mime_type: String,
+    filename: String,
+}
+
+/// The fields of the upload form.
+#[derive(Default)]
+struct UploadForm {
+    files: Vec<Received>,
+    prefix: String,
+    csrf_token: String,
+}
+
+fn multipart_error(ctx: &ServerContext, e: MultipartError) -> UploadError {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        UploadError::TooLarge(crate::upload::max_size(&ctx.args))
+    } else {
+        UploadError::Invalid(e.body_text())
+    }
+}
+
+/// Read the form while writing the files to temporary files.
+///
+/// The prefix comes after the files in the form, so the files can only be
+/// stored once the whole form has been read.
+async fn receive(
+    ctx: &ServerContext,
+    multipart: &mut Multipart,
+) -> Result<UploadForm, UploadError> {
+    let mut form = UploadForm::default();
+    let limit = crate::upload::max_size(&ctx.args);
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| multipart_error(ctx, e))?
+    {
+        match field.name().unwrap_or_default() {
+            "file" => {
+                let filename = field.file_name().unwrap_or_default().to_string();
+                if filename.is_empty() {
+                    // Occurs when clicking "Upload" without selecting any files.
+                    continue;
+                }
+                let sent = field.content_type().map(str::to_string);
+                let mut upload = TempUpload::new(limit).await?;
+                while let Some(chunk) = field.chunk().await.map_err(|e| multipart_error(ctx, e))? {
+                    upload.write(&chunk).await?;
+                }
+                let mime_type = crate::upload::content_type(sent.as_deref(), upload.head());
+                form.files.push(Received {
+                    upload,
+                    mime_type,
+                    filename,
+                });
+            }
+            "prefix" => {
+                form.prefix = field.text().await.map_err(|e| multipart_error(ctx, e))?;
+            }
+            crate::csrf::FIELD => {
+                form.csrf_token = field.text().await.unwrap_or_default();
+            }
+            name => tracing::warn!("unknown field: {name:?}"),
+        }
+    }
+    Ok(form)
+}
+
+async fn upload_failed(ctx: &ServerContext, e: UploadError) -> Response<Body> {
+    tracing::error!("upload failed: {e}");
+    let msg = crate::html::escape_html(&e.to_string());
+    crate::serve::error(ctx, e.status(), "Upload Failed", &msg).await
 }
 
 async fn post_file(
     State(ctx): State<ServerContext>,
     jar: CookieJar,
+    _: SameOrigin,
     mut multipart: Multipart,
 ) -> Response<Body> {
     let is_logged_in = is_logged_in(&ctx, &jar);
     if !is_logged_in {
         return crate::serve::unauthorized(&ctx).await;
     }
-    let mut received_files = Vec::new();
-    let mut prefix = String::new();
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name();
-        if name == Some("file") {
-            let filename = field.file_name().unwrap().to_string();
-            if filename.is_empty() {
-                // Occurs when clicking "Upload" without selecting any files.
-                continue;
-            }
-            let mime_type = field.content_type().unwrap().to_string();
-            let data = field
-                .bytes()
-                .await
-                .expect("Failed to read file; the file could be too large.");
-            let file = File::new(&mime_type, &filename, data);
-            received_files.push(file);
-        } else if name == Some("prefix") {
-            let bytes = field.bytes().await.unwrap();
-            prefix = String::from_utf8(bytes.to_vec()).unwrap();
-        } else {
-            tracing::warn!("unknown field: {:?}", name);
-        }
+    let form = match receive(&ctx, &mut multipart).await {
+        Ok(form) => form,
+        Err(e) => return upload_failed(&ctx, e).await,
+    };
+    if !crate::csrf::verify(&ctx, &jar, &form.csrf_token) {
+        return crate::csrf::rejected(&ctx).await;
     }
 
-    for file in received_files {
-        let filename = if !prefix.is_empty() {
-            format!("{}{}", prefix, file.filename)
-        } else {
-            file.filename
-        };
-        let file = File::new(&file.mime_type, &filename, file.data);
-        File::insert(&ctx.conn(), &file).unwrap();
+    for file in form.files {
+        let filename = format!("{}{}", form.prefix, file.filename);
+        if let Err(e) = file.upload.store(&ctx, &file.mime_type, &filename).await {
+            return upload_failed(&ctx, e).await;
+        }
     }
 
     crate::trigger::trigger_github_backup(&ctx).await;
@@ -369,10 +1038,20 @@ async fn get_delete(
         Top::GoHome,
         extra_head,
     );
+    let csrf = crate::csrf::input(&ctx, &jar);
+    let used_in = crate::usage::posts(&ctx.conn(), &sha).unwrap_or_default();
+    let usage = if used_in.is_empty() {
+        "<p>This file is not used in any post.</p>".to_string()
+    } else {
+        let links = post_links(&used_in, &post_titles(&ctx.conn()));
+        format!("<p>The links to this file in these posts will stop working: {links}</p>")
+    };
     let body = indoc::formatdoc! {r#"
         <div class='medium-text' style='text-align: center; font-weight: bold;'>
             <p>Are you sure you want to delete <code>{}</code>? This action cannot be undone.</p>
+            {usage}
             <form action='/files/delete/{sha}' method='post'>
+                {csrf}
                 <button type='submit'>Delete</button>
             </form>
             <br>
@@ -385,15 +1064,109 @@ async fn get_delete(
 async fn post_delete(
     State(ctx): State<ServerContext>,
     Path(sha): Path<String>,
-    jar: CookieJar,
+    _: Csrf,
 ) -> Response<Body> {
+    File::delete(&ctx.conn(), &ctx.file_index, &sha).unwrap();
+    tracing::info!("\"POST /files/delete/{sha} HTTP/1.1\" 303");
+    crate::trigger::trigger_github_backup(&ctx).await;
+    crate::serve::see_other(&ctx, "/files")
+}
+
+/// Files that are not used in any post.
+///
+/// Theme files and files that are linked from the about text, the extra HTML
+/// head, or the podcast artwork are used outside posts, so they are never
+/// orphaned.
+fn orphaned(conn: &Connection) -> rusqlite::Result<Vec<FileInfo>> {
+    let usage = crate::usage::all(conn)?;
+    let in_settings = crate::usage::used_in_settings(conn);
+    let files = File::list(conn)?
+        .into_iter()
+        .filter(|file| {
+            !usage.contains_key(&file.sha)
+                && !in_settings.contains(&file.sha)
+                && !file.filename.starts_with("theme/")
+        })
+        .collect();
+    Ok(files)
+}
+
+async fn get_orphaned(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
     let is_logged_in = is_logged_in(&ctx, &jar);
     if !is_logged_in {
         return crate::serve::unauthorized(&ctx).await;
     }
-    File::delete(&ctx.conn(), &sha).unwrap();
-    tracing::info!("\"POST /files/delete/{sha} HTTP/1.1\" 303");
-    crate::trigger::trigger_github_backup(&ctx).await;
+    let files = orphaned(&ctx.conn()).unwrap();
+    let csrf = crate::csrf::input(&ctx, &jar);
+    let body = if files.is_empty() {
+        "<p style='text-align: center;'>All files are used in posts.</p>".to_string()
+    } else {
+        let files = files
+            .iter()
+            .map(|file| {
+                let sha = &file.sha;
+                let filename = crate::html::url_encode(&file.filename_without_prefix());
+                format!(
+                    "
+                    <div style='padding: 6px; border-bottom: 1px solid var(--border);'>
+                        <input type='checkbox' id='sha-{sha}' name='sha' value='{sha}' checked />
+                        <label for='sha-{sha}'>{}</label>
+                        (<a href='/files/{sha}/{filename}'>view</a>)
+                    </div>
+                    ",
+                    crate::html::escape_html(&file.filename)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("");
+        indoc::formatdoc! {r#"
+            <p style='font-size: 0.8rem;'>
+                These files are not used in any post, the about text, or the extra HTML head.
+                Deleting them cannot be undone.
+            </p>
+            <form action='/files/orphaned' method='post'>
+                {csrf}
+                {files}
+                <div style='margin-top: 10px; text-align: center;'>
+                    <button type='submit'>Delete selected files</button>
+                </div>
+            </form>
+        "#}
+    };
+    let page_settings = PageSettings::new(
+        "Orphaned files",
+        Some(is_logged_in),
+        None,
+        false,
+        Top::GoHome,
+        "",
+    );
+    let body = page(&ctx, &page_settings, &body).await;
+    tracing::info!("\"GET /files/orphaned HTTP/1.1\" 200");
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+async fn post_orphaned(
+    State(ctx): State<ServerContext>,
+    CsrfForm(form): CsrfForm<Vec<(String, String)>>,
+) -> Response<Body> {
+    let selected = form
+        .into_iter()
+        .filter(|(key, _)| key == "sha")
+        .map(|(_, sha)| sha)
+        .collect::<Vec<_>>();
+    let conn = ctx.conn();
+    // Checking again since a post might link to a file by now.
+    let orphaned = orphaned(&conn).unwrap();
+    let mut deleted = 0;
+    for file in orphaned.iter().filter(|file| selected.contains(&file.sha)) {
+        File::delete(&conn, &ctx.file_index, &file.sha).unwrap();
+        deleted += 1;
+    }
+    tracing::info!("\"POST /files/orphaned HTTP/1.1\" 303 ({deleted} deleted)");
+    if 0 < deleted {
+        crate::trigger::trigger_github_backup(&ctx).await;
+    }
     crate::serve::see_other(&ctx, "/files")
 }
 
@@ -421,10 +1194,12 @@ async fn get_rename(
         Top::GoHome,
         extra_head,
     );
+    let csrf = crate::csrf::input(&ctx, &jar);
     let body = indoc::formatdoc! {r#"
         <div class='medium-text' style='text-align: center;'>
             <p>Rename file: <code>{}</code></p>
             <form action='/files/rename/{sha}' method='post'>
+                {csrf}
                 <div>
                     <label for='filename'>New filename:</label>
                     <input type='text' id='filename' name='filename' value='{}' />
@@ -447,30 +1222,126 @@ struct RenameForm {
 }
 
 async fn post_rename(
+    State(ctx): State<ServerContext>,
+    Path(sha): Path<String>,
+    CsrfForm(rename_form): CsrfForm<RenameForm>,
+) -> Response<Body> {
+    let filename = rename_form.filename;
+    File::rename(&ctx.conn(), &ctx.file_index, &sha, &filename).unwrap();
+    crate::trigger::trigger_github_backup(&ctx).await;
+    crate::serve::see_other(&ctx, "/files")
+}
+
+async fn get_describe(
     State(ctx): State<ServerContext>,
     Path(sha): Path<String>,
     jar: CookieJar,
-    Form(rename_form): Form<RenameForm>,
 ) -> Response<Body> {
     let is_logged_in = is_logged_in(&ctx, &jar);
     if !is_logged_in {
         return crate::serve::unauthorized(&ctx).await;
     }
-    let filename = rename_form.filename;
-    File::rename(&ctx.conn(), &sha, &filename).unwrap();
+    let file = match File::info(&ctx.conn(), &sha) {
+        Ok(file) => file,
+        Err(_) => return not_found(State(ctx.clone())).await,
+    };
+    let extra_head = &Kv::get_or_empty_string(&ctx.conn(), "extra_head");
+    let title = format!("Describe: {}", file.filename);
+    let settings = PageSettings::new(
+        &title,
+        Some(is_logged_in),
+        None,
+        false,
+        Top::GoHome,
+        extra_head,
+    );
+    let csrf = crate::csrf::input(&ctx, &jar);
+    let value = |text: &str| crate::html::escape_single_quote(&crate::html::escape_html(text));
+    let body = indoc::formatdoc! {r#"
+        <div class='medium-text'>
+            <p style='text-align: center;'>Describe file: <code>{}</code></p>
+            <img src='/files/{sha}' alt='' style='max-height: 200px;' />
+            <form action='/files/describe/{sha}' method='post'>
+                {csrf}
+                <div>
+                    <label for='alt'>Alt text:</label><br>
+                    <input type='text' id='alt' name='alt' value='{}' style='width: 100%;' />
+                    <br>
+                    <span style='font-size: 0.8rem;'>
+                        Describes the image for people who cannot see it.
+                        Used when the image in a post has no alt text of its own.
+                    </span>
+                </div>
+                <div style='margin-top: 10px;'>
+                    <label for='title'>Title:</label><br>
+                    <input type='text' id='title' name='title' value='{}' style='width: 100%;' />
+                </div>
+                <div style='margin-top: 10px;'>
+                    <label for='caption'>Caption:</label><br>
+                    <textarea id='caption' name='caption' rows='3' style='width: 100%;'>{}</textarea>
+                    <br>
+                    <span style='font-size: 0.8rem;'>
+                        Shown below the image when it is on a line of its own.
+                    </span>
+                </div>
+                <div style='margin-top: 10px; text-align: center;'>
+                    <button type='submit'>Save</button>
+                </div>
+            </form>
+            <br>
+        </div>
+    "#, crate::html::escape_html(&file.filename), value(&file.alt), value(&file.title),
+    crate::html::escape_html(&file.caption)};
+    let body = page(&ctx, &settings, &body).await;
+    tracing::info!("\"GET /files/describe/{sha} HTTP/1.1\" 200");
+    response::<String>(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+#[derive(Debug, Deserialize)]
+struct DescribeForm {
+    alt: String,
+    caption: String,
+    title: String,
+}
+
+async fn post_describe(
+    State(ctx): State<ServerContext>,
+    Path(sha): Path<String>,
+    CsrfForm(form): CsrfForm<DescribeForm>,
+) -> Response<Body> {
+    let alt = form.alt.trim();
+    let caption = form.caption.trim();
+    let title = form.title.trim();
+    match File::describe(&ctx.conn(), &ctx.file_index, &sha, alt, caption, title) {
+        Ok(0) => return not_found(State(ctx)).await,
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!("failed to describe file {sha}: {e}");
+            return not_found(State(ctx)).await;
+        }
+    }
+    tracing::info!("\"POST /files/describe/{sha} HTTP/1.1\" 303");
     crate::trigger::trigger_github_backup(&ctx).await;
     crate::serve::see_other(&ctx, "/files")
 }
 
-pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+pub fn routes(router: &Router<ServerContext>, args: &ServeArgs) -> Router<ServerContext> {
     router
         .clone()
         .route("/files", get(get_files))
         .route("/files/{sha}", get(get_file))
         .route("/files/{sha}/{filename}", get(get_file_with_filename))
-        .route("/files/add", post(post_file))
+        .route("/files/variants/{sha}/{name}", get(get_variant))
+        .route(
+            "/files/add",
+            post(post_file).layer(crate::upload::body_limit(args)),
+        )
+        .route("/files/orphaned", get(get_orphaned))
+        .route("/files/orphaned", post(post_orphaned))
         .route("/files/delete/{sha}", get(get_delete))
         .route("/files/delete/{sha}", post(post_delete))
         .route("/files/rename/{sha}", get(get_rename))
         .route("/files/rename/{sha}", post(post_rename))
+        .route("/files/describe/{sha}", get(get_describe))
+        .route("/files/describe/{sha}", post(post_describe))
 }
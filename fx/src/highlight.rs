@@ -0,0 +1,144 @@
+//! Syntax highlighting of code blocks while rendering.
+//!
+//! Highlighting on the server means that readers do not have to load a
+//! highlighter from a third party, that code does not flash unstyled, and that
+//! it also works without JavaScript and in feed readers. The output only
+//! contains classes, so the colors are defined in `/static/highlight.css`.
+use regex::Regex;
+use std::sync::LazyLock;
+use syntect::highlighting::Theme;
+use syntect::html::ClassStyle;
+use syntect::html::ClassedHTMLGenerator;
+use syntect::html::css_for_theme_with_class_style;
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use two_face::theme::EmbeddedThemeName;
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// Grammars for the languages, loaded once since this takes a while.
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(two_face::syntax::extra_newlines);
+
+/// Code blocks from the Markdown renderer that have a language.
+static CODE_BLOCK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)<pre><code class="language-([^"]+)">(.*?)</code></pre>"#).unwrap()
+});
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(html: &str) -> String {
+    html.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Highlighted HTML for the code or `None` when the language is unknown.
+fn highlight(code: &str, lang: &str) -> Option<String> {
+    let syntax = SYNTAXES.find_syntax_by_token(lang)?;
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        if let Err(e) = generator.parse_html_for_line_which_includes_newline(line) {
+            tracing::warn!("failed to highlight {lang} code: {e}");
+            return None;
+        }
+    }
+    Some(generator.finalize())
+}
+
+/// Code block with highlighted `code` for the language `lang`.
+///
+/// The `pre` element gets a class so that the block is not highlighted again
+/// by [highlight_html].
+pub fn code_block(code: &str, lang: &str) -> String {
+    let class = escape(lang);
+    let html = match highlight(code, lang) {
+        Some(html) => html,
+        None => escape(code),
+    };
+    format!("<pre class=\"highlight\"><code class=\"language-{class} hl-code\">{html}</code></pre>")
+}
+
+/// Highlight the code blocks in HTML produced by the Markdown renderer.
+///
+/// Math blocks are left alone since they are not code.
+pub fn highlight_html(html: &str) -> String {
+    CODE_BLOCK
+        .replace_all(html, |caps: &regex::Captures| {
+            let lang = &caps[1];
+            if lang.starts_with("math") {
+                return caps[0].to_string();
+            }
+            code_block(&unescape(&caps[2]), lang)
+        })
+        .to_string()
+}
+
+#[test]
+fn test_highlight_html() {
+    let html = "<pre><code class=\"language-rust\">let x = &quot;a&lt;b&quot;;\n</code></pre>";
+    let highlighted = highlight_html(html);
+    assert!(
+        highlighted.starts_with("<pre class=\"highlight\"><code class=\"language-rust hl-code\">")
+    );
+    assert!(highlighted.contains("<span class=\"hl-storage hl-type hl-rust\">let</span>"));
+    assert!(highlighted.contains("&lt;"));
+    assert!(!highlighted.contains("a<b"));
+    // Already highlighted blocks are not highlighted again.
+    assert_eq!(highlight_html(&highlighted), highlighted);
+
+    let html = "<pre><code class=\"language-julia\">function f(x)\nend\n</code></pre>";
+    assert!(highlight_html(html).contains("hl-keyword"));
+
+    let html = "<pre><code class=\"language-unknown\">a &amp; b</code></pre>";
+    let expected =
+        "<pre class=\"highlight\"><code class=\"language-unknown hl-code\">a &amp; b</code></pre>";
+    assert_eq!(highlight_html(html), expected);
+
+    let html = "<pre><code class=\"language-math math-display\">x^2</code></pre>";
+    assert_eq!(highlight_html(html), html);
+}
+
+fn theme_css(theme: &Theme) -> String {
+    css_for_theme_with_class_style(theme, CLASS_STYLE).unwrap()
+}
+
+/// Stylesheet with the light theme and the dark theme.
+///
+/// The dark theme follows the same rules as `style.css`, so it is not used
+/// when dark mode is disabled in the settings.
+pub fn stylesheet() -> String {
+    let themes = two_face::theme::extra();
+    let light = theme_css(themes.get(EmbeddedThemeName::Github));
+    let dark = theme_css(themes.get(EmbeddedThemeName::OneHalfDark));
+    let dark = dark
+        .lines()
+        .map(|line| match line.strip_suffix(" {") {
+            Some(selectors) => {
+                let selectors = selectors
+                    .split(", ")
+                    .map(|selector| format!(":root:not([data-theme='light']) {selector}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{selectors} {{")
+            }
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("{light}\n@media (prefers-color-scheme: dark) {{\n{dark}\n}}\n")
+}
+
+#[test]
+fn test_stylesheet() {
+    let css = stylesheet();
+    assert!(css.contains(".hl-comment"));
+    assert!(css.contains("@media (prefers-color-scheme: dark)"));
+    assert!(css.contains(":root:not([data-theme='light']) .hl-code {"));
+}
@@ -1,5 +1,7 @@
+use crate::assets::url;
 use crate::data::Kv;
 use crate::data::Post;
+use crate::files::FileIndex;
 use crate::serve::ServerContext;
 use chrono::DateTime;
 use chrono::Duration;
@@ -36,6 +38,22 @@ pub fn escape_single_quote(s: &str) -> String {
     s.replace('\'', "&#39;")
 }
 
+/// Escape text that is shown inside HTML elements.
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[test]
+fn test_escape_html() {
+    assert_eq!(
+        escape_html("<a href=\"x\">&</a>"),
+        "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+    );
+}
+
 pub fn show_date<Tz: chrono::TimeZone>(datetime: &DateTime<Tz>) -> String {
     let now = chrono::Utc::now();
     let duration = now.signed_duration_since(datetime.clone());
@@ -61,9 +79,6 @@ pub fn show_date<Tz: chrono::TimeZone>(datetime: &DateTime<Tz>) -> String {
     }
 }
 
-const SET_LEAVE_CONFIRMATION: &str = "window.onbeforeunload = () => true;";
-const UNSET_LEAVE_CONFIRMATION: &str = "window.onbeforeunload = null;";
-
 /// Automatically set the `id` attribute for headers.
 ///
 /// pulldown-cmark supports header attributes, but markdown-rs does not. That's
@@ -169,7 +184,12 @@ pub fn post_link(post: &Post, slug: &str) -> String {
 }
 
 /// Add extra information such as last update date around the post content.
-pub fn wrap_post_content(post: &Post, slug: &str, is_front_page_preview: bool) -> String {
+pub fn wrap_post_content(
+    post: &Post,
+    slug: &str,
+    is_front_page_preview: bool,
+    index: &FileIndex,
+) -> String {
     // Not wrapping the full post in a `href` because that prevents text
     // selection. I've tried all kinds of workarounds with putting a `position:
     // relative` object in front of the link with `z-index`, but that didn't
@@ -179,9 +199,10 @@ pub fn wrap_post_content(post: &Post, slug: &str, is_front_page_preview: bool) -
         // Front page preview is already HTML.
         post.content.clone()
     } else {
-        crate::md::content_to_html(&post.content)
+        crate::md::content_to_html(&post.content, index)
     };
     let html = set_header_id(&html);
+    let html = crate::audio::render_html(&html, index);
     let style = if is_front_page_preview {
         &border_style(1)
     } else {
@@ -221,7 +242,7 @@ pub fn wrap_post_content(post: &Post, slug: &str, is_front_page_preview: bool) -
                  <a href='{}' class='unstyled-link' id='long-url'>
                     🔗 Link
                  </a>&nbsp;(
-                 <a id='copy-long-url' href='javascript:void(0)' onclick='copyLongUrl()'>
+                 <a id='copy-long-url' href='#'>
                     copy
                  </a>)
             </div>
@@ -272,6 +293,9 @@ pub struct PageSettings {
     show_about: bool,
     top: Top,
     extra_head: String,
+    /// Hidden CSRF input for the forms that [page] adds, such as the form
+    /// to add a post on the homepage.
+    csrf: String,
 }
 
 impl PageSettings {
@@ -290,8 +314,13 @@ impl PageSettings {
             show_about,
             top,
             extra_head: extra_head.to_string(),
+            csrf: "".to_string(),
         }
     }
+    pub fn with_csrf(mut self, csrf: &str) -> Self {
+        self.csrf = csrf.to_string();
+        self
+    }
 }
 
 pub fn edit_post_buttons(_ctx: &ServerContext, post: &Post) -> String {
@@ -308,16 +337,17 @@ pub fn edit_post_buttons(_ctx: &ServerContext, post: &Post) -> String {
     "#}
 }
 
-fn add_post_form() -> String {
+fn add_post_form(csrf: &str) -> String {
     let markdown_link = crate::md::markdown_link();
     format!(
         "
-    <form style='width: 100%;' action='/posts/add' method='post'>
+    <form style='width: 100%;' action='/posts/add' method='post' \
+      data-leave-confirmation>
+        {csrf}
         <textarea \
           style='display: block; width: 100%; height: 180px; margin-top: 10px;' \
           class='boxsizing-border' \
-          oninput='disable_form_submit_if_empty(this);' \
-          onchange='{SET_LEAVE_CONFIRMATION}' \
+          data-disable-submit-if-empty \
           id='content' name='content' placeholder='Your text..' required>
         </textarea>
         <div style='font-size: 0.8rem; text-align: right;'>
@@ -325,10 +355,8 @@ fn add_post_form() -> String {
         </div>
         <br>
         <div style='display: flex; justify-content: flex-end;'>
-            <input type='submit' onclick='{UNSET_LEAVE_CONFIRMATION}' \
-              name='preview' value='Preview'/>
-            <input type='submit' onclick='{UNSET_LEAVE_CONFIRMATION}' \
-              name='publish' value='Publish'/>
+            <input type='submit' name='preview' value='Preview'/>
+            <input type='submit' name='publish' value='Publish'/>
         </div>
     </form>
     "
@@ -336,18 +364,19 @@ fn add_post_form() -> String {
     .to_string()
 }
 
-pub fn edit_post_form(post: &Post) -> String {
+pub fn edit_post_form(post: &Post, csrf: &str) -> String {
     let id = post.id;
     let content = &post.content;
     let markdown_link = crate::md::markdown_link();
     format!(
         "
     <form style='width: 100%;' action='/posts/edit/{id}' \
-      method='post' onchange='{SET_LEAVE_CONFIRMATION}'>
+      method='post' data-leave-confirmation>
+        {csrf}
         <textarea \
           style='display: block; width: 100%; height: 60vh; margin-top: 10px;' \
           class='boxsizing-border' \
-          oninput='disable_form_submit_if_empty(this);' \
+          data-disable-submit-if-empty \
           id='content' name='content' placeholder='Your text..'>\n{content}
         </textarea>
         <div style='font-size: 0.8rem; text-align: right;'>
@@ -355,16 +384,28 @@ pub fn edit_post_form(post: &Post) -> String {
         </div>
         <br>
         <div style='display: flex; justify-content: flex-end;'>
-            <input type='submit' onclick='{UNSET_LEAVE_CONFIRMATION}' \
-              name='preview' value='Preview'/>
-            <input type='submit' onclick='{UNSET_LEAVE_CONFIRMATION}' \
-              name='publish' value='Publish'/>
+            <input type='submit' name='preview' value='Preview'/>
+            <input type='submit' name='publish' value='Publish'/>
         </div>
     </form>
     "
     )
 }
 
+/// Whether the line starts a code block, such as `<pre><code>` or
+/// `<pre class="highlight"><code class="language-rust hl-code">`.
+fn starts_code_block(line: &str) -> bool {
+    let Some(rest) = line.strip_prefix("<pre") else {
+        return false;
+    };
+    match rest.split_once('>') {
+        Some((attributes, rest)) => {
+            (attributes.is_empty() || attributes.starts_with(' ')) && rest.starts_with("<code")
+        }
+        None => false,
+    }
+}
+
 /// Return formatted HTML/CSS that is small and readable.
 pub fn minify(page: &str) -> String {
     let mut lines = Vec::new();
@@ -380,15 +421,20 @@ pub fn minify(page: &str) -> String {
             lines.push(trimmed);
             continue;
         }
-        // Don't minify code blocks.
-        if trimmed.starts_with("<pre><code") {
-            inside_code = true;
+        // Don't minify code blocks. The block ends on the line with the
+        // closing tags, which is also the first line for single line blocks.
+        if !inside_code && starts_code_block(trimmed) {
+            inside_code = !trimmed.contains("</code></pre>");
             lines.push(trimmed);
             continue;
         }
-        if trimmed.starts_with("</code></pre>") {
+        if inside_code && line.contains("</code></pre>") {
             inside_code = false;
-            lines.push(trimmed);
+            if trimmed.starts_with("</code></pre>") {
+                lines.push(trimmed);
+            } else {
+                lines.push(line);
+            }
             continue;
         }
         if trimmed.starts_with("</textarea>") {
@@ -420,6 +466,23 @@ fn test_minify() {
     .trim();
     assert_eq!(minify(page), expected);
 
+    let page = indoc::indoc! {r#"
+      <pre class="highlight"><code class="language-rust hl-code">fn f() {
+
+        x
+    }</code></pre>
+      <p>after</p>
+    "#};
+    let expected = indoc::indoc! {r#"
+    <pre class="highlight"><code class="language-rust hl-code">fn f() {
+
+        x
+    }</code></pre>
+    <p>after</p>
+    "#}
+    .trim();
+    assert_eq!(minify(page), expected);
+
     let page = indoc::indoc! {r#"
     <textarea id='about'>
     x = 1;
@@ -433,7 +496,7 @@ fn test_minify() {
 async fn about(ctx: &ServerContext, settings: &PageSettings) -> String {
     let about = Kv::get(&ctx.conn(), "about").unwrap();
     let about = String::from_utf8(about).unwrap();
-    let about = crate::md::content_to_html(&about);
+    let about = crate::md::content_to_html(&about, &ctx.file_index);
     let author_name = Kv::get(&ctx.conn(), "author_name").unwrap();
     let author_name = String::from_utf8(author_name).unwrap();
     let style = "font-size: 0.8rem; padding-top: 0.1rem;";
@@ -518,21 +581,27 @@ async fn about(ctx: &ServerContext, settings: &PageSettings) -> String {
     )
 }
 
-fn katex_head(body: &str) -> String {
-    let has_math = body.contains("<code class=\"language-math");
+/// Scripts for rendering math with KaTeX in the browser.
+///
+/// Math is converted to MathML on the server, so KaTeX is only loaded when it
+/// is enabled or when an expression could not be converted.
+fn katex_head(body: &str, katex: bool, nonce: &str) -> String {
+    let unconverted = body.contains("<code class=\"language-math");
+    let has_math = unconverted || body.contains("<math");
     let prefix = "https://cdn.jsdelivr.net/npm/katex@0.16.22/dist";
-    if has_math {
+    let katex_js = url("katex.js");
+    if unconverted || (katex && has_math) {
         format!(
             "
             <link rel='stylesheet' href='{prefix}/katex.min.css' \
               crossorigin='anonymous'>
-            <script defer src='{prefix}/katex.min.js' \
+            <script nonce='{nonce}' defer src='{prefix}/katex.min.js' \
               crossorigin='anonymous'>
             </script>
-            <script defer src='{prefix}/contrib/auto-render.min.js' \
+            <script nonce='{nonce}' defer src='{prefix}/contrib/auto-render.min.js' \
               crossorigin='anonymous'>
             </script>
-            <script defer src='/static/katex.js'>
+            <script nonce='{nonce}' defer src='{katex_js}'>
             </script>
             "
         )
@@ -569,58 +638,10 @@ fn test_has_code() {
     assert!(has_code(body));
 }
 
-fn contains_language(body: &str, language: &str) -> bool {
-    let text = format!(r#"<code class="language-{language}""#);
-    body.contains(&text)
-}
-
-async fn highlight_head(ctx: &ServerContext, body: &str) -> String {
-    let prefix = "https://cdnjs.cloudflare.com/ajax/libs/highlight.js/11.11.1";
-    let julia = if contains_language(body, "julia") {
-        format!(
-            "
-            <script src='{prefix}/languages/julia.min.js' defer></script>
-            "
-        )
-    } else {
-        "".to_string()
-    };
+/// Stylesheet for the code blocks that were highlighted by [crate::highlight].
+fn highlight_head(body: &str) -> String {
     if has_code(body) {
-        let dark_mode = Kv::get(&ctx.conn(), "dark_mode").unwrap();
-        let dark_mode = String::from_utf8(dark_mode).unwrap();
-        let dark_stylesheet = if dark_mode == "on" {
-            format!(
-                "
-            <link rel='stylesheet' href='{prefix}/styles/github.min.css' \
-            media='(prefers-color-scheme: light)'>
-            <link rel='stylesheet' href='{prefix}/styles/github-dark.min.css' \
-            media='(prefers-color-scheme: dark)'>
-            "
-            )
-        } else {
-            format!(
-                "
-            <link rel='stylesheet' href='{prefix}/styles/github.min.css'>
-            "
-            )
-        };
-        format!(
-            "
-            {dark_stylesheet}
-            <script src='{prefix}/highlight.min.js' defer></script>
-            {julia}
-            <script defer>
-                document.addEventListener('DOMContentLoaded', function() {{
-                    document.querySelectorAll('pre code').forEach((el) => {{
-                        if (el.classList.contains('language-math')) {{
-                            return;
-                        }}
-                        hljs.highlightElement(el);
-                    }});
-                }});
-            </script>
-            "
-        )
+        format!("<link rel='stylesheet' href='{}'>", url("highlight.css"))
     } else {
         "".to_string()
     }
@@ -649,10 +670,21 @@ pub async fn page(ctx: &ServerContext, settings: &PageSettings, body: &str) -> S
         Some(false) => r#"<a class="unstyled-link menu-space" href="/login">Login</a>"#,
         None => "",
     };
+    let nonce = crate::csp::nonce();
+    let go_back = format!(
+        r#"
+        <noscript>
+            // no button because loading back will remove the previous content.
+        </noscript>
+        <script nonce='{nonce}'>
+            document.write("<a href='/' class='button' data-history-back>← back</a>");
+        </script>
+        "#
+    );
     let top = match settings.top {
         Top::Homepage => {
             if settings.is_logged_in.unwrap_or(false) {
-                &add_post_form()
+                &add_post_form(&settings.csrf)
             } else {
                 ""
             }
@@ -660,20 +692,17 @@ pub async fn page(ctx: &ServerContext, settings: &PageSettings, body: &str) -> S
         Top::GoHome => indoc::indoc! {"
         <a href='/' class='button'>← back</a>
         "},
-        Top::GoBack => indoc::indoc! {r#"
-        <noscript>
-            // no button because loading back will remove the previous content.
-        </noscript>
-        <script>
-            document.write("<a href='javascript:history.back()' class='button'>← back</a>");
-        </script>
-        "#},
+        Top::GoBack => &go_back,
     };
     let html_lang = &ctx.args.html_lang;
-    let extra_head = &settings.extra_head;
+    let extra_head = crate::csp::add_nonce(&settings.extra_head, &nonce);
     let version = include_str!("version.txt").trim();
-    let highlight = highlight_head(ctx, body).await;
-    let katex = katex_head(body);
+    let highlight = highlight_head(body);
+    let katex = katex_head(body, ctx.args.katex, &nonce);
+    let theme = crate::assets::theme_head(ctx, &nonce);
+    let style_css = url("style.css");
+    let script_js = url("script.js");
+    let nodefer_js = url("nodefer.js");
     let og_title = if settings.title.is_empty() {
         &site_name
     } else {
@@ -693,9 +722,11 @@ pub async fn page(ctx: &ServerContext, settings: &PageSettings, body: &str) -> S
         <head>
             <meta charset='utf-8'>
             <meta name='viewport' content='width=device-width, initial-scale=1'>
-            <link rel='stylesheet' href='/static/style.css'>
+            <link rel='stylesheet' href='{style_css}'>
             <link rel='alternate' type='application/rss+xml' href='/feed.xml'>
-            <script src='/static/script.js' defer></script>
+            <link rel='alternate' type='application/atom+xml' href='/atom.xml'>
+            <link rel='alternate' type='application/feed+json' href='/feed.json'>
+            <script nonce='{nonce}' src='{script_js}' defer></script>
             <title>{full_title}</title>
             <meta name='description' content='{description}'/>
             <meta property='og:description' content='{description}'/>
@@ -703,6 +734,7 @@ pub async fn page(ctx: &ServerContext, settings: &PageSettings, body: &str) -> S
             <meta property='og:title' content='{og_title}'/>
             {katex}
             {highlight}
+            {theme}
             {extra_head}
         </head>
         <body>
@@ -719,7 +751,7 @@ pub async fn page(ctx: &ServerContext, settings: &PageSettings, body: &str) -> S
                     </div>
                 </div>
             </div>
-            <script src='/static/nodefer.js'></script>
+            <script nonce='{nonce}' src='{nodefer_js}'></script>
         </body>
         "#,
     };
@@ -746,7 +778,9 @@ pub async fn login(ctx: &ServerContext, error: Option<&str>) -> String {
             {error}
             <input style='{input_style}' type='submit' value='login'/>
         </form>
-    "
+        <div style='text-align: center;'>{}</div>
+    ",
+        crate::passkeys::login_button(ctx, false)
     );
     page(ctx, &settings, &body).await
 }
@@ -0,0 +1,659 @@
+//! Processing of uploaded images.
+//!
+//! Photos from phones are large and contain metadata such as the location
+//! where they were taken. On upload, the metadata is removed, the dimensions
+//! are recorded, and smaller variants are generated. Pages then use `srcset`
+//! so that browsers only download the size that fits the screen. Images whose
+//! metadata cannot be removed are rejected.
+use crate::files::FileIndex;
+use bytes::Bytes;
+use image::DynamicImage;
+use image::GenericImageView;
+use image::ImageDecoder;
+use image::ImageFormat;
+use image::ImageReader;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use img_parts::ImageEXIF;
+use regex::Regex;
+use rusqlite::Connection;
+use rusqlite::params;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::LazyLock;
+
+/// Widths of the generated variants.
+///
+/// Only widths that are smaller than the original are generated.
+pub const WIDTHS: [u32; 3] = [480, 960, 1920];
+
+/// Quality of generated JPEG images.
+const JPEG_QUALITY: u8 = 85;
+
+/// A resized copy of an uploaded image.
+pub struct Variant {
+    pub width: u32,
+    pub height: u32,
+    pub mime_type: String,
+    pub data: Bytes,
+}
+
+/// An uploaded image after processing.
+pub struct Processed {
+    /// The original without metadata.
+    pub data: Bytes,
+    /// Not set for images that could not be decoded.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub variants: Vec<Variant>,
+}
+
+fn format(mime_type: &str) -> Option<ImageFormat> {
+    match mime_type {
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/png" => Some(ImageFormat::Png),
+        "image/webp" => Some(ImageFormat::WebP),
+        "image/gif" => Some(ImageFormat::Gif),
+        _ => None,
+    }
+}
+
+/// Whether uploads of this type are processed.
+pub fn is_supported(mime_type: &str) -> bool {
+    format(mime_type).is_some()
+}
+
+/// Whether uploads of this type can be stored.
+///
+/// Images are only stored when their metadata can be removed. SVG files are
+/// text that does not come from a camera, so they are stored as they are.
+pub fn check(mime_type: &str) -> Result<(), String> {
+    let is_image = mime_type.starts_with("image/");
+    if !is_image || mime_type == "image/svg+xml" || is_supported(mime_type) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Cannot remove the metadata of {mime_type} images. \
+            Convert the image to JPEG, PNG, WebP, or GIF first."
+        ))
+    }
+}
+
+#[test]
+fn test_check() {
+    assert!(check("image/jpeg").is_ok());
+    assert!(check("image/gif").is_ok());
+    assert!(check("image/svg+xml").is_ok());
+    assert!(check("application/pdf").is_ok());
+    assert!(check("image/heic").is_err());
+    assert!(check("image/avif").is_err());
+}
+
+/// File extension for the variants.
+pub fn extension(mime_type: &str) -> Option<&'static str> {
+    match mime_type {
+        "image/jpeg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/webp" => Some("webp"),
+        _ => None,
+    }
+}
+
+/// Remove the metadata without decoding and encoding the image again.
+///
+/// The color profile is kept since colors would look different without it.
+fn strip_metadata(format: ImageFormat, data: Bytes) -> Result<Bytes, String> {
+    let data = match format {
+        ImageFormat::Jpeg => {
+            let mut jpeg = img_parts::jpeg::Jpeg::from_bytes(data).map_err(|e| e.to_string())?;
+            jpeg.set_exif(None);
+            // XMP, Photoshop (IPTC), and comments.
+            for marker in [0xe1, 0xed, 0xfe] {
+                jpeg.remove_segments_by_marker(marker);
+            }
+            jpeg.encoder().bytes()
+        }
+        ImageFormat::Png => {
+            let mut png = img_parts::png::Png::from_bytes(data).map_err(|e| e.to_string())?;
+            png.set_exif(None);
+            for kind in [*b"tEXt", *b"zTXt", *b"iTXt", *b"tIME"] {
+                png.remove_chunks_by_type(kind);
+            }
+            png.encoder().bytes()
+        }
+        ImageFormat::WebP => {
+            let mut webp = img_parts::webp::WebP::from_bytes(data).map_err(|e| e.to_string())?;
+            webp.set_exif(None);
+            webp.remove_chunks_by_id(img_parts::webp::CHUNK_XMP);
+            webp.encoder().bytes()
+        }
+        ImageFormat::Gif => strip_gif(&data)?,
+        format => return Err(format!("cannot remove metadata from {format:?}")),
+    };
+    Ok(data)
+}
+
+/// Application extensions that affect how a GIF is shown.
+const GIF_APPLICATIONS: [&[u8]; 3] = [b"NETSCAPE2.0", b"ANIMEXTS1.0", b"ICCRGBG1012"];
+
+/// Remove the comments and the application extensions, such as XMP, from a
+/// GIF.
+///
+/// The `img-parts` crate does not support GIF, so the blocks are copied
+/// except for the metadata.
+fn strip_gif(data: &[u8]) -> Result<Bytes, String> {
+    let invalid = || "invalid GIF".to_string();
+    let byte = |at: usize| data.get(at).copied().ok_or_else(invalid);
+    // End of the sub-blocks that start at `at`.
+    let sub_blocks = |mut at: usize| -> Result<usize, String> {
+        loop {
+            let size = usize::from(byte(at)?);
+            at += 1 + size;
+            if size == 0 {
+                return Ok(at);
+            }
+        }
+    };
+    let color_table = |flags: u8| {
+        if flags & 0x80 == 0 {
+            0
+        } else {
+            3 << ((flags & 0x07) + 1)
+        }
+    };
+    // Header and logical screen descriptor.
+    let mut at = 13 + color_table(byte(10)?);
+    let mut out = data.get(..at).ok_or_else(invalid)?.to_vec();
+    loop {
+        let start = at;
+        match byte(at)? {
+            // Trailer.
+            0x3b => {
+                out.push(0x3b);
+                return Ok(Bytes::from(out));
+            }
+            // Image descriptor, color table, and image data.
+            0x2c => {
+                at += 10 + color_table(byte(at + 9)?);
+                // LZW minimum code size.
+                at = sub_blocks(at + 1)?;
+                out.extend_from_slice(data.get(start..at).ok_or_else(invalid)?);
+            }
+            0x21 => {
+                let label = byte(at + 1)?;
+                at = sub_blocks(at + 2)?;
+                let application = data.get(start + 3..start + 14);
+                let keep = match label {
+                    0xfe => false,
+                    0xff => application.is_some_and(|id| GIF_APPLICATIONS.contains(&id)),
+                    _ => true,
+                };
+                if keep {
+                    out.extend_from_slice(data.get(start..at).ok_or_else(invalid)?);
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let result = match format {
+        ImageFormat::Jpeg => {
+            let encoder =
+                image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY);
+            // JPEG has no transparency.
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)
+        }
+        ImageFormat::WebP => {
+            let encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut out);
+            if image.color().has_alpha() {
+                DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder)
+            } else {
+                DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)
+            }
+        }
+        format => image.write_to(&mut Cursor::new(&mut out), format),
+    };
+    result.map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+fn resize(image: &DynamicImage, format: ImageFormat) -> Result<Vec<Variant>, String> {
+    let (width, height) = image.dimensions();
+    let mut variants = Vec::new();
+    for target in WIDTHS.into_iter().filter(|target| *target < width) {
+        let target_height = (u64::from(height) * u64::from(target) / u64::from(width)).max(1);
+        let resized = image.resize_exact(target, target_height as u32, FilterType::Lanczos3);
+        let variant = |format: ImageFormat, data: Vec<u8>| Variant {
+            width: target,
+            height: target_height as u32,
+            mime_type: format.to_mime_type().to_string(),
+            data: Bytes::from(data),
+        };
+        let original = encode(&resized, format)?;
+        if format != ImageFormat::WebP {
+            // The encoder only supports lossless WebP, which is larger than
+            // JPEG for most photos. Such variants would only waste space.
+            let webp = encode(&resized, ImageFormat::WebP)?;
+            if webp.len() < original.len() {
+                variants.push(variant(ImageFormat::WebP, webp));
+            }
+        }
+        variants.push(variant(format, original));
+    }
+    Ok(variants)
+}
+
+/// Remove the metadata and generate the variants of an uploaded image.
+///
+/// Returns `None` for files that are stored as they are, such as files that
+/// are no images. Returns an error for images whose metadata cannot be
+/// removed. The metadata is removed before anything else, so images that
+/// cannot be decoded or resized are still stored without it.
+pub fn process(mime_type: &str, data: Bytes) -> Result<Option<Processed>, String> {
+    check(mime_type)?;
+    let Some(format) = format(mime_type) else {
+        return Ok(None);
+    };
+    let stripped = strip_metadata(format, data.clone())
+        .map_err(|e| format!("Failed to read the {mime_type} image: {e}"))?;
+    let unprocessed = |data: Bytes| Processed {
+        data,
+        width: None,
+        height: None,
+        variants: vec![],
+    };
+    let decoded = ImageReader::with_format(Cursor::new(&data), format)
+        .into_decoder()
+        .and_then(|mut decoder| {
+            let orientation = decoder.orientation()?;
+            let image = DynamicImage::from_decoder(decoder)?;
+            Ok((orientation, image))
+        });
+    let (orientation, mut image) = match decoded {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            tracing::warn!("failed to decode {mime_type} image: {e}");
+            return Ok(Some(unprocessed(stripped)));
+        }
+    };
+    let data = if orientation == Orientation::NoTransforms {
+        stripped
+    } else {
+        // Phones store the rotation in the metadata, which was removed, so
+        // the rotation is applied to the pixels.
+        image.apply_orientation(orientation);
+        match encode(&image, format) {
+            Ok(data) => Bytes::from(data),
+            Err(e) => {
+                tracing::warn!("failed to rotate {mime_type} image: {e}");
+                return Ok(Some(unprocessed(stripped)));
+            }
+        }
+    };
+    // Resizing animations would keep only the first frame.
+    let variants = if format == ImageFormat::Gif {
+        vec![]
+    } else {
+        resize(&image, format).unwrap_or_else(|e| {
+            tracing::warn!("failed to resize {mime_type} image: {e}");
+            vec![]
+        })
+    };
+    let (width, height) = image.dimensions();
+    Ok(Some(Processed {
+        data,
+        width: Some(width),
+        height: Some(height),
+        variants,
+    }))
+}
+
+#[cfg(test)]
+fn test_image(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+    let image = image::RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+    });
+    encode(&DynamicImage::ImageRgb8(image), format).unwrap()
+}
+
+#[test]
+fn test_process() {
+    let data = test_image(1000, 500, ImageFormat::Png);
+    let processed = process("image/png", Bytes::from(data)).unwrap().unwrap();
+    assert_eq!((processed.width, processed.height), (Some(1000), Some(500)));
+    let widths = processed
+        .variants
+        .iter()
+        .map(|variant| (variant.width, variant.height))
+        .collect::<Vec<_>>();
+    assert!(widths.contains(&(480, 240)));
+    assert!(widths.contains(&(960, 480)));
+    assert!(!widths.iter().any(|(width, _)| *width == 1920));
+
+    let data = test_image(100, 50, ImageFormat::Jpeg);
+    let processed = process("image/jpeg", Bytes::from(data)).unwrap().unwrap();
+    assert!(processed.variants.is_empty());
+
+    let data = Bytes::from_static(b"hello");
+    assert!(process("text/plain", data.clone()).unwrap().is_none());
+    assert!(process("image/svg+xml", data.clone()).unwrap().is_none());
+    assert!(process("image/png", data.clone()).is_err());
+    assert!(process("image/heic", data).is_err());
+}
+
+#[test]
+fn test_strip_metadata() {
+    let data = Bytes::from(test_image(10, 10, ImageFormat::Jpeg));
+    let mut jpeg = img_parts::jpeg::Jpeg::from_bytes(data).unwrap();
+    jpeg.set_exif(Some(Bytes::from_static(b"MM\0*GPS")));
+    let data = jpeg.encoder().bytes();
+    assert!(data.windows(3).any(|window| window == b"GPS"));
+    let stripped = strip_metadata(ImageFormat::Jpeg, data).unwrap();
+    assert!(!stripped.windows(3).any(|window| window == b"GPS"));
+    assert!(image::load_from_memory(&stripped).is_ok());
+}
+
+#[test]
+fn test_strip_gif() {
+    let data = test_image(10, 10, ImageFormat::Gif);
+    // Insert a comment and an XMP extension after the header.
+    let flags = data[10];
+    let color_table = if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    };
+    let header = 13 + color_table;
+    let mut with_metadata = data[..header].to_vec();
+    with_metadata.extend_from_slice(b"\x21\xfe\x03GPS\x00");
+    with_metadata.extend_from_slice(b"\x21\xff\x0bXMP DataXMP\x03GPS\x00");
+    with_metadata.extend_from_slice(&data[header..]);
+    assert!(with_metadata.windows(3).any(|window| window == b"GPS"));
+    let stripped = strip_metadata(ImageFormat::Gif, Bytes::from(with_metadata)).unwrap();
+    assert!(!stripped.windows(3).any(|window| window == b"GPS"));
+    assert_eq!(stripped, data);
+    let processed = process("image/gif", stripped).unwrap().unwrap();
+    assert_eq!(processed.width, Some(10));
+
+    assert!(strip_gif(&data[..data.len() - 1]).is_err());
+    assert!(strip_gif(b"GIF89a").is_err());
+}
+
+impl Variant {
+    pub fn create_table(conn: &Connection) -> rusqlite::Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS file_variants (
+                sha TEXT NOT NULL,
+                width INTEGER NOT NULL,
+                height INTEGER NOT NULL,
+                mime_type TEXT NOT NULL,
+                data BLOB NOT NULL,
+                PRIMARY KEY (sha, width, mime_type)
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    pub fn insert(conn: &Connection, sha: &str, variant: &Self) -> rusqlite::Result<usize> {
+        let sql = "
+            INSERT OR REPLACE INTO file_variants (sha, width, height, mime_type, data)
+            VALUES (?, ?, ?, ?, ?);
+            ";
+        let params = params![
+            sha,
+            variant.width,
+            variant.height,
+            variant.mime_type,
+            variant.data.to_vec()
+        ];
+        conn.execute(sql, params)
+    }
+    pub fn delete(conn: &Connection, sha: &str) -> rusqlite::Result<usize> {
+        let sql = "DELETE FROM file_variants WHERE sha = ?";
+        conn.execute(sql, [sha])
+    }
+}
+
+/// What the renderer needs to know about an uploaded image.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Image {
+    pub mime_type: String,
+    /// Not set for images that could not be processed, such as SVG files.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Width and mime type of each variant.
+    pub variants: Vec<(u32, String)>,
+    pub alt: String,
+    pub caption: String,
+    pub title: String,
+}
+
+/// Read the images from the database, or only the image with `sha`.
+pub(crate) fn read(
+    conn: &Connection,
+    sha: Option<&str>,
+) -> rusqlite::Result<HashMap<String, Image>> {
+    let mut images = HashMap::new();
+    let stmt = "
+        SELECT sha, mime_type, width, height, alt, caption, title
+        FROM files
+        WHERE mime_type LIKE 'image/%' AND (?1 IS NULL OR sha = ?1);
+        ";
+    let mut stmt = conn.prepare(stmt)?;
+    let rows = stmt.query_map([sha], |row| {
+        let image = Image {
+            mime_type: row.get("mime_type")?,
+            width: row.get("width")?,
+            height: row.get("height")?,
+            variants: vec![],
+            alt: row.get("alt")?,
+            caption: row.get("caption")?,
+            title: row.get("title")?,
+        };
+        Ok((row.get::<_, String>("sha")?, image))
+    })?;
+    for row in rows {
+        let (sha, image) = row?;
+        images.insert(sha, image);
+    }
+    let stmt = "
+        SELECT sha, width, mime_type
+        FROM file_variants
+        WHERE ?1 IS NULL OR sha = ?1
+        ORDER BY width;
+        ";
+    let mut stmt = conn.prepare(stmt)?;
+    let rows = stmt.query_map([sha], |row| {
+        let sha: String = row.get("sha")?;
+        let width: u32 = row.get("width")?;
+        let mime_type: String = row.get("mime_type")?;
+        Ok((sha, width, mime_type))
+    })?;
+    for row in rows {
+        let (sha, width, mime_type) = row?;
+        if let Some(image) = images.get_mut(&sha) {
+            image.variants.push((width, mime_type));
+        }
+    }
+    Ok(images)
+}
+
+/// URL of a variant.
+pub fn variant_url(sha: &str, width: u32, mime_type: &str) -> String {
+    let extension = extension(mime_type).unwrap_or("bin");
+    format!("/files/variants/{sha}/{width}.{extension}")
+}
+
+/// Matches the width of the content column in the stylesheet.
+const SIZES: &str = "(max-width: 70ch) 100vw, 70ch";
+
+fn srcset(sha: &str, image: &Image, mime_type: &str) -> String {
+    image
+        .variants
+        .iter()
+        .filter(|(_, variant_type)| variant_type == mime_type)
+        .map(|(width, _)| format!("{} {width}w", variant_url(sha, *width, mime_type)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn attribute(text: &str) -> String {
+    crate::html::escape_single_quote(&crate::html::escape_html(text))
+}
+
+/// Use the stored alt text and title unless the post sets its own.
+fn describe(attributes: &str, image: &Image) -> String {
+    let mut attributes = attributes.to_string();
+    if !image.alt.is_empty() {
+        let alt = format!(" alt='{}'", attribute(&image.alt));
+        if attributes.contains(" alt=\"\"") || attributes.contains(" alt=''") {
+            attributes = attributes
+                .replacen(" alt=\"\"", &alt, 1)
+                .replacen(" alt=''", &alt, 1);
+        } else if !attributes.contains(" alt=") {
+            attributes.push_str(&alt);
+        }
+    }
+    if !image.title.is_empty() && !attributes.contains(" title=") {
+        attributes.push_str(&format!(" title='{}'", attribute(&image.title)));
+    }
+    attributes
+}
+
+/// Rewrite an `img` element for an uploaded image.
+fn img_html(src: &str, sha: &str, attributes: &str, image: &Image) -> String {
+    let attributes = describe(attributes, image);
+    let mut img = format!("<img src='{src}'");
+    let original = srcset(sha, image, &image.mime_type);
+    if let (Some(width), Some(height)) = (image.width, image.height) {
+        if !original.is_empty() {
+            let srcset = format!("{original}, {src} {width}w");
+            img.push_str(&format!(" srcset='{srcset}' sizes='{SIZES}'"));
+        }
+        img.push_str(&format!(" width='{width}' height='{height}'"));
+    }
+    img.push_str(&format!(" loading='lazy'{attributes} />"));
+    let webp = if image.mime_type == "image/webp" {
+        String::new()
+    } else {
+        srcset(sha, image, "image/webp")
+    };
+    if webp.is_empty() {
+        img
+    } else {
+        format!(
+            "<picture><source type='image/webp' srcset='{webp}' sizes='{SIZES}'>{img}</picture>"
+        )
+    }
+}
+
+/// Images that point to an uploaded file, optionally alone in a paragraph.
+static IMG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(<p>)?<img src=["'](/files/([0-9a-f]{16})(?:/[^"']*)?)["']([^>]*?)\s*/?>(</p>)?"#)
+        .unwrap()
+});
+
+/// Add the dimensions, variants, and descriptions to images that point to
+/// `/files/{sha}`.
+///
+/// Images with a caption that are alone in a paragraph become figures.
+pub fn render_html(html: &str, index: &FileIndex) -> String {
+    IMG.replace_all(html, |caps: &regex::Captures| {
+        let src = &caps[2];
+        let sha = &caps[3];
+        let attributes = &caps[4];
+        // The preview is already rendered when it is converted again for the
+        // feed.
+        let rendered = attributes.contains("loading=");
+        let image = match index.image(sha) {
+            Some(image) if !rendered => image,
+            _ => return caps[0].to_string(),
+        };
+        let img = img_html(src, sha, attributes, &image);
+        let paragraph = caps.get(1).is_some() && caps.get(5).is_some();
+        if paragraph && !image.caption.is_empty() {
+            let caption = crate::html::escape_html(&image.caption);
+            format!("<figure>{img}<figcaption>{caption}</figcaption></figure>")
+        } else {
+            let open = caps.get(1).map_or("", |m| m.as_str());
+            let close = caps.get(5).map_or("", |m| m.as_str());
+            format!("{open}{img}{close}")
+        }
+    })
+    .to_string()
+}
+
+#[test]
+fn test_render_html() {
+    let index = FileIndex::default();
+    let sha = "0123456789abcdef";
+    let image = Image {
+        mime_type: "image/jpeg".to_string(),
+        width: Some(1000),
+        height: Some(500),
+        variants: vec![
+            (480, "image/webp".to_string()),
+            (480, "image/jpeg".to_string()),
+            (960, "image/jpeg".to_string()),
+        ],
+        ..Default::default()
+    };
+    index.insert_image(sha, image);
+    let html = format!(r#"<p><img src="/files/{sha}" alt="A cat" /></p>"#);
+    let expected = "<p><picture>\
+        <source type='image/webp' \
+        srcset='/files/variants/0123456789abcdef/480.webp 480w' \
+        sizes='(max-width: 70ch) 100vw, 70ch'>\
+        <img src='/files/0123456789abcdef' \
+        srcset='/files/variants/0123456789abcdef/480.jpg 480w, \
+        /files/variants/0123456789abcdef/960.jpg 960w, \
+        /files/0123456789abcdef 1000w' \
+        sizes='(max-width: 70ch) 100vw, 70ch' \
+        width='1000' height='500' loading='lazy' alt=\"A cat\" />\
+        </picture></p>";
+    let rendered = render_html(&html, &index);
+    assert_eq!(rendered, expected);
+    assert_eq!(render_html(&rendered, &index), rendered);
+
+    let small = "fedcba9876543210";
+    let image = Image {
+        mime_type: "image/svg+xml".to_string(),
+        ..Default::default()
+    };
+    index.insert_image(small, image);
+    let html = format!("<img src='/files/{small}/a.svg' alt='a' />");
+    let expected = format!("<img src='/files/{small}/a.svg' loading='lazy' alt='a' />");
+    assert_eq!(render_html(&html, &index), expected);
+
+    let html = "<img src='/files/1111111111111111' alt='' />";
+    assert_eq!(render_html(html, &index), html);
+}
+
+#[test]
+fn test_render_description() {
+    let index = FileIndex::default();
+    let sha = "00000000000000aa";
+    let image = Image {
+        mime_type: "image/svg+xml".to_string(),
+        alt: "A 'red' <circle>".to_string(),
+        caption: "Figure 1 & 2".to_string(),
+        title: "Circle".to_string(),
+        ..Default::default()
+    };
+    index.insert_image(sha, image);
+    let html = format!(r#"<p><img src="/files/{sha}" alt="" /></p>"#);
+    let expected = format!(
+        "<figure><img src='/files/{sha}' loading='lazy' \
+        alt='A &#39;red&#39; &lt;circle&gt;' title='Circle' />\
+        <figcaption>Figure 1 &amp; 2</figcaption></figure>"
+    );
+    assert_eq!(render_html(&html, &index), expected);
+
+    // The alt text and title from the post are kept.
+    let html = format!(r#"<p>See <img src="/files/{sha}" alt="Own" title="T" /></p>"#);
+    let expected =
+        format!(r#"<p>See <img src='/files/{sha}' loading='lazy' alt="Own" title="T" /></p>"#);
+    assert_eq!(render_html(&html, &index), expected);
+}
@@ -0,0 +1,703 @@
+//! IndieAuth authorization and token endpoints at `/auth` and `/token`.
+//!
+//! This allows external clients to post on behalf of the site owner without
+//! knowing the admin password. See <https://indieauth.spec.indieweb.org/>.
+use crate::csrf::SameOrigin;
+use crate::data::SqliteDateTime;
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::escape_single_quote;
+use crate::html::page;
+use crate::serve::ServerContext;
+use crate::serve::is_logged_in;
+use crate::serve::response;
+use crate::serve::response_json;
+use axum::Form;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::DateTime;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::Result;
+use rusqlite::params;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use sha2::Digest;
+
+/// Scopes that can be granted to a client.
+///
+/// These are the scopes used by Micropub clients.
+pub const SCOPES: &[&str] = &["profile", "read", "create", "update", "delete", "media"];
+
+/// How long an authorization code can be redeemed.
+const CODE_MAX_AGE_SEC: i64 = 10 * 60;
+
+/// Authorization code that the client exchanges for an access token.
+struct AuthorizationCode {
+    client_id: String,
+    redirect_uri: String,
+    code_challenge: String,
+    scope: String,
+    created: DateTime<Utc>,
+}
+
+impl AuthorizationCode {
+    fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS indieauth_codes (
+                code_hash TEXT PRIMARY KEY,
+                client_id TEXT NOT NULL,
+                redirect_uri TEXT NOT NULL,
+                code_challenge TEXT NOT NULL,
+                scope TEXT NOT NULL,
+                created DATETIME NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    /// Store the code and return the plaintext code for the client.
+    fn insert(&self, conn: &Connection) -> Result<String> {
+        let code = fx_auth::generate_token();
+        let stmt = "
+            INSERT INTO indieauth_codes
+            (code_hash, client_id, redirect_uri, code_challenge, scope, created)
+            VALUES (?, ?, ?, ?, ?, ?);
+        ";
+        let params = params![
+            fx_auth::hash_token(&code),
+            self.client_id,
+            self.redirect_uri,
+            self.code_challenge,
+            self.scope,
+            self.created.to_sqlite(),
+        ];
+        conn.execute(stmt, params)?;
+        Ok(code)
+    }
+    /// Remove the code from the database and return it.
+    ///
+    /// Codes are single-use, so they are removed even when the redemption
+    /// fails later on.
+    fn take(conn: &Connection, code: &str) -> Result<Option<Self>> {
+        let code_hash = fx_auth::hash_token(code);
+        let stmt = "
+            SELECT client_id, redirect_uri, code_challenge, scope, created
+            FROM indieauth_codes
+            WHERE code_hash = ?;
+        ";
+        let code = conn
+            .prepare(stmt)?
+            .query_row([&code_hash], |row| {
+                let created: String = row.get("created")?;
+                Ok(AuthorizationCode {
+                    client_id: row.get("client_id")?,
+                    redirect_uri: row.get("redirect_uri")?,
+                    code_challenge: row.get("code_challenge")?,
+                    scope: row.get("scope")?,
+                    created: DateTime::from_sqlite(&created),
+                })
+            })
+            .optional()?;
+        let stmt = "DELETE FROM indieauth_codes WHERE code_hash = ?";
+        conn.execute(stmt, [&code_hash])?;
+        Ok(code)
+    }
+    fn is_expired(&self) -> bool {
+        let age = Utc::now().signed_duration_since(self.created);
+        CODE_MAX_AGE_SEC < age.num_seconds()
+    }
+}
+
+/// Access token issued via the token endpoint.
+///
+/// Only the hash of the token is stored, so a leaked database does not leak
+/// usable tokens.
+#[derive(Clone, Debug)]
+pub struct AccessToken {
+    pub client_id: String,
+    pub scope: String,
+    pub created: DateTime<Utc>,
+}
+
+impl AccessToken {
+    fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS indieauth_tokens (
+                token_hash TEXT PRIMARY KEY,
+                client_id TEXT NOT NULL,
+                scope TEXT NOT NULL,
+                created DATETIME NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    /// Store the token and return the plaintext token for the client.
+    fn insert(&self, conn: &Connection) -> Result<String> {
+        let token = fx_auth::generate_token();
+        let stmt = "
+            INSERT INTO indieauth_tokens (token_hash, client_id, scope, created)
+            VALUES (?, ?, ?, ?);
+        ";
+        let params = params![
+            fx_auth::hash_token(&token),
+            self.client_id,
+            self.scope,
+            self.created.to_sqlite(),
+        ];
+        conn.execute(stmt, params)?;
+        Ok(token)
+    }
+    /// Return the token if it exists and was not revoked.
+    pub fn get(conn: &Connection, token: &str) -> Result<Option<Self>> {
+        let stmt = "
+            SELECT client_id, scope, created
+            FROM indieauth_tokens
+            WHERE token_hash = ?;
+        ";
+        conn.prepare(stmt)?
+            .query_row([fx_auth::hash_token(token)], |row| {
+                let created: String = row.get("created")?;
+                Ok(AccessToken {
+                    client_id: row.get("client_id")?,
+                    scope: row.get("scope")?,
+                    created: DateTime::from_sqlite(&created),
+                })
+            })
+            .optional()
+    }
+    pub fn revoke(conn: &Connection, token: &str) -> Result<usize> {
+        let stmt = "DELETE FROM indieauth_tokens WHERE token_hash = ?";
+        conn.execute(stmt, [fx_auth::hash_token(token)])
+    }
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope.split_ascii_whitespace().any(|s| s == scope)
+    }
+}
+
+pub fn create_tables(conn: &Connection) -> Result<()> {
+    AuthorizationCode::create_table(conn)?;
+    AccessToken::create_table(conn)?;
+    Ok(())
+}
+
+/// Verify the PKCE code verifier against the stored challenge.
+///
+/// Only the `S256` method is supported since `plain` offers no protection.
+fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    // RFC 7636 section 4.1.
+    if code_verifier.len() < 43 || 128 < code_verifier.len() {
+        return false;
+    }
+    let hash = sha2::Sha256::digest(code_verifier.as_bytes());
+    let expected = URL_SAFE_NO_PAD.encode(hash);
+    crate::serve::constant_time_eq(&expected, code_challenge)
+}
+
+#[test]
+fn test_verify_pkce() {
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW-gFWFOEjXk";
+    let challenge = "90EpwHQr_xi9uDtjYyz5mq9Z4RekugHRqg5ijpXC3FQ";
+    assert!(verify_pkce(verifier, challenge));
+    assert!(!verify_pkce(
+        verifier,
+        "90EpwHQr_xi9uDtjYyz5mq9Z4RekugHRqg5ijpXC3FR"
+    ));
+    // Too short verifiers are rejected.
+    assert!(!verify_pkce(
+        "abc",
+        "ungWv48Bz-pBQUDeXa4iI7ADYaOWF3qctBD_YfIAFa0"
+    ));
+}
+
+/// The URL that identifies the site owner.
+fn me(ctx: &ServerContext) -> String {
+    format!("{}/", ctx.base_url())
+}
+
+/// Return the origin (scheme, host and port) of a URL.
+fn origin(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let host_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    if host_end == 0 {
+        return None;
+    }
+    let scheme_len = url.len() - rest.len();
+    Some(&url[..scheme_len + host_end])
+}
+
+#[test]
+fn test_origin() {
+    assert_eq!(origin("https://example.com/"), Some("https://example.com"));
+    assert_eq!(
+        origin("http://localhost:8000"),
+        Some("http://localhost:8000")
+    );
+    assert_eq!(origin("https://a.com?x=1"), Some("https://a.com"));
+    assert_eq!(origin("https:///foo"), None);
+    assert_eq!(origin("javascript:alert(1)"), None);
+}
+
+/// Add query parameters to a URL that may already contain a query.
+fn with_query(url: &str, params: &[(&str, &str)]) -> String {
+    let query = serde_urlencoded::to_string(params).unwrap();
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}{query}")
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuthorizationRequest {
+    pub response_type: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub scope: Option<String>,
+    pub me: Option<String>,
+}
+
+/// Validated fields of an authorization request.
+struct Authorization {
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    code_challenge: String,
+    scope: String,
+}
+
+impl AuthorizationRequest {
+    fn validate(&self) -> std::result::Result<Authorization, &'static str> {
+        let response_type = self.response_type.as_deref().unwrap_or("code");
+        if response_type != "code" {
+            return Err("Unsupported response_type; expected 'code'.");
+        }
+        let client_id = self.client_id.clone().ok_or("Missing client_id.")?;
+        let redirect_uri = self.redirect_uri.clone().ok_or("Missing redirect_uri.")?;
+        let state = self.state.clone().ok_or("Missing state.")?;
+        let client_origin = origin(&client_id).ok_or("Invalid client_id.")?;
+        let redirect_origin = origin(&redirect_uri).ok_or("Invalid redirect_uri.")?;
+        // Redirect URIs on other origins would require fetching and verifying
+        // the client metadata. Without that check, anyone could send the code
+        // to a server of their choosing.
+        if client_origin != redirect_origin {
+            return Err("The redirect_uri must be on the same origin as the client_id.");
+        }
+        let code_challenge = self
+            .code_challenge
+            .clone()
+            .ok_or("Missing code_challenge; PKCE is required.")?;
+        if self.code_challenge_method.as_deref() != Some("S256") {
+            return Err("Unsupported code_challenge_method; expected 'S256'.");
+        }
+        let scope = self
+            .scope
+            .as_deref()
+            .unwrap_or("")
+            .split_ascii_whitespace()
+            .filter(|s| SCOPES.contains(s))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(Authorization {
+            client_id,
+            redirect_uri,
+            state,
+            code_challenge,
+            scope,
+        })
+    }
+}
+
+fn hidden_input(name: &str, value: &str) -> String {
+    let value = escape_single_quote(&crate::html::escape_html(value));
+    format!("<input type='hidden' name='{name}' value='{value}'/>")
+}
+
+fn consent_form(ctx: &ServerContext, jar: &CookieJar, auth: &Authorization) -> String {
+    let client_id = crate::html::escape_html(&auth.client_id);
+    let redirect_uri = crate::html::escape_html(&auth.redirect_uri);
+    let me = me(ctx);
+    let scopes = auth
+        .scope
+        .split_ascii_whitespace()
+        .map(|scope| {
+            format!(
+                "
+                <input type='checkbox' id='scope-{scope}' name='scope' \
+                  value='{scope}' checked/>
+                <label for='scope-{scope}'>{scope}</label><br>
+                "
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("");
+    let scopes = if scopes.is_empty() {
+        "<p>The client only asks to confirm your identity.</p>".to_string()
+    } else {
+        format!("<p>Requested permissions:</p>{scopes}")
+    };
+    let deny = with_query(
+        &auth.redirect_uri,
+        &[("error", "access_denied"), ("state", &auth.state)],
+    );
+    let deny = escape_single_quote(&crate::html::escape_html(&deny));
+    format!(
+        "
+        <form style='margin-top: 5vh;' method='post' action='/auth/approve'>
+            <p>
+                <code>{client_id}</code> wants to sign in as <code>{me}</code>.
+            </p>
+            {scopes}
+            <p style='font-size: 0.8rem;'>
+                After approving, you will be redirected to <code>{redirect_uri}</code>.
+            </p>
+            {}
+            {}
+            {}
+            {}
+            {}
+            <div style='display: flex; justify-content: flex-end;'>
+                <a class='button' href='{deny}'>Deny</a>
+                <input type='submit' value='Approve'/>
+            </div>
+        </form>
+        ",
+        crate::csrf::input(ctx, jar),
+        hidden_input("client_id", &auth.client_id),
+        hidden_input("redirect_uri", &auth.redirect_uri),
+        hidden_input("state", &auth.state),
+        hidden_input("code_challenge", &auth.code_challenge),
+    )
+}
+
+async fn get_auth(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    Query(request): Query<AuthorizationRequest>,
+) -> Response<Body> {
+    let auth = match request.validate() {
+        Ok(auth) => auth,
+        Err(msg) => {
+            return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Invalid request", msg)
+                .await;
+        }
+    };
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    if !is_logged_in {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let body = consent_form(&ctx, &jar, &auth);
+    let settings = PageSettings::new(
+        "Authorize",
+        Some(is_logged_in),
+        None,
+        false,
+        Top::GoHome,
+        "",
+    );
+    let body = page(&ctx, &settings, &body).await;
+    tracing::info!("\"GET /auth HTTP/1.1\" 200");
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+#[derive(Debug, Deserialize)]
+struct ApproveForm {
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    code_challenge: String,
+}
+
+async fn post_approve(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    _: SameOrigin,
+    body: String,
+) -> Response<Body> {
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    if !is_logged_in {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    // Parsing manually because `scope` can occur multiple times.
+    let pairs = match serde_urlencoded::from_str::<Vec<(String, String)>>(&body) {
+        Ok(pairs) => pairs,
+        Err(_) => {
+            let msg = "Could not parse form";
+            return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Invalid request", msg)
+                .await;
+        }
+    };
+    let csrf_token = pairs
+        .iter()
+        .find(|(key, _)| key == crate::csrf::FIELD)
+        .map(|(_, value)| value.as_str())
+        .unwrap_or_default();
+    if !crate::csrf::verify(&ctx, &jar, csrf_token) {
+        return crate::csrf::rejected(&ctx).await;
+    }
+    let form = match serde_urlencoded::from_str::<ApproveForm>(&body) {
+        Ok(form) => form,
+        Err(_) => {
+            let msg = "Missing fields in form";
+            return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Invalid request", msg)
+                .await;
+        }
+    };
+    let scope = pairs
+        .iter()
+        .filter(|(key, _)| key == "scope")
+        .map(|(_, value)| value.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    // The hidden fields come from the client, so they have to pass the same
+    // checks as the request that showed the consent form.
+    let request = AuthorizationRequest {
+        response_type: None,
+        client_id: Some(form.client_id),
+        redirect_uri: Some(form.redirect_uri),
+        state: Some(form.state),
+        code_challenge: Some(form.code_challenge),
+        code_challenge_method: Some("S256".to_string()),
+        scope: Some(scope),
+        me: None,
+    };
+    let auth = match request.validate() {
+        Ok(auth) => auth,
+        Err(msg) => {
+            return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Invalid request", msg)
+                .await;
+        }
+    };
+    let code = AuthorizationCode {
+        client_id: auth.client_id,
+        redirect_uri: auth.redirect_uri.clone(),
+        code_challenge: auth.code_challenge,
+        scope: auth.scope,
+        created: Utc::now(),
+    };
+    let code = match code.insert(&ctx.conn()) {
+        Ok(code) => code,
+        Err(e) => {
+            tracing::error!("failed to store authorization code: {e}");
+            let msg = "Could not store authorization code";
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    let iss = me(&ctx);
+    let params = [
+        ("code", code.as_str()),
+        ("state", &auth.state),
+        ("iss", &iss),
+    ];
+    let url = with_query(&auth.redirect_uri, &params);
+    tracing::info!("\"POST /auth/approve HTTP/1.1\" 303");
+    crate::serve::see_other(&ctx, &url)
+}
+
+/// Request to redeem an authorization code.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RedeemRequest {
+    pub grant_type: Option<String>,
+    pub code: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+}
+
+fn oauth_error(ctx: &ServerContext, error: &str, description: &str) -> Response<Body> {
+    let body = json!({
+        "error": error,
+        "error_description": description,
+    })
+    .to_string();
+    response_json(StatusCode::BAD_REQUEST, body, ctx)
+}
+
+/// Check the redemption request against the stored code.
+///
+/// Returns the OAuth error code and description on failure.
+fn redeem(
+    ctx: &ServerContext,
+    request: &RedeemRequest,
+) -> std::result::Result<AuthorizationCode, (&'static str, &'static str)> {
+    if request.grant_type.as_deref() != Some("authorization_code") {
+        let msg = "expected grant_type authorization_code";
+        return Err(("unsupported_grant_type", msg));
+    }
+    let invalid = |msg| ("invalid_grant", msg);
+    let code = request.code.as_deref().ok_or(invalid("missing code"))?;
+    let code = match AuthorizationCode::take(&ctx.conn(), code) {
+        Ok(Some(code)) => code,
+        Ok(None) => return Err(invalid("unknown code")),
+        Err(e) => {
+            tracing::error!("failed to get authorization code: {e}");
+            return Err(invalid("unknown code"));
+        }
+    };
+    if code.is_expired() {
+        return Err(invalid("code expired"));
+    }
+    if request.client_id.as_deref() != Some(&code.client_id) {
+        return Err(invalid("client_id does not match"));
+    }
+    if request.redirect_uri.as_deref() != Some(&code.redirect_uri) {
+        return Err(invalid("redirect_uri does not match"));
+    }
+    let verifier = request.code_verifier.as_deref().unwrap_or("");
+    if !verify_pkce(verifier, &code.code_challenge) {
+        return Err(invalid("code_verifier does not match"));
+    }
+    Ok(code)
+}
+
+/// Redeem the code for the profile URL only, without an access token.
+async fn post_auth(
+    State(ctx): State<ServerContext>,
+    Form(request): Form<RedeemRequest>,
+) -> Response<Body> {
+    if let Err((error, description)) = redeem(&ctx, &request) {
+        return oauth_error(&ctx, error, description);
+    }
+    let body = json!({ "me": me(&ctx) }).to_string();
+    response_json(StatusCode::OK, body, &ctx)
+}
+
+async fn post_token(
+    State(ctx): State<ServerContext>,
+    Form(request): Form<RedeemRequest>,
+) -> Response<Body> {
+    let code = match redeem(&ctx, &request) {
+        Ok(code) => code,
+        Err((error, description)) => return oauth_error(&ctx, error, description),
+    };
+    // The spec says that no token should be issued for an empty scope.
+    if code.scope.is_empty() {
+        return oauth_error(&ctx, "invalid_scope", "no scope was granted");
+    }
+    let token = AccessToken {
+        client_id: code.client_id,
+        scope: code.scope,
+        created: Utc::now(),
+    };
+    let access_token = match token.insert(&ctx.conn()) {
+        Ok(access_token) => access_token,
+        Err(e) => {
+            tracing::error!("failed to store access token: {e}");
+            let body = json!({ "error": "server_error" }).to_string();
+            return response_json(StatusCode::INTERNAL_SERVER_ERROR, body, &ctx);
+        }
+    };
+    tracing::info!("\"POST /token HTTP/1.1\" 200");
+    let body = json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "scope": token.scope,
+        "me": me(&ctx),
+    })
+    .to_string();
+    response_json(StatusCode::OK, body, &ctx)
+}
+
+/// Return the token from an `Authorization: Bearer` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let header = headers.get("Authorization")?.to_str().ok()?;
+    let parts = header.split_ascii_whitespace().collect::<Vec<&str>>();
+    if parts.len() != 2 || parts[0] != "Bearer" {
+        return None;
+    }
+    Some(parts[1])
+}
+
+/// Token verification for resource servers.
+async fn get_token(State(ctx): State<ServerContext>, headers: HeaderMap) -> Response<Body> {
+    let unauthorized = || {
+        let body = json!({ "error": "invalid_token" }).to_string();
+        response_json(StatusCode::UNAUTHORIZED, body, &ctx)
+    };
+    let token = match bearer_token(&headers) {
+        Some(token) => token,
+        None => return unauthorized(),
+    };
+    let token = match AccessToken::get(&ctx.conn(), token) {
+        Ok(Some(token)) => token,
+        _ => return unauthorized(),
+    };
+    let body = json!({
+        "me": me(&ctx),
+        "client_id": token.client_id,
+        "scope": token.scope,
+    })
+    .to_string();
+    response_json(StatusCode::OK, body, &ctx)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RevokeRequest {
+    pub token: String,
+}
+
+/// Token revocation according to RFC 7009.
+async fn post_revoke(
+    State(ctx): State<ServerContext>,
+    Form(request): Form<RevokeRequest>,
+) -> Response<Body> {
+    if let Err(e) = AccessToken::revoke(&ctx.conn(), &request.token) {
+        tracing::error!("failed to revoke token: {e}");
+    }
+    // RFC 7009 requires 200 also for invalid tokens.
+    tracing::info!("\"POST /revoke HTTP/1.1\" 200");
+    response_json(StatusCode::OK, "{}", &ctx)
+}
+
+async fn get_metadata(State(ctx): State<ServerContext>) -> Response<Body> {
+    let base = ctx.base_url();
+    let body = json!({
+        "issuer": me(&ctx),
+        "authorization_endpoint": format!("{base}/auth"),
+        "token_endpoint": format!("{base}/token"),
+        "revocation_endpoint": format!("{base}/revoke"),
+        "revocation_endpoint_auth_methods_supported": ["none"],
+        "scopes_supported": SCOPES,
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code"],
+        "code_challenge_methods_supported": ["S256"],
+        "authorization_response_iss_parameter_supported": true,
+    })
+    .to_string();
+    response_json(StatusCode::OK, body, &ctx)
+}
+
+/// Links for the homepage so that clients can discover the endpoints.
+pub fn discovery_links(ctx: &ServerContext) -> String {
+    let base = ctx.base_url();
+    indoc::formatdoc! {"
+        <link rel='indieauth-metadata' href='{base}/.well-known/oauth-authorization-server'/>
+        <link rel='authorization_endpoint' href='{base}/auth'/>
+        <link rel='token_endpoint' href='{base}/token'/>
+    "}
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/auth", get(get_auth))
+        .route("/auth", post(post_auth))
+        .route("/auth/approve", post(post_approve))
+        .route("/token", get(get_token))
+        .route("/token", post(post_token))
+        .route("/revoke", post(post_revoke))
+        .route("/.well-known/oauth-authorization-server", get(get_metadata))
+}
@@ -1,16 +1,35 @@
 mod ap;
 mod api;
+mod assets;
+mod audio;
 pub mod blogroll;
+pub mod compression;
+mod conditional;
+pub mod csp;
+mod csrf;
 pub mod data;
 mod discovery;
 mod files;
 pub mod health;
+mod highlight;
 pub mod html;
+mod images;
+mod indieauth;
+mod mathml;
 mod md;
+mod og;
+mod passkeys;
+mod range;
 mod search;
 pub mod serve;
+mod sessions;
 mod settings;
+mod throttle;
+mod tokens;
+mod totp;
 mod trigger;
+mod upload;
+mod usage;
 
 use clap::Parser;
 
@@ -26,8 +45,17 @@ pub struct ServeArgs {
     #[arg(long, env = "FX_USERNAME", default_value = "admin")]
     pub username: String,
     /// The password for the admin interface.
-    #[arg(long, env = "FX_PASSWORD")]
+    #[arg(long, env = "FX_PASSWORD", conflicts_with = "password_hash")]
     pub password: Option<String>,
+    /// Argon2 hash of the password for the admin interface as printed by
+    /// `fx hash-password`.
+    #[arg(long, env = "FX_PASSWORD_HASH", value_parser = fx_auth::parse_password_hash)]
+    pub password_hash: Option<String>,
+    /// Secret from which the cookie encryption key is derived.
+    ///
+    /// When not set, a random secret is generated and stored in the database.
+    #[arg(long, env = "FX_SECRET")]
+    pub secret: Option<String>,
     /// The domain name of the website.
     #[arg(long, env = "FX_DOMAIN", default_value = "")]
     pub domain: String,
@@ -37,6 +65,55 @@ pub struct ServeArgs {
     /// The logging level.
     #[arg(long, env = "FX_LOG_LEVEL", default_value = "info")]
     pub log_level: String,
+    /// Comma-separated IPs or CIDR ranges of reverse proxies whose
+    /// `X-Forwarded-For` header is used to determine the client IP.
+    #[arg(
+        long,
+        env = "FX_TRUSTED_PROXIES",
+        value_delimiter = ',',
+        value_parser = throttle::parse_trusted_proxy
+    )]
+    pub trusted_proxies: Vec<ipnet::IpNet>,
+
+    /// How the Content-Security-Policy is applied.
+    #[arg(long, env = "FX_CSP", value_enum, default_value = "enforce")]
+    pub csp: csp::Mode,
+
+    /// Log policy violations that browsers report to `/csp-report`.
+    #[arg(long, env = "FX_CSP_REPORT")]
+    pub csp_report: bool,
+
+    /// Comma-separated encodings for compressing responses in order of
+    /// preference. Set to `identity` to disable compression.
+    #[arg(
+        long,
+        env = "FX_COMPRESSION",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "br,zstd,gzip"
+    )]
+    pub compression: Vec<compression::Encoding>,
+
+    /// Responses smaller than this number of bytes are not compressed.
+    #[arg(long, env = "FX_COMPRESSION_MIN_SIZE", default_value = "1024")]
+    pub compression_min_size: usize,
+
+    /// Responses larger than this number of bytes are not compressed.
+    #[arg(long, env = "FX_COMPRESSION_MAX_SIZE", default_value = "10485760")]
+    pub compression_max_size: usize,
+
+    /// Number of posts in the feeds. Older posts are in archive pages.
+    #[arg(long, env = "FX_FEED_SIZE", default_value = "20")]
+    pub feed_size: usize,
+
+    /// Maximum size of uploaded files in megabytes.
+    #[arg(long, env = "FX_MAX_UPLOAD_SIZE", default_value = "100")]
+    pub max_upload_size: u64,
+
+    /// Render math with KaTeX in the browser instead of only showing the
+    /// MathML that is generated on the server.
+    #[arg(long, env = "FX_KATEX")]
+    pub katex: bool,
 
     /// The token for triggering GitHub Actions.
     #[arg(long, env = "FX_TRIGGER_TOKEN")]
@@ -1,6 +1,7 @@
 use clap::Parser;
 use fx::ServeArgs;
 use fx::health::HealthArgs;
+use std::io::IsTerminal;
 use tracing::Level;
 use tracing::subscriber::SetGlobalDefaultError;
 
@@ -9,6 +10,8 @@ use tracing::subscriber::SetGlobalDefaultError;
 enum Task {
     /// Run a health check on the given port.
     CheckHealth(HealthArgs),
+    /// Prompt for a password and print its hash for `FX_PASSWORD_HASH`.
+    HashPassword,
     /// Print the project's license.
     License,
     /// Start the server.
@@ -28,6 +31,21 @@ struct Args {
     task: Task,
 }
 
+/// Read the password from the terminal or, when piped, from stdin.
+fn read_password() -> std::io::Result<String> {
+    if !std::io::stdin().is_terminal() {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+    let password = rpassword::prompt_password("Password: ")?;
+    let confirmation = rpassword::prompt_password("Repeat password: ")?;
+    if password != confirmation {
+        return Err(std::io::Error::other("passwords do not match"));
+    }
+    Ok(password)
+}
+
 /// Initialize logging with the given level.
 pub fn init_subscriber(level: Level, ansi: bool) -> Result<(), SetGlobalDefaultError> {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
@@ -49,6 +67,20 @@ async fn main() {
         Task::CheckHealth(args) => {
             fx::health::check_health(args).await;
         }
+        Task::HashPassword => {
+            let password = match read_password() {
+                Ok(password) => password,
+                Err(e) => {
+                    eprintln!("Failed to read password: {e}");
+                    std::process::exit(1);
+                }
+            };
+            if password.is_empty() {
+                eprintln!("Password cannot be empty");
+                std::process::exit(1);
+            }
+            println!("{}", fx_auth::hash_password(&password));
+        }
         Task::License => {
             let license_content = include_str!("../../LICENSE");
             println!("{}", license_content);
@@ -0,0 +1,985 @@
+//! Conversion of LaTeX math to MathML while rendering.
+//!
+//! Browsers can display MathML without any scripts, so math is readable in
+//! feed readers and without JavaScript. Only the subset of LaTeX that is
+//! commonly used in posts is supported. When an expression uses something
+//! else, it is left as a `language-math` code element so that KaTeX can still
+//! render it in the browser.
+//!
+//! The LaTeX source is kept in an `annotation` element. This allows KaTeX to
+//! re-render the expressions when it is enabled.
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// What kind of element a parsed atom is.
+///
+/// Needed to decide how scripts are placed.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Normal,
+    /// Operators such as `\sum` whose limits go below and above in display
+    /// mode.
+    Limits,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// The `mathvariant` set by commands such as `\mathbf`.
+    variant: Option<&'static str>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(html: &str) -> String {
+    html.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+fn mrow(children: Vec<String>) -> String {
+    if children.len() == 1 {
+        children.into_iter().next().unwrap()
+    } else {
+        format!("<mrow>{}</mrow>", children.concat())
+    }
+}
+
+fn mo(op: &str) -> String {
+    format!("<mo>{}</mo>", escape(op))
+}
+
+fn greek(name: &str) -> Option<&'static str> {
+    let letter = match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" => "ϵ",
+        "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "vartheta" => "ϑ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "varpi" => "ϖ",
+        "rho" => "ρ",
+        "varrho" => "ϱ",
+        "sigma" => "σ",
+        "varsigma" => "ς",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" => "ϕ",
+        "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        _ => return None,
+    };
+    Some(letter)
+}
+
+/// Symbols that are identifiers rather than operators.
+fn identifier(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "emptyset" | "varnothing" => "∅",
+        "hbar" => "ℏ",
+        "ell" => "ℓ",
+        "Re" => "ℜ",
+        "Im" => "ℑ",
+        "aleph" => "ℵ",
+        "angle" => "∠",
+        "triangle" => "△",
+        "top" => "⊤",
+        "bot" => "⊥",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+fn operator(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        "cdot" => "⋅",
+        "times" => "×",
+        "div" => "÷",
+        "pm" => "±",
+        "mp" => "∓",
+        "ast" => "∗",
+        "star" => "⋆",
+        "circ" => "∘",
+        "bullet" => "∙",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "leq" | "le" => "≤",
+        "geq" | "ge" => "≥",
+        "neq" | "ne" => "≠",
+        "ll" => "≪",
+        "gg" => "≫",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "cong" => "≅",
+        "propto" => "∝",
+        "perp" => "⊥",
+        "parallel" => "∥",
+        "mid" => "∣",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "forall" => "∀",
+        "exists" => "∃",
+        "neg" | "lnot" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" => "⇔",
+        "implies" => "⟹",
+        "iff" => "⟺",
+        "mapsto" => "↦",
+        "uparrow" => "↑",
+        "downarrow" => "↓",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "prime" => "′",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "vert" | "lvert" | "rvert" => "|",
+        "Vert" | "lVert" | "rVert" | "|" => "‖",
+        "{" | "lbrace" => "{",
+        "}" | "rbrace" => "}",
+        "colon" => ":",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+/// Operators that take limits below and above them.
+fn large_operator(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+fn integral(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+fn function(name: &str) -> Option<Kind> {
+    let kind = match name {
+        "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "arcsin" | "arccos" | "arctan" | "sinh"
+        | "cosh" | "tanh" | "log" | "ln" | "lg" | "exp" | "dim" | "ker" | "deg" | "arg" | "hom" => {
+            Kind::Normal
+        }
+        "lim" | "liminf" | "limsup" | "max" | "min" | "sup" | "inf" | "det" | "gcd" | "Pr" => {
+            Kind::Limits
+        }
+        _ => return None,
+    };
+    Some(kind)
+}
+
+fn space(name: &str) -> Option<&'static str> {
+    let width = match name {
+        "," => "0.1667em",
+        ":" | ">" => "0.2222em",
+        ";" => "0.2778em",
+        " " => "0.3333em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        "!" => "-0.1667em",
+        _ => return None,
+    };
+    Some(width)
+}
+
+fn accent(name: &str) -> Option<(&'static str, bool)> {
+    // The boolean says whether the accent is placed above the argument.
+    let accent = match name {
+        "hat" => ("^", true),
+        "widehat" => ("^", true),
+        "bar" | "overline" => ("‾", true),
+        "vec" => ("→", true),
+        "dot" => ("˙", true),
+        "ddot" => ("¨", true),
+        "tilde" | "widetilde" => ("~", true),
+        "overbrace" => ("⏞", true),
+        "underline" => ("_", false),
+        "underbrace" => ("⏟", false),
+        _ => return None,
+    };
+    Some(accent)
+}
+
+fn variant(name: &str) -> Option<&'static str> {
+    let variant = match name {
+        "mathrm" | "rm" => "normal",
+        "mathbf" | "bf" | "boldsymbol" => "bold",
+        "mathit" => "italic",
+        "mathbb" => "double-struck",
+        "mathcal" => "script",
+        "mathfrak" => "fraktur",
+        "mathsf" => "sans-serif",
+        "mathtt" => "monospace",
+        _ => return None,
+    };
+    Some(variant)
+}
+
+fn big(name: &str) -> Option<&'static str> {
+    let size = match name {
+        "big" | "bigl" | "bigr" | "bigm" => "1.2em",
+        "Big" | "Bigl" | "Bigr" | "Bigm" => "1.623em",
+        "bigg" | "biggl" | "biggr" | "biggm" => "2.047em",
+        "Bigg" | "Biggl" | "Biggr" | "Biggm" => "2.470em",
+        _ => return None,
+    };
+    Some(size)
+}
+
+/// Delimiters around environments such as `pmatrix`.
+fn environment_delimiters(name: &str) -> Option<(&'static str, &'static str)> {
+    let delimiters = match name {
+        "matrix" | "smallmatrix" | "aligned" | "align" | "align*" | "split" | "gathered"
+        | "gather" | "gather*" | "array" => ("", ""),
+        "pmatrix" => ("(", ")"),
+        "bmatrix" => ("[", "]"),
+        "Bmatrix" => ("{", "}"),
+        "vmatrix" => ("|", "|"),
+        "Vmatrix" => ("‖", "‖"),
+        "cases" => ("{", ""),
+        _ => return None,
+    };
+    Some(delimiters)
+}
+
+fn fenced(open: &str, content: String, close: &str) -> String {
+    let open = if open.is_empty() {
+        String::new()
+    } else {
+        format!("<mo fence=\"true\" form=\"prefix\">{}</mo>", escape(open))
+    };
+    let close = if close.is_empty() {
+        String::new()
+    } else {
+        format!("<mo fence=\"true\" form=\"postfix\">{}</mo>", escape(close))
+    };
+    format!("<mrow>{open}{content}{close}</mrow>")
+}
+
+impl Parser {
+    fn new(tex: &str) -> Self {
+        Self {
+            chars: tex.chars().collect(),
+            pos: 0,
+            variant: None,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '%' {
+                // Comments run until the end of the line.
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else if c.is_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Name of the command at the current position without the backslash.
+    fn peek_command(&self) -> Option<String> {
+        if self.peek() != Some('\\') {
+            return None;
+        }
+        let rest = &self.chars[self.pos + 1..];
+        match rest.first() {
+            Some(c) if c.is_ascii_alphabetic() => Some(
+                rest.iter()
+                    .take_while(|c| c.is_ascii_alphabetic())
+                    .collect(),
+            ),
+            Some(c) => Some(c.to_string()),
+            None => Some(String::new()),
+        }
+    }
+
+    fn read_command(&mut self) -> Option<String> {
+        let name = self.peek_command()?;
+        self.pos += 1 + name.chars().count();
+        Some(name)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected '{expected}' at position {}", self.pos))
+        }
+    }
+
+    /// Text between braces, such as the argument of `\text`.
+    fn read_braced_text(&mut self) -> Result<String> {
+        self.expect('{')?;
+        let mut depth = 0;
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Ok(text),
+                '}' => depth -= 1,
+                _ => {}
+            }
+            text.push(c);
+        }
+        Err("unterminated group".to_string())
+    }
+
+    fn at_end_of_list(&mut self) -> bool {
+        self.skip_whitespace();
+        match self.peek() {
+            None | Some('}') | Some('&') => true,
+            Some('\\') => matches!(
+                self.peek_command().as_deref(),
+                Some("\\") | Some("end") | Some("right") | Some("")
+            ),
+            _ => false,
+        }
+    }
+
+    /// Parse atoms until the end of the current group, cell, or fence.
+    fn parse_list(&mut self) -> Result<Vec<String>> {
+        let mut children = Vec::new();
+        while !self.at_end_of_list() {
+            if let Some(child) = self.parse_scripted()? {
+                children.push(child);
+            }
+        }
+        Ok(children)
+    }
+
+    /// Argument of a command or script, which is either a group or a single
+    /// token.
+    fn parse_argument(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let children = self.parse_list()?;
+                self.expect('}')?;
+                Ok(mrow(children))
+            }
+            None => Err("missing argument".to_string()),
+            _ => match self.parse_atom(true)? {
+                Some((atom, _)) => Ok(atom),
+                None => Err(format!("missing argument at position {}", self.pos)),
+            },
+        }
+    }
+
+    fn parse_scripted(&mut self) -> Result<Option<String>> {
+        let Some((base, kind)) = self.parse_atom(false)? else {
+            return Ok(None);
+        };
+        let mut sub = None;
+        let mut sup = None;
+        let mut primes = String::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.parse_argument()?);
+                }
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.parse_argument()?);
+                }
+                Some('\'') => {
+                    self.pos += 1;
+                    primes.push('′');
+                }
+                Some('\\')
+                    if matches!(
+                        self.peek_command().as_deref(),
+                        Some("limits") | Some("nolimits")
+                    ) =>
+                {
+                    self.read_command();
+                }
+                _ => break,
+            }
+        }
+        if !primes.is_empty() {
+            let primes = mo(&primes);
+            sup = Some(match sup {
+                Some(sup) => format!("<mrow>{primes}{sup}</mrow>"),
+                None => primes,
+            });
+        }
+        let (under, over, both) = match kind {
+            Kind::Limits => ("munder", "mover", "munderover"),
+            Kind::Normal => ("msub", "msup", "msubsup"),
+        };
+        let element = match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{under}>{base}{sub}</{under}>"),
+            (None, Some(sup)) => format!("<{over}>{base}{sup}</{over}>"),
+            (Some(sub), Some(sup)) => format!("<{both}>{base}{sub}{sup}</{both}>"),
+        };
+        Ok(Some(element))
+    }
+
+    fn identifier(&self, text: &str) -> String {
+        match self.variant {
+            Some(variant) => format!("<mi mathvariant=\"{variant}\">{}</mi>", escape(text)),
+            None => format!("<mi>{}</mi>", escape(text)),
+        }
+    }
+
+    /// Parse a single atom.
+    ///
+    /// Returns `None` for things that produce no output, such as
+    /// `\displaystyle`. When `single` is set, numbers are read one digit at a
+    /// time like TeX does for script arguments.
+    fn parse_atom(&mut self, single: bool) -> Result<Option<(String, Kind)>> {
+        self.skip_whitespace();
+        let Some(c) = self.peek() else {
+            return Err("unexpected end".to_string());
+        };
+        let atom = match c {
+            '\\' => return self.parse_command(),
+            '{' => {
+                self.pos += 1;
+                let children = self.parse_list()?;
+                self.expect('}')?;
+                mrow(children)
+            }
+            '^' | '_' => return Err(format!("double script at position {}", self.pos)),
+            '~' => {
+                self.pos += 1;
+                "<mspace width=\"0.3333em\"></mspace>".to_string()
+            }
+            '#' | '$' => return Err(format!("unexpected '{c}'")),
+            c if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self.peek() {
+                    let is_decimal = c == '.'
+                        && !number.is_empty()
+                        && self
+                            .chars
+                            .get(self.pos + 1)
+                            .is_some_and(|c| c.is_ascii_digit());
+                    if !(c.is_ascii_digit() || is_decimal) {
+                        break;
+                    }
+                    number.push(c);
+                    self.pos += 1;
+                    if single {
+                        break;
+                    }
+                }
+                format!("<mn>{number}</mn>")
+            }
+            c if c.is_alphabetic() => {
+                self.pos += 1;
+                self.identifier(&c.to_string())
+            }
+            c => {
+                self.pos += 1;
+                let op = if c == '-' { '−' } else { c };
+                mo(&op.to_string())
+            }
+        };
+        Ok(Some((atom, Kind::Normal)))
+    }
+
+    /// Delimiter after `\left`, `\right`, or `\big`.
+    fn parse_delimiter(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        if let Some(name) = self.read_command() {
+            return match operator(&name) {
+                Some(symbol) => Ok(symbol.to_string()),
+                None => Err(format!("unknown delimiter \\{name}")),
+            };
+        }
+        match self.peek() {
+            Some('.') => {
+                self.pos += 1;
+                Ok(String::new())
+            }
+            Some(c) if "()[]|/<>".contains(c) => {
+                self.pos += 1;
+                let delimiter = match c {
+                    '<' => '⟨',
+                    '>' => '⟩',
+                    c => c,
+                };
+                Ok(delimiter.to_string())
+            }
+            _ => Err(format!("missing delimiter at position {}", self.pos)),
+        }
+    }
+
+    fn parse_command(&mut self) -> Result<Option<(String, Kind)>> {
+        let name = self.read_command().unwrap();
+        let name = name.as_str();
+        if let Some(letter) = greek(name) {
+            let atom = if letter.chars().next().unwrap().is_uppercase() && self.variant.is_none() {
+                format!("<mi mathvariant=\"normal\">{letter}</mi>")
+            } else {
+                self.identifier(letter)
+            };
+            return Ok(Some((atom, Kind::Normal)));
+        }
+        if let Some(symbol) = identifier(name) {
+            return Ok(Some((self.identifier(symbol), Kind::Normal)));
+        }
+        if let Some(symbol) = operator(name) {
+            return Ok(Some((mo(symbol), Kind::Normal)));
+        }
+        if let Some(symbol) = large_operator(name) {
+            let atom = format!("<mo movablelimits=\"true\">{symbol}</mo>");
+            return Ok(Some((atom, Kind::Limits)));
+        }
+        if let Some(symbol) = integral(name) {
+            return Ok(Some((mo(symbol), Kind::Normal)));
+        }
+        if let Some(kind) = function(name) {
+            let atom = match kind {
+                Kind::Limits => format!("<mo movablelimits=\"true\" form=\"prefix\">{name}</mo>"),
+                Kind::Normal => format!("<mi>{name}</mi>"),
+            };
+            return Ok(Some((atom, kind)));
+        }
+        if let Some(width) = space(name) {
+            let atom = format!("<mspace width=\"{width}\"></mspace>");
+            return Ok(Some((atom, Kind::Normal)));
+        }
+        if let Some((symbol, above)) = accent(name) {
+            let base = self.parse_argument()?;
+            let stretchy = if name.starts_with("wide")
+                || name.starts_with("over")
+                || name.starts_with("under")
+            {
+                " stretchy=\"true\""
+            } else {
+                " stretchy=\"false\""
+            };
+            let symbol = escape(symbol);
+            let atom = if above {
+                format!("<mover accent=\"true\">{base}<mo{stretchy}>{symbol}</mo></mover>")
+            } else {
+                format!("<munder accentunder=\"true\">{base}<mo{stretchy}>{symbol}</mo></munder>")
+            };
+            return Ok(Some((atom, Kind::Normal)));
+        }
+        if let Some(variant) = variant(name) {
+            let outer = self.variant.replace(variant);
+            let argument = self.parse_argument();
+            self.variant = outer;
+            return Ok(Some((argument?, Kind::Normal)));
+        }
+        if let Some(size) = big(name) {
+            let delimiter = escape(&self.parse_delimiter()?);
+            let atom = format!(
+                "<mo fence=\"false\" stretchy=\"true\" minsize=\"{size}\" maxsize=\"{size}\">\
+                {delimiter}</mo>"
+            );
+            return Ok(Some((atom, Kind::Normal)));
+        }
+        let atom = match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.parse_argument()?;
+                let denominator = self.parse_argument()?;
+                format!("<mfrac>{numerator}{denominator}</mfrac>")
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let n = self.parse_argument()?;
+                let k = self.parse_argument()?;
+                let fraction = format!("<mfrac linethickness=\"0\">{n}{k}</mfrac>");
+                fenced("(", fraction, ")")
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                if self.peek() == Some('[') {
+                    self.pos += 1;
+                    let mut index = Vec::new();
+                    while {
+                        self.skip_whitespace();
+                        self.peek().is_some_and(|c| c != ']')
+                    } {
+                        if let Some(child) = self.parse_scripted()? {
+                            index.push(child);
+                        }
+                    }
+                    self.expect(']')?;
+                    let radicand = self.parse_argument()?;
+                    format!("<mroot>{radicand}{}</mroot>", mrow(index))
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_argument()?)
+                }
+            }
+            "text" | "textrm" | "textnormal" | "textit" | "textbf" | "mbox" => {
+                // Spaces at the edges would otherwise be collapsed.
+                let text = self.read_braced_text()?.replace(' ', "\u{a0}");
+                format!("<mtext>{}</mtext>", escape(&text))
+            }
+            "operatorname" => {
+                let text = self.read_braced_text()?;
+                format!("<mi>{}</mi>", escape(text.trim()))
+            }
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let content = self.parse_list()?;
+                if self.read_command().as_deref() != Some("right") {
+                    return Err("\\left without \\right".to_string());
+                }
+                let close = self.parse_delimiter()?;
+                fenced(&open, content.concat(), &close)
+            }
+            "middle" => {
+                let delimiter = escape(&self.parse_delimiter()?);
+                format!("<mo fence=\"true\" stretchy=\"true\">{delimiter}</mo>")
+            }
+            "begin" => self.parse_environment()?,
+            "displaystyle" | "textstyle" | "limits" | "nolimits" | "nonumber" | "notag" => {
+                return Ok(None);
+            }
+            _ => return Err(format!("unsupported command \\{name}")),
+        };
+        Ok(Some((atom, Kind::Normal)))
+    }
+
+    fn parse_environment(&mut self) -> Result<String> {
+        let name = self.read_braced_text()?;
+        let Some((open, close)) = environment_delimiters(&name) else {
+            return Err(format!("unsupported environment {name}"));
+        };
+        let aligned = matches!(name.as_str(), "aligned" | "align" | "align*" | "split");
+        let columns = if name == "array" {
+            let spec = self.read_braced_text()?;
+            spec.chars()
+                .filter_map(|c| match c {
+                    'l' => Some("left"),
+                    'c' => Some("center"),
+                    'r' => Some("right"),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        } else if aligned {
+            vec!["right", "left"]
+        } else if name == "cases" {
+            vec!["left", "left"]
+        } else {
+            vec!["center"]
+        };
+        let mut rows: Vec<Vec<String>> = vec![vec![]];
+        loop {
+            let mut cell = self.parse_list()?;
+            let row = rows.last_mut().unwrap();
+            // Relations at the start of a cell, such as in `&= x`, need
+            // something on the left to get the spacing of a binary relation.
+            if aligned && row.len() % 2 == 1 {
+                cell.insert(0, "<mi></mi>".to_string());
+            }
+            row.push(format!("<mtd>{}</mtd>", cell.concat()));
+            match self.peek() {
+                Some('&') => {
+                    self.pos += 1;
+                }
+                Some('\\') => match self.read_command().as_deref() {
+                    Some("\\") => rows.push(vec![]),
+                    Some("end") => {
+                        let end = self.read_braced_text()?;
+                        if end != name {
+                            return Err(format!("\\begin{{{name}}} ended by \\end{{{end}}}"));
+                        }
+                        break;
+                    }
+                    _ => return Err(format!("unexpected command in {name}")),
+                },
+                _ => return Err(format!("unterminated environment {name}")),
+            }
+        }
+        // A trailing `\\` does not start a new row.
+        if rows.len() > 1 && rows.last().unwrap().concat() == "<mtd></mtd>" {
+            rows.pop();
+        }
+        let rows = rows
+            .into_iter()
+            .map(|cells| format!("<mtr>{}</mtr>", cells.concat()))
+            .collect::<String>();
+        let align = if columns.len() == 1 {
+            String::new()
+        } else {
+            format!(" columnalign=\"{}\"", columns.join(" "))
+        };
+        let style = if aligned || name.starts_with("gather") {
+            " displaystyle=\"true\""
+        } else {
+            ""
+        };
+        let table = format!("<mtable{align}{style}>{rows}</mtable>");
+        Ok(if open.is_empty() && close.is_empty() {
+            table
+        } else {
+            fenced(open, table, close)
+        })
+    }
+}
+
+/// Convert LaTeX math to a MathML element.
+pub fn to_mathml(tex: &str, display: bool) -> Result<String> {
+    let mut parser = Parser::new(tex);
+    let children = parser.parse_list()?;
+    if parser.peek().is_some() {
+        return Err(format!("unexpected input at position {}", parser.pos));
+    }
+    let display = if display { " display=\"block\"" } else { "" };
+    let annotation = escape(tex.trim());
+    Ok(format!(
+        "<math{display}><semantics><mrow>{}</mrow>\
+        <annotation encoding=\"application/x-tex\">{annotation}</annotation>\
+        </semantics></math>",
+        children.concat()
+    ))
+}
+
+/// MathML for the math or a code element for KaTeX when it cannot be
+/// converted.
+pub fn math_html(tex: &str, display: bool) -> String {
+    match to_mathml(tex, display) {
+        Ok(mathml) => mathml,
+        Err(e) => {
+            tracing::debug!("failed to convert math to MathML: {e}");
+            let tex = escape(tex);
+            if display {
+                format!("<pre><code class=\"language-math math-display\">{tex}</code></pre>")
+            } else {
+                format!("<code class=\"language-math math-inline\">{tex}</code>")
+            }
+        }
+    }
+}
+
+/// Math code elements, optionally in a `pre` element for display math.
+static MATH: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?s)(<pre>\s*)?<code class="language-math( math-(inline|display))?">(.*?)</code>(\s*</pre>)?"#,
+    )
+    .unwrap()
+});
+
+/// Replace the math code elements in HTML produced by the Markdown renderer.
+///
+/// Expressions that cannot be converted are left unchanged.
+pub fn render_html(html: &str) -> String {
+    MATH.replace_all(html, |caps: &regex::Captures| {
+        let display = caps.get(1).is_some() || caps.get(3).is_some_and(|m| m.as_str() == "display");
+        let tex = unescape(&caps[4]);
+        match to_mathml(&tex, display) {
+            Ok(mathml) => mathml,
+            Err(e) => {
+                tracing::debug!("failed to convert math to MathML: {e}");
+                caps[0].to_string()
+            }
+        }
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn inner(tex: &str) -> String {
+        let mathml = to_mathml(tex, false).unwrap();
+        let start = "<math><semantics><mrow>";
+        let end = mathml.find("<annotation").unwrap();
+        mathml[start.len()..end - "</mrow>".len()].to_string()
+    }
+
+    #[test]
+    fn test_basic() {
+        assert_eq!(
+            inner("E = mc^2"),
+            "<mi>E</mi><mo>=</mo><mi>m</mi><msup><mi>c</mi><mn>2</mn></msup>"
+        );
+        assert_eq!(inner("x_{12}"), "<msub><mi>x</mi><mn>12</mn></msub>");
+        assert_eq!(inner("x^23"), "<msup><mi>x</mi><mn>2</mn></msup><mn>3</mn>");
+        assert_eq!(inner("3.14"), "<mn>3.14</mn>");
+        assert_eq!(inner("a < b"), "<mi>a</mi><mo>&lt;</mo><mi>b</mi>");
+        assert_eq!(inner("f'"), "<msup><mi>f</mi><mo>′</mo></msup>");
+        assert!(inner("a - b").contains("<mo>−</mo>"));
+    }
+
+    #[test]
+    fn test_fractions() {
+        assert_eq!(
+            inner(r"\frac{a+1}{2}"),
+            "<mfrac><mrow><mi>a</mi><mo>+</mo><mn>1</mn></mrow><mn>2</mn></mfrac>"
+        );
+        assert_eq!(inner(r"\frac12"), "<mfrac><mn>1</mn><mn>2</mn></mfrac>");
+        assert_eq!(
+            inner(r"\frac{\partial f}{\partial x}"),
+            "<mfrac><mrow><mi>∂</mi><mi>f</mi></mrow><mrow><mi>∂</mi><mi>x</mi></mrow></mfrac>"
+        );
+        assert_eq!(inner(r"\sqrt[3]{x}"), "<mroot><mi>x</mi><mn>3</mn></mroot>");
+        assert!(inner(r"\binom{n}{k}").contains("<mfrac linethickness=\"0\">"));
+    }
+
+    #[test]
+    fn test_matrices() {
+        let expected = "<mrow><mo fence=\"true\" form=\"prefix\">(</mo><mtable>\
+            <mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr>\
+            <mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr>\
+            </mtable><mo fence=\"true\" form=\"postfix\">)</mo></mrow>";
+        assert_eq!(
+            inner(r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}"),
+            expected
+        );
+        // A trailing row separator is ignored.
+        assert_eq!(
+            inner(r"\begin{pmatrix} a & b \\ c & d \\ \end{pmatrix}"),
+            expected
+        );
+        assert!(inner(r"\begin{bmatrix} 1 \end{bmatrix}").contains(">[</mo>"));
+        let cases = inner(r"|x| = \begin{cases} x & x \geq 0 \\ -x & \text{otherwise} \end{cases}");
+        assert!(cases.contains("<mtable columnalign=\"left left\">"));
+        assert!(cases.contains("<mtext>otherwise</mtext>"));
+        assert!(to_mathml(r"\begin{pmatrix} a \end{bmatrix}", false).is_err());
+    }
+
+    #[test]
+    fn test_aligned() {
+        let tex = indoc::indoc! {r"
+            \begin{aligned}
+            f(x) &= (x + 1)^2 \\
+                 &= x^2 + 2x + 1
+            \end{aligned}
+        "};
+        let mathml = inner(tex);
+        assert!(mathml.starts_with(
+            "<mtable columnalign=\"right left\" displaystyle=\"true\"><mtr><mtd><mi>f</mi>"
+        ));
+        assert!(mathml.contains("<mtd><mi></mi><mo>=</mo><msup>"));
+        assert_eq!(mathml.matches("<mtr>").count(), 2);
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            inner(r"\sum_{i=1}^n i"),
+            "<munderover><mo movablelimits=\"true\">∑</mo>\
+            <mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>i</mi>"
+        );
+        assert_eq!(
+            inner(r"\int_0^1"),
+            "<msubsup><mo>∫</mo><mn>0</mn><mn>1</mn></msubsup>"
+        );
+        assert_eq!(
+            inner(r"\mathbb{R}"),
+            "<mi mathvariant=\"double-struck\">R</mi>"
+        );
+        assert_eq!(
+            inner(r"\alpha\Omega"),
+            "<mi>α</mi><mi mathvariant=\"normal\">Ω</mi>"
+        );
+        assert!(inner(r"\left( \frac{1}{2} \right)").starts_with("<mrow><mo fence"));
+        assert_eq!(inner(r"\text{a b}"), "<mtext>a\u{a0}b</mtext>");
+        assert!(to_mathml(r"\unknown", false).is_err());
+        assert!(to_mathml(r"x^", false).is_err());
+        assert!(to_mathml(r"{x", false).is_err());
+        assert!(to_mathml(r"x}", false).is_err());
+    }
+
+    #[test]
+    fn test_render_html() {
+        let html = "<p>Let <code class=\"language-math math-inline\">a &lt; b</code>.</p>";
+        let rendered = render_html(html);
+        assert!(rendered.starts_with("<p>Let <math><semantics>"));
+        assert!(rendered.contains("<mo>&lt;</mo>"));
+        assert!(rendered.contains(">a &lt; b</annotation>"));
+
+        let html = "<pre><code class=\"language-math math-display\">x^2\n</code></pre>";
+        assert!(render_html(html).starts_with("<math display=\"block\">"));
+
+        // Left for KaTeX.
+        let html = "<code class=\"language-math math-inline\">\\unknown</code>";
+        assert_eq!(render_html(html), html);
+        assert_eq!(math_html("\\unknown", false), html);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::data::Post;
+use crate::files::FileIndex;
 use markdown::Options;
 use markdown::ParseOptions;
 use markdown::mdast::Node;
@@ -11,7 +12,7 @@ pub fn markdown_link() -> &'static str {
 /// Convert a Markdown AST node back to a `String` with the same structure.
 ///
 /// The default `to_string()` method only returns text.
-fn node_to_html(node: &Node) -> String {
+fn node_to_html(node: &Node, index: &FileIndex) -> String {
     // Maybe this method should be rewritten to return Markdown or use some
     // internal logic from the `markdown` crate for the preview. I think the
     // reason that this part is converting to HTML now is that HTML is more
@@ -22,23 +23,22 @@ fn node_to_html(node: &Node) -> String {
     match node {
         Node::Code(code) => {
             let lang = code.lang.clone().unwrap_or("".to_string());
-            let class = if lang.is_empty() {
-                ""
+            let block = if lang.is_empty() {
+                format!("<pre><code >{}</code></pre>", code.value)
             } else {
-                &format!("class=\"language-{lang}\"")
+                crate::highlight::code_block(&code.value, &lang)
             };
             let html = format!(
                 "
-                <pre><code {class}>{}</code></pre>
-                ",
-                code.value
+                {block}
+                "
             );
             preview.push_str(&html);
         }
         Node::Paragraph(paragraph) => {
             preview.push_str("<p>");
             for child in paragraph.children.iter() {
-                let text = node_to_html(child);
+                let text = node_to_html(child, index);
                 preview.push_str(&text);
             }
             preview.push_str("</p>");
@@ -46,7 +46,7 @@ fn node_to_html(node: &Node) -> String {
         Node::Heading(heading) => {
             preview.push_str(&format!("<h{}>", heading.depth));
             for child in heading.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str(&format!("</h{}>", heading.depth));
             preview.push_str("\n\n");
@@ -54,51 +54,48 @@ fn node_to_html(node: &Node) -> String {
         Node::Emphasis(emphasis) => {
             preview.push_str("<em>");
             for child in emphasis.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str("</em>");
         }
         Node::Strong(strong) => {
             preview.push_str("<strong>");
             for child in strong.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str("</strong>");
         }
         Node::Delete(delete) => {
             preview.push_str("<del>");
             for child in delete.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str("</del>");
         }
         Node::Text(text) => preview.push_str(&text.value),
         Node::Html(html) => preview.push_str(&html.value),
         Node::Link(link) => {
-            let text = node_to_html(link.children.first().unwrap());
+            let text = node_to_html(link.children.first().unwrap(), index);
             let url = &link.url;
             preview.push_str(&format!("<a href='{url}'>{text}</a>"));
         }
         Node::Math(math) => {
-            preview.push_str(&format!(
-                r#"
-                <pre><code class="language-math math-display">{}
-                </code></pre>
-                "#,
-                math.value
-            ));
+            // Wrapped in a `div` so that the MathML stays an HTML block when
+            // the preview is parsed as Markdown again for the RSS feed.
+            let math = crate::mathml::math_html(&math.value, true);
+            preview.push_str(&format!("\n<div>{math}</div>\n"));
         }
         Node::Table(table) => {
             preview.push_str("<table>");
             for child in table.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str("</table>");
         }
         Node::TableRow(table_row) => {
             preview.push_str("<tr>");
             for child in table_row.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str("</tr>");
         }
@@ -108,7 +105,7 @@ fn node_to_html(node: &Node) -> String {
         Node::TableCell(table_cell) => {
             preview.push_str("<td>");
             for child in table_cell.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str("</td>");
         }
@@ -116,34 +113,34 @@ fn node_to_html(node: &Node) -> String {
             preview.push_str(&format!("<code>{}</code>", inline_code.value));
         }
         Node::InlineMath(inline_math) => {
-            let value = &inline_math.value;
-            preview.push_str(&format!("<code class=\"language-math\">{value}</code>"));
+            preview.push_str(&crate::mathml::math_html(&inline_math.value, false));
         }
         Node::List(list) => {
             let tag = if list.ordered { "ol" } else { "ul" };
             preview.push_str(&format!("<{tag}>"));
             for child in list.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str(&format!("</{tag}>"));
         }
         Node::ListItem(list_item) => {
             preview.push_str("<li>");
             for child in list_item.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str("</li>");
         }
         Node::Image(image) => {
             let url = &image.url;
             let alt = &image.alt;
-            preview.push_str(&format!("<img src='{url}' alt='{alt}' />"));
+            let img = format!("<img src='{url}' alt='{alt}' />");
+            preview.push_str(&crate::images::render_html(&img, index));
         }
         Node::FootnoteDefinition(_footnote_definition) => {}
         Node::Blockquote(blockquote) => {
             preview.push_str("<blockquote>");
             for child in blockquote.children.iter() {
-                preview.push_str(&node_to_html(child));
+                preview.push_str(&node_to_html(child, index));
             }
             preview.push_str("</blockquote>");
         }
@@ -156,10 +153,9 @@ fn node_to_html(node: &Node) -> String {
 fn parse_options() -> ParseOptions {
     let mut options = ParseOptions::default();
     options.constructs.gfm_table = true;
-    // KaTeX does not exactly match CommonMark, so we parse math to HTML and
-    // then figure out how to render the produced code blocks in Javascript.
-    // Note that this also makes it easy to detect whether a post contains math,
-    // and thus easy to decide whether to load KaTeX.
+    // Math is parsed into code elements with the class `language-math`, which
+    // are then converted to MathML by `crate::mathml`. Expressions that cannot
+    // be converted stay code elements and are rendered by KaTeX.
     options.constructs.math_flow = true;
     options.constructs.math_text = true;
     options.constructs.gfm_footnote_definition = true;
@@ -174,13 +170,16 @@ fn to_html_options() -> Options {
     options
 }
 
-pub fn content_to_html(content: &str) -> String {
+pub fn content_to_html(content: &str, index: &FileIndex) -> String {
     let options = to_html_options();
-    markdown::to_html_with_options(content, &options).unwrap()
+    let html = markdown::to_html_with_options(content, &options).unwrap();
+    let html = crate::mathml::render_html(&html);
+    let html = crate::images::render_html(&html, index);
+    crate::highlight::highlight_html(&html)
 }
 
 /// Prepare post to be shown as preview.
-pub fn preview(post: &mut Post, max_length: usize) {
+pub fn preview(post: &mut Post, max_length: usize, index: &FileIndex) {
     let options = parse_options();
     let tree = to_mdast(&post.content, &options).unwrap();
     let mut preview = String::new();
@@ -201,7 +200,7 @@ pub fn preview(post: &mut Post, max_length: usize) {
             preview.push_str(&expand);
             break;
         }
-        preview.push_str(&node_to_html(node));
+        preview.push_str(&node_to_html(node, index));
     }
     post.content = preview;
 }
@@ -219,7 +218,7 @@ fn test_keep_link() {
         created: chrono::Utc::now(),
         updated: chrono::Utc::now(),
     };
-    preview(&mut post, 600);
+    preview(&mut post, 600, &FileIndex::default());
     let expected = indoc::indoc! {"
         <h1>Title</h1>
 
@@ -259,7 +258,7 @@ fn test_sanitize_preview() {
         created: Utc::now(),
         updated: Utc::now(),
     };
-    preview(&mut post, 600);
+    preview(&mut post, 600, &FileIndex::default());
     println!("post:\n{}", post.content);
     assert!(post.content.contains("Show more"));
     assert!(post.content.contains("<p>Lorem"));
@@ -402,15 +401,13 @@ fn test_extract_slug() {
     assert_eq!(extract_slug(&post), "lorem--ipsum");
 }
 
-/// Used for RSS feed description field.
+/// Used for the content of the feed entries.
 ///
 /// Many readers expect the description to be the full post, see for example,
-/// <https://stackoverflow.com/a/7369487/5056635>.
-pub fn extract_rss_description(post: &Post) -> String {
-    let mut post = post.clone();
-    // Should not truncate the post, but instead implement feed pages.
-    preview(&mut post, 600);
-    content_to_html(&post.content)
+/// <https://stackoverflow.com/a/7369487/5056635>. The feeds only contain the
+/// newest posts, so they do not grow with every post.
+pub fn extract_rss_description(post: &Post, index: &FileIndex) -> String {
+    content_to_html(&post.content, index)
 }
 
 #[cfg(test)]
@@ -437,7 +434,7 @@ mod test {
         };
         let title = extract_html_title(&post);
         assert_eq!(title, "Title");
-        let description = extract_rss_description(&post);
+        let description = extract_rss_description(&post, &FileIndex::default());
         assert_eq!(description, "<h1>Title</h1>\n<p>ipsum</p>");
     }
 }
@@ -0,0 +1,371 @@
+//! Open Graph images for posts.
+//!
+//! Social networks show the `og:image` of a page when a link is shared, so
+//! each post gets a card with the title, the site name, and the author. The
+//! cards are rendered on the server with an embedded font, so they look the
+//! same on every installation.
+//!
+//! Rendering takes a few milliseconds, so the cards are stored in the database
+//! together with a hash of the text on the card. A card is only rendered again
+//! when the text changed.
+use crate::data::Post;
+use crate::serve::ServerContext;
+use crate::serve::content_type;
+use crate::serve::internal_server_error;
+use crate::serve::not_found;
+use crate::serve::response;
+use ab_glyph::Font;
+use ab_glyph::FontRef;
+use ab_glyph::PxScale;
+use ab_glyph::ScaleFont;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::get;
+use image::ImageFormat;
+use image::Rgb;
+use image::RgbImage;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use sha2::Digest;
+
+/// Size that Facebook, LinkedIn, and X recommend for link previews.
+pub const WIDTH: u32 = 1200;
+pub const HEIGHT: u32 = 630;
+
+/// Increase when the layout changes so that the stored cards are rendered
+/// again.
+const LAYOUT_VERSION: u32 = 1;
+
+const FONT: &[u8] = include_bytes!("fonts/DejaVuSans-Bold.ttf");
+
+/// The embedded font at a size in pixels.
+type Scaled<'a> = ab_glyph::PxScaleFont<&'a FontRef<'static>>;
+
+const MARGIN: f32 = 80.0;
+const MAX_TITLE_LINES: usize = 3;
+
+// The colors of the light theme in `style.css`.
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const TEXT: Rgb<u8> = Rgb([2, 17, 34]);
+const HEADER: Rgb<u8> = Rgb([5, 45, 87]);
+const ACCENT: Rgb<u8> = Rgb([57, 144, 246]);
+const MUTED: Rgb<u8> = Rgb([102, 102, 102]);
+
+/// The text on the card of a post.
+#[derive(Debug, PartialEq)]
+pub struct Card {
+    pub title: String,
+    pub site_name: String,
+    pub author: String,
+}
+
+impl Card {
+    pub fn new(conn: &Connection, post: &Post) -> Self {
+        let kv = |key: &str| crate::data::Kv::get_or_empty_string(conn, key);
+        Card {
+            title: crate::md::extract_html_title(post),
+            site_name: kv("site_name"),
+            author: kv("author_name"),
+        }
+    }
+
+    /// Hash of everything that determines the image.
+    pub fn hash(&self) -> String {
+        let mut hasher = sha2::Sha256::new();
+        for text in [&self.title, &self.site_name, &self.author] {
+            // Length prefixes so that moving text between fields changes the
+            // hash.
+            hasher.update(text.len().to_le_bytes());
+            hasher.update(text.as_bytes());
+        }
+        hasher.update(LAYOUT_VERSION.to_le_bytes());
+        hex::encode(&hasher.finalize()[..8])
+    }
+}
+
+fn text_width(font: &Scaled, text: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+/// Break the text into lines that fit in `max_width`.
+///
+/// Words that are wider than a line are broken between characters. Text
+/// that does not fit in `max_lines` is cut off with an ellipsis.
+fn wrap(font: &Scaled, text: &str, max_width: f32, max_lines: usize) -> (Vec<String>, bool) {
+    let fits = |line: &str| text_width(font, line) <= max_width;
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{line} {word}")
+        };
+        if fits(&candidate) {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        for c in word.chars() {
+            line.push(c);
+            if !fits(&line) && 1 < line.chars().count() {
+                line.pop();
+                lines.push(std::mem::replace(&mut line, c.to_string()));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    let truncated = max_lines < lines.len();
+    if truncated {
+        lines.truncate(max_lines);
+        let last = lines.last_mut().unwrap();
+        while !last.is_empty() && !fits(&format!("{last}…")) {
+            last.pop();
+        }
+        *last = format!("{}…", last.trim_end());
+    }
+    (lines, truncated)
+}
+
+/// Draw a line of text with its baseline at `y`.
+fn draw_text(image: &mut RgbImage, font: &Scaled, text: &str, x: f32, y: f32, color: Rgb<u8>) {
+    let mut caret = x;
+    let mut previous = None;
+    for c in text.chars() {
+        let mut glyph = font.scaled_glyph(c);
+        if let Some(previous) = previous {
+            caret += font.kern(previous, glyph.id);
+        }
+        glyph.position = ab_glyph::point(caret, y);
+        caret += font.h_advance(glyph.id);
+        previous = Some(glyph.id);
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i64 + gx as i64;
+            let py = bounds.min.y as i64 + gy as i64;
+            if px < 0 || py < 0 || WIDTH as i64 <= px || HEIGHT as i64 <= py {
+                return;
+            }
+            let pixel = image.get_pixel_mut(px as u32, py as u32);
+            let coverage = coverage.clamp(0.0, 1.0);
+            for i in 0..3 {
+                let blended = pixel[i] as f32 * (1.0 - coverage) + color[i] as f32 * coverage;
+                pixel[i] = blended.round() as u8;
+            }
+        });
+    }
+}
+
+/// Render the card as PNG.
+pub fn render(card: &Card) -> Vec<u8> {
+    let font = FontRef::try_from_slice(FONT).expect("embedded font is valid");
+    let mut image = RgbImage::from_pixel(WIDTH, HEIGHT, BACKGROUND);
+    let max_width = WIDTH as f32 - 2.0 * MARGIN;
+
+    // A bar at the top in the color of the links.
+    for y in 0..16 {
+        for x in 0..WIDTH {
+            image.put_pixel(x, y, ACCENT);
+        }
+    }
+
+    let small = font.as_scaled(PxScale::from(36.0));
+    let (site_name, _) = wrap(&small, &card.site_name, max_width, 1);
+    if let Some(site_name) = site_name.first() {
+        let y = MARGIN + 16.0 + small.ascent();
+        draw_text(&mut image, &small, site_name, MARGIN, y, HEADER);
+    }
+
+    // Long titles get a smaller font before they are cut off.
+    let mut large = font.as_scaled(PxScale::from(72.0));
+    let mut lines = Vec::new();
+    for size in [72.0, 60.0, 52.0] {
+        large = font.as_scaled(PxScale::from(size));
+        let truncated;
+        (lines, truncated) = wrap(&large, &card.title, max_width, MAX_TITLE_LINES);
+        if !truncated {
+            break;
+        }
+    }
+    let line_height = large.height() + large.line_gap() + 8.0;
+    let text_height = line_height * lines.len() as f32;
+    let top = (HEIGHT as f32 - text_height) / 2.0;
+    for (i, line) in lines.iter().enumerate() {
+        let y = top + i as f32 * line_height + large.ascent();
+        draw_text(&mut image, &large, line, MARGIN, y, TEXT);
+    }
+
+    let (author, _) = wrap(&small, &card.author, max_width, 1);
+    if let Some(author) = author.first() {
+        let y = HEIGHT as f32 - MARGIN + small.descent();
+        draw_text(&mut image, &small, author, MARGIN, y, MUTED);
+    }
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageFormat::Png)
+        .expect("encoding PNG in memory does not fail");
+    png.into_inner()
+}
+
+#[test]
+fn test_wrap() {
+    let font = FontRef::try_from_slice(FONT).unwrap();
+    let font = font.as_scaled(PxScale::from(40.0));
+    let (lines, truncated) = wrap(&font, "A short title", 1000.0, 3);
+    assert_eq!(lines, vec!["A short title"]);
+    assert!(!truncated);
+
+    let text = "one two three four five six seven eight nine ten";
+    let (lines, truncated) = wrap(&font, text, 300.0, 10);
+    assert!(1 < lines.len());
+    assert!(!truncated);
+    assert_eq!(lines.join(" "), text);
+    assert!(lines.iter().all(|line| text_width(&font, line) <= 300.0));
+
+    let (lines, truncated) = wrap(&font, text, 300.0, 2);
+    assert_eq!(lines.len(), 2);
+    assert!(truncated);
+    assert!(lines[1].ends_with('…'));
+
+    // Words without spaces, such as URLs, are broken between characters.
+    let (lines, _) = wrap(&font, &"x".repeat(100), 300.0, 10);
+    assert!(1 < lines.len());
+    assert!(lines.iter().all(|line| text_width(&font, line) <= 300.0));
+}
+
+#[test]
+fn test_render() {
+    let card = Card {
+        title: "A title that is long enough to need more than one line on the card".to_string(),
+        site_name: "John's Weblog".to_string(),
+        author: "John".to_string(),
+    };
+    let png = render(&card);
+    let image = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+    assert_eq!((image.width(), image.height()), (WIDTH, HEIGHT));
+    let image = image.to_rgb8();
+    assert_eq!(*image.get_pixel(0, 0), ACCENT);
+    assert_eq!(*image.get_pixel(WIDTH - 1, HEIGHT - 1), BACKGROUND);
+    // Some text was drawn.
+    assert!(image.pixels().any(|pixel| *pixel == TEXT));
+
+    let hash = card.hash();
+    let other = Card {
+        author: "Jane".to_string(),
+        ..card
+    };
+    assert_ne!(hash, other.hash());
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<usize> {
+    let stmt = "
+        CREATE TABLE IF NOT EXISTS og_images (
+            post_id INTEGER PRIMARY KEY,
+            hash TEXT NOT NULL,
+            data BLOB NOT NULL
+        );
+    ";
+    conn.execute(stmt, [])
+}
+
+fn cached(conn: &Connection, post_id: i64, hash: &str) -> rusqlite::Result<Option<Vec<u8>>> {
+    let stmt = "SELECT data FROM og_images WHERE post_id = ? AND hash = ?";
+    conn.query_row(stmt, rusqlite::params![post_id, hash], |row| row.get(0))
+        .optional()
+}
+
+fn store(conn: &Connection, post_id: i64, hash: &str, data: &[u8]) -> rusqlite::Result<usize> {
+    let stmt = "INSERT OR REPLACE INTO og_images (post_id, hash, data) VALUES (?, ?, ?)";
+    conn.execute(stmt, rusqlite::params![post_id, hash, data])
+}
+
+/// Remove the card of a deleted post.
+pub fn delete(conn: &Connection, post_id: i64) -> rusqlite::Result<usize> {
+    conn.execute("DELETE FROM og_images WHERE post_id = ?", [post_id])
+}
+
+/// The meta tags for the card of the post.
+pub fn meta_tags(ctx: &ServerContext, post: &Post, title: &str) -> String {
+    let url = format!("{}/posts/{}/og.png", ctx.base_url(), post.id);
+    let alt = crate::html::escape_single_quote(title);
+    indoc::formatdoc! {"
+        <meta property='og:image' content='{url}'/>
+        <meta property='og:image:type' content='image/png'/>
+        <meta property='og:image:width' content='{WIDTH}'/>
+        <meta property='og:image:height' content='{HEIGHT}'/>
+        <meta property='og:image:alt' content='{alt}'/>
+        <meta name='twitter:card' content='summary_large_image'/>
+        <meta name='twitter:image' content='{url}'/>
+    "}
+}
+
+async fn get_og_image(State(ctx): State<ServerContext>, Path(id): Path<i64>) -> Response<Body> {
+    // The connection is returned to the pool before rendering, which takes a
+    // while.
+    let (card, hash, data) = {
+        let conn = ctx.conn();
+        let post = match Post::get(&conn, id) {
+            Ok(post) if post.content != "<DELETED>" => post,
+            _ => return not_found(State(ctx)).await,
+        };
+        let card = Card::new(&conn, &post);
+        let hash = card.hash();
+        match cached(&conn, id, &hash) {
+            Ok(data) => (card, hash, data),
+            Err(e) => {
+                let msg = format!("Failed to read the cached card: {e}");
+                return internal_server_error(&ctx, &msg).await;
+            }
+        }
+    };
+    let data = match data {
+        Some(data) => data,
+        None => {
+            let data = match tokio::task::spawn_blocking(move || render(&card)).await {
+                Ok(data) => data,
+                Err(e) => {
+                    let msg = format!("Failed to render the card: {e}");
+                    return internal_server_error(&ctx, &msg).await;
+                }
+            };
+            if let Err(e) = store(&ctx.conn(), id, &hash, &data) {
+                let msg = format!("Failed to store the card: {e}");
+                return internal_server_error(&ctx, &msg).await;
+            }
+            data
+        }
+    };
+    let mut headers = HeaderMap::new();
+    content_type(&mut headers, "image/png");
+    crate::conditional::set_etag(&mut headers, &hash);
+    response(StatusCode::OK, headers, data, &ctx)
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/posts/{id}/og.png", get(get_og_image))
+}
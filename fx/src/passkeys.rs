@@ -0,0 +1,930 @@
+//! Passkey (WebAuthn) login.
+//!
+//! Only ES256 keys and the `none` attestation are supported. That is what
+//! passkeys on phones and in password managers use, and attestation is not
+//! needed since there is only one user who registers their own keys.
+//!
+//! Depending on the mode, a passkey either replaces the password or is asked
+//! for after the password.
+use crate::csrf::Csrf;
+use crate::csrf::CsrfForm;
+use crate::csrf::SameOrigin;
+use crate::data::Kv;
+use crate::data::SqliteDateTime;
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::escape_html;
+use crate::html::page;
+use crate::serve::ServerContext;
+use crate::serve::admin_login;
+use crate::serve::is_logged_in;
+use crate::serve::response;
+use crate::serve::response_json;
+use crate::settings::Settings;
+use crate::throttle::ClientIp;
+use crate::throttle::show_ip;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::DateTime;
+use chrono::Utc;
+use ciborium::Value;
+use p256::ecdsa::Signature;
+use p256::ecdsa::VerifyingKey;
+use p256::ecdsa::signature::Verifier;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::Result;
+use rusqlite::params;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Digest;
+use sha2::Sha256;
+use std::io::Cursor;
+use std::time::Instant;
+
+const CHALLENGE_MAX_AGE_SEC: i64 = 5 * 60;
+/// Challenges per purpose that can be outstanding at the same time.
+///
+/// The login options are requested without being logged in, so this limits
+/// how many rows anyone can add. Older challenges are dropped first. The limit
+/// is per purpose so that login requests cannot drop the challenge of a
+/// passkey that is being registered.
+const MAX_CHALLENGES: i64 = 100;
+const MODE_KEY: &str = "passkey_mode";
+
+/// COSE algorithm identifier for ECDSA with SHA-256 on P-256.
+const ES256: i64 = -7;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// A passkey is enough to log in.
+    Passwordless,
+    /// A passkey is asked for after the password.
+    SecondFactor,
+}
+
+impl Mode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Passwordless => "passwordless",
+            Mode::SecondFactor => "second_factor",
+        }
+    }
+    fn get(conn: &Connection) -> Self {
+        match Kv::get_or_empty_string(conn, MODE_KEY).as_str() {
+            "second_factor" => Mode::SecondFactor,
+            _ => Mode::Passwordless,
+        }
+    }
+}
+
+/// Registered passkey.
+#[derive(Clone, Debug)]
+pub struct Passkey {
+    pub id: i64,
+    pub name: String,
+    /// Base64url encoded credential ID as chosen by the authenticator.
+    pub credential_id: String,
+    /// Uncompressed SEC1 encoded P-256 public key.
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+    pub created: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl Passkey {
+    fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS passkeys (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                credential_id TEXT NOT NULL UNIQUE,
+                public_key BLOB NOT NULL,
+                sign_count INTEGER NOT NULL,
+                created DATETIME NOT NULL,
+                last_used DATETIME
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    fn insert(&self, conn: &Connection) -> Result<usize> {
+        let stmt = "
+            INSERT INTO passkeys
+            (name, credential_id, public_key, sign_count, created)
+            VALUES (?, ?, ?, ?, ?);
+        ";
+        let params = params![
+            self.name,
+            self.credential_id,
+            self.public_key,
+            self.sign_count,
+            self.created.to_sqlite(),
+        ];
+        conn.execute(stmt, params)
+    }
+    fn from_row(row: &rusqlite::Row) -> Result<Self> {
+        let created: String = row.get("created")?;
+        let last_used: Option<String> = row.get("last_used")?;
+        Ok(Passkey {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            credential_id: row.get("credential_id")?,
+            public_key: row.get("public_key")?,
+            sign_count: row.get("sign_count")?,
+            created: DateTime::from_sqlite(&created),
+            last_used: last_used.map(|text| DateTime::from_sqlite(&text)),
+        })
+    }
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        let stmt = "
+            SELECT id, name, credential_id, public_key, sign_count, created, last_used
+            FROM passkeys
+            ORDER BY id DESC;
+        ";
+        conn.prepare(stmt)?
+            .query_map([], Self::from_row)?
+            .collect::<Result<Vec<_>, _>>()
+    }
+    fn get(conn: &Connection, credential_id: &str) -> Result<Option<Self>> {
+        let stmt = "
+            SELECT id, name, credential_id, public_key, sign_count, created, last_used
+            FROM passkeys
+            WHERE credential_id = ?;
+        ";
+        conn.prepare(stmt)?
+            .query_row([credential_id], Self::from_row)
+            .optional()
+    }
+    fn touch(conn: &Connection, id: i64, sign_count: u32) -> Result<usize> {
+        let stmt = "UPDATE passkeys SET sign_count = ?, last_used = ? WHERE id = ?";
+        conn.execute(stmt, params![sign_count, Utc::now().to_sqlite(), id])
+    }
+    fn delete(conn: &Connection, id: i64) -> Result<usize> {
+        let stmt = "DELETE FROM passkeys WHERE id = ?";
+        conn.execute(stmt, [id])
+    }
+}
+
+/// Single-use challenge that the authenticator signs.
+struct Challenge;
+
+impl Challenge {
+    fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS webauthn_challenges (
+                challenge TEXT PRIMARY KEY,
+                purpose TEXT NOT NULL,
+                created DATETIME NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    /// Store a new challenge and return it base64url encoded.
+    fn insert(conn: &Connection, purpose: &str) -> Result<String> {
+        let expired = Utc::now() - chrono::Duration::seconds(CHALLENGE_MAX_AGE_SEC);
+        let stmt = "DELETE FROM webauthn_challenges WHERE created < ?";
+        conn.execute(stmt, [expired.to_sqlite()])?;
+        let stmt = "
+            DELETE FROM webauthn_challenges WHERE rowid IN (
+                SELECT rowid FROM webauthn_challenges
+                WHERE purpose = ?
+                ORDER BY created DESC, rowid DESC
+                LIMIT -1 OFFSET ?
+            );
+        ";
+        conn.execute(stmt, params![purpose, MAX_CHALLENGES - 1])?;
+        let challenge = hex::decode(fx_auth::generate_token()).unwrap();
+        let challenge = URL_SAFE_NO_PAD.encode(challenge);
+        let stmt = "
+            INSERT INTO webauthn_challenges (challenge, purpose, created)
+            VALUES (?, ?, ?);
+        ";
+        conn.execute(stmt, params![challenge, purpose, Utc::now().to_sqlite()])?;
+        Ok(challenge)
+    }
+    /// Remove the challenge and return whether it was valid.
+    fn take(conn: &Connection, challenge: &str, purpose: &str) -> Result<bool> {
+        let stmt = "
+            SELECT created FROM webauthn_challenges
+            WHERE challenge = ? AND purpose = ?;
+        ";
+        let created: Option<String> = conn
+            .prepare(stmt)?
+            .query_row([challenge, purpose], |row| row.get("created"))
+            .optional()?;
+        let stmt = "DELETE FROM webauthn_challenges WHERE challenge = ?";
+        conn.execute(stmt, [challenge])?;
+        Ok(match created {
+            Some(created) => {
+                let created = DateTime::from_sqlite(&created);
+                let age = Utc::now().signed_duration_since(created);
+                age.num_seconds() <= CHALLENGE_MAX_AGE_SEC
+            }
+            None => false,
+        })
+    }
+}
+
+pub fn create_tables(conn: &Connection) -> Result<()> {
+    Passkey::create_table(conn)?;
+    Challenge::create_table(conn)?;
+    Ok(())
+}
+
+/// Whether a passkey is asked for after the password.
+pub fn is_second_factor(ctx: &ServerContext) -> bool {
+    let conn = ctx.conn();
+    let has_passkeys = Passkey::list(&conn).is_ok_and(|passkeys| !passkeys.is_empty());
+    has_passkeys && Mode::get(&conn) == Mode::SecondFactor
+}
+
+/// Relying party ID, which is the domain that the passkeys are bound to.
+fn rp_id(ctx: &ServerContext) -> String {
+    let domain = ctx.args.domain.trim().trim_end_matches('/');
+    if domain.is_empty() {
+        "localhost".to_string()
+    } else {
+        domain.to_string()
+    }
+}
+
+/// Origin that the browser reports in the client data.
+fn expected_origin(ctx: &ServerContext) -> String {
+    let base_url = ctx.base_url();
+    if base_url.is_empty() {
+        format!("http://localhost:{}", ctx.args.port)
+    } else {
+        base_url
+    }
+}
+
+type VerifyResult<T> = std::result::Result<T, &'static str>;
+
+fn decode(value: &str) -> VerifyResult<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| "invalid base64url")
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Verify the client data that the browser created.
+fn verify_client_data(
+    ctx: &ServerContext,
+    client_data_json: &[u8],
+    kind: &str,
+) -> VerifyResult<()> {
+    let client_data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| "invalid client data")?;
+    if client_data.kind != kind {
+        return Err("unexpected client data type");
+    }
+    if client_data.origin != expected_origin(ctx) {
+        return Err("unexpected origin");
+    }
+    let purpose = kind.trim_start_matches("webauthn.");
+    match Challenge::take(&ctx.conn(), &client_data.challenge, purpose) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("unknown or expired challenge"),
+        Err(e) => {
+            tracing::error!("failed to take challenge: {e}");
+            Err("unknown or expired challenge")
+        }
+    }
+}
+
+/// Parsed authenticator data.
+#[derive(Debug)]
+struct AuthData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    /// Credential ID and public key, only present after registration.
+    credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+fn parse_auth_data(data: &[u8]) -> VerifyResult<AuthData> {
+    if data.len() < 37 {
+        return Err("authenticator data too short");
+    }
+    let rp_id_hash = data[0..32].to_vec();
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes(data[33..37].try_into().unwrap());
+    let credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // Skip the 16 byte AAGUID.
+        let rest = data.get(37 + 16..).ok_or("attested data too short")?;
+        if rest.len() < 2 {
+            return Err("attested data too short");
+        }
+        let id_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let id = rest.get(2..2 + id_len).ok_or("credential ID too short")?;
+        let key = rest.get(2 + id_len..).ok_or("public key missing")?;
+        // The key is followed by extensions, so only read one CBOR item.
+        let mut cursor = Cursor::new(key);
+        let value: Value =
+            ciborium::de::from_reader(&mut cursor).map_err(|_| "invalid public key")?;
+        Some((id.to_vec(), parse_cose_key(&value)?))
+    } else {
+        None
+    };
+    Ok(AuthData {
+        rp_id_hash,
+        flags,
+        sign_count,
+        credential,
+    })
+}
+
+fn map_get<'a>(map: &'a [(Value, Value)], key: &Value) -> Option<&'a Value> {
+    map.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+/// Convert a COSE key to an uncompressed SEC1 public key.
+fn parse_cose_key(value: &Value) -> VerifyResult<Vec<u8>> {
+    let map = value.as_map().ok_or("public key is not a map")?;
+    let int = |key: i64| map_get(map, &Value::from(key));
+    let kty = int(1).and_then(|v| v.as_integer());
+    let alg = int(3).and_then(|v| v.as_integer());
+    let crv = int(-1).and_then(|v| v.as_integer());
+    if kty != Some(2.into()) || alg != Some(ES256.into()) || crv != Some(1.into()) {
+        return Err("only ES256 passkeys are supported");
+    }
+    let x = int(-2).and_then(|v| v.as_bytes()).ok_or("missing x")?;
+    let y = int(-3).and_then(|v| v.as_bytes()).ok_or("missing y")?;
+    if x.len() != 32 || y.len() != 32 {
+        return Err("invalid coordinates");
+    }
+    let mut key = vec![0x04];
+    key.extend_from_slice(x);
+    key.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&key).map_err(|_| "invalid public key")?;
+    Ok(key)
+}
+
+fn verify_rp(ctx: &ServerContext, auth_data: &AuthData) -> VerifyResult<()> {
+    let expected = Sha256::digest(rp_id(ctx).as_bytes());
+    if auth_data.rp_id_hash != expected.as_slice() {
+        return Err("passkey belongs to another domain");
+    }
+    if auth_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err("user was not present");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct Registration {
+    name: String,
+    client_data_json: String,
+    attestation_object: String,
+}
+
+/// Verify a registration and return the new passkey.
+fn verify_registration(ctx: &ServerContext, registration: &Registration) -> VerifyResult<Passkey> {
+    let client_data_json = decode(&registration.client_data_json)?;
+    verify_client_data(ctx, &client_data_json, "webauthn.create")?;
+    let attestation = decode(&registration.attestation_object)?;
+    let attestation: Value =
+        ciborium::de::from_reader(attestation.as_slice()).map_err(|_| "invalid attestation")?;
+    let attestation = attestation.as_map().ok_or("invalid attestation")?;
+    // The attestation statement is ignored since `none` was requested.
+    let auth_data = map_get(attestation, &Value::from("authData"))
+        .and_then(|v| v.as_bytes())
+        .ok_or("missing authenticator data")?;
+    let auth_data = parse_auth_data(auth_data)?;
+    verify_rp(ctx, &auth_data)?;
+    // Passkeys can replace the password, so they have to verify the user via
+    // a PIN or biometrics.
+    if auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err("user was not verified");
+    }
+    let (credential_id, public_key) = auth_data.credential.ok_or("missing credential")?;
+    let name = registration.name.trim();
+    let name = if name.is_empty() { "Passkey" } else { name };
+    Ok(Passkey {
+        id: 0,
+        name: name.to_string(),
+        credential_id: URL_SAFE_NO_PAD.encode(credential_id),
+        public_key,
+        sign_count: auth_data.sign_count,
+        created: Utc::now(),
+        last_used: None,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct Assertion {
+    id: String,
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+}
+
+/// Verify a login and return the passkey with the new sign count.
+fn verify_assertion(
+    ctx: &ServerContext,
+    assertion: &Assertion,
+    require_user_verification: bool,
+) -> VerifyResult<Passkey> {
+    let client_data_json = decode(&assertion.client_data_json)?;
+    verify_client_data(ctx, &client_data_json, "webauthn.get")?;
+    let id = URL_SAFE_NO_PAD.encode(decode(&assertion.id)?);
+    let mut passkey = match Passkey::get(&ctx.conn(), &id) {
+        Ok(Some(passkey)) => passkey,
+        Ok(None) => return Err("unknown passkey"),
+        Err(e) => {
+            tracing::error!("failed to get passkey: {e}");
+            return Err("unknown passkey");
+        }
+    };
+    let raw_auth_data = decode(&assertion.authenticator_data)?;
+    let auth_data = parse_auth_data(&raw_auth_data)?;
+    verify_rp(ctx, &auth_data)?;
+    if require_user_verification && auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err("user was not verified");
+    }
+    let key = VerifyingKey::from_sec1_bytes(&passkey.public_key)
+        .map_err(|_| "invalid stored public key")?;
+    let signature = decode(&assertion.signature)?;
+    let signature = Signature::from_der(&signature).map_err(|_| "invalid signature")?;
+    let mut signed = raw_auth_data.clone();
+    signed.extend_from_slice(&Sha256::digest(&client_data_json));
+    key.verify(&signed, &signature)
+        .map_err(|_| "invalid signature")?;
+    // Authenticators that do not count always report zero. Otherwise, a
+    // counter that did not increase suggests a cloned authenticator.
+    let counts = auth_data.sign_count != 0 || passkey.sign_count != 0;
+    if counts && auth_data.sign_count <= passkey.sign_count {
+        return Err("sign count did not increase");
+    }
+    passkey.sign_count = auth_data.sign_count;
+    Ok(passkey)
+}
+
+fn json_error(ctx: &ServerContext, status: StatusCode, message: &str) -> Response<Body> {
+    crate::api::error(ctx, status, message)
+}
+
+fn user_id(ctx: &ServerContext) -> String {
+    let hash = Sha256::digest(format!("fx:{}", ctx.args.username).as_bytes());
+    URL_SAFE_NO_PAD.encode(&hash[..16])
+}
+
+async fn post_register_options(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    _: SameOrigin,
+) -> Response<Body> {
+    if !is_logged_in(&ctx, &jar) {
+        return json_error(&ctx, StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+    let conn = ctx.conn();
+    let challenge = match Challenge::insert(&conn, "create") {
+        Ok(challenge) => challenge,
+        Err(e) => {
+            tracing::error!("failed to store challenge: {e}");
+            let msg = "failed to store challenge";
+            return json_error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, msg);
+        }
+    };
+    let exclude = Passkey::list(&conn)
+        .unwrap_or_default()
+        .iter()
+        .map(|passkey| json!({"type": "public-key", "id": passkey.credential_id}))
+        .collect::<Vec<_>>();
+    let site_name = match Settings::from_db(&conn) {
+        Ok(settings) => settings.site_name,
+        Err(_) => "fx".to_string(),
+    };
+    drop(conn);
+    let username = &ctx.args.username;
+    let options = json!({
+        "challenge": challenge,
+        "rp": {"id": rp_id(&ctx), "name": site_name},
+        "user": {"id": user_id(&ctx), "name": username, "displayName": username},
+        "pubKeyCredParams": [{"type": "public-key", "alg": ES256}],
+        "excludeCredentials": exclude,
+        "authenticatorSelection": {
+            "residentKey": "required",
+            "userVerification": "required",
+        },
+        "attestation": "none",
+        "timeout": CHALLENGE_MAX_AGE_SEC * 1000,
+    });
+    response_json(StatusCode::OK, options.to_string(), &ctx)
+}
+
+async fn post_register(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    _: SameOrigin,
+    body: String,
+) -> Response<Body> {
+    if !is_logged_in(&ctx, &jar) {
+        return json_error(&ctx, StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+    let registration = match serde_json::from_str::<Registration>(&body) {
+        Ok(registration) => registration,
+        Err(_) => return json_error(&ctx, StatusCode::BAD_REQUEST, "invalid registration"),
+    };
+    let passkey = match verify_registration(&ctx, &registration) {
+        Ok(passkey) => passkey,
+        Err(msg) => {
+            tracing::warn!("rejected passkey registration: {msg}");
+            return json_error(&ctx, StatusCode::BAD_REQUEST, msg);
+        }
+    };
+    if let Err(e) = passkey.insert(&ctx.conn()) {
+        tracing::error!("failed to store passkey: {e}");
+        let msg = "failed to store passkey";
+        return json_error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, msg);
+    }
+    tracing::warn!("registered passkey {:?}", passkey.name);
+    tracing::info!("\"POST /webauthn/register HTTP/1.1\" 200");
+    response_json(StatusCode::OK, json!({"status": 200}).to_string(), &ctx)
+}
+
+async fn post_login_options(
+    State(ctx): State<ServerContext>,
+    ClientIp(ip): ClientIp,
+    _: SameOrigin,
+) -> Response<Body> {
+    // Counts as a login attempt until the login succeeds. Otherwise, anyone
+    // could request challenges until the pending one of the admin is dropped.
+    let attempt = ctx.login_throttle.lock().await.begin(ip, Instant::now());
+    if let Err(wait) = attempt {
+        tracing::warn!(
+            "rejected passkey options for {} during lockout",
+            show_ip(ip)
+        );
+        let msg = format!(
+            "Too many failed login attempts. Try again in {}.",
+            crate::throttle::show_wait(wait)
+        );
+        return json_error(&ctx, StatusCode::TOO_MANY_REQUESTS, &msg);
+    }
+    let challenge = match Challenge::insert(&ctx.conn(), "get") {
+        Ok(challenge) => challenge,
+        Err(e) => {
+            tracing::error!("failed to store challenge: {e}");
+            let msg = "failed to store challenge";
+            return json_error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, msg);
+        }
+    };
+    // No `allowCredentials` so that the browser offers the discoverable
+    // passkeys for this domain.
+    let options = json!({
+        "challenge": challenge,
+        "rpId": rp_id(&ctx),
+        "userVerification": "required",
+        "timeout": CHALLENGE_MAX_AGE_SEC * 1000,
+    });
+    response_json(StatusCode::OK, options.to_string(), &ctx)
+}
+
+async fn post_login(
+    State(ctx): State<ServerContext>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    jar: CookieJar,
+    _: SameOrigin,
+    body: String,
+) -> Response<Body> {
+    if admin_login(&ctx).is_none() {
+        let msg = "admin password not set";
+        return json_error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, msg);
+    }
+    let mode = Mode::get(&ctx.conn());
+    if mode == Mode::SecondFactor && !fx_auth::is_login_pending(&ctx.key, &jar) {
+        let msg = "log in with the password first";
+        return json_error(&ctx, StatusCode::UNAUTHORIZED, msg);
+    }
+    let now = Instant::now();
+    let attempt = ctx.login_throttle.lock().await.begin(ip, now);
+    let failures = match attempt {
+        Ok(failures) => failures,
+        Err(wait) => {
+            tracing::warn!("rejected passkey from {} during lockout", show_ip(ip));
+            let msg = format!(
+                "Too many failed login attempts. Try again in {}.",
+                crate::throttle::show_wait(wait)
+            );
+            return json_error(&ctx, StatusCode::TOO_MANY_REQUESTS, &msg);
+        }
+    };
+    let assertion = match serde_json::from_str::<Assertion>(&body) {
+        Ok(assertion) => assertion,
+        Err(_) => return json_error(&ctx, StatusCode::BAD_REQUEST, "invalid assertion"),
+    };
+    // Without the password, the passkey has to verify the user via a PIN or
+    // biometrics.
+    let require_user_verification = mode == Mode::Passwordless;
+    let passkey = match verify_assertion(&ctx, &assertion, require_user_verification) {
+        Ok(passkey) => passkey,
+        Err(msg) => {
+            tracing::warn!(
+                "failed passkey login from {}: {msg}; {failures} consecutive failures",
+                show_ip(ip)
+            );
+            tracing::info!("\"POST /webauthn/login HTTP/1.1\" 401");
+            return json_error(&ctx, StatusCode::UNAUTHORIZED, msg);
+        }
+    };
+    if let Err(e) = Passkey::touch(&ctx.conn(), passkey.id, passkey.sign_count) {
+        tracing::error!("failed to update passkey: {e}");
+    }
+    ctx.login_throttle.lock().await.record_success(ip);
+    tracing::info!(
+        "successful login with passkey {:?} from {}",
+        passkey.name,
+        show_ip(ip)
+    );
+    let jar = match crate::sessions::start(&ctx, &headers, ip, jar) {
+        Ok(jar) => jar,
+        Err(e) => {
+            tracing::error!("failed to start session: {e}");
+            let msg = "failed to start session";
+            return json_error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, msg);
+        }
+    };
+    tracing::info!("\"POST /webauthn/login HTTP/1.1\" 200");
+    let body = json!({"status": 200, "redirect": "/"}).to_string();
+    (jar, response_json(StatusCode::OK, body, &ctx)).into_response()
+}
+
+/// Button that starts the passkey login.
+///
+/// Returns an empty string when no passkey can be used at this point.
+pub fn login_button(ctx: &ServerContext, pending: bool) -> String {
+    let conn = ctx.conn();
+    let has_passkeys = Passkey::list(&conn).is_ok_and(|passkeys| !passkeys.is_empty());
+    let mode = Mode::get(&conn);
+    drop(conn);
+    let usable = match mode {
+        Mode::Passwordless => !pending,
+        Mode::SecondFactor => pending,
+    };
+    if !has_passkeys || !usable {
+        return "".to_string();
+    }
+    let nonce = crate::csp::nonce();
+    let script = crate::assets::url("passkeys.js");
+    format!(
+        "
+        <div style='margin-top: 2vh;'>
+            <button id='passkey-login' type='button'>login with passkey</button>
+            <div id='passkey-error' style='font-style: italic;'></div>
+        </div>
+        <script nonce='{nonce}' src='{script}' defer></script>
+        "
+    )
+}
+
+async fn get_login_passkey(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    let pending = admin_login(&ctx).is_some() && fx_auth::is_login_pending(&ctx.key, &jar);
+    if !pending {
+        return crate::serve::see_other(&ctx, "/login");
+    }
+    let top = Top::Homepage;
+    let description = "Second step of the login";
+    let settings = PageSettings::new("Login", None, Some(description), false, top, "");
+    let body = format!(
+        "
+        <div style='text-align: center; margin-top: 15vh;'>
+            <p>Confirm the login with your passkey.</p>
+            {}
+        </div>
+        ",
+        login_button(&ctx, true)
+    );
+    let body = page(&ctx, &settings, &body).await;
+    tracing::info!("\"GET /login/passkey HTTP/1.1\" 200");
+    response::<String>(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+fn show_passkey(passkey: &Passkey, csrf: &str) -> String {
+    let id = passkey.id;
+    let name = escape_html(&passkey.name);
+    let created = crate::html::show_date(&passkey.created);
+    let last_used = match passkey.last_used {
+        Some(last_used) => format!("last used {}", crate::html::show_date(&last_used)),
+        None => "never used".to_string(),
+    };
+    format!(
+        "
+        <div style='padding: 6px; padding-top: 12px; \
+          border-bottom: 1px solid var(--border); font-size: 0.8rem;'>
+            <div style='display: flex; justify-content: space-between;'>
+                <strong>{name}</strong>
+                <form method='post' action='/settings/passkeys/delete/{id}'>
+                    {csrf}
+                    <button type='submit'>Delete</button>
+                </form>
+            </div>
+            <span>Added {created}, {last_used}.</span>
+        </div>
+        "
+    )
+}
+
+fn mode_form(mode: Mode, csrf: &str) -> String {
+    let option = |value: Mode, label: &str| {
+        let value = value.as_str();
+        let checked = if mode.as_str() == value {
+            "checked"
+        } else {
+            ""
+        };
+        format!(
+            "
+            <input type='radio' id='mode-{value}' name='mode' value='{value}' {checked}/>
+            <label for='mode-{value}'>{label}</label><br>
+            "
+        )
+    };
+    format!(
+        "
+        <form method='post' action='/settings/passkeys/mode' style='margin-top: 5vh;'>
+            {csrf}
+            {}
+            {}
+            <input style='margin-left: 0;' type='submit' value='Save'/>
+        </form>
+        ",
+        option(
+            Mode::Passwordless,
+            "Log in with a passkey instead of the password"
+        ),
+        option(Mode::SecondFactor, "Ask for a passkey after the password"),
+    )
+}
+
+async fn get_passkeys(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    if !is_logged_in(&ctx, &jar) {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let conn = ctx.conn();
+    let passkeys = match Passkey::list(&conn) {
+        Ok(passkeys) => passkeys,
+        Err(e) => {
+            let msg = "Could not get passkeys from database";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    let mode = Mode::get(&conn);
+    drop(conn);
+    let csrf = crate::csrf::input(&ctx, &jar);
+    let passkeys = passkeys
+        .iter()
+        .map(|passkey| show_passkey(passkey, &csrf))
+        .collect::<Vec<_>>()
+        .join("");
+    let body = format!(
+        "
+        <div style='margin-top: 5vh;'>
+            <label for='passkey-name'>Name</label><br>
+            <input type='text' id='passkey-name' placeholder='Phone' \
+              style='width: 100%; margin-left: 0;'/><br>
+            <button id='passkey-register' type='button' style='margin-left: 0;'>
+                Add passkey
+            </button>
+            <div id='passkey-error' style='font-style: italic;'></div>
+        </div>
+        {}
+        <div style='margin-top: 5vh;'>
+            {passkeys}
+        </div>
+        <script nonce='{}' src='{}' defer></script>
+        ",
+        mode_form(mode, &csrf),
+        crate::csp::nonce(),
+        crate::assets::url("passkeys.js")
+    );
+    let settings = PageSettings::new("Passkeys", Some(true), None, false, Top::GoHome, "");
+    let body = page(&ctx, &settings, &body).await;
+    tracing::info!("\"GET /settings/passkeys HTTP/1.1\" 200");
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+#[derive(Debug, Deserialize)]
+struct ModeForm {
+    mode: String,
+}
+
+async fn post_mode(
+    State(ctx): State<ServerContext>,
+    CsrfForm(form): CsrfForm<ModeForm>,
+) -> Response<Body> {
+    let mode = match form.mode.as_str() {
+        "second_factor" => Mode::SecondFactor,
+        _ => Mode::Passwordless,
+    };
+    if let Err(e) = Kv::insert(&ctx.conn(), MODE_KEY, mode.as_str().as_bytes()) {
+        let msg = "Could not save passkey mode";
+        tracing::error!("{msg}: {e}");
+        return crate::serve::internal_server_error(&ctx, msg).await;
+    }
+    tracing::warn!("set passkey mode to {}", mode.as_str());
+    tracing::info!("\"POST /settings/passkeys/mode HTTP/1.1\" 303");
+    crate::serve::see_other(&ctx, "/settings/passkeys")
+}
+
+async fn post_delete(
+    State(ctx): State<ServerContext>,
+    Path(id): Path<i64>,
+    _: Csrf,
+) -> Response<Body> {
+    if let Err(e) = Passkey::delete(&ctx.conn(), id) {
+        let msg = "Could not delete passkey";
+        tracing::error!("{msg}: {e}");
+        return crate::serve::internal_server_error(&ctx, msg).await;
+    }
+    tracing::warn!("deleted passkey {id}");
+    tracing::info!("\"POST /settings/passkeys/delete/{id} HTTP/1.1\" 303");
+    crate::serve::see_other(&ctx, "/settings/passkeys")
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/login/passkey", get(get_login_passkey))
+        .route("/webauthn/register/options", post(post_register_options))
+        .route("/webauthn/register", post(post_register))
+        .route("/webauthn/login/options", post(post_login_options))
+        .route("/webauthn/login", post(post_login))
+        .route("/settings/passkeys", get(get_passkeys))
+        .route("/settings/passkeys/mode", post(post_mode))
+        .route("/settings/passkeys/delete/{id}", post(post_delete))
+}
+
+#[test]
+fn test_parse_auth_data() {
+    let mut data = Sha256::digest(b"localhost").to_vec();
+    data.push(FLAG_USER_PRESENT);
+    data.extend_from_slice(&7u32.to_be_bytes());
+    let auth_data = parse_auth_data(&data).unwrap();
+    assert_eq!(auth_data.flags, FLAG_USER_PRESENT);
+    assert_eq!(auth_data.sign_count, 7);
+    assert!(auth_data.credential.is_none());
+    assert!(parse_auth_data(&data[..36]).is_err());
+    data[32] |= FLAG_ATTESTED_CREDENTIAL;
+    assert!(parse_auth_data(&data).is_err());
+}
+
+#[test]
+fn test_parse_cose_key() {
+    // RSA keys (kty 3) are not supported.
+    let key = Value::Map(vec![
+        (Value::from(1), Value::from(3)),
+        (Value::from(3), Value::from(-257)),
+    ]);
+    assert!(parse_cose_key(&key).is_err());
+    let key = Value::Map(vec![
+        (Value::from(1), Value::from(2)),
+        (Value::from(3), Value::from(ES256)),
+        (Value::from(-1), Value::from(1)),
+        (Value::from(-2), Value::Bytes(vec![0; 32])),
+        (Value::from(-3), Value::Bytes(vec![0; 32])),
+    ]);
+    // Not a point on the curve.
+    assert!(parse_cose_key(&key).is_err());
+}
+
+#[test]
+fn test_challenge_limit() {
+    let conn = Connection::open_in_memory().unwrap();
+    Challenge::create_table(&conn).unwrap();
+    let create = Challenge::insert(&conn, "create").unwrap();
+    let first = Challenge::insert(&conn, "get").unwrap();
+    for _ in 0..MAX_CHALLENGES {
+        Challenge::insert(&conn, "get").unwrap();
+    }
+    let stmt = "SELECT COUNT(*) FROM webauthn_challenges WHERE purpose = 'get'";
+    let count: i64 = conn.query_row(stmt, [], |row| row.get(0)).unwrap();
+    assert_eq!(count, MAX_CHALLENGES);
+    assert!(!Challenge::take(&conn, &first, "get").unwrap());
+    // Login challenges do not drop the registration challenge.
+    assert!(Challenge::take(&conn, &create, "create").unwrap());
+}
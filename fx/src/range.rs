@@ -0,0 +1,184 @@
+//! HTTP range requests.
+//!
+//! Browsers request parts of audio and video files when seeking, and download
+//! managers use ranges to resume downloads.
+
+/// A range of bytes with an inclusive end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value for the `Content-Range` header.
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{size}", self.start, self.end)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    /// Send the full content since the header is missing, invalid, or asks
+    /// for too many ranges.
+    Full,
+    Satisfiable(Vec<ByteRange>),
+    Unsatisfiable,
+}
+
+/// Ranges with more parts than this are answered with the full content.
+///
+/// Many small ranges make responses much larger than the content itself.
+const MAX_RANGES: usize = 16;
+
+fn parse_spec(spec: &str, size: u64) -> Option<Option<ByteRange>> {
+    let (start, end) = spec.trim().split_once('-')?;
+    let range = if start.is_empty() {
+        // A suffix range such as `-500` for the last 500 bytes.
+        let length = end.parse::<u64>().ok()?;
+        if length == 0 || size == 0 {
+            return Some(None);
+        }
+        ByteRange {
+            start: size.saturating_sub(length),
+            end: size - 1,
+        }
+    } else {
+        let start = start.parse::<u64>().ok()?;
+        let end = if end.is_empty() {
+            u64::MAX
+        } else {
+            end.parse::<u64>().ok()?
+        };
+        if end < start {
+            return None;
+        }
+        if size <= start {
+            return Some(None);
+        }
+        ByteRange {
+            start,
+            end: end.min(size - 1),
+        }
+    };
+    Some(Some(range))
+}
+
+/// Sort the ranges and merge the ones that overlap or are adjacent.
+///
+/// Otherwise, a request such as `bytes=0-,0-` would get the content many times
+/// in one response. RFC 9110 allows coalescing ranges like this.
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Parse the `Range` header for content of `size` bytes.
+pub fn parse(header: &str, size: u64) -> Ranges {
+    let Some((unit, specs)) = header.split_once('=') else {
+        return Ranges::Full;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Ranges::Full;
+    }
+    let specs = specs.split(',').collect::<Vec<_>>();
+    if MAX_RANGES < specs.len() {
+        return Ranges::Full;
+    }
+    let mut ranges = Vec::new();
+    for spec in specs {
+        match parse_spec(spec, size) {
+            Some(Some(range)) => ranges.push(range),
+            Some(None) => {}
+            // Invalid headers are ignored according to RFC 9110.
+            None => return Ranges::Full,
+        }
+    }
+    if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Satisfiable(coalesce(ranges))
+    }
+}
+
+#[test]
+fn test_parse() {
+    let range = |start, end| ByteRange { start, end };
+    assert_eq!(
+        parse("bytes=0-499", 1000),
+        Ranges::Satisfiable(vec![range(0, 499)])
+    );
+    assert_eq!(
+        parse("bytes=500-", 1000),
+        Ranges::Satisfiable(vec![range(500, 999)])
+    );
+    assert_eq!(
+        parse("bytes=-100", 1000),
+        Ranges::Satisfiable(vec![range(900, 999)])
+    );
+    assert_eq!(
+        parse("bytes=-2000", 1000),
+        Ranges::Satisfiable(vec![range(0, 999)])
+    );
+    assert_eq!(
+        parse("bytes=0-0, 900-5000", 1000),
+        Ranges::Satisfiable(vec![range(0, 0), range(900, 999)])
+    );
+    assert_eq!(
+        parse("bytes=0-1, 2000-", 1000),
+        Ranges::Satisfiable(vec![range(0, 1)])
+    );
+    assert_eq!(parse("bytes=1000-", 1000), Ranges::Unsatisfiable);
+    assert_eq!(parse("bytes=-0", 1000), Ranges::Unsatisfiable);
+    assert_eq!(parse("bytes=0-", 0), Ranges::Unsatisfiable);
+    assert_eq!(parse("bytes=5-1", 1000), Ranges::Full);
+    assert_eq!(parse("bytes=a-b", 1000), Ranges::Full);
+    assert_eq!(parse("items=0-1", 1000), Ranges::Full);
+    let many = format!("bytes={}", vec!["0-0"; 17].join(","));
+    assert_eq!(parse(&many, 1000), Ranges::Full);
+
+    // Overlapping and adjacent ranges are merged, so the content is not sent
+    // more than once.
+    let repeated = format!("bytes={}", vec!["0-"; 16].join(","));
+    assert_eq!(
+        parse(&repeated, 1000),
+        Ranges::Satisfiable(vec![range(0, 999)])
+    );
+    assert_eq!(
+        parse("bytes=500-599, 0-9, 10-19, 550-", 1000),
+        Ranges::Satisfiable(vec![range(0, 19), range(500, 999)])
+    );
+}
+
+/// Whether the `If-Range` header allows sending a part of the content.
+///
+/// Only strong entity tags can be used. Dates are not supported, so the full
+/// content is sent for them, which is always allowed.
+pub fn if_range_matches(if_range: &str, etag: &str) -> bool {
+    let if_range = if_range.trim();
+    !if_range.starts_with("W/") && if_range == etag
+}
+
+#[test]
+fn test_if_range_matches() {
+    assert!(if_range_matches("\"abc\"", "\"abc\""));
+    assert!(!if_range_matches("W/\"abc\"", "\"abc\""));
+    assert!(!if_range_matches("\"def\"", "\"abc\""));
+    assert!(!if_range_matches(
+        "Sun, 06 Nov 1994 08:49:37 GMT",
+        "\"abc\""
+    ));
+}
@@ -109,9 +109,9 @@ async fn get_search(
         .iter_mut()
         .map(|p| {
             let slug = crate::md::extract_slug(p);
-            crate::md::preview(p, 60);
+            crate::md::preview(p, 60, &ctx.file_index);
             let is_front_page_preview = true;
-            wrap_post_content(p, &slug, is_front_page_preview)
+            wrap_post_content(p, &slug, is_front_page_preview, &ctx.file_index)
         })
         .collect::<Vec<_>>();
     let results = results.join("\n");
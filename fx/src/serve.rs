@@ -1,67 +1,91 @@
 use crate::ServeArgs;
 use crate::blogroll::BlogCache;
+use crate::csrf::Csrf;
+use crate::csrf::CsrfForm;
+use crate::csrf::SameOrigin;
 use crate::data;
 use crate::data::DbPool;
 use crate::data::Kv;
 use crate::data::Post;
+use crate::files::FileIndex;
 use crate::html::PageSettings;
 use crate::html::Top;
 use crate::html::page;
 use crate::html::wrap_post_content;
+use crate::throttle::ClientIp;
+use crate::throttle::LoginThrottle;
+use crate::throttle::show_ip;
+use crate::upload::UploadLocks;
 use axum::Form;
 use axum::Router;
 use axum::body::Body;
 use axum::extract::DefaultBodyLimit;
 use axum::extract::Path;
 use axum::extract::Query;
-use axum::extract::Request;
 use axum::extract::State;
 use axum::http::HeaderMap;
 use axum::http::HeaderValue;
 use axum::http::Response;
 use axum::http::StatusCode;
-use axum::response::IntoResponse;
 use axum::response::Redirect;
 use axum::routing::get;
 use axum::routing::post;
 use axum_extra::extract::CookieJar;
 use chrono::Utc;
 use futures_util::FutureExt;
+use fx_auth::Admin;
+use fx_auth::CookieKey;
 use fx_auth::Login;
+use fx_auth::Password;
 use fx_auth::Salt;
 use fx_rss::RssFeed;
-use http_body_util::BodyExt;
 use r2d2::PooledConnection;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::Connection;
 use serde::Deserialize;
 use serde::Serialize;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::Instant;
+use subtle::ConstantTimeEq;
 use tokio::sync::Mutex;
 use tokio_cron_scheduler::Job;
 use tokio_cron_scheduler::JobScheduler;
 
+/// Maximum size of request bodies.
+///
+/// The routes that receive files set their own limit, see
+/// `crate::upload::body_limit`.
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
 #[derive(Clone)]
 pub struct ServerContext {
     pub args: ServeArgs,
     pub pool: DbPool,
-    pub salt: Salt,
+    /// Key that encrypts the login cookies.
+    pub key: CookieKey,
     pub blog_cache: Arc<Mutex<BlogCache>>,
+    pub login_throttle: Arc<Mutex<LoginThrottle>>,
+    pub file_index: FileIndex,
+    pub upload_locks: UploadLocks,
 }
 
 impl ServerContext {
     pub async fn new(
         args: ServeArgs,
         pool: DbPool,
-        salt: Salt,
+        key: CookieKey,
         blog_cache: Arc<Mutex<BlogCache>>,
     ) -> Self {
+        let file_index = FileIndex::load(&pool.get().unwrap()).expect("Failed to load files");
         Self {
             args: args.clone(),
             pool,
-            salt,
+            key,
             blog_cache,
+            login_throttle: Arc::new(Mutex::new(LoginThrottle::new())),
+            file_index,
+            upload_locks: UploadLocks::default(),
         }
     }
     pub fn conn(&self) -> PooledConnection<SqliteConnectionManager> {
@@ -95,6 +119,14 @@ where
     let mut response: Response<Body> = Response::default();
     *response.status_mut() = status;
     *response.headers_mut() = headers;
+    // Most responses are pages. Set the type explicitly since browsers do not
+    // guess it due to `X-Content-Type-Options: nosniff`.
+    if !response.headers().contains_key("Content-Type") {
+        response.headers_mut().insert(
+            "Content-Type",
+            HeaderValue::from_static("text/html; charset=utf-8"),
+        );
+    }
     // Prevent framing the page in an iframe.
     response
         .headers_mut()
@@ -160,19 +192,32 @@ where
     response(status, headers, body, ctx)
 }
 
-pub fn is_logged_in(ctx: &ServerContext, jar: &CookieJar) -> bool {
-    let password = match &ctx.args.password {
-        Some(password) => password,
-        None => {
+/// Compare two strings without leaking timing information.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// Credentials of the admin or `None` when the password is not set.
+pub fn admin_login(ctx: &ServerContext) -> Option<Admin> {
+    let password = match (&ctx.args.password, &ctx.args.password_hash) {
+        (_, Some(hash)) => Password::Hash(hash.clone()),
+        (Some(password), None) => Password::Plain(password.clone()),
+        (None, None) => {
             tracing::warn!("admin password not set");
-            return false;
+            return None;
         }
     };
-    let login = Login {
-        username: Some(ctx.args.username.clone()),
-        password: Some(password.clone()),
-    };
-    fx_auth::is_logged_in(&ctx.salt, &login, jar)
+    Some(Admin {
+        username: ctx.args.username.clone(),
+        password,
+    })
+}
+
+pub fn is_logged_in(ctx: &ServerContext, jar: &CookieJar) -> bool {
+    match admin_login(ctx) {
+        Some(_) => crate::sessions::current(ctx, jar).is_some(),
+        None => false,
+    }
 }
 
 async fn list_posts(ctx: &ServerContext, page: usize) -> (bool, String) {
@@ -191,8 +236,8 @@ async fn list_posts(ctx: &ServerContext, page: usize) -> (bool, String) {
         .iter_mut()
         .map(|post| {
             let slug = crate::md::extract_slug(post);
-            crate::md::preview(post, 600);
-            wrap_post_content(post, &slug, true)
+            crate::md::preview(post, 600, &ctx.file_index);
+            wrap_post_content(post, &slug, true, &ctx.file_index)
         })
         .collect::<Vec<String>>();
     (has_next, posts.join("\n"))
@@ -216,9 +261,11 @@ async fn get_posts(
     let show_about = pagination.page.is_none();
     let current_page = pagination.page.unwrap_or(1);
     let extra_head = Kv::get_or_empty_string(&ctx.conn(), "extra_head");
+    let indieauth = crate::indieauth::discovery_links(&ctx);
     let extra_head = format!(
         "
         <meta property='og:type' content='website'/>
+        {indieauth}
         {}
         ",
         &extra_head
@@ -228,7 +275,8 @@ async fn get_posts(
     } else {
         Top::GoHome
     };
-    let settings = PageSettings::new("", is_logged_in, None, show_about, top, &extra_head);
+    let settings = PageSettings::new("", is_logged_in, None, show_about, top, &extra_head)
+        .with_csrf(&crate::csrf::input(&ctx, &jar));
     let (has_next, posts) = list_posts(&ctx, current_page).await;
     let prev_link = if current_page == 1 {
         ""
@@ -277,38 +325,6 @@ pub fn enable_caching(headers: &mut HeaderMap, max_age: u32) {
     headers.insert(hyper::header::CACHE_CONTROL, val);
 }
 
-async fn get_style(State(ctx): State<ServerContext>) -> Response<Body> {
-    let body = crate::html::minify(include_str!("static/style.css"));
-    let mut headers = HeaderMap::new();
-    content_type(&mut headers, "text/css");
-    enable_caching(&mut headers, 600);
-    response(StatusCode::OK, headers, body, &ctx)
-}
-
-async fn get_script(State(ctx): State<ServerContext>) -> Response<Body> {
-    let body = crate::html::minify(include_str!("static/script.js"));
-    let mut headers = HeaderMap::new();
-    content_type(&mut headers, "text/javascript");
-    enable_caching(&mut headers, 600);
-    response(StatusCode::OK, headers, body, &ctx)
-}
-
-async fn get_katex(State(ctx): State<ServerContext>) -> Response<Body> {
-    let body = crate::html::minify(include_str!("static/katex.js"));
-    let mut headers = HeaderMap::new();
-    content_type(&mut headers, "text/javascript");
-    enable_caching(&mut headers, 600);
-    response(StatusCode::OK, headers, body, &ctx)
-}
-
-async fn get_nodefer(State(ctx): State<ServerContext>) -> Response<Body> {
-    let body = crate::html::minify(include_str!("static/nodefer.js"));
-    let mut headers = HeaderMap::new();
-    content_type(&mut headers, "text/javascript");
-    enable_caching(&mut headers, 600);
-    response(StatusCode::OK, headers, body, &ctx)
-}
-
 async fn get_delete(
     State(ctx): State<ServerContext>,
     Path(id): Path<i64>,
@@ -333,16 +349,22 @@ async fn get_delete(
         Top::GoHome,
         extra_head,
     );
+    let csrf = crate::csrf::input(&ctx, &jar);
     let delete_button = indoc::formatdoc! {r#"
         <div class='medium-text' style='text-align: center; font-weight: bold;'>
             <p>Are you sure you want to delete this post? This action cannot be undone.</p>
             <form action='/posts/delete/{id}' method='post'>
+                {csrf}
                 <button type='submit'>delete</button>
             </form>
             <br>
         </div>
     "#};
-    let body = format!("{}\n{}", delete_button, wrap_post_content(&post, "", false));
+    let body = format!(
+        "{}\n{}",
+        delete_button,
+        wrap_post_content(&post, "", false, &ctx.file_index)
+    );
     let body = page(&ctx, &settings, &body).await;
     response::<String>(StatusCode::OK, HeaderMap::new(), body, &ctx)
 }
@@ -360,7 +382,7 @@ async fn get_edit(
     };
     let title = crate::md::extract_html_title(&post);
     let title = format!("Edit '{title}'");
-    let body = crate::html::edit_post_form(&post);
+    let body = crate::html::edit_post_form(&post, &crate::csrf::input(&ctx, &jar));
     let extra_head = Kv::get_or_empty_string(&ctx.conn(), "extra_head");
     let settings = PageSettings::new(
         &title,
@@ -400,6 +422,7 @@ async fn get_post_with_slug(
     let updated = iso8601(&post.updated);
     let slug = crate::md::extract_slug(&post);
     let canonical = format!("{}/posts/{}/{slug}", &ctx.base_url(), &post.id);
+    let og_image = crate::og::meta_tags(&ctx, &post, &title);
     let extra_head = Kv::get_or_empty_string(&ctx.conn(), "extra_head");
     let extra_head = indoc::formatdoc! {r#"
         <meta property='article:author' content='{author}'/>
@@ -407,6 +430,7 @@ async fn get_post_with_slug(
         <meta property='article:modified_time' content='{updated}'/>
         <meta property='og:url' content='{canonical}'/>
         <meta property='og:type' content='article'/>
+        {og_image}
         <link rel='canonical' href='{canonical}'/>
         {}
     "#, &extra_head};
@@ -419,14 +443,15 @@ async fn get_post_with_slug(
         Top::GoHome,
         &extra_head,
     );
-    let mut body = wrap_post_content(&post, &slug, false);
+    let mut body = wrap_post_content(&post, &slug, false, &ctx.file_index);
     if is_logged_in {
         body = format!("{}\n{body}", crate::html::edit_post_buttons(&ctx, &post));
     }
     let body = page(&ctx, &settings, &body).await;
+    let headers = HeaderMap::new();
     // Can safely assume HTTP/1.1 because we're not handling TLS.
     tracing::info!("\"GET /posts/{id} HTTP/1.1\" 200");
-    response::<String>(StatusCode::OK, HeaderMap::new(), body, &ctx)
+    response::<String>(StatusCode::OK, headers, body, &ctx)
 }
 
 async fn get_post(
@@ -520,15 +545,30 @@ pub struct LoginForm {
     pub password: String,
 }
 
+async fn too_many_attempts(ctx: &ServerContext, wait: Duration) -> Response<Body> {
+    let msg = format!(
+        "Too many failed login attempts. Try again in {}.",
+        crate::throttle::show_wait(wait)
+    );
+    let body = crate::html::login(ctx, Some(&msg)).await;
+    let mut headers = HeaderMap::new();
+    let retry_after = wait.as_secs().max(1).to_string();
+    headers.insert("Retry-After", HeaderValue::from_str(&retry_after).unwrap());
+    tracing::info!("\"POST /login HTTP/1.1\" 429");
+    response::<String>(StatusCode::TOO_MANY_REQUESTS, headers, body, ctx)
+}
+
 async fn post_login(
     State(ctx): State<ServerContext>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
     jar: CookieJar,
+    _: SameOrigin,
     Form(form): Form<LoginForm>,
 ) -> Result<(CookieJar, Redirect), Response<Body>> {
-    let password = match &ctx.args.password {
-        Some(password) => password,
+    let actual = match admin_login(&ctx) {
+        Some(actual) => actual,
         None => {
-            tracing::warn!("admin password not set");
             return Err(response(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 HeaderMap::new(),
@@ -537,21 +577,57 @@ async fn post_login(
             ));
         }
     };
-    let actual = Login {
-        username: Some(ctx.args.username.clone()),
-        password: Some(password.clone()),
+    let now = Instant::now();
+    // Checking before verifying the password so that guesses during the
+    // lockout reveal nothing.
+    let attempt = ctx.login_throttle.lock().await.begin(ip, now);
+    let failures = match attempt {
+        Ok(failures) => failures,
+        Err(wait) => {
+            tracing::warn!("rejected login from {} during lockout", show_ip(ip));
+            return Err(too_many_attempts(&ctx, wait).await);
+        }
     };
+    let username = form.username.clone();
     let received = Login {
         username: Some(form.username),
         password: Some(form.password),
     };
-    let new_jar = fx_auth::handle_login(&ctx.salt, &actual, &received, jar.clone());
-    match new_jar {
-        Some(jar) => {
+    // Argon2 takes tens of milliseconds, which is too long for the executor.
+    let verify = move || fx_auth::verify_login(&actual, &received);
+    let is_correct = tokio::task::spawn_blocking(verify).await.unwrap();
+    match is_correct {
+        true if crate::totp::is_enabled(&ctx) => {
+            let jar = fx_auth::require_second_factor(&ctx.key, jar);
+            tracing::info!("correct password from {}; asking for TOTP", show_ip(ip));
+            tracing::info!("\"POST /login HTTP/1.1\" 303");
+            Ok((jar, Redirect::to("/login/totp")))
+        }
+        true if crate::passkeys::is_second_factor(&ctx) => {
+            let jar = fx_auth::require_second_factor(&ctx.key, jar);
+            tracing::info!("correct password from {}; asking for passkey", show_ip(ip));
+            tracing::info!("\"POST /login HTTP/1.1\" 303");
+            Ok((jar, Redirect::to("/login/passkey")))
+        }
+        true => {
+            let jar = match crate::sessions::start(&ctx, &headers, ip, jar) {
+                Ok(jar) => jar,
+                Err(e) => {
+                    let msg = "Could not start session";
+                    tracing::error!("{msg}: {e}");
+                    return Err(internal_server_error(&ctx, msg).await);
+                }
+            };
+            ctx.login_throttle.lock().await.record_success(ip);
+            tracing::info!("successful login from {}", show_ip(ip));
             tracing::info!("\"POST /login HTTP/1.1\" 200");
             Ok((jar, Redirect::to("/")))
         }
-        None => {
+        false => {
+            tracing::warn!(
+                "failed login for username {username:?} from {}; {failures} consecutive failures",
+                show_ip(ip)
+            );
             let body = crate::html::login(&ctx, Some("Invalid username or password"));
             tracing::info!("\"POST /login HTTP/1.1\" 401");
             Err(response::<String>(
@@ -564,7 +640,8 @@ async fn post_login(
     }
 }
 
-async fn get_logout(State(_ctx): State<ServerContext>, jar: CookieJar) -> (CookieJar, Redirect) {
+async fn get_logout(State(ctx): State<ServerContext>, jar: CookieJar) -> (CookieJar, Redirect) {
+    crate::sessions::end(&ctx, &jar);
     let updated_jar = fx_auth::handle_logout(jar.clone());
     tracing::info!("\"GET /logout HTTP/1.1\" 200");
     (updated_jar, Redirect::to("/"))
@@ -573,17 +650,8 @@ async fn get_logout(State(_ctx): State<ServerContext>, jar: CookieJar) -> (Cooki
 async fn post_delete(
     State(ctx): State<ServerContext>,
     Path(id): Path<i64>,
-    jar: CookieJar,
+    _: Csrf,
 ) -> Result<Redirect, Response<Body>> {
-    let is_logged_in = is_logged_in(&ctx, &jar);
-    if !is_logged_in {
-        return Err(response(
-            StatusCode::UNAUTHORIZED,
-            HeaderMap::new(),
-            "Unauthorized",
-            &ctx,
-        ));
-    }
     Post::delete(&ctx.conn(), id).unwrap();
     crate::trigger::trigger_github_backup(&ctx).await;
     Ok(Redirect::to("/"))
@@ -592,6 +660,9 @@ async fn post_delete(
 #[derive(Debug, Deserialize, Serialize)]
 pub struct EditPostForm {
     pub content: String,
+    /// Set when the publish button was pressed instead of the preview button.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish: Option<String>,
 }
 
 /// Return a 303 redirect to the given url.
@@ -613,33 +684,11 @@ pub fn trim_newline_suffix(s: &str) -> String {
 
 async fn post_edit(
     State(ctx): State<ServerContext>,
-    jar: CookieJar,
     Path(id): Path<i64>,
-    req: Request,
+    CsrfForm(form): CsrfForm<EditPostForm>,
 ) -> Response<Body> {
-    let is_logged_in = is_logged_in(&ctx, &jar);
-    if !is_logged_in {
-        return not_found(State(ctx)).await;
-    }
     let extra_head = &Kv::get_or_empty_string(&ctx.conn(), "extra_head");
-    let settings = PageSettings::new("", Some(is_logged_in), None, false, Top::GoBack, extra_head);
-    let (_, body) = req.into_parts();
-    let bytes = body
-        .collect()
-        .await
-        .map_err(|_err| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read request body",
-            )
-                .into_response()
-        })
-        .unwrap()
-        .to_bytes();
-    let bytes = bytes.to_vec();
-    let input = String::from_utf8(bytes).unwrap();
-    let publish = input.contains("publish=Publish");
-    let form = serde_urlencoded::from_str::<EditPostForm>(&input).unwrap();
+    let settings = PageSettings::new("", Some(true), None, false, Top::GoBack, extra_head);
     let created = match Post::get(&ctx.conn(), id) {
         Ok(post) => post.created,
         Err(_) => Utc::now(),
@@ -650,7 +699,7 @@ async fn post_edit(
         updated: Utc::now(),
         content: trim_newline_suffix(&form.content),
     };
-    if publish {
+    if form.publish.is_some() {
         let post = post.update(&ctx.conn());
         if post.is_err() {
             return response(
@@ -664,7 +713,7 @@ async fn post_edit(
         crate::trigger::trigger_github_backup(&ctx).await;
         see_other(&ctx, &url)
     } else {
-        let preview = crate::html::wrap_post_content(&post, "", false);
+        let preview = crate::html::wrap_post_content(&post, "", false, &ctx.file_index);
         let body = page(&ctx, &settings, &preview).await;
         response(StatusCode::OK, HeaderMap::new(), body, &ctx)
     }
@@ -673,6 +722,9 @@ async fn post_edit(
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AddPostForm {
     pub content: String,
+    /// Set when the publish button was pressed instead of the preview button.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish: Option<String>,
 }
 
 /// H2-H6 headings (for example, `## Heading`) cause problems since the hash
@@ -696,33 +748,11 @@ fn test_fix_invalid_heading_issue_179() {
 
 async fn post_add(
     State(ctx): State<ServerContext>,
-    jar: CookieJar,
-    req: Request,
+    CsrfForm(form): CsrfForm<AddPostForm>,
 ) -> Response<Body> {
-    let is_logged_in = is_logged_in(&ctx, &jar);
-    if !is_logged_in {
-        return not_found(State(ctx)).await;
-    }
     let extra_head = &Kv::get_or_empty_string(&ctx.conn(), "extra_head");
-    let settings = PageSettings::new("", Some(is_logged_in), None, false, Top::GoBack, extra_head);
-    let (_, body) = req.into_parts();
-    let bytes = body
-        .collect()
-        .await
-        .map_err(|_err| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read request body",
-            )
-                .into_response()
-        })
-        .unwrap()
-        .to_bytes();
-    let bytes = bytes.to_vec();
-    let input = String::from_utf8(bytes).unwrap();
-    let publish = input.contains("publish=Publish");
-    let form = serde_urlencoded::from_str::<AddPostForm>(&input).unwrap();
-    if publish {
+    let settings = PageSettings::new("", Some(true), None, false, Top::GoBack, extra_head);
+    if form.publish.is_some() {
         let now = Utc::now();
         let content = trim_newline_suffix(&form.content);
         let content = fix_invalid_heading_issue_179(&content);
@@ -746,7 +776,8 @@ async fn post_add(
             content: form.content,
         };
         let is_front_page_preview = false;
-        let preview = crate::html::wrap_post_content(&post, "", is_front_page_preview);
+        let preview =
+            crate::html::wrap_post_content(&post, "", is_front_page_preview, &ctx.file_index);
         let body = page(&ctx, &settings, &preview).await;
         response(StatusCode::OK, HeaderMap::new(), body, &ctx)
     }
@@ -782,21 +813,59 @@ pub fn app(ctx: ServerContext) -> Router {
         .route("/login", get(get_login))
         .route("/login", post(post_login))
         .route("/logout", get(get_logout))
-        .route("/static/style.css", get(get_style))
-        .route("/static/script.js", get(get_script))
-        .route("/static/katex.js", get(get_katex))
-        .route("/static/nodefer.js", get(get_nodefer))
         .route("/.well-known/webfinger", get(get_webfinger));
-    let router = crate::api::routes(&router);
+    let router = crate::api::routes(&router, &ctx.args);
+    let router = crate::assets::routes(&router);
     let router = crate::blogroll::routes(&router);
+    let router = crate::csp::routes(&router);
     let router = crate::discovery::routes(&router);
-    let router = crate::files::routes(&router);
+    let router = crate::files::routes(&router, &ctx.args);
+    let router = crate::indieauth::routes(&router);
+    let router = crate::og::routes(&router);
+    let router = crate::passkeys::routes(&router);
     let router = crate::search::routes(&router);
+    let router = crate::sessions::routes(&router);
     let router = crate::settings::routes(&router);
+    let router = crate::tokens::routes(&router);
+    let router = crate::totp::routes(&router);
+    let router = crate::upload::routes(&router);
     let router = router.fallback(not_found);
-    // Files larger than this will be rejected during upload.
-    let limit = 15 * 1024 * 1024;
-    router.with_state(ctx).layer(DefaultBodyLimit::max(limit))
+    let renew = axum::middleware::from_fn_with_state(ctx.clone(), crate::sessions::renew);
+    let conditional = axum::middleware::from_fn(crate::conditional::layer);
+    let compression = axum::middleware::from_fn_with_state(ctx.clone(), crate::compression::layer);
+    let csp = axum::middleware::from_fn_with_state(ctx.clone(), crate::csp::headers);
+    // The conditional layer runs inside the CSP layer since it needs the
+    // nonce.
+    router
+        .layer(renew)
+        .layer(conditional)
+        .layer(compression)
+        .layer(csp)
+        .with_state(ctx)
+        .layer(DefaultBodyLimit::max(BODY_LIMIT))
+}
+
+/// Return the secret for the cookie key.
+///
+/// Falls back to a random secret that is stored in the database next to the
+/// salt.
+fn obtain_secret(args: &ServeArgs, conn: &Connection) -> String {
+    if let Some(secret) = &args.secret {
+        return secret.clone();
+    }
+    if args.production {
+        let secret = data::Kv::get_or_empty_string(conn, "secret");
+        if secret.is_empty() {
+            let secret = fx_auth::generate_token();
+            data::Kv::insert(conn, "secret", secret.as_bytes()).unwrap();
+            secret
+        } else {
+            secret
+        }
+    } else {
+        // Allow the login to persist across restarts.
+        "development-secret".to_string()
+    }
 }
 
 /// Return the salt by either generating a new one or reading it from the db.
@@ -870,14 +939,16 @@ async fn schedule_jobs(blog_cache: Arc<Mutex<BlogCache>>, ctx: ServerContext) {
 }
 
 pub async fn run(args: &ServeArgs) {
+    crate::assets::init();
     let pool = data::connect(args).unwrap();
     let conn = pool.get().unwrap();
     data::init(args, &conn);
     let salt = obtain_salt(args, &conn);
+    let key = CookieKey::new(&salt, &obtain_secret(args, &conn));
     let blog_cache = init_blog_cache(&conn).await;
     drop(conn);
     let blog_cache = Arc::new(Mutex::new(blog_cache));
-    let ctx = ServerContext::new(args.clone(), pool, salt, blog_cache.clone()).await;
+    let ctx = ServerContext::new(args.clone(), pool, key, blog_cache.clone()).await;
     schedule_jobs(blog_cache.clone(), ctx.clone()).await;
     let app = app(ctx);
     // Listen on both IPv4 and IPv6. This seems to not be necessary behind the
@@ -887,5 +958,7 @@ pub async fn run(args: &ServeArgs) {
     let addr = addr.parse::<std::net::SocketAddr>().unwrap();
     tracing::info!("Listening on {addr}");
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    // Connect info provides the peer address for the login throttle.
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
     axum::serve(listener, app).await.unwrap();
 }
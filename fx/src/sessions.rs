@@ -0,0 +1,348 @@
+//! Server-side login sessions at `/settings/sessions`.
+//!
+//! The `auth` cookie only contains a random session token. The session itself
+//! lives in the database so that it can be revoked, for example when a device
+//! was lost.
+use crate::csrf::Csrf;
+use crate::data::SqliteDateTime;
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::escape_html;
+use crate::html::page;
+use crate::serve::ServerContext;
+use crate::serve::admin_login;
+use crate::serve::response;
+use crate::throttle::show_ip;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use fx_auth::SESSION_MAX_AGE_SEC;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::Result;
+use rusqlite::params;
+use std::net::IpAddr;
+
+/// Minimum time between two updates of the last seen time.
+///
+/// Avoids a database write on every request.
+const RENEW_INTERVAL_SEC: i64 = 10 * 60;
+const MAX_USER_AGENT_LEN: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub id: i64,
+    pub user_agent: String,
+    pub ip: String,
+    pub created: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Sessions that were not seen since this time are expired.
+fn expired_before() -> String {
+    (Utc::now() - Duration::seconds(SESSION_MAX_AGE_SEC)).to_sqlite()
+}
+
+impl Session {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                token_hash TEXT NOT NULL UNIQUE,
+                user_agent TEXT NOT NULL,
+                ip TEXT NOT NULL,
+                created DATETIME NOT NULL,
+                last_seen DATETIME NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    /// Store a new session and return the plaintext token.
+    fn insert(conn: &Connection, user_agent: &str, ip: &str) -> Result<String> {
+        conn.execute(
+            "DELETE FROM sessions WHERE last_seen < ?",
+            [expired_before()],
+        )?;
+        let token = fx_auth::generate_token();
+        let stmt = "
+            INSERT INTO sessions (token_hash, user_agent, ip, created, last_seen)
+            VALUES (?, ?, ?, ?, ?);
+        ";
+        let now = Utc::now().to_sqlite();
+        let params = params![fx_auth::hash_token(&token), user_agent, ip, now, now];
+        conn.execute(stmt, params)?;
+        Ok(token)
+    }
+    fn from_row(row: &rusqlite::Row) -> Result<Self> {
+        let created: String = row.get("created")?;
+        let last_seen: String = row.get("last_seen")?;
+        Ok(Session {
+            id: row.get("id")?,
+            user_agent: row.get("user_agent")?,
+            ip: row.get("ip")?,
+            created: DateTime::from_sqlite(&created),
+            last_seen: DateTime::from_sqlite(&last_seen),
+        })
+    }
+    /// Active session for the token.
+    fn get(conn: &Connection, token: &str) -> Result<Option<Self>> {
+        let stmt = "
+            SELECT id, user_agent, ip, created, last_seen FROM sessions
+            WHERE token_hash = ? AND last_seen >= ?;
+        ";
+        let params = params![fx_auth::hash_token(token), expired_before()];
+        conn.prepare(stmt)?
+            .query_row(params, Self::from_row)
+            .optional()
+    }
+    /// Active sessions with the most recently used first.
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        let stmt = "
+            SELECT id, user_agent, ip, created, last_seen FROM sessions
+            WHERE last_seen >= ?
+            ORDER BY last_seen DESC;
+        ";
+        conn.prepare(stmt)?
+            .query_map([expired_before()], Self::from_row)?
+            .collect::<Result<Vec<_>, _>>()
+    }
+    fn touch(conn: &Connection, id: i64) -> Result<usize> {
+        let stmt = "UPDATE sessions SET last_seen = ? WHERE id = ?";
+        conn.execute(stmt, params![Utc::now().to_sqlite(), id])
+    }
+    fn delete(conn: &Connection, id: i64) -> Result<usize> {
+        conn.execute("DELETE FROM sessions WHERE id = ?", [id])
+    }
+    fn delete_all(conn: &Connection) -> Result<usize> {
+        conn.execute("DELETE FROM sessions", [])
+    }
+}
+
+#[test]
+fn test_session_expiry() {
+    let conn = Connection::open_in_memory().unwrap();
+    Session::create_table(&conn).unwrap();
+    let token = Session::insert(&conn, "Firefox", "192.0.2.1").unwrap();
+    let session = Session::get(&conn, &token).unwrap().unwrap();
+    assert_eq!(session.user_agent, "Firefox");
+    assert!(Session::get(&conn, "other").unwrap().is_none());
+
+    // Just before the expiry, the session is still active.
+    let almost = Utc::now() - Duration::seconds(SESSION_MAX_AGE_SEC - 60);
+    let stmt = "UPDATE sessions SET last_seen = ?";
+    conn.execute(stmt, [almost.to_sqlite()]).unwrap();
+    assert!(Session::get(&conn, &token).unwrap().is_some());
+    Session::touch(&conn, session.id).unwrap();
+    assert!(Session::get(&conn, &token).unwrap().is_some());
+
+    let expired = Utc::now() - Duration::seconds(SESSION_MAX_AGE_SEC + 60);
+    conn.execute(stmt, [expired.to_sqlite()]).unwrap();
+    assert!(Session::get(&conn, &token).unwrap().is_none());
+    assert!(Session::list(&conn).unwrap().is_empty());
+}
+
+/// Start a session after a successful login and set the `auth` cookie.
+pub fn start(
+    ctx: &ServerContext,
+    headers: &HeaderMap,
+    ip: Option<IpAddr>,
+    jar: CookieJar,
+) -> Result<CookieJar> {
+    let user_agent = headers
+        .get("User-Agent")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let user_agent = user_agent
+        .chars()
+        .take(MAX_USER_AGENT_LEN)
+        .collect::<String>();
+    let token = Session::insert(&ctx.conn(), &user_agent, &show_ip(ip))?;
+    Ok(fx_auth::login(&ctx.key, &token, jar))
+}
+
+/// Session that belongs to the `auth` cookie.
+pub fn current(ctx: &ServerContext, jar: &CookieJar) -> Option<Session> {
+    let token = fx_auth::session(&ctx.key, jar)?;
+    match Session::get(&ctx.conn(), &token) {
+        Ok(session) => session,
+        Err(e) => {
+            tracing::error!("failed to get session: {e}");
+            None
+        }
+    }
+}
+
+/// End the session that belongs to the `auth` cookie.
+pub fn end(ctx: &ServerContext, jar: &CookieJar) {
+    if let Some(session) = current(ctx, jar)
+        && let Err(e) = Session::delete(&ctx.conn(), session.id)
+    {
+        tracing::error!("failed to delete session: {e}");
+    }
+}
+
+/// Middleware that keeps active sessions alive.
+///
+/// Each renewal moves the expiry of both the session and the cookie forward,
+/// so only sessions that are unused for [SESSION_MAX_AGE_SEC] expire.
+pub async fn renew(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    req: Request,
+    next: Next,
+) -> Response<Body> {
+    let renewed = match current(&ctx, &jar) {
+        Some(session) => {
+            let age = Utc::now().signed_duration_since(session.last_seen);
+            if RENEW_INTERVAL_SEC <= age.num_seconds() {
+                match Session::touch(&ctx.conn(), session.id) {
+                    Ok(_) => fx_auth::session(&ctx.key, &jar)
+                        .map(|token| fx_auth::login(&ctx.key, &token, CookieJar::new())),
+                    Err(e) => {
+                        tracing::error!("failed to renew session: {e}");
+                        None
+                    }
+                }
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+    let response = next.run(req).await;
+    // Handlers that change the `auth` cookie, such as logout, take precedence.
+    let sets_auth = response
+        .headers()
+        .get_all("Set-Cookie")
+        .iter()
+        .any(|value| value.as_bytes().starts_with(b"auth="));
+    match renewed {
+        Some(jar) if !sets_auth => (jar, response).into_response(),
+        _ => response,
+    }
+}
+
+fn show_session(session: &Session, current: Option<i64>, csrf: &str) -> String {
+    let id = session.id;
+    let user_agent = if session.user_agent.is_empty() {
+        "Unknown device".to_string()
+    } else {
+        escape_html(&session.user_agent)
+    };
+    let ip = escape_html(&session.ip);
+    let created = crate::html::show_date(&session.created);
+    let last_seen = crate::html::show_date(&session.last_seen);
+    let action = if current == Some(id) {
+        "<span>This session</span>".to_string()
+    } else {
+        format!(
+            "
+            <form method='post' action='/settings/sessions/revoke/{id}'>
+                {csrf}
+                <button type='submit'>Revoke</button>
+            </form>
+            "
+        )
+    };
+    format!(
+        "
+        <div class='session' style='padding: 6px; padding-top: 12px; \
+          border-bottom: 1px solid var(--border); font-size: 0.8rem;'>
+            <div style='display: flex; justify-content: space-between;'>
+                <strong>{user_agent}</strong>
+                {action}
+            </div>
+            <span>From {ip}, logged in {created}, last seen {last_seen}.</span>
+        </div>
+        "
+    )
+}
+
+async fn get_sessions(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    let current = match admin_login(&ctx).and(current(&ctx, &jar)) {
+        Some(current) => current,
+        None => return crate::serve::unauthorized(&ctx).await,
+    };
+    let sessions = match Session::list(&ctx.conn()) {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            let msg = "Could not get sessions from database";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    let csrf = crate::csrf::input(&ctx, &jar);
+    let sessions = sessions
+        .iter()
+        .map(|session| show_session(session, Some(current.id), &csrf))
+        .collect::<Vec<_>>()
+        .join("");
+    let body = format!(
+        "
+        <p style='margin-top: 5vh;'>
+            Devices that are logged in. Sessions expire after two weeks without use.
+        </p>
+        {sessions}
+        <form method='post' action='/settings/sessions/revoke-all' style='margin-top: 5vh;'>
+            {csrf}
+            <input style='margin-left: 0;' type='submit' value='Log out everywhere'/>
+        </form>
+        "
+    );
+    let settings = PageSettings::new("Sessions", Some(true), None, false, Top::GoHome, "");
+    let body = page(&ctx, &settings, &body).await;
+    tracing::info!("\"GET /settings/sessions HTTP/1.1\" 200");
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+async fn post_revoke(
+    State(ctx): State<ServerContext>,
+    Path(id): Path<i64>,
+    _: Csrf,
+) -> Response<Body> {
+    if let Err(e) = Session::delete(&ctx.conn(), id) {
+        let msg = "Could not revoke session";
+        tracing::error!("{msg}: {e}");
+        return crate::serve::internal_server_error(&ctx, msg).await;
+    }
+    tracing::warn!("revoked session {id}");
+    tracing::info!("\"POST /settings/sessions/revoke/{id} HTTP/1.1\" 303");
+    crate::serve::see_other(&ctx, "/settings/sessions")
+}
+
+async fn post_revoke_all(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    _: Csrf,
+) -> Response<Body> {
+    if let Err(e) = Session::delete_all(&ctx.conn()) {
+        let msg = "Could not revoke sessions";
+        tracing::error!("{msg}: {e}");
+        return crate::serve::internal_server_error(&ctx, msg).await;
+    }
+    tracing::warn!("revoked all sessions");
+    tracing::info!("\"POST /settings/sessions/revoke-all HTTP/1.1\" 303");
+    let jar = fx_auth::handle_logout(jar);
+    (jar, crate::serve::see_other(&ctx, "/login")).into_response()
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/settings/sessions", get(get_sessions))
+        .route("/settings/sessions/revoke/{id}", post(post_revoke))
+        .route("/settings/sessions/revoke-all", post(post_revoke_all))
+}
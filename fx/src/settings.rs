@@ -1,3 +1,4 @@
+use crate::csrf::CsrfForm;
 use crate::data::Kv;
 use crate::data::cleanup_content;
 use crate::html::PageSettings;
@@ -6,7 +7,6 @@ use crate::html::page;
 use crate::serve::ServerContext;
 use crate::serve::is_logged_in;
 use crate::serve::response;
-use axum::Form;
 use axum::Router;
 use axum::body::Body;
 use axum::extract::State;
@@ -19,8 +19,9 @@ use axum_extra::extract::CookieJar;
 use rusqlite::Connection;
 use serde::Deserialize;
 use serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct Settings {
     pub site_name: String,
     pub site_description: String,
@@ -29,6 +30,16 @@ pub struct Settings {
     pub dark_mode: Option<String>,
     pub extra_head: String,
     pub blogroll_feeds: String,
+    /// URL of the square cover art of the podcast, such as `/files/{sha}`.
+    #[serde(default)]
+    pub podcast_artwork: String,
+    /// Apple Podcasts category, such as `Technology` or
+    /// `Society & Culture > Documentary`.
+    #[serde(default)]
+    pub podcast_category: String,
+    /// Whether the podcast contains explicit content.
+    #[serde(default)]
+    pub podcast_explicit: Option<String>,
 }
 
 impl Settings {
@@ -46,6 +57,12 @@ impl Settings {
         };
         let extra_head = Kv::get_or_empty_string(conn, "extra_head");
         let blogroll_feeds = Kv::get(conn, crate::data::BLOGROLL_SETTINGS_KEY)?;
+        let podcast_explicit = Kv::get_or_empty_string(conn, "podcast_explicit");
+        let podcast_explicit = if podcast_explicit == "on" {
+            Some("on".to_string())
+        } else {
+            None
+        };
         Ok(Self {
             site_name: String::from_utf8(site_name).unwrap(),
             site_description: String::from_utf8(site_description).unwrap(),
@@ -54,12 +71,57 @@ impl Settings {
             dark_mode,
             extra_head,
             blogroll_feeds: String::from_utf8(blogroll_feeds).unwrap(),
+            podcast_artwork: Kv::get_or_empty_string(conn, "podcast_artwork"),
+            podcast_category: Kv::get_or_empty_string(conn, "podcast_category"),
+            podcast_explicit,
         })
     }
     pub fn set_about(conn: &Connection, about: &str) -> rusqlite::Result<()> {
         Kv::insert(conn, "about", about.as_bytes())?;
         Ok(())
     }
+    /// Store the settings after cleaning up the user input.
+    pub fn save(&self, conn: &Connection) -> rusqlite::Result<()> {
+        Kv::insert(conn, "site_name", self.site_name.trim().as_bytes())?;
+        Kv::insert(
+            conn,
+            "site_description",
+            self.site_description.trim().as_bytes(),
+        )?;
+        Kv::insert(conn, "author_name", self.author_name.trim().as_bytes())?;
+        let dark_mode = if self.dark_mode.is_some() {
+            "on"
+        } else {
+            "off"
+        };
+        Kv::insert(conn, "dark_mode", dark_mode.as_bytes())?;
+        let about = cleanup_content(&self.about);
+        Kv::insert(conn, "about", about.as_bytes())?;
+        let extra_head = cleanup_content(&self.extra_head);
+        Kv::insert(conn, "extra_head", extra_head.as_bytes())?;
+
+        let key = crate::data::BLOGROLL_SETTINGS_KEY;
+        let mut feeds = self
+            .blogroll_feeds
+            .split("\n")
+            .map(|line| line.trim())
+            .collect::<Vec<_>>();
+        feeds.sort();
+        let feeds = feeds.join("\n");
+        Kv::insert(conn, key, feeds.trim().as_bytes())?;
+
+        let artwork = self.podcast_artwork.trim();
+        Kv::insert(conn, "podcast_artwork", artwork.as_bytes())?;
+        let category = self.podcast_category.trim();
+        Kv::insert(conn, "podcast_category", category.as_bytes())?;
+        let explicit = if self.podcast_explicit.is_some() {
+            "on"
+        } else {
+            "off"
+        };
+        Kv::insert(conn, "podcast_explicit", explicit.as_bytes())?;
+        Ok(())
+    }
 }
 
 pub enum InputType {
@@ -157,9 +219,26 @@ async fn get_settings(State(ctx): State<ServerContext>, jar: CookieJar) -> Respo
             {}
             {}
             {}
+            {}
+            <h2>Podcast</h2>
+            <p>
+                Posts that link to an uploaded audio file are published as
+                episodes at <a href='/podcast.xml'>/podcast.xml</a>.
+            </p>
+            {}
+            {}
+            {}
             <input style='margin-left: 0;' type='submit' value='Save'/>
         </form>
+        <p style='margin-top: 5vh;'>
+            Scripts can access the API via <a href='/settings/tokens'>API tokens</a>.
+            The login can be protected with
+            <a href='/settings/totp'>two-factor authentication</a>
+            and <a href='/settings/passkeys'>passkeys</a>.
+            Logged in devices are listed under <a href='/settings/sessions'>sessions</a>.
+        </p>
         ",
+        crate::csrf::input(&ctx, &jar),
         text_input(
             InputType::Text,
             "site_name",
@@ -222,6 +301,36 @@ async fn get_settings(State(ctx): State<ServerContext>, jar: CookieJar) -> Respo
             The list will be sorted alphabetically upon save.
             ",
             false,
+        ),
+        text_input(
+            InputType::Text,
+            "podcast_artwork",
+            "Podcast Artwork (optional)",
+            &crate::html::escape_single_quote(&settings.podcast_artwork),
+            "URL of a square image of 1400 to 3000 pixels, such as
+            <code>/files/9f0b7bb83bd82946</code> for an uploaded file.",
+            false,
+        ),
+        text_input(
+            InputType::Text,
+            "podcast_category",
+            "Podcast Category (optional)",
+            &crate::html::escape_single_quote(&settings.podcast_category),
+            "A category of Apple Podcasts, such as <code>Technology</code>
+            or <code>Society &amp; Culture &gt; Documentary</code> for a subcategory.",
+            false,
+        ),
+        text_input(
+            InputType::Checkbox,
+            "podcast_explicit",
+            "Explicit podcast",
+            if settings.podcast_explicit.is_some() {
+                "on"
+            } else {
+                "off"
+            },
+            "When enabled, podcast apps mark the episodes as explicit.",
+            false,
         )
     );
     let page_settings =
@@ -230,7 +339,7 @@ async fn get_settings(State(ctx): State<ServerContext>, jar: CookieJar) -> Respo
     response(StatusCode::OK, HeaderMap::new(), body, &ctx)
 }
 
-async fn update_feeds(ctx: &ServerContext) {
+pub async fn update_feeds(ctx: &ServerContext) {
     let blog_cache = ctx.blog_cache.clone();
     let mut blog_cache = blog_cache.lock().await;
     blog_cache.update(ctx).await;
@@ -238,44 +347,9 @@ async fn update_feeds(ctx: &ServerContext) {
 
 async fn post_settings(
     State(ctx): State<ServerContext>,
-    jar: CookieJar,
-    Form(form): Form<Settings>,
+    CsrfForm(form): CsrfForm<Settings>,
 ) -> Response<Body> {
-    let is_logged_in = is_logged_in(&ctx, &jar);
-    if !is_logged_in {
-        return crate::serve::unauthorized(&ctx).await;
-    }
-    {
-        let conn = ctx.conn();
-        Kv::insert(&conn, "site_name", form.site_name.trim().as_bytes()).unwrap();
-        Kv::insert(
-            &conn,
-            "site_description",
-            form.site_description.trim().as_bytes(),
-        )
-        .unwrap();
-        Kv::insert(&conn, "author_name", form.author_name.trim().as_bytes()).unwrap();
-        let dark_mode = if form.dark_mode.is_some() {
-            "on"
-        } else {
-            "off"
-        };
-        Kv::insert(&conn, "dark_mode", dark_mode.as_bytes()).unwrap();
-        let about = cleanup_content(&form.about);
-        Kv::insert(&conn, "about", about.as_bytes()).unwrap();
-        let extra_head = cleanup_content(&form.extra_head);
-        Kv::insert(&conn, "extra_head", extra_head.as_bytes()).unwrap();
-
-        let key = crate::data::BLOGROLL_SETTINGS_KEY;
-        let mut feeds = form
-            .blogroll_feeds
-            .split("\n")
-            .map(|line| line.trim())
-            .collect::<Vec<_>>();
-        feeds.sort();
-        let feeds = feeds.join("\n");
-        Kv::insert(&conn, key, feeds.trim().as_bytes()).unwrap();
-    }
+    form.save(&ctx.conn()).unwrap();
     let ctx_clone = ctx.clone();
     tokio::task::spawn_blocking(async move || {
         update_feeds(&ctx_clone).await;
@@ -1,14 +1,5 @@
 document.addEventListener("DOMContentLoaded", function() {
-    // The CommonMark parser wraps the math code in <code> tags with the class
-    // `language-math`. It also adds a class `math-inline` to inline math and
-    // `math-display` to display math.
-    const mathCodeBlocks = document.querySelectorAll('code.language-math');
-
-    mathCodeBlocks.forEach((codeBlock) => {
-        const tex = codeBlock.textContent;
-
-        const isDisplayMode = codeBlock.classList.contains('math-display');
-
+    function render(tex, element, isDisplayMode) {
         const container = document.createElement(isDisplayMode ? 'div' : 'span');
         if (isDisplayMode) {
             container.classList.add('katex-display');
@@ -19,14 +10,35 @@ document.addEventListener("DOMContentLoaded", function() {
                 throwOnError: false,
                 displayMode: isDisplayMode
             });
-
-            if (isDisplayMode && codeBlock.parentNode) {
-                codeBlock.parentNode.parentNode.replaceChild(container, codeBlock.parentNode);
-             } else if (codeBlock.parentNode){
-                codeBlock.parentNode.replaceChild(container, codeBlock);
-             }
+            if (element.parentNode) {
+                element.parentNode.replaceChild(container, element);
+            }
         } catch (e) {
             console.error("KaTeX rendering error for:", tex, "\nError:", e);
         }
+    }
+
+    // Math that was converted to MathML on the server keeps the LaTeX source
+    // in an annotation.
+    const mathElements = document.querySelectorAll('math');
+
+    mathElements.forEach((math) => {
+        const annotation = math.querySelector('annotation[encoding="application/x-tex"]');
+        if (annotation) {
+            render(annotation.textContent, math, math.getAttribute('display') === 'block');
+        }
+    });
+
+    // The CommonMark parser wraps the math code in <code> tags with the class
+    // `language-math`. It also adds a class `math-inline` to inline math and
+    // `math-display` to display math. These are only left when the math could
+    // not be converted to MathML.
+    const mathCodeBlocks = document.querySelectorAll('code.language-math');
+
+    mathCodeBlocks.forEach((codeBlock) => {
+        const isDisplayMode = codeBlock.classList.contains('math-display');
+        const isInPre = codeBlock.parentNode && codeBlock.parentNode.tagName === 'PRE';
+        const element = isDisplayMode && isInPre ? codeBlock.parentNode : codeBlock;
+        render(codeBlock.textContent, element, isDisplayMode);
     });
 });
@@ -40,3 +40,43 @@ function disable_form_submit_on_start() {
 }
 
 disable_form_submit_on_start();
+
+// Inline event handlers are blocked by the Content-Security-Policy, so the
+// handlers are attached here instead.
+function add_event_listeners() {
+    document.querySelectorAll('[data-copy-code]').forEach((link) => {
+        link.addEventListener('click', (event) => {
+            event.preventDefault();
+            copyCode(link.getAttribute('data-copy-code'));
+        });
+    });
+    const copyLongUrlLink = document.getElementById('copy-long-url');
+    if (copyLongUrlLink) {
+        copyLongUrlLink.addEventListener('click', (event) => {
+            event.preventDefault();
+            copyLongUrl();
+        });
+    }
+    document.querySelectorAll('[data-history-back]').forEach((link) => {
+        link.addEventListener('click', (event) => {
+            event.preventDefault();
+            history.back();
+        });
+    });
+    document.querySelectorAll('textarea[data-disable-submit-if-empty]').forEach((textarea) => {
+        textarea.addEventListener('input', () => {
+            disable_form_submit_if_empty(textarea);
+        });
+    });
+    // Ask for confirmation before leaving a form with unsaved changes.
+    document.querySelectorAll('form[data-leave-confirmation]').forEach((form) => {
+        form.addEventListener('change', () => {
+            window.onbeforeunload = () => true;
+        });
+        form.addEventListener('submit', () => {
+            window.onbeforeunload = null;
+        });
+    });
+}
+
+add_event_listeners();
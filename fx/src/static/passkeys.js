@@ -0,0 +1,92 @@
+// Registration and login with passkeys.
+//
+// The browser API works with ArrayBuffers while the server sends and expects
+// base64url strings, so most of this is converting between the two.
+function b64url_to_buffer(value) {
+    const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+    const padded = base64 + "=".repeat((4 - (base64.length % 4)) % 4);
+    const binary = atob(padded);
+    const bytes = new Uint8Array(binary.length);
+    for (let i = 0; i < binary.length; i++) {
+        bytes[i] = binary.charCodeAt(i);
+    }
+    return bytes.buffer;
+}
+
+function buffer_to_b64url(buffer) {
+    const bytes = new Uint8Array(buffer);
+    let binary = "";
+    for (let i = 0; i < bytes.length; i++) {
+        binary += String.fromCharCode(bytes[i]);
+    }
+    return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+function show_passkey_error(message) {
+    const error = document.getElementById("passkey-error");
+    if (error) {
+        error.textContent = message;
+    }
+}
+
+async function post_json(url, body) {
+    const response = await fetch(url, {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify(body),
+    });
+    const json = await response.json();
+    if (!response.ok) {
+        throw new Error(json.message);
+    }
+    return json;
+}
+
+async function register_passkey() {
+    const options = await post_json("/webauthn/register/options", {});
+    options.challenge = b64url_to_buffer(options.challenge);
+    options.user.id = b64url_to_buffer(options.user.id);
+    options.excludeCredentials.forEach((credential) => {
+        credential.id = b64url_to_buffer(credential.id);
+    });
+    const credential = await navigator.credentials.create({ publicKey: options });
+    const name = document.getElementById("passkey-name").value;
+    await post_json("/webauthn/register", {
+        name: name,
+        client_data_json: buffer_to_b64url(credential.response.clientDataJSON),
+        attestation_object: buffer_to_b64url(credential.response.attestationObject),
+    });
+    window.location.reload();
+}
+
+async function login_with_passkey() {
+    const options = await post_json("/webauthn/login/options", {});
+    options.challenge = b64url_to_buffer(options.challenge);
+    const credential = await navigator.credentials.get({ publicKey: options });
+    const json = await post_json("/webauthn/login", {
+        id: buffer_to_b64url(credential.rawId),
+        client_data_json: buffer_to_b64url(credential.response.clientDataJSON),
+        authenticator_data: buffer_to_b64url(credential.response.authenticatorData),
+        signature: buffer_to_b64url(credential.response.signature),
+    });
+    window.location.href = json.redirect;
+}
+
+function setup_passkey_button(id, action) {
+    const button = document.getElementById(id);
+    if (!button) {
+        return;
+    }
+    if (!window.PublicKeyCredential) {
+        button.disabled = true;
+        show_passkey_error("This browser does not support passkeys.");
+        return;
+    }
+    button.addEventListener("click", () => {
+        show_passkey_error("");
+        action().catch((error) => show_passkey_error(error.message));
+    });
+}
+
+setup_passkey_button("passkey-register", register_passkey);
+setup_passkey_button("passkey-login", login_with_passkey);
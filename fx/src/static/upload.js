@@ -0,0 +1,117 @@
+// Uploads on the files page with progress and resumption.
+//
+// Files are sent in chunks. When a chunk fails, for example because the
+// connection dropped, the upload continues from the offset that the server
+// reports. Without this script, the form is submitted as usual.
+const upload_form = document.getElementById("upload-form");
+const upload_status = document.getElementById("upload-status");
+const csrf_token = upload_form.elements.csrf_token.value;
+const retries = 5;
+
+function sleep(ms) {
+    return new Promise((resolve) => setTimeout(resolve, ms));
+}
+
+async function start_upload(file, prefix) {
+    const response = await fetch("/files/uploads", {
+        method: "POST",
+        headers: { "Content-Type": "application/json", "X-CSRF-Token": csrf_token },
+        body: JSON.stringify({
+            filename: file.name,
+            prefix: prefix,
+            mime_type: file.type,
+            size: file.size,
+            key: `${file.name} ${file.size} ${file.lastModified}`,
+        }),
+    });
+    const json = await response.json();
+    if (!response.ok) {
+        throw new Error(json.message);
+    }
+    return json;
+}
+
+// Send a chunk with XMLHttpRequest since fetch does not report the progress
+// of the request body.
+function send_chunk(id, offset, chunk, on_progress) {
+    return new Promise((resolve, reject) => {
+        const request = new XMLHttpRequest();
+        request.open("PUT", `/files/uploads/${id}`);
+        request.setRequestHeader("Content-Type", "application/octet-stream");
+        request.setRequestHeader("X-CSRF-Token", csrf_token);
+        request.setRequestHeader("Upload-Offset", offset);
+        request.responseType = "json";
+        request.upload.onprogress = (event) => on_progress(offset + event.loaded);
+        request.onload = () => {
+            const json = request.response || {};
+            if (request.status === 200 || request.status === 409) {
+                resolve(json);
+            } else {
+                reject(new Error(json.message || `status ${request.status}`));
+            }
+        };
+        request.onerror = () => reject(new Error("connection failed"));
+        request.send(chunk);
+    });
+}
+
+async function upload_file(file, prefix) {
+    const line = document.createElement("div");
+    const progress = document.createElement("progress");
+    progress.max = file.size || 1;
+    progress.value = 0;
+    const label = document.createElement("span");
+    label.textContent = ` ${file.name}`;
+    line.append(progress, label);
+    upload_status.append(line);
+    const on_progress = (value) => {
+        progress.value = value;
+    };
+
+    let upload = await start_upload(file, prefix);
+    let offset = upload.offset;
+    let failures = 0;
+    // Empty files are sent as one empty chunk so that the server stores them.
+    do {
+        const chunk = file.slice(offset, offset + upload.chunk_size);
+        try {
+            const json = await send_chunk(upload.id, offset, chunk, on_progress);
+            offset = json.offset;
+            failures = 0;
+        } catch (error) {
+            failures += 1;
+            if (retries < failures) {
+                throw error;
+            }
+            label.textContent = ` ${file.name} (retrying: ${error.message})`;
+            await sleep(1000 * 2 ** failures);
+            upload = await start_upload(file, prefix);
+            offset = upload.offset;
+            label.textContent = ` ${file.name}`;
+        }
+        on_progress(offset);
+    } while (offset < file.size);
+}
+
+upload_form.addEventListener("submit", async (event) => {
+    const files = Array.from(upload_form.elements.file.files);
+    if (files.length === 0) {
+        return;
+    }
+    event.preventDefault();
+    const submit = upload_form.querySelector("input[type='submit']");
+    submit.disabled = true;
+    const prefix = upload_form.elements.prefix.value;
+    try {
+        for (const file of files) {
+            await upload_file(file, prefix);
+        }
+        window.location.href = "/files";
+    } catch (error) {
+        const message = document.createElement("div");
+        message.style.fontStyle = "italic";
+        message.textContent = `Upload failed: ${error.message}`;
+        upload_status.append(message);
+        submit.disabled = false;
+    }
+});
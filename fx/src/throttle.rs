@@ -0,0 +1,358 @@
+//! Brute-force protection for `/login`.
+//!
+//! Failed attempts are tracked per client and for the whole site. After a few
+//! free attempts, each further failure doubles the time that has to pass
+//! before the next attempt is accepted.
+use crate::serve::ServerContext;
+use axum::extract::ConnectInfo;
+use axum::extract::FromRequestParts;
+use axum::http::HeaderMap;
+use axum::http::request::Parts;
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Failures per client before the backoff starts.
+const CLIENT_FREE_ATTEMPTS: u32 = 5;
+/// Failures over all clients before the backoff starts for everyone.
+///
+/// This protects against attackers that spread the attempts over many IPs.
+const GLOBAL_FREE_ATTEMPTS: u32 = 100;
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(15 * 60);
+/// Failures are forgotten when no new failure occurred for this long.
+const CLIENT_FORGET_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+const GLOBAL_FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+/// Limit memory usage when attempts come from many different IPs.
+const MAX_CLIENTS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Failures {
+    fn locked_until(&self, free_attempts: u32) -> Option<Instant> {
+        if self.count < free_attempts {
+            return None;
+        }
+        let exponent = (self.count - free_attempts).min(16);
+        let delay = BASE_DELAY.saturating_mul(1 << exponent).min(MAX_DELAY);
+        Some(self.last + delay)
+    }
+    fn is_forgotten(&self, now: Instant, forget_after: Duration) -> bool {
+        now.saturating_duration_since(self.last) > forget_after
+    }
+}
+
+/// Failed login attempts.
+///
+/// Clients are identified by IP. Unknown IPs, which should only occur in
+/// tests, share one entry.
+#[derive(Debug, Default)]
+pub struct LoginThrottle {
+    clients: HashMap<Option<IpAddr>, Failures>,
+    global: Option<Failures>,
+}
+
+/// Key for the client.
+///
+/// IPv6 addresses are grouped by /64 since a single host usually has a whole
+/// /64 at its disposal.
+fn client_key(ip: Option<IpAddr>) -> Option<IpAddr> {
+    ip.map(|ip| match ip.to_canonical() {
+        IpAddr::V6(v6) => {
+            let prefix = u128::from(v6) & !(u128::MAX >> 64);
+            IpAddr::V6(prefix.into())
+        }
+        v4 => v4,
+    })
+}
+
+impl LoginThrottle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn forget(&mut self, now: Instant) {
+        self.clients
+            .retain(|_, failures| !failures.is_forgotten(now, CLIENT_FORGET_AFTER));
+        if let Some(global) = self.global
+            && global.is_forgotten(now, GLOBAL_FORGET_AFTER)
+        {
+            self.global = None;
+        }
+    }
+    /// Return how long the client has to wait before it can try again.
+    fn check(&mut self, ip: Option<IpAddr>, now: Instant) -> Option<Duration> {
+        self.forget(now);
+        let client = self
+            .clients
+            .get(&client_key(ip))
+            .and_then(|failures| failures.locked_until(CLIENT_FREE_ATTEMPTS));
+        let global = self
+            .global
+            .and_then(|failures| failures.locked_until(GLOBAL_FREE_ATTEMPTS));
+        client
+            .into_iter()
+            .chain(global)
+            .max()
+            .filter(|until| now < *until)
+            .map(|until| until - now)
+    }
+    /// Record a failed attempt and return the number of consecutive failures
+    /// for this client.
+    fn record_failure(&mut self, ip: Option<IpAddr>, now: Instant) -> u32 {
+        self.forget(now);
+        if MAX_CLIENTS <= self.clients.len() {
+            let oldest = self
+                .clients
+                .iter()
+                .min_by_key(|(_, failures)| failures.last)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.clients.remove(&oldest);
+            }
+        }
+        let client = self.clients.entry(client_key(ip)).or_insert(Failures {
+            count: 0,
+            last: now,
+        });
+        client.count += 1;
+        client.last = now;
+        let count = client.count;
+        let global = self.global.get_or_insert(Failures {
+            count: 0,
+            last: now,
+        });
+        global.count += 1;
+        global.last = now;
+        if global.count == GLOBAL_FREE_ATTEMPTS {
+            tracing::error!(
+                "{GLOBAL_FREE_ATTEMPTS} failed logins within the last hour; \
+                delaying all login attempts"
+            );
+        }
+        count
+    }
+    /// Start a login attempt before verifying the credentials.
+    ///
+    /// Returns how long the client has to wait when it is locked out, and the
+    /// number of consecutive attempts otherwise. The check and the count
+    /// happen at once, so parallel guesses cannot all pass the check before
+    /// the first failure is recorded. The attempt counts as failure until
+    /// [LoginThrottle::record_success] is called.
+    pub fn begin(&mut self, ip: Option<IpAddr>, now: Instant) -> Result<u32, Duration> {
+        match self.check(ip, now) {
+            Some(wait) => Err(wait),
+            None => Ok(self.record_failure(ip, now)),
+        }
+    }
+    /// Clear the attempts of the client after a successful login.
+    pub fn record_success(&mut self, ip: Option<IpAddr>) {
+        if let Some(client) = self.clients.remove(&client_key(ip))
+            && let Some(global) = &mut self.global
+        {
+            global.count = global.count.saturating_sub(client.count);
+        }
+    }
+}
+
+#[test]
+fn test_begin() {
+    let mut throttle = LoginThrottle::new();
+    let ip = Some("192.0.2.1".parse().unwrap());
+    let now = Instant::now();
+    // Parallel attempts are counted before any of them is verified.
+    for i in 1..=CLIENT_FREE_ATTEMPTS {
+        assert_eq!(throttle.begin(ip, now), Ok(i));
+    }
+    assert_eq!(throttle.begin(ip, now), Err(Duration::from_secs(1)));
+
+    let other = Some("192.0.2.2".parse().unwrap());
+    assert_eq!(throttle.begin(other, now), Ok(1));
+    throttle.record_success(other);
+    assert_eq!(throttle.begin(other, now), Ok(1));
+    throttle.record_success(other);
+    // Successful logins do not count towards the global backoff.
+    assert_eq!(throttle.global.unwrap().count, CLIENT_FREE_ATTEMPTS);
+}
+
+#[test]
+fn test_backoff() {
+    let mut throttle = LoginThrottle::new();
+    let ip = Some("192.0.2.1".parse().unwrap());
+    let start = Instant::now();
+    for i in 1..CLIENT_FREE_ATTEMPTS {
+        assert_eq!(throttle.record_failure(ip, start), i);
+        assert_eq!(throttle.check(ip, start), None);
+    }
+    throttle.record_failure(ip, start);
+    assert_eq!(throttle.check(ip, start), Some(Duration::from_secs(1)));
+    let later = start + Duration::from_secs(1);
+    assert_eq!(throttle.check(ip, later), None);
+    throttle.record_failure(ip, later);
+    assert_eq!(throttle.check(ip, later), Some(Duration::from_secs(2)));
+    for _ in 0..20 {
+        throttle.record_failure(ip, later);
+    }
+    assert_eq!(throttle.check(ip, later), Some(MAX_DELAY));
+
+    let other = Some("192.0.2.2".parse().unwrap());
+    assert_eq!(throttle.check(other, later), None);
+
+    let much_later = later + CLIENT_FORGET_AFTER + Duration::from_secs(1);
+    assert_eq!(throttle.check(ip, much_later), None);
+    assert_eq!(throttle.record_failure(ip, much_later), 1);
+
+    throttle.record_success(ip);
+    assert_eq!(throttle.record_failure(ip, much_later), 1);
+}
+
+#[test]
+fn test_global_backoff() {
+    let mut throttle = LoginThrottle::new();
+    let now = Instant::now();
+    for i in 0..GLOBAL_FREE_ATTEMPTS {
+        let ip = Some(IpAddr::from([198, 51, 100, i as u8]));
+        throttle.record_failure(ip, now);
+    }
+    let ip = Some("203.0.113.1".parse().unwrap());
+    assert_eq!(throttle.check(ip, now), Some(Duration::from_secs(1)));
+}
+
+#[test]
+fn test_client_key() {
+    let a = Some("2001:db8::1".parse().unwrap());
+    let b = Some("2001:db8::2".parse().unwrap());
+    let c = Some("2001:db8:0:1::1".parse().unwrap());
+    assert_eq!(client_key(a), client_key(b));
+    assert_ne!(client_key(a), client_key(c));
+    let mapped = Some("::ffff:192.0.2.1".parse().unwrap());
+    assert_eq!(client_key(mapped), Some("192.0.2.1".parse().unwrap()));
+}
+
+/// Parse a trusted proxy, which is either an IP or a CIDR range.
+pub fn parse_trusted_proxy(s: &str) -> Result<IpNet, String> {
+    let s = s.trim();
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Ok(IpNet::from(ip));
+    }
+    s.parse::<IpNet>()
+        .map_err(|_| format!("expected an IP or CIDR range but got {s:?}"))
+}
+
+#[test]
+fn test_parse_trusted_proxy() {
+    let proxy = parse_trusted_proxy("127.0.0.1").unwrap();
+    assert!(proxy.contains(&"127.0.0.1".parse::<IpAddr>().unwrap()));
+    let proxy = parse_trusted_proxy("172.16.0.0/12").unwrap();
+    assert!(proxy.contains(&"172.18.0.2".parse::<IpAddr>().unwrap()));
+    assert!(parse_trusted_proxy("localhost").is_err());
+}
+
+/// Determine the client IP for a connection from `peer`.
+///
+/// `X-Forwarded-For` can be set by anyone, so it is only used when the peer is
+/// a trusted proxy. The header is read from right to left since each proxy
+/// appends the address that it received the request from. The first address
+/// that is not a trusted proxy is the client.
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    let mut client = peer.to_canonical();
+    if !is_trusted(&client) {
+        return client;
+    }
+    let forwarded = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|addr| addr.trim())
+        .collect::<Vec<_>>();
+    for addr in forwarded.iter().rev() {
+        match addr.parse::<IpAddr>() {
+            Ok(ip) => client = ip.to_canonical(),
+            // Stop at garbage since anything to the left of it is unreliable.
+            Err(_) => return client,
+        }
+        if !is_trusted(&client) {
+            return client;
+        }
+    }
+    client
+}
+
+#[test]
+fn test_client_ip() {
+    let proxy = "10.0.0.1".parse().unwrap();
+    let client: IpAddr = "203.0.113.7".parse().unwrap();
+    let trusted = vec![parse_trusted_proxy("10.0.0.0/8").unwrap()];
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "X-Forwarded-For",
+        "198.51.100.1, 203.0.113.7, 10.0.0.2".parse().unwrap(),
+    );
+    assert_eq!(client_ip(proxy, &headers, &trusted), client);
+    // Spoofed header from an untrusted peer.
+    assert_eq!(client_ip(client, &headers, &trusted), client);
+    assert_eq!(client_ip(proxy, &headers, &[]), proxy);
+    headers.insert("X-Forwarded-For", "garbage, 10.0.0.2".parse().unwrap());
+    let last_proxy: IpAddr = "10.0.0.2".parse().unwrap();
+    assert_eq!(client_ip(proxy, &headers, &trusted), last_proxy);
+}
+
+/// IP of the client as determined by [client_ip].
+///
+/// Contains `None` when the server was not started with connect info, which
+/// is the case in tests.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl FromRequestParts<ServerContext> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        ctx: &ServerContext,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip());
+        let trusted_proxies = &ctx.args.trusted_proxies;
+        let ip = peer.map(|peer| client_ip(peer, &parts.headers, trusted_proxies));
+        Ok(ClientIp(ip))
+    }
+}
+
+/// Show the client IP in the audit log.
+pub fn show_ip(ip: Option<IpAddr>) -> String {
+    match ip {
+        Some(ip) => ip.to_string(),
+        None => "unknown IP".to_string(),
+    }
+}
+
+/// Show the wait time in the lockout message.
+pub fn show_wait(wait: Duration) -> String {
+    let secs = wait.as_secs().max(1);
+    if secs < 60 {
+        let unit = if secs == 1 { "second" } else { "seconds" };
+        format!("{secs} {unit}")
+    } else {
+        let mins = secs.div_ceil(60);
+        let unit = if mins == 1 { "minute" } else { "minutes" };
+        format!("{mins} {unit}")
+    }
+}
+
+#[test]
+fn test_show_wait() {
+    assert_eq!(show_wait(Duration::from_millis(300)), "1 second");
+    assert_eq!(show_wait(Duration::from_secs(30)), "30 seconds");
+    assert_eq!(show_wait(Duration::from_secs(61)), "2 minutes");
+}
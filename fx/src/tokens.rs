@@ -0,0 +1,518 @@
+//! Scoped API tokens at `/settings/tokens`.
+//!
+//! Tokens allow scripts such as the backup workflow to use the API without
+//! knowing the admin password. Each token only gets the scopes that it needs
+//! and can be revoked without affecting the other tokens.
+use crate::csrf::Csrf;
+use crate::csrf::CsrfForm;
+use crate::data::SqliteDateTime;
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::escape_html;
+use crate::html::page;
+use crate::indieauth::AccessToken;
+use crate::serve::ServerContext;
+use crate::serve::is_logged_in;
+use crate::serve::response;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::FromRequestParts;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::Result;
+use rusqlite::params;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// Read posts, files, and settings.
+    Read,
+    /// Create, update, and delete posts.
+    WritePosts,
+    /// Upload and delete files.
+    WriteFiles,
+    /// Update the settings.
+    Settings,
+    /// Download the full archive.
+    Backup,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 5] = [
+        Scope::Read,
+        Scope::WritePosts,
+        Scope::WriteFiles,
+        Scope::Settings,
+        Scope::Backup,
+    ];
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::WritePosts => "write_posts",
+            Scope::WriteFiles => "write_files",
+            Scope::Settings => "settings",
+            Scope::Backup => "backup",
+        }
+    }
+    pub fn parse(s: &str) -> Option<Self> {
+        Scope::ALL.into_iter().find(|scope| scope.as_str() == s)
+    }
+    fn description(&self) -> &'static str {
+        match self {
+            Scope::Read => "Read posts, files, and settings",
+            Scope::WritePosts => "Create, update, and delete posts",
+            Scope::WriteFiles => "Upload and delete files",
+            Scope::Settings => "Update the settings",
+            Scope::Backup => "Download the backup archive",
+        }
+    }
+    /// Scopes that an IndieAuth access token grants.
+    ///
+    /// Tokens that only confirm the identity, such as with the `profile`
+    /// scope, cannot use the API since it also exposes drafts and settings.
+    fn from_indieauth(token: &AccessToken) -> Vec<Scope> {
+        let mut scopes = Vec::new();
+        let writes_posts = ["create", "update", "delete"]
+            .iter()
+            .any(|s| token.has_scope(s));
+        let writes_files = token.has_scope("media");
+        if token.has_scope("read") || writes_posts || writes_files {
+            scopes.push(Scope::Read);
+        }
+        if writes_posts {
+            scopes.push(Scope::WritePosts);
+        }
+        if writes_files {
+            scopes.push(Scope::WriteFiles);
+        }
+        scopes
+    }
+}
+
+#[test]
+fn test_from_indieauth() {
+    let token = |scope: &str| AccessToken {
+        client_id: "https://app.example.com/".to_string(),
+        scope: scope.to_string(),
+        created: Utc::now(),
+    };
+    assert_eq!(Scope::from_indieauth(&token("")), vec![]);
+    assert_eq!(Scope::from_indieauth(&token("profile")), vec![]);
+    assert_eq!(Scope::from_indieauth(&token("read")), vec![Scope::Read]);
+    let scopes = Scope::from_indieauth(&token("profile create media"));
+    let expected = vec![Scope::Read, Scope::WritePosts, Scope::WriteFiles];
+    assert_eq!(scopes, expected);
+}
+
+fn parse_scopes(s: &str) -> Vec<Scope> {
+    s.split_ascii_whitespace()
+        .filter_map(Scope::parse)
+        .collect()
+}
+
+#[test]
+fn test_parse_scopes() {
+    let scopes = parse_scopes("read backup unknown");
+    assert_eq!(scopes, vec![Scope::Read, Scope::Backup]);
+}
+
+/// Token as stored in the database.
+///
+/// Only the hash of the token is stored. The token itself is shown once after
+/// creating it.
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+fn optional_datetime(text: Option<String>) -> Option<DateTime<Utc>> {
+    text.map(|text| DateTime::from_sqlite(&text))
+}
+
+impl ApiToken {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS api_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT NOT NULL,
+                created DATETIME NOT NULL,
+                expires DATETIME,
+                last_used DATETIME
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    /// Store a new token and return the plaintext token.
+    pub fn insert(
+        conn: &Connection,
+        name: &str,
+        scopes: &[Scope],
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<String> {
+        // The prefix makes it easier to recognize leaked tokens.
+        let token = format!("fx_{}", fx_auth::generate_token());
+        let stmt = "
+            INSERT INTO api_tokens (name, token_hash, scopes, created, expires)
+            VALUES (?, ?, ?, ?, ?);
+        ";
+        let scopes = scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let params = params![
+            name,
+            fx_auth::hash_token(&token),
+            scopes,
+            Utc::now().to_sqlite(),
+            expires.map(|expires| expires.to_sqlite()),
+        ];
+        conn.execute(stmt, params)?;
+        Ok(token)
+    }
+    fn from_row(row: &rusqlite::Row) -> Result<Self> {
+        let scopes: String = row.get("scopes")?;
+        let created: String = row.get("created")?;
+        Ok(ApiToken {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            scopes: parse_scopes(&scopes),
+            created: DateTime::from_sqlite(&created),
+            expires: optional_datetime(row.get("expires")?),
+            last_used: optional_datetime(row.get("last_used")?),
+        })
+    }
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        let stmt = "
+            SELECT id, name, scopes, created, expires, last_used
+            FROM api_tokens
+            ORDER BY id DESC;
+        ";
+        conn.prepare(stmt)?
+            .query_map([], Self::from_row)?
+            .collect::<Result<Vec<_>, _>>()
+    }
+    /// Return the token if it exists, regardless of whether it expired.
+    pub fn get(conn: &Connection, token: &str) -> Result<Option<Self>> {
+        let stmt = "
+            SELECT id, name, scopes, created, expires, last_used
+            FROM api_tokens
+            WHERE token_hash = ?;
+        ";
+        conn.prepare(stmt)?
+            .query_row([fx_auth::hash_token(token)], Self::from_row)
+            .optional()
+    }
+    pub fn touch(conn: &Connection, id: i64) -> Result<usize> {
+        let stmt = "UPDATE api_tokens SET last_used = ? WHERE id = ?";
+        conn.execute(stmt, params![Utc::now().to_sqlite(), id])
+    }
+    pub fn revoke(conn: &Connection, id: i64) -> Result<usize> {
+        let stmt = "DELETE FROM api_tokens WHERE id = ?";
+        conn.execute(stmt, [id])
+    }
+    pub fn is_expired(&self) -> bool {
+        match self.expires {
+            Some(expires) => expires < Utc::now(),
+            None => false,
+        }
+    }
+}
+
+/// Authentication for the `/api` routes.
+///
+/// Resolves the `Authorization: Bearer` header to the scopes that the caller
+/// is allowed to use. Rejects the request when the token is missing, unknown,
+/// or expired.
+pub struct ApiAuth {
+    scopes: Vec<Scope>,
+}
+
+impl ApiAuth {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+fn resolve_scopes(ctx: &ServerContext, token: &str) -> Option<Vec<Scope>> {
+    let conn = ctx.conn();
+    match ApiToken::get(&conn, token) {
+        Ok(Some(api_token)) => {
+            if api_token.is_expired() {
+                return None;
+            }
+            if let Err(e) = ApiToken::touch(&conn, api_token.id) {
+                tracing::error!("failed to update last used of token: {e}");
+            }
+            return Some(api_token.scopes);
+        }
+        Ok(None) => (),
+        Err(e) => {
+            tracing::error!("failed to get token: {e}");
+            return None;
+        }
+    }
+    if let Ok(Some(access_token)) = AccessToken::get(&conn, token) {
+        let scopes = Scope::from_indieauth(&access_token);
+        return (!scopes.is_empty()).then_some(scopes);
+    }
+    None
+}
+
+impl FromRequestParts<ServerContext> for ApiAuth {
+    type Rejection = Response<Body>;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        ctx: &ServerContext,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let scopes = crate::indieauth::bearer_token(&parts.headers)
+            .and_then(|token| resolve_scopes(ctx, token));
+        match scopes {
+            Some(scopes) => Ok(ApiAuth { scopes }),
+            None => Err(crate::api::error(
+                ctx,
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+            )),
+        }
+    }
+}
+
+fn show_token(token: &ApiToken, csrf: &str) -> String {
+    let id = token.id;
+    let name = escape_html(&token.name);
+    let scopes = token
+        .scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let created = crate::html::show_date(&token.created);
+    let expires = match token.expires {
+        Some(_) if token.is_expired() => "expired".to_string(),
+        Some(expires) => format!("expires {}", crate::html::show_date(&expires)),
+        None => "never expires".to_string(),
+    };
+    let last_used = match token.last_used {
+        Some(last_used) => format!("last used {}", crate::html::show_date(&last_used)),
+        None => "never used".to_string(),
+    };
+    format!(
+        "
+        <div style='padding: 6px; padding-top: 12px; \
+          border-bottom: 1px solid var(--border); font-size: 0.8rem;'>
+            <div style='display: flex; justify-content: space-between;'>
+                <strong>{name}</strong>
+                <form method='post' action='/settings/tokens/revoke/{id}'>
+                    {csrf}
+                    <button type='submit'>Revoke</button>
+                </form>
+            </div>
+            <span>Scopes: {scopes}</span><br>
+            <span>Created {created}, {expires}, {last_used}.</span>
+        </div>
+        "
+    )
+}
+
+fn new_token_form(csrf: &str) -> String {
+    let scopes = Scope::ALL
+        .iter()
+        .map(|scope| {
+            let name = scope.as_str();
+            let description = scope.description();
+            format!(
+                "
+                <input type='checkbox' id='scope-{name}' name='scope' value='{name}'/>
+                <label for='scope-{name}'>{description} (<code>{name}</code>)</label><br>
+                "
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("");
+    format!(
+        "
+        <form method='post' action='/settings/tokens' style='margin-top: 5vh;'>
+            {csrf}
+            <label for='name'>Name</label><br>
+            <input type='text' id='name' name='name' placeholder='backup' \
+              style='width: 100%; margin-left: 0;' required/><br>
+            <br>
+            {scopes}
+            <br>
+            <label for='expires_in_days'>Expiration</label><br>
+            <select id='expires_in_days' name='expires_in_days'>
+                <option value='30'>30 days</option>
+                <option value='90'>90 days</option>
+                <option value='365'>1 year</option>
+                <option value=''>Never</option>
+            </select><br>
+            <br>
+            <input style='margin-left: 0;' type='submit' value='Create token'/>
+        </form>
+        "
+    )
+}
+
+async fn tokens_page(
+    ctx: &ServerContext,
+    jar: &CookieJar,
+    new_token: Option<&str>,
+) -> Response<Body> {
+    let tokens = match ApiToken::list(&ctx.conn()) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            let msg = "Could not get tokens from database";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(ctx, msg).await;
+        }
+    };
+    let new_token = match new_token {
+        Some(token) => format!(
+            "
+            <div style='margin-top: 5vh;'>
+                <p>Copy the new token now. It will not be shown again.</p>
+                <pre><code id='new-token'>{token}</code></pre>
+            </div>
+            "
+        ),
+        None => "".to_string(),
+    };
+    let csrf = crate::csrf::input(ctx, jar);
+    let tokens = tokens
+        .iter()
+        .map(|token| show_token(token, &csrf))
+        .collect::<Vec<_>>()
+        .join("");
+    let body = format!(
+        "
+        {new_token}
+        {}
+        <div style='margin-top: 5vh;'>
+            {tokens}
+        </div>
+        ",
+        new_token_form(&csrf)
+    );
+    let settings = PageSettings::new("API tokens", Some(true), None, false, Top::GoHome, "");
+    let body = page(ctx, &settings, &body).await;
+    response(StatusCode::OK, HeaderMap::new(), body, ctx)
+}
+
+async fn get_tokens(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    if !is_logged_in(&ctx, &jar) {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    tracing::info!("\"GET /settings/tokens HTTP/1.1\" 200");
+    tokens_page(&ctx, &jar, None).await
+}
+
+async fn post_tokens(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    // Parsing manually because `scope` can occur multiple times.
+    CsrfForm(pairs): CsrfForm<Vec<(String, String)>>,
+) -> Response<Body> {
+    let bad_request = |msg| crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Bad Request", msg);
+    let field = |name: &str| {
+        pairs
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let name = match field("name") {
+        Some(name) => name.trim(),
+        None => return bad_request("Missing fields in form").await,
+    };
+    let scopes = pairs
+        .iter()
+        .filter(|(key, _)| key == "scope")
+        .filter_map(|(_, value)| Scope::parse(value))
+        .collect::<Vec<_>>();
+    if scopes.is_empty() {
+        return bad_request("Select at least one scope").await;
+    }
+    if name.is_empty() {
+        return bad_request("Name must not be empty").await;
+    }
+    let expires = match field("expires_in_days") {
+        None | Some("") => None,
+        Some(days) => match expires_in(Utc::now(), days) {
+            Some(expires) => Some(expires),
+            None => return bad_request("Invalid expiration").await,
+        },
+    };
+    let token = match ApiToken::insert(&ctx.conn(), name, &scopes, expires) {
+        Ok(token) => token,
+        Err(e) => {
+            let msg = "Could not store token";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    tracing::info!("\"POST /settings/tokens HTTP/1.1\" 200");
+    tokens_page(&ctx, &jar, Some(&token)).await
+}
+
+/// Longest expiration that can be chosen, which is ten years.
+const MAX_EXPIRES_IN_DAYS: i64 = 3650;
+
+/// When a token expires that is created at `now` and valid for `days`.
+fn expires_in(now: DateTime<Utc>, days: &str) -> Option<DateTime<Utc>> {
+    let days = days.parse::<i64>().ok()?;
+    if !(1..=MAX_EXPIRES_IN_DAYS).contains(&days) {
+        return None;
+    }
+    now.checked_add_signed(TimeDelta::try_days(days)?)
+}
+
+#[test]
+fn test_expires_in() {
+    let now = Utc::now();
+    assert_eq!(expires_in(now, "30"), Some(now + TimeDelta::days(30)));
+    assert_eq!(expires_in(now, "3650"), Some(now + TimeDelta::days(3650)));
+    for days in ["0", "-1", "3651", "9223372036854775807", "a"] {
+        assert_eq!(expires_in(now, days), None, "{days}");
+    }
+}
+
+async fn post_revoke(
+    State(ctx): State<ServerContext>,
+    Path(id): Path<i64>,
+    _: Csrf,
+) -> Response<Body> {
+    if let Err(e) = ApiToken::revoke(&ctx.conn(), id) {
+        let msg = "Could not revoke token";
+        tracing::error!("{msg}: {e}");
+        return crate::serve::internal_server_error(&ctx, msg).await;
+    }
+    tracing::info!("\"POST /settings/tokens/revoke/{id} HTTP/1.1\" 303");
+    crate::serve::see_other(&ctx, "/settings/tokens")
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/settings/tokens", get(get_tokens))
+        .route("/settings/tokens", post(post_tokens))
+        .route("/settings/tokens/revoke/{id}", post(post_revoke))
+}
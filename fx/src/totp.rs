@@ -0,0 +1,478 @@
+//! Two-factor authentication via TOTP.
+//!
+//! When enabled, `/login` only checks the password and then asks for a code
+//! from an authenticator app at `/login/totp`. Recovery codes can be used
+//! instead of a code when the phone is lost.
+use crate::csrf::Csrf;
+use crate::csrf::CsrfForm;
+use crate::csrf::SameOrigin;
+use crate::data::Kv;
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::escape_html;
+use crate::html::page;
+use crate::serve::ServerContext;
+use crate::serve::admin_login;
+use crate::serve::is_logged_in;
+use crate::serve::response;
+use crate::settings::Settings;
+use crate::throttle::ClientIp;
+use crate::throttle::show_ip;
+use axum::Form;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use chrono::Utc;
+use fx_auth::totp;
+use qrcode::QrCode;
+use qrcode::render::svg;
+use rusqlite::Connection;
+use rusqlite::TransactionBehavior;
+use serde::Deserialize;
+use std::time::Instant;
+
+const SECRET_KEY: &str = "totp_secret";
+const LAST_STEP_KEY: &str = "totp_last_step";
+const RECOVERY_CODES_KEY: &str = "totp_recovery_codes";
+const RECOVERY_CODES: usize = 10;
+
+fn secret(conn: &Connection) -> Option<Vec<u8>> {
+    let secret = Kv::get_or_empty_string(conn, SECRET_KEY);
+    if secret.is_empty() {
+        return None;
+    }
+    totp::decode_secret(&secret)
+}
+
+pub fn is_enabled(ctx: &ServerContext) -> bool {
+    secret(&ctx.conn()).is_some()
+}
+
+/// Hashes of the unused recovery codes.
+fn recovery_code_hashes(conn: &Connection) -> Vec<String> {
+    Kv::get_or_empty_string(conn, RECOVERY_CODES_KEY)
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Replace the recovery codes and return the new codes.
+fn new_recovery_codes(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let codes = totp::generate_recovery_codes(RECOVERY_CODES);
+    let hashes = codes
+        .iter()
+        .map(|code| fx_auth::hash_token(&totp::normalize_recovery_code(code)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Kv::insert(conn, RECOVERY_CODES_KEY, hashes.as_bytes())?;
+    Ok(codes)
+}
+
+fn enable(conn: &Connection, secret: &[u8], step: i64) -> rusqlite::Result<Vec<String>> {
+    Kv::insert(conn, SECRET_KEY, totp::encode_secret(secret).as_bytes())?;
+    Kv::insert(conn, LAST_STEP_KEY, step.to_string().as_bytes())?;
+    new_recovery_codes(conn)
+}
+
+fn disable(conn: &Connection) -> rusqlite::Result<()> {
+    for key in [SECRET_KEY, LAST_STEP_KEY, RECOVERY_CODES_KEY] {
+        Kv::delete(conn, key)?;
+    }
+    Ok(())
+}
+
+/// Verify a code from the authenticator app or a recovery code.
+///
+/// Runs in a transaction so that two requests cannot both use the same code.
+fn verify(conn: &mut Connection, code: &str) -> rusqlite::Result<bool> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let secret = match secret(&tx) {
+        Some(secret) => secret,
+        None => return Ok(false),
+    };
+    let last_step = Kv::get_or_empty_string(&tx, LAST_STEP_KEY).parse().ok();
+    let now = Utc::now().timestamp();
+    if let Some(step) = totp::verify(&secret, code, now, last_step) {
+        Kv::insert(&tx, LAST_STEP_KEY, step.to_string().as_bytes())?;
+        tx.commit()?;
+        return Ok(true);
+    }
+    let hash = fx_auth::hash_token(&totp::normalize_recovery_code(code));
+    let hashes = recovery_code_hashes(&tx);
+    if !hashes
+        .iter()
+        .any(|h| crate::serve::constant_time_eq(h, &hash))
+    {
+        return Ok(false);
+    }
+    let remaining = hashes
+        .into_iter()
+        .filter(|h| *h != hash)
+        .collect::<Vec<_>>()
+        .join("\n");
+    Kv::insert(&tx, RECOVERY_CODES_KEY, remaining.as_bytes())?;
+    tx.commit()?;
+    tracing::warn!("used a TOTP recovery code");
+    Ok(true)
+}
+
+#[test]
+fn test_verify() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    Kv::create_table(&conn).unwrap();
+    assert!(!verify(&mut conn, "123456").unwrap());
+    let key = totp::generate_secret();
+    let codes = enable(&conn, &key, 0).unwrap();
+    assert_eq!(codes.len(), RECOVERY_CODES);
+
+    let code = totp::code(&key, Utc::now().timestamp());
+    assert!(verify(&mut conn, &code).unwrap());
+    // Replay.
+    assert!(!verify(&mut conn, &code).unwrap());
+
+    assert!(verify(&mut conn, &codes[0].to_uppercase()).unwrap());
+    assert!(!verify(&mut conn, &codes[0]).unwrap());
+    assert_eq!(recovery_code_hashes(&conn).len(), RECOVERY_CODES - 1);
+
+    disable(&conn).unwrap();
+    assert!(secret(&conn).is_none());
+}
+
+fn code_form(csrf: &str, action: &str, submit: &str, placeholder: &str) -> String {
+    let input_style = "font-size: 1rem;";
+    format!(
+        "
+        <form method='post' action='{action}'>
+            {csrf}
+            <input style='{input_style}' id='code' name='code' type='text' \
+              autocomplete='one-time-code' placeholder='{placeholder}' required/><br>
+            <input style='{input_style} margin-left: 0;' type='submit' value='{submit}'/>
+        </form>
+        "
+    )
+}
+
+async fn login_page(
+    ctx: &ServerContext,
+    status: StatusCode,
+    error: Option<&str>,
+) -> Response<Body> {
+    let top = Top::Homepage;
+    let description = "Second step of the login";
+    let settings = PageSettings::new("Login", None, Some(description), false, top, "");
+    let error = match error {
+        Some(error) => format!("<div style='font-style: italic;'>{error}</div>"),
+        None => "".to_string(),
+    };
+    let input_style = "font-size: 1rem;";
+    let body = format!(
+        "
+        <form style='text-align: center; margin-top: 15vh;' method='post' action='/login/totp'>
+            <p>Enter the code from your authenticator app or a recovery code.</p>
+            <input style='{input_style}' id='code' name='code' type='text' \
+              autocomplete='one-time-code' placeholder='code' autofocus required/><br>
+            {error}
+            <input style='{input_style}' type='submit' value='login'/>
+        </form>
+        <div style='text-align: center;'>{}</div>
+        ",
+        crate::passkeys::login_button(ctx, true)
+    );
+    let body = page(ctx, &settings, &body).await;
+    response::<String>(status, HeaderMap::new(), body, ctx)
+}
+
+fn is_login_pending(ctx: &ServerContext, jar: &CookieJar) -> bool {
+    admin_login(ctx).is_some() && fx_auth::is_login_pending(&ctx.key, jar)
+}
+
+async fn get_login_totp(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    if !is_login_pending(&ctx, &jar) {
+        return crate::serve::see_other(&ctx, "/login");
+    }
+    tracing::info!("\"GET /login/totp HTTP/1.1\" 200");
+    login_page(&ctx, StatusCode::OK, None).await
+}
+
+#[derive(Debug, Deserialize)]
+struct CodeForm {
+    code: String,
+}
+
+async fn post_login_totp(
+    State(ctx): State<ServerContext>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    jar: CookieJar,
+    _: SameOrigin,
+    Form(form): Form<CodeForm>,
+) -> Response<Body> {
+    if !is_login_pending(&ctx, &jar) {
+        return crate::serve::see_other(&ctx, "/login");
+    }
+    let now = Instant::now();
+    let attempt = ctx.login_throttle.lock().await.begin(ip, now);
+    let failures = match attempt {
+        Ok(failures) => failures,
+        Err(wait) => {
+            tracing::warn!("rejected TOTP code from {} during lockout", show_ip(ip));
+            let msg = format!(
+                "Too many failed login attempts. Try again in {}.",
+                crate::throttle::show_wait(wait)
+            );
+            return login_page(&ctx, StatusCode::TOO_MANY_REQUESTS, Some(&msg)).await;
+        }
+    };
+    let verified = match verify(&mut ctx.conn(), &form.code) {
+        Ok(verified) => verified,
+        Err(e) => {
+            let msg = "Could not verify code";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    if !verified {
+        tracing::warn!(
+            "invalid TOTP code from {}; {failures} consecutive failures",
+            show_ip(ip)
+        );
+        tracing::info!("\"POST /login/totp HTTP/1.1\" 401");
+        return login_page(&ctx, StatusCode::UNAUTHORIZED, Some("Invalid code")).await;
+    }
+    let jar = match crate::sessions::start(&ctx, &headers, ip, jar) {
+        Ok(jar) => jar,
+        Err(e) => {
+            let msg = "Could not start session";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    ctx.login_throttle.lock().await.record_success(ip);
+    tracing::info!("successful login with TOTP from {}", show_ip(ip));
+    tracing::info!("\"POST /login/totp HTTP/1.1\" 303");
+    (jar, crate::serve::see_other(&ctx, "/")).into_response()
+}
+
+fn qr_code(uri: &str) -> String {
+    let code = QrCode::new(uri.as_bytes()).unwrap();
+    code.render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .quiet_zone(true)
+        .build()
+}
+
+fn show_recovery_codes(codes: &[String]) -> String {
+    let codes = codes.join("\n");
+    format!(
+        "
+        <p>
+            Store these recovery codes in a safe place.
+            Each code can be used once instead of a code from the app.
+            They will not be shown again.
+        </p>
+        <pre><code id='recovery-codes'>{codes}</code></pre>
+        "
+    )
+}
+
+async fn enrollment(ctx: &ServerContext, jar: &CookieJar, secret: &[u8]) -> String {
+    let site_name = match Settings::from_db(&ctx.conn()) {
+        Ok(settings) => settings.site_name,
+        Err(_) => "fx".to_string(),
+    };
+    let uri = totp::otpauth_uri(secret, &site_name, &ctx.args.username);
+    let qr = qr_code(&uri);
+    let encoded = totp::encode_secret(secret);
+    let csrf = crate::csrf::input(ctx, jar);
+    let input_style = "font-size: 1rem;";
+    format!(
+        "
+        <p>
+            Scan the QR code with an authenticator app and enter the code that
+            the app shows to enable two-factor authentication.
+        </p>
+        <div style='max-width: 250px;'>{qr}</div>
+        <p>Or enter this key manually: <code id='totp-secret'>{encoded}</code></p>
+        <form method='post' action='/settings/totp/enable'>
+            {csrf}
+            <input type='hidden' name='secret' value='{encoded}'/>
+            <input style='{input_style}' id='code' name='code' type='text' \
+              autocomplete='one-time-code' placeholder='code' required/><br>
+            <input style='{input_style} margin-left: 0;' type='submit' value='Enable'/>
+        </form>
+        "
+    )
+}
+
+async fn settings_page(
+    ctx: &ServerContext,
+    jar: &CookieJar,
+    status: StatusCode,
+    message: &str,
+    pending_secret: Option<&[u8]>,
+) -> Response<Body> {
+    let conn = ctx.conn();
+    let enabled = secret(&conn).is_some();
+    let remaining = recovery_code_hashes(&conn).len();
+    drop(conn);
+    let csrf = crate::csrf::input(ctx, jar);
+    let content = if enabled {
+        format!(
+            "
+            <p>Two-factor authentication is enabled.</p>
+            <p>{remaining} unused recovery codes left.</p>
+            <form method='post' action='/settings/totp/recovery-codes'>
+                {csrf}
+                <input style='margin-left: 0;' type='submit' value='Generate new recovery codes'/>
+            </form>
+            <p style='margin-top: 5vh;'>Enter a code to disable two-factor authentication.</p>
+            {}
+            ",
+            code_form(&csrf, "/settings/totp/disable", "Disable", "code")
+        )
+    } else {
+        let secret = match pending_secret {
+            Some(secret) => secret.to_vec(),
+            None => totp::generate_secret(),
+        };
+        enrollment(ctx, jar, &secret).await
+    };
+    let body = format!(
+        "
+        <div style='margin-top: 5vh;'>
+            {message}
+            {content}
+        </div>
+        "
+    );
+    let settings = PageSettings::new(
+        "Two-factor authentication",
+        Some(true),
+        None,
+        false,
+        Top::GoHome,
+        "",
+    );
+    let body = page(ctx, &settings, &body).await;
+    response(status, HeaderMap::new(), body, ctx)
+}
+
+fn show_error(msg: &str) -> String {
+    let msg = escape_html(msg);
+    format!("<p style='font-style: italic;'>{msg}</p>")
+}
+
+async fn get_settings_totp(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    if !is_logged_in(&ctx, &jar) {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    tracing::info!("\"GET /settings/totp HTTP/1.1\" 200");
+    settings_page(&ctx, &jar, StatusCode::OK, "", None).await
+}
+
+#[derive(Debug, Deserialize)]
+struct EnableForm {
+    secret: String,
+    code: String,
+}
+
+async fn post_enable(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    CsrfForm(form): CsrfForm<EnableForm>,
+) -> Response<Body> {
+    let secret = match totp::decode_secret(&form.secret) {
+        Some(secret) if secret.len() >= 16 => secret,
+        _ => {
+            let msg = show_error("Invalid secret");
+            return settings_page(&ctx, &jar, StatusCode::BAD_REQUEST, &msg, None).await;
+        }
+    };
+    let now = Utc::now().timestamp();
+    let step = match totp::verify(&secret, &form.code, now, None) {
+        Some(step) => step,
+        None => {
+            let msg = show_error("Invalid code. Check the time on your phone and try again.");
+            return settings_page(&ctx, &jar, StatusCode::BAD_REQUEST, &msg, Some(&secret)).await;
+        }
+    };
+    let codes = match enable(&ctx.conn(), &secret, step) {
+        Ok(codes) => codes,
+        Err(e) => {
+            let msg = "Could not enable two-factor authentication";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    tracing::warn!("enabled two-factor authentication");
+    tracing::info!("\"POST /settings/totp/enable HTTP/1.1\" 200");
+    let msg = show_recovery_codes(&codes);
+    settings_page(&ctx, &jar, StatusCode::OK, &msg, None).await
+}
+
+async fn post_disable(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    CsrfForm(form): CsrfForm<CodeForm>,
+) -> Response<Body> {
+    let verified = verify(&mut ctx.conn(), &form.code);
+    match verified {
+        Ok(true) => (),
+        Ok(false) => {
+            let msg = show_error("Invalid code");
+            return settings_page(&ctx, &jar, StatusCode::BAD_REQUEST, &msg, None).await;
+        }
+        Err(e) => {
+            let msg = "Could not verify code";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    }
+    if let Err(e) = disable(&ctx.conn()) {
+        let msg = "Could not disable two-factor authentication";
+        tracing::error!("{msg}: {e}");
+        return crate::serve::internal_server_error(&ctx, msg).await;
+    }
+    tracing::warn!("disabled two-factor authentication");
+    tracing::info!("\"POST /settings/totp/disable HTTP/1.1\" 303");
+    crate::serve::see_other(&ctx, "/settings/totp")
+}
+
+async fn post_recovery_codes(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    _: Csrf,
+) -> Response<Body> {
+    if secret(&ctx.conn()).is_none() {
+        return crate::serve::see_other(&ctx, "/settings/totp");
+    }
+    let codes = match new_recovery_codes(&ctx.conn()) {
+        Ok(codes) => codes,
+        Err(e) => {
+            let msg = "Could not generate recovery codes";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    tracing::info!("\"POST /settings/totp/recovery-codes HTTP/1.1\" 200");
+    let msg = show_recovery_codes(&codes);
+    settings_page(&ctx, &jar, StatusCode::OK, &msg, None).await
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/login/totp", get(get_login_totp))
+        .route("/login/totp", post(post_login_totp))
+        .route("/settings/totp", get(get_settings_totp))
+        .route("/settings/totp/enable", post(post_enable))
+        .route("/settings/totp/disable", post(post_disable))
+        .route("/settings/totp/recovery-codes", post(post_recovery_codes))
+}
@@ -0,0 +1,624 @@
+//! Receiving uploaded files.
+//!
+//! Uploads are written to a temporary file while they are hashed, so large
+//! files do not have to fit in memory. The script on the files page sends
+//! files in chunks to show the progress. When the connection drops, it asks
+//! how much the server already received and continues from there, also after
+//! reloading the page and selecting the same file again.
+use crate::ServeArgs;
+use crate::data::SqliteDateTime;
+use crate::files::File;
+use crate::serve::ServerContext;
+use crate::serve::is_logged_in;
+use crate::serve::response;
+use axum::Router;
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::routing::put;
+use axum_extra::extract::CookieJar;
+use chrono::Duration;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::params;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::OwnedMutexGuard;
+
+const MB: u64 = 1024 * 1024;
+
+/// Maximum size of an upload in bytes.
+pub fn max_size(args: &ServeArgs) -> u64 {
+    // A size that does not fit is effectively unlimited.
+    args.max_upload_size.saturating_mul(MB)
+}
+
+/// Room for the other fields and the boundaries of an upload form.
+const FORM_OVERHEAD: u64 = 64 * 1024;
+
+/// Body limit for the routes that receive whole files.
+///
+/// The other routes keep the small default limit since uploads larger than a
+/// chunk are sent to `put_chunk`.
+pub fn body_limit(args: &ServeArgs) -> DefaultBodyLimit {
+    let limit = max_size(args).saturating_add(FORM_OVERHEAD);
+    DefaultBodyLimit::max(usize::try_from(limit).unwrap_or(usize::MAX))
+}
+
+/// Size of the chunks that the script sends.
+const CHUNK_SIZE: u64 = 4 * MB;
+
+/// Number of bytes at the start of a file that are kept for sniffing.
+const HEAD_SIZE: usize = 16;
+
+/// Chunked uploads that were not finished after this are removed.
+const STALE_AFTER_SEC: i64 = 24 * 60 * 60;
+
+/// Content type from the magic bytes at the start of the data.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+    let content_type = if at(0, b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if at(0, b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        "image/gif"
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        "audio/wav"
+    } else if at(4, b"ftypavif") {
+        "image/avif"
+    } else if at(4, b"ftypM4A ") {
+        "audio/mp4"
+    } else if at(4, b"ftypqt  ") {
+        "video/quicktime"
+    } else if at(4, b"ftyp") {
+        "video/mp4"
+    } else if at(0, b"\x1a\x45\xdf\xa3") {
+        "video/webm"
+    } else if at(0, b"ID3") || at(0, b"\xff\xfb") || at(0, b"\xff\xf3") || at(0, b"\xff\xf2") {
+        "audio/mpeg"
+    } else if at(0, b"OggS") {
+        "audio/ogg"
+    } else if at(0, b"fLaC") {
+        "audio/flac"
+    } else if at(0, b"%PDF-") {
+        "application/pdf"
+    } else if at(0, b"PK\x03\x04") {
+        "application/zip"
+    } else if at(0, b"\x1f\x8b") {
+        "application/gzip"
+    } else {
+        return None;
+    };
+    Some(content_type)
+}
+
+#[test]
+fn test_sniff() {
+    assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+    assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+    assert_eq!(sniff(b"\0\0\0\x20ftypM4A \0\0\0\0"), Some("audio/mp4"));
+    assert_eq!(sniff(b"\0\0\0\x20ftypisom\0\0\0\0"), Some("video/mp4"));
+    assert_eq!(sniff(b"ID3\x04\0\0"), Some("audio/mpeg"));
+    assert_eq!(sniff(b"%PDF-1.7"), Some("application/pdf"));
+    assert_eq!(sniff(b"RIFF"), None);
+    assert_eq!(sniff(b"hello"), None);
+    assert_eq!(sniff(b""), None);
+}
+
+/// The content type that the client sent, or the sniffed one when the client
+/// did not know.
+///
+/// Browsers send `application/octet-stream` for extensions that they do not
+/// recognize.
+pub fn content_type(sent: Option<&str>, head: &[u8]) -> String {
+    let sent = sent.map(str::trim).unwrap_or("");
+    if sent.is_empty() || sent == "application/octet-stream" {
+        sniff(head)
+            .unwrap_or("application/octet-stream")
+            .to_string()
+    } else {
+        sent.to_string()
+    }
+}
+
+#[test]
+fn test_content_type() {
+    let png = b"\x89PNG\r\n\x1a\n";
+    assert_eq!(content_type(None, png), "image/png");
+    assert_eq!(
+        content_type(Some("application/octet-stream"), png),
+        "image/png"
+    );
+    assert_eq!(content_type(Some("text/plain"), png), "text/plain");
+    assert_eq!(content_type(None, b"abc"), "application/octet-stream");
+}
+
+/// Why an upload failed.
+#[derive(Debug)]
+pub enum UploadError {
+    TooLarge(u64),
+    Invalid(String),
+    Io(std::io::Error),
+    Db(rusqlite::Error),
+}
+
+impl UploadError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            UploadError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            UploadError::Invalid(_) => StatusCode::BAD_REQUEST,
+            UploadError::Io(_) | UploadError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadError::TooLarge(limit) => {
+                write!(
+                    f,
+                    "The file is too large. The maximum is {} MB.",
+                    limit / MB
+                )
+            }
+            UploadError::Invalid(msg) => write!(f, "{msg}"),
+            UploadError::Io(e) => write!(f, "Failed to store the upload: {e}"),
+            UploadError::Db(e) => write!(f, "Failed to store the file: {e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        UploadError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for UploadError {
+    fn from(e: rusqlite::Error) -> Self {
+        UploadError::Db(e)
+    }
+}
+
+/// Directory for uploads that are not stored yet.
+fn dir() -> std::io::Result<PathBuf> {
+    let dir = std::env::temp_dir().join("fx-uploads");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// An upload that is written to a temporary file while it is received.
+///
+/// The temporary file is removed when this is dropped.
+pub struct TempUpload {
+    path: PathBuf,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    head: Vec<u8>,
+    size: u64,
+    limit: u64,
+}
+
+impl TempUpload {
+    pub async fn new(limit: u64) -> std::io::Result<Self> {
+        let path = dir()?.join(format!("{}.tmp", fx_auth::generate_token()));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Self {
+            path,
+            file,
+            hasher: Sha256::new(),
+            head: Vec::new(),
+            size: 0,
+            limit,
+        })
+    }
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), UploadError> {
+        self.size += chunk.len() as u64;
+        if self.limit < self.size {
+            return Err(UploadError::TooLarge(self.limit));
+        }
+        if self.head.len() < HEAD_SIZE {
+            let missing = (HEAD_SIZE - self.head.len()).min(chunk.len());
+            self.head.extend_from_slice(&chunk[..missing]);
+        }
+        self.hasher.update(chunk);
+        self.file.write_all(chunk).await?;
+        Ok(())
+    }
+    /// The first bytes for sniffing the content type.
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+    /// Store the upload as a file and return its sha.
+    pub async fn store(
+        mut self,
+        ctx: &ServerContext,
+        mime_type: &str,
+        filename: &str,
+    ) -> Result<String, UploadError> {
+        self.file.flush().await?;
+        let sha = crate::files::short_sha(&self.hasher.clone().finalize());
+        store(ctx, self.path.clone(), &sha, self.size, mime_type, filename).await
+    }
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Store the data from `path` as a file and return its sha.
+///
+/// Images are loaded into memory since they are processed anyway. Other files
+/// are copied into the database in chunks.
+async fn store(
+    ctx: &ServerContext,
+    path: PathBuf,
+    sha: &str,
+    size: u64,
+    mime_type: &str,
+    filename: &str,
+) -> Result<String, UploadError> {
+    crate::images::check(mime_type).map_err(UploadError::Invalid)?;
+    if crate::images::is_supported(mime_type) {
+        let data = tokio::fs::read(&path).await?;
+        let file = File::new(mime_type, filename, Bytes::from(data));
+        let file = crate::files::store_upload(ctx, file).await?;
+        return Ok(file.sha);
+    }
+    let pool = ctx.pool.clone();
+    let index = ctx.file_index.clone();
+    let (sha, mime_type, filename) = (sha.to_string(), mime_type.to_string(), filename.to_string());
+    let insert = move || -> Result<String, UploadError> {
+        let conn = pool.get().map_err(std::io::Error::other)?;
+        let mut reader = std::fs::File::open(&path)?;
+        File::insert_reader(&conn, &sha, &mime_type, &filename, size, &mut reader)?;
+        index.refresh(&conn, &sha)?;
+        Ok(sha)
+    };
+    tokio::task::spawn_blocking(insert)
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// Locks that allow only one chunk of an upload to be handled at a time.
+///
+/// Otherwise, two requests with the same offset could both append their
+/// chunk, or both store the file after the last chunk.
+#[derive(Clone, Default)]
+pub struct UploadLocks(Arc<std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>);
+
+impl UploadLocks {
+    /// Wait until no other request handles a chunk of the upload.
+    async fn lock(&self, id: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.0.lock().unwrap();
+            // Drop the locks that no request holds or waits for.
+            locks.retain(|_, lock| 1 < Arc::strong_count(lock));
+            locks.entry(id.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+/// A chunked upload that has not been completed yet.
+struct Upload {
+    id: String,
+    filename: String,
+    mime_type: String,
+    size: u64,
+}
+
+impl Upload {
+    fn path(id: &str) -> std::io::Result<PathBuf> {
+        Ok(dir()?.join(format!("{id}.part")))
+    }
+    /// Number of bytes that were received so far.
+    fn offset(id: &str) -> u64 {
+        Self::path(id)
+            .and_then(std::fs::metadata)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0)
+    }
+    fn get(conn: &Connection, id: &str) -> rusqlite::Result<Option<Self>> {
+        let stmt = "SELECT id, filename, mime_type, size FROM uploads WHERE id = ?";
+        conn.query_row(stmt, [id], |row| {
+            Ok(Upload {
+                id: row.get("id")?,
+                filename: row.get("filename")?,
+                mime_type: row.get("mime_type")?,
+                size: row.get::<_, i64>("size")? as u64,
+            })
+        })
+        .optional()
+    }
+    fn insert(&self, conn: &Connection) -> rusqlite::Result<usize> {
+        let stmt = "
+            INSERT OR IGNORE INTO uploads (id, filename, mime_type, size, created)
+            VALUES (?, ?, ?, ?, ?);
+            ";
+        let now = Utc::now().to_sqlite();
+        let params = params![
+            self.id,
+            self.filename,
+            self.mime_type,
+            self.size as i64,
+            now
+        ];
+        conn.execute(stmt, params)
+    }
+    fn delete(conn: &Connection, id: &str) -> rusqlite::Result<usize> {
+        if let Ok(path) = Self::path(id) {
+            let _ = std::fs::remove_file(path);
+        }
+        conn.execute("DELETE FROM uploads WHERE id = ?", [id])
+    }
+    /// Remove the uploads that were abandoned.
+    fn delete_stale(conn: &Connection) -> rusqlite::Result<()> {
+        let before = (Utc::now() - Duration::seconds(STALE_AFTER_SEC)).to_sqlite();
+        let mut stmt = conn.prepare("SELECT id FROM uploads WHERE created < ?")?;
+        let ids = stmt
+            .query_map([before], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for id in ids {
+            Self::delete(conn, &id)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<usize> {
+    let stmt = "
+        CREATE TABLE IF NOT EXISTS uploads (
+            id TEXT PRIMARY KEY,
+            filename TEXT NOT NULL,
+            mime_type TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            created DATETIME NOT NULL
+        );
+    ";
+    conn.execute(stmt, [])
+}
+
+/// Read the file, return its sha, and the first bytes for sniffing.
+fn hash_file(path: &std::path::Path) -> std::io::Result<(String, Vec<u8>)> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut head = Vec::new();
+    let mut buf = vec![0; 256 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if head.len() < HEAD_SIZE {
+            let missing = (HEAD_SIZE - head.len()).min(n);
+            head.extend_from_slice(&buf[..missing]);
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok((crate::files::short_sha(&hasher.finalize()), head))
+}
+
+fn json<T: Serialize>(ctx: &ServerContext, status: StatusCode, body: &T) -> Response<Body> {
+    let mut headers = HeaderMap::new();
+    let value = HeaderValue::from_static("application/json");
+    headers.insert("Content-Type", value);
+    let body = serde_json::to_string(body).unwrap();
+    response(status, headers, body, ctx)
+}
+
+fn json_error(ctx: &ServerContext, status: StatusCode, message: &str) -> Response<Body> {
+    crate::api::error(ctx, status, message)
+}
+
+/// Whether the request comes from the logged in admin via the script.
+///
+/// The script sends the CSRF token in a header since the bodies are JSON or
+/// raw file data.
+fn is_authorized(ctx: &ServerContext, jar: &CookieJar, headers: &HeaderMap) -> bool {
+    let token = headers
+        .get(crate::csrf::HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    is_logged_in(ctx, jar) && crate::csrf::verify(ctx, jar, token)
+}
+
+#[derive(Deserialize)]
+struct StartRequest {
+    filename: String,
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    mime_type: String,
+    size: u64,
+    /// Identifies the file on the client so that the same file resumes the
+    /// same upload.
+    key: String,
+}
+
+#[derive(Serialize)]
+struct StartResponse {
+    id: String,
+    offset: u64,
+    chunk_size: u64,
+}
+
+/// Start an upload or return how much of it was already received.
+async fn post_start(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    if !is_authorized(&ctx, &jar, &headers) {
+        return json_error(&ctx, StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+    let start = match serde_json::from_str::<StartRequest>(&body) {
+        Ok(start) => start,
+        Err(e) => return json_error(&ctx, StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+    let filename = format!("{}{}", start.prefix, start.filename.trim());
+    if start.filename.trim().is_empty() {
+        return json_error(&ctx, StatusCode::BAD_REQUEST, "missing filename");
+    }
+    let limit = max_size(&ctx.args);
+    if limit < start.size {
+        let msg = UploadError::TooLarge(limit).to_string();
+        return json_error(&ctx, StatusCode::PAYLOAD_TOO_LARGE, &msg);
+    }
+    let key = format!("{} {} {}", start.key, filename, start.size);
+    let id = crate::files::short_sha(&Sha256::digest(key.as_bytes()));
+    let upload = Upload {
+        id,
+        filename,
+        mime_type: start.mime_type,
+        size: start.size,
+    };
+    let conn = ctx.conn();
+    let stored = Upload::delete_stale(&conn).and_then(|_| upload.insert(&conn));
+    if let Err(e) = stored {
+        tracing::error!("failed to start upload: {e}");
+        let msg = "failed to start upload";
+        return json_error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, msg);
+    }
+    let start = StartResponse {
+        offset: Upload::offset(&upload.id),
+        id: upload.id,
+        chunk_size: CHUNK_SIZE.min(limit),
+    };
+    json(&ctx, StatusCode::OK, &start)
+}
+
+#[derive(Serialize)]
+struct ChunkResponse {
+    offset: u64,
+    /// The sha of the stored file once all chunks were received.
+    sha: Option<String>,
+}
+
+/// Header with the position of the chunk in the file.
+const OFFSET_HEADER: &str = "Upload-Offset";
+
+/// Receive a chunk and store the file after the last one.
+async fn put_chunk(
+    State(ctx): State<ServerContext>,
+    Path(id): Path<String>,
+    jar: CookieJar,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    if !is_authorized(&ctx, &jar, &headers) {
+        return json_error(&ctx, StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+    // Held until the response, so a retry of this chunk sees the new offset,
+    // or that the upload was already stored.
+    let _lock = ctx.upload_locks.lock(&id).await;
+    let upload = match Upload::get(&ctx.conn(), &id) {
+        Ok(Some(upload)) => upload,
+        Ok(None) => return json_error(&ctx, StatusCode::NOT_FOUND, "unknown upload"),
+        Err(e) => {
+            tracing::error!("failed to get upload: {e}");
+            let msg = "failed to get upload";
+            return json_error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, msg);
+        }
+    };
+    let offset = headers
+        .get(OFFSET_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let received = Upload::offset(&id);
+    if offset != Some(received) {
+        // The client resumes from the offset in the response.
+        let chunk = ChunkResponse {
+            offset: received,
+            sha: None,
+        };
+        return json(&ctx, StatusCode::CONFLICT, &chunk);
+    }
+    if upload.size < received + body.len() as u64 {
+        let msg = "chunk exceeds the size of the upload";
+        return json_error(&ctx, StatusCode::BAD_REQUEST, msg);
+    }
+    match append(&id, &body).await {
+        Ok(()) => {}
+        Err(e) => {
+            tracing::error!("failed to write chunk: {e}");
+            let msg = "failed to write chunk";
+            return json_error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, msg);
+        }
+    }
+    let offset = received + body.len() as u64;
+    if offset < upload.size {
+        let chunk = ChunkResponse { offset, sha: None };
+        return json(&ctx, StatusCode::OK, &chunk);
+    }
+    match finish(&ctx, &upload).await {
+        Ok(sha) => {
+            crate::trigger::trigger_github_backup(&ctx).await;
+            tracing::info!("\"PUT /files/uploads/{id} HTTP/1.1\" 200");
+            let chunk = ChunkResponse {
+                offset,
+                sha: Some(sha),
+            };
+            json(&ctx, StatusCode::OK, &chunk)
+        }
+        Err(e) => {
+            tracing::error!("failed to store upload {id}: {e}");
+            json_error(&ctx, e.status(), &e.to_string())
+        }
+    }
+}
+
+async fn append(id: &str, data: &[u8]) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(Upload::path(id)?)
+        .await?;
+    file.write_all(data).await?;
+    file.flush().await
+}
+
+/// Store the file of a completely received upload.
+async fn finish(ctx: &ServerContext, upload: &Upload) -> Result<String, UploadError> {
+    let path = Upload::path(&upload.id)?;
+    let hash_path = path.clone();
+    let (sha, head) = tokio::task::spawn_blocking(move || hash_file(&hash_path))
+        .await
+        .map_err(std::io::Error::other)??;
+    let mime_type = content_type(Some(&upload.mime_type), &head);
+    let sha = store(ctx, path, &sha, upload.size, &mime_type, &upload.filename).await?;
+    Upload::delete(&ctx.conn(), &upload.id)?;
+    Ok(sha)
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/files/uploads", post(post_start))
+        .route(
+            "/files/uploads/{id}",
+            put(put_chunk).layer(DefaultBodyLimit::max(CHUNK_SIZE as usize)),
+        )
+}
@@ -0,0 +1,105 @@
+//! Which posts link to which uploaded files.
+//!
+//! Deleting a file breaks the posts that link to it, so the files page shows
+//! where each file is used. The index is updated whenever a post is stored.
+use regex::Regex;
+use rusqlite::Connection;
+use rusqlite::Result;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+pub fn create_table(conn: &Connection) -> Result<usize> {
+    let stmt = "
+        CREATE TABLE IF NOT EXISTS file_usage (
+            sha TEXT NOT NULL,
+            post_id INTEGER NOT NULL,
+            PRIMARY KEY (sha, post_id)
+        );
+    ";
+    conn.execute(stmt, [])
+}
+
+/// Links to uploaded files and their variants.
+static FILE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"/files/(?:variants/)?([0-9a-f]{16})").unwrap());
+
+/// The shas of the files that the content links to.
+///
+/// Matches links such as `/files/{sha}`, `/files/{sha}/a.txt`, and the
+/// variants of images, both relative and absolute.
+fn shas(content: &str) -> BTreeSet<String> {
+    FILE.captures_iter(content)
+        .map(|caps| caps[1].to_string())
+        .collect()
+}
+
+#[test]
+fn test_shas() {
+    let content = "
+        ![](/files/69b83ddf8f65695f)
+        [a.txt](https://example.com/files/0123456789abcdef/a.txt)
+        <img src='/files/variants/69b83ddf8f65695f/480.webp'>
+        /files/add
+        ";
+    let expected = ["0123456789abcdef", "69b83ddf8f65695f"];
+    assert_eq!(shas(content), BTreeSet::from(expected.map(String::from)));
+}
+
+/// Update the index for a post that was inserted, updated, or deleted.
+pub fn index_post(conn: &Connection, post_id: i64, content: &str) -> Result<()> {
+    conn.execute("DELETE FROM file_usage WHERE post_id = ?", [post_id])?;
+    let stmt = "INSERT OR IGNORE INTO file_usage (sha, post_id) VALUES (?, ?)";
+    let mut stmt = conn.prepare(stmt)?;
+    for sha in shas(content) {
+        stmt.execute(rusqlite::params![sha, post_id])?;
+    }
+    Ok(())
+}
+
+/// Build the index from scratch.
+///
+/// Runs at startup so that posts from before the index existed, or posts that
+/// were changed directly in the database, are included.
+pub fn rebuild(conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM file_usage", [])?;
+    let posts = crate::data::Post::list(conn)?;
+    for post in posts {
+        index_post(conn, post.id, &post.content)?;
+    }
+    Ok(())
+}
+
+/// Ids of the posts that link to the file.
+pub fn posts(conn: &Connection, sha: &str) -> Result<Vec<i64>> {
+    let stmt = "SELECT post_id FROM file_usage WHERE sha = ? ORDER BY post_id";
+    let mut stmt = conn.prepare(stmt)?;
+    let ids = stmt.query_map([sha], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// Ids of the posts that link to each file.
+pub fn all(conn: &Connection) -> Result<HashMap<String, Vec<i64>>> {
+    let stmt = "SELECT sha, post_id FROM file_usage ORDER BY post_id";
+    let mut stmt = conn.prepare(stmt)?;
+    let rows = stmt.query_map([], |row| {
+        let sha: String = row.get(0)?;
+        let post_id: i64 = row.get(1)?;
+        Ok((sha, post_id))
+    })?;
+    let mut usage = HashMap::<String, Vec<i64>>::new();
+    for row in rows {
+        let (sha, post_id) = row?;
+        usage.entry(sha).or_default().push(post_id);
+    }
+    Ok(usage)
+}
+
+/// The shas that are used outside of posts, such as in the about text, the
+/// extra HTML head, or the podcast artwork.
+pub fn used_in_settings(conn: &Connection) -> BTreeSet<String> {
+    ["about", "extra_head", "podcast_artwork"]
+        .iter()
+        .flat_map(|key| shas(&crate::data::Kv::get_or_empty_string(conn, key)))
+        .collect()
+}
@@ -2,12 +2,15 @@ mod common;
 
 use axum::body::Body;
 use axum::extract::Request;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
 use axum::http::StatusCode;
 use chrono::Utc;
 use common::*;
 use fx::serve::ServerContext;
 use fx::serve::app;
 use http_body_util::BodyExt;
+use serde_json::Value;
 use std::io::Cursor;
 use std::io::Read;
 use tar::Archive;
@@ -31,8 +34,7 @@ async fn test_no_access() {
 }
 
 fn auth_header(ctx: &ServerContext) -> String {
-    let auth = ctx.args.password.clone().unwrap();
-    format!("Bearer {auth}")
+    format!("Bearer {}", api_token(ctx))
 }
 
 pub async fn request_body_authenticated(uri: &str) -> (StatusCode, Vec<u8>) {
@@ -125,3 +127,506 @@ async fn test_download_all() {
 
     assert!(entries.next().is_none());
 }
+
+async fn send(ctx: &ServerContext, req: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap();
+    (status, headers, body.to_bytes().into())
+}
+
+fn json_request(ctx: &ServerContext, method: &str, uri: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", auth_header(ctx))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_api_requires_authentication() {
+    let endpoints = ["/api/posts", "/api/posts/1", "/api/files", "/api/settings"];
+    for endpoint in endpoints {
+        let (status, body) = request_body(endpoint).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["status"], 401);
+        assert_eq!(body["message"], "unauthorized");
+    }
+
+    // The admin password is not a token.
+    let ctx = server_context().await;
+    let password = ctx.args.password.clone().unwrap();
+    let req = token_request("GET", "/api/posts", &password, "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_api_posts() {
+    let ctx = server_context().await;
+
+    let req = json_request(&ctx, "GET", "/api/posts?per_page=1", "");
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["posts"].as_array().unwrap().len(), 1);
+    assert_eq!(body["next"], "/api/posts?page=2&per_page=1");
+
+    let req = json_request(&ctx, "POST", "/api/posts", r#"{"content": "Hello API"}"#);
+    let (status, headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(headers.get("Location").unwrap(), "/api/posts/3");
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["id"], 3);
+    assert_eq!(body["content"], "Hello API\n");
+    let etag = headers.get("ETag").unwrap().to_str().unwrap().to_string();
+
+    let req = json_request(&ctx, "POST", "/api/posts", "not json");
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["status"], 400);
+
+    let req = json_request(&ctx, "GET", "/api/posts/3", "");
+    let (status, headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers.get("ETag").unwrap().to_str().unwrap(), etag);
+
+    let mut req = json_request(&ctx, "PUT", "/api/posts/3", r#"{"content": "Updated"}"#);
+    let outdated = HeaderValue::from_static("\"outdated\"");
+    req.headers_mut().insert("If-Match", outdated);
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+    let mut req = json_request(&ctx, "PUT", "/api/posts/3", r#"{"content": "Updated"}"#);
+    let current = HeaderValue::from_str(&etag).unwrap();
+    req.headers_mut().insert("If-Match", current);
+    let (status, headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(headers.get("ETag").unwrap().to_str().unwrap(), etag);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["content"], "Updated\n");
+
+    let req = json_request(&ctx, "DELETE", "/api/posts/3", "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let req = json_request(&ctx, "GET", "/api/posts/3", "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_theme_assets() {
+    let ctx = server_context().await;
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/files?filename=theme%2Fcustom.css")
+        .header("Authorization", auth_header(&ctx))
+        .header("Content-Type", "text/css")
+        .body(Body::from("body {\n    color: red;\n}\n"))
+        .unwrap();
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let sha = body["sha"].as_str().unwrap().to_string();
+
+    let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    let url = format!("/static/theme/{sha}.css");
+    let link = format!("<link rel='stylesheet' href='{url}'>");
+    assert!(String::from_utf8(body).unwrap().contains(&link));
+
+    let req = Request::builder().uri(&url).body(Body::empty()).unwrap();
+    let (status, headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        headers.get("Content-Type").unwrap(),
+        "text/css; charset=utf-8"
+    );
+    let cache_control = headers.get("Cache-Control").unwrap().to_str().unwrap();
+    assert!(cache_control.contains("immutable"));
+    assert_eq!(String::from_utf8(body).unwrap(), "body {\ncolor: red;\n}");
+
+    // Only the theme extension is served.
+    let other = format!("/static/theme/{sha}.js");
+    let req = Request::builder().uri(&other).body(Body::empty()).unwrap();
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let req = json_request(&ctx, "DELETE", &format!("/api/files/{sha}"), "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let req = Request::builder().uri(&url).body(Body::empty()).unwrap();
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_compressed_upload() {
+    let ctx = server_context().await;
+    let text = "lorem ipsum ".repeat(1000);
+    let gzipped =
+        fx::compression::compress(fx::compression::Encoding::Gzip, text.as_bytes(), false);
+    let large = "lorem ipsum ".repeat(20_000);
+    let files = [
+        ("a.txt", text.into_bytes()),
+        ("a.txt.gz", gzipped),
+        ("large.txt", large.into_bytes()),
+    ];
+    for (filename, data) in files {
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("/api/files?filename={filename}"))
+            .header("Authorization", auth_header(&ctx))
+            .header("Content-Type", "text/plain")
+            .body(Body::from(data))
+            .unwrap();
+        let (status, _headers, body) = send(&ctx, req).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let sha = body["sha"].as_str().unwrap();
+
+        let req = Request::builder()
+            .uri(format!("/files/{sha}"))
+            .header("Accept-Encoding", "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, _body) = send(&ctx, req).await;
+        assert_eq!(status, StatusCode::OK);
+        let encoding = headers.get("Content-Encoding");
+        if filename.ends_with(".gz") {
+            // Already compressed data is sent as is.
+            assert!(encoding.is_none());
+        } else if filename == "large.txt" {
+            // Larger than `compression_max_size`, so it is streamed as is.
+            assert!(encoding.is_none());
+        } else {
+            assert_eq!(encoding.unwrap(), "gzip");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_api_files() {
+    let ctx = server_context().await;
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/files?filename=hello.txt")
+        .header("Authorization", auth_header(&ctx))
+        .header("Content-Type", "text/plain")
+        .body(Body::from("hello"))
+        .unwrap();
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["filename"], "hello.txt");
+    assert_eq!(body["size"], 5);
+    let sha = body["sha"].as_str().unwrap().to_string();
+
+    let req = json_request(&ctx, "GET", "/api/files", "");
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 2);
+
+    let uri = format!("/api/files/{sha}");
+    let req = json_request(&ctx, "DELETE", &uri, "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    let req = json_request(&ctx, "GET", &uri, "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_api_settings() {
+    let ctx = server_context().await;
+
+    let req = json_request(&ctx, "GET", "/api/settings", "");
+    let (status, headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    let etag = headers.get("ETag").unwrap().clone();
+    let mut settings: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(settings["author_name"], "John");
+
+    settings["author_name"] = Value::from("Jane");
+    let body = settings.to_string();
+    let mut req = json_request(&ctx, "PUT", "/api/settings", &body);
+    req.headers_mut().insert("If-Match", etag.clone());
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["author_name"], "Jane");
+
+    // The old ETag no longer matches.
+    let mut req = json_request(&ctx, "PUT", "/api/settings", &settings.to_string());
+    req.headers_mut().insert("If-Match", etag);
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+}
+
+async fn create_token(ctx: &ServerContext, cookie: &str, form: &str) -> String {
+    let csrf = csrf_token(ctx, cookie).await;
+    let req = Request::builder()
+        .method("POST")
+        .uri("/settings/tokens")
+        .header("Cookie", format!("auth={cookie}"))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(format!("{form}&csrf_token={csrf}")))
+        .unwrap();
+    let (status, _headers, body) = send(ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = String::from_utf8(body).unwrap();
+    let token = body.split("<code id='new-token'>").nth(1).unwrap();
+    let token = token.split("</code>").next().unwrap();
+    assert!(token.starts_with("fx_"));
+    token.to_string()
+}
+
+fn token_request(method: &str, uri: &str, token: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", format!("Bearer {token}"))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_api_tokens() {
+    let (ctx, cookie) = request_cookie().await;
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/settings/tokens")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from("name=backup&scope=backup"))
+        .unwrap();
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    for days in ["-1", "9223372036854775807"] {
+        let csrf = csrf_token(&ctx, &cookie).await;
+        let form = format!("name=reader&scope=read&expires_in_days={days}&csrf_token={csrf}");
+        let req = Request::builder()
+            .method("POST")
+            .uri("/settings/tokens")
+            .header("Cookie", format!("auth={cookie}"))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(form))
+            .unwrap();
+        let (status, _headers, _body) = send(&ctx, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    let token = create_token(&ctx, &cookie, "name=reader&scope=read&expires_in_days=30").await;
+
+    let req = token_request("GET", "/api/posts", &token, "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let req = token_request("POST", "/api/posts", &token, r#"{"content":"x"}"#);
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["status"], 403);
+
+    let req = token_request("GET", "/api/download/all.tar.xz", &token, "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let writer = create_token(&ctx, &cookie, "name=writer&scope=read&scope=write_posts").await;
+    let req = token_request("POST", "/api/posts", &writer, r#"{"content":"x"}"#);
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let req = Request::builder()
+        .uri("/settings/tokens")
+        .header("Cookie", format!("auth={cookie}"))
+        .body(Body::empty())
+        .unwrap();
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains("reader"));
+    assert!(body.contains("writer"));
+    assert!(!body.contains(&token));
+    let id = body.split("/settings/tokens/revoke/").nth(2).unwrap();
+    let id = id.split('\'').next().unwrap();
+
+    // Tokens are listed newest first, so the second form revokes the reader.
+    let csrf = csrf_token(&ctx, &cookie).await;
+    let req = Request::builder()
+        .method("POST")
+        .uri(format!("/settings/tokens/revoke/{id}"))
+        .header("Cookie", format!("auth={cookie}"))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(format!("csrf_token={csrf}")))
+        .unwrap();
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+
+    let req = token_request("GET", "/api/posts", &token, "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let req = token_request("GET", "/api/posts", &writer, "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+/// Collect all `$ref` values in the document.
+fn refs(value: &Value, out: &mut Vec<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => out.push(reference.clone()),
+                    _ => refs(value, out),
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| refs(value, out)),
+        _ => (),
+    }
+}
+
+#[tokio::test]
+async fn test_openapi() {
+    let (status, body) = request_body("/api/openapi.json").await;
+    assert_eq!(status, StatusCode::OK);
+    let spec = oas3::from_json(&body).unwrap();
+    spec.validate_version().unwrap();
+
+    let value: Value = serde_json::from_str(&body).unwrap();
+    let mut references = vec![];
+    refs(&value, &mut references);
+    assert!(!references.is_empty());
+    for reference in references {
+        let pointer = reference.strip_prefix('#').unwrap();
+        assert!(value.pointer(pointer).is_some(), "dangling {reference}");
+    }
+    let schemes = &value["components"]["securitySchemes"];
+    assert_eq!(schemes["bearer"]["scheme"], "bearer");
+
+    let ctx = server_context().await;
+    let mut operations = 0;
+    for (path, method, operation) in spec.operations() {
+        operations += 1;
+        operation.parameters(&spec).unwrap();
+        operation.request_body(&spec).unwrap();
+        let responses = operation.responses(&spec);
+        // Every documented route must exist and must not be reachable without
+        // a token unless it is public.
+        let uri = path.replace("{id}", "1").replace("{sha}", "abc");
+        let req = Request::builder()
+            .method(method.as_str())
+            .uri(&uri)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _headers, _body) = send(&ctx, req).await;
+        if operation.security.is_empty() {
+            assert_eq!(status, StatusCode::OK, "{method} {path}");
+        } else {
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{method} {path}");
+            assert!(responses.contains_key("401"), "{method} {path}");
+            assert!(responses.contains_key("403"), "{method} {path}");
+        }
+    }
+    assert_eq!(operations, 15);
+}
+
+async fn get(ctx: &ServerContext, uri: &str) -> (StatusCode, Vec<u8>) {
+    let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let (status, _headers, body) = send(ctx, req).await;
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_image_upload() {
+    let ctx = server_context().await;
+    let image = image::RgbImage::from_fn(1200, 800, |x, y| {
+        image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+    });
+    let mut jpeg = Vec::new();
+    image::DynamicImage::ImageRgb8(image)
+        .write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+        .unwrap();
+    let mut jpeg = img_parts::jpeg::Jpeg::from_bytes(jpeg.into()).unwrap();
+    img_parts::ImageEXIF::set_exif(&mut jpeg, Some("MM\0*GPSLocation".into()));
+    let data = jpeg.encoder().bytes();
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/files?filename=cat.jpg")
+        .header("Authorization", auth_header(&ctx))
+        .header("Content-Type", "image/jpeg")
+        .body(Body::from(data))
+        .unwrap();
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["width"], 1200);
+    assert_eq!(body["height"], 800);
+    let sha = body["sha"].as_str().unwrap().to_string();
+
+    let (status, body) = get(&ctx, &format!("/files/{sha}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body.windows(11).any(|window| window == b"GPSLocation"));
+
+    let (status, body) = get(&ctx, &format!("/files/variants/{sha}/480.jpg")).await;
+    assert_eq!(status, StatusCode::OK);
+    let variant = image::load_from_memory(&body).unwrap();
+    assert_eq!((variant.width(), variant.height()), (480, 320));
+    let (status, _body) = get(&ctx, &format!("/files/variants/{sha}/960.jpg")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _body) = get(&ctx, &format!("/files/variants/{sha}/1920.jpg")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let content = format!(r#"{{"content": "![A cat](/files/{sha})"}}"#);
+    let req = json_request(&ctx, "POST", "/api/posts", &content);
+    let (status, headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let id = headers.get("Location").unwrap().to_str().unwrap();
+    let id = id.rsplit('/').next().unwrap();
+    let req = Request::builder()
+        .uri(format!("/posts/{id}"))
+        .body(Body::empty())
+        .unwrap();
+    let (_status, headers, _body) = send(&ctx, req).await;
+    let location = headers.get("Location").unwrap().to_str().unwrap();
+    let (status, body) = get(&ctx, location).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains(&format!("/files/variants/{sha}/480.jpg 480w")));
+    assert!(body.contains("width='1200' height='800' loading='lazy'"));
+
+    let req = json_request(&ctx, "DELETE", &format!("/api/files/{sha}"), "");
+    let (status, _headers, _body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _body) = get(&ctx, &format!("/files/variants/{sha}/480.jpg")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The location in the metadata of other photos cannot be removed.
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/files?filename=cat.heic")
+        .header("Authorization", auth_header(&ctx))
+        .header("Content-Type", "image/heic")
+        .body(Body::from("GPSLocation"))
+        .unwrap();
+    let (status, _headers, body) = send(&ctx, req).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert!(body["message"].as_str().unwrap().contains("image/heic"));
+}
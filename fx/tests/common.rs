@@ -31,7 +31,22 @@ impl TestDefault for ServeArgs {
             username: "test-admin".to_string(),
             html_lang: "en".to_string(),
             log_level: "info".to_string(),
+            trusted_proxies: vec![],
+            csp: fx::csp::Mode::Enforce,
+            csp_report: true,
+            katex: false,
+            compression: vec![
+                fx::compression::Encoding::Br,
+                fx::compression::Encoding::Zstd,
+                fx::compression::Encoding::Gzip,
+            ],
+            compression_min_size: 1024,
+            compression_max_size: 100 * 1024,
+            feed_size: 20,
+            max_upload_size: 100,
             password: Some("test-password".to_string()),
+            password_hash: None,
+            secret: None,
             domain: "".to_string(),
         }
     }
@@ -50,10 +65,10 @@ impl TestDefault for DbPool {
 pub async fn server_context() -> ServerContext {
     let args = ServeArgs::test_default();
     let conn = DbPool::test_default();
-    let salt = fx_auth::generate_salt();
+    let key = fx_auth::CookieKey::new(&fx_auth::generate_salt(), "test-secret");
     let blog_cache = BlogCache::new(vec![]).await;
     let blog_cache = Arc::new(Mutex::new(blog_cache));
-    ServerContext::new(args, conn, salt, blog_cache).await
+    ServerContext::new(args, conn, key, blog_cache).await
 }
 
 pub async fn request_body(uri: &str) -> (StatusCode, String) {
@@ -108,3 +123,35 @@ pub async fn request_body_logged_in(uri: &str) -> (StatusCode, Vec<u8>) {
     let body: Vec<u8> = body.to_bytes().into();
     (status, body)
 }
+
+/// CSRF token from the forms on the pages of a logged in user.
+#[allow(dead_code)]
+pub async fn csrf_token(ctx: &ServerContext, auth: &str) -> String {
+    let req = Request::builder()
+        .uri("/settings")
+        .header("Cookie", format!("auth={auth}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    let token = body.split("name='csrf_token' value='").nth(1).unwrap();
+    let token = token.split('\'').next().unwrap();
+    assert!(!token.is_empty());
+    token.to_string()
+}
+
+/// An API token with all scopes for requests to the API.
+#[allow(dead_code)]
+pub fn api_token(ctx: &ServerContext) -> String {
+    let token = "fx_test-token";
+    let stmt = "
+        INSERT OR IGNORE INTO api_tokens (name, token_hash, scopes, created)
+        VALUES ('test', ?, 'read write_posts write_files settings backup', '2024-01-01 00:00:00');
+    ";
+    ctx.conn()
+        .execute(stmt, [fx_auth::hash_token(token)])
+        .unwrap();
+    token.to_string()
+}
@@ -1,12 +1,14 @@
 mod common;
 
 use axum::body::Body;
+use axum::extract::ConnectInfo;
 use axum::extract::Request;
 use axum::http::StatusCode;
 use common::*;
 use fx::serve::LoginForm;
 use fx::serve::app;
 use http_body_util::BodyExt;
+use std::net::SocketAddr;
 use tower::util::ServiceExt;
 
 #[tokio::test]
@@ -22,9 +24,11 @@ async fn test_home() {
     assert!(body.contains("<meta property='og:type' content='website'/>"));
     assert!(body.contains("<meta property='og:title' content='John&#39;s Weblog'/>"));
     // Assumes that the "Code" post shows a code block in the preview.
-    assert!(body.contains("highlight.js"));
+    assert!(body.contains("/static/highlight."));
+    assert!(!body.contains("highlight.js"));
     // Assumes that math is shown in one of the previews.
-    assert!(body.contains("katex"));
+    assert!(body.contains("<math><semantics><mrow><mi>e</mi>"));
+    assert!(!body.contains("katex"));
 }
 
 #[tokio::test]
@@ -37,28 +41,69 @@ async fn test_get_post() {
     assert!(body.contains("<meta property='og:site_name' content='John&#39;s Weblog'/>"));
     assert!(body.contains("<meta property='og:type' content='article'/>"));
     assert!(body.contains("<meta property='og:title' content='Code'/>"));
+    assert!(body.contains("<meta property='og:image' content='/posts/2/og.png'/>"));
+    assert!(body.contains("<meta name='twitter:card' content='summary_large_image'/>"));
 
     let (status, _body) = request_body("/posts/2").await;
     assert_eq!(status, StatusCode::PERMANENT_REDIRECT);
 }
 
+#[tokio::test]
+async fn test_og_image() {
+    let ctx = server_context().await;
+    let response = conditional_get(&ctx, "/posts/2/og.png", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "image/png");
+    let etag = response.headers().get("ETag").unwrap().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let image = image::load_from_memory_with_format(&body, image::ImageFormat::Png).unwrap();
+    assert_eq!((image.width(), image.height()), (1200, 630));
+
+    // The second request is served from the cache.
+    let response = conditional_get(&ctx, "/posts/2/og.png", &[]).await;
+    assert_eq!(response.headers().get("ETag").unwrap(), &etag);
+    let cached = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(cached, body);
+    let etag = etag.to_str().unwrap();
+    let response = conditional_get(&ctx, "/posts/2/og.png", &[("If-None-Match", etag)]).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // Changing the title renders a new card.
+    let conn = ctx.conn();
+    let mut post = fx::data::Post::get(&conn, 2).unwrap();
+    post.content = post.content.replacen("Code", "Other", 1);
+    post.update(&conn).unwrap();
+    let response = conditional_get(&ctx, "/posts/2/og.png", &[]).await;
+    assert_ne!(response.headers().get("ETag").unwrap(), etag);
+
+    let response = conditional_get(&ctx, "/posts/999/og.png", &[]).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
 #[tokio::test]
 async fn test_front_page_preview() {
     let (status, body) = request_body("/").await;
     assert_eq!(status, StatusCode::OK);
     let body = fx::html::minify(&body);
     println!("body:\n{body}");
-    let code_area = indoc::indoc! {
-        r#"
-        <pre><code class="language-julia">function f(x)
+    let start = r#"<pre class="highlight"><code class="language-julia hl-code">"#;
+    let block = &body[body.find(start).unwrap() + start.len()..];
+    let block = &block[..block.find("</code></pre>").unwrap()];
+    let keyword = r#"<span class="hl-keyword hl-declaration hl-function hl-julia">function</span>"#;
+    assert!(block.contains(keyword));
+    // Minifying the page keeps the indentation and the empty line.
+    let tags = regex::Regex::new("<[^>]*>").unwrap();
+    let code = tags.replace_all(block, "").replace("&quot;", "\"");
+    let expected = indoc::indoc! {r#"
+        function f(x)
             println(1)
             return x
         end
 
-        find . -iname "*.tex" -o -iname "*.bib" | entr latexmk -pdf</code></pre>
+        find . -iname "*.tex" -o -iname "*.bib" | entr latexmk -pdf
         "#
     };
-    assert!(body.contains(code_area.trim()));
+    assert_eq!(code, expected.trim_end());
     assert!(body.contains("Show more"));
 }
 
@@ -69,6 +114,66 @@ async fn test_style() {
     assert!(body.contains("body {"));
 }
 
+#[tokio::test]
+async fn test_fingerprinted_assets() {
+    let (status, body) = request_body("/").await;
+    assert_eq!(status, StatusCode::OK);
+    let start = body.find("href='/static/style.").unwrap() + "href='".len();
+    let url = &body[start..];
+    let url = &url[..url.find('\'').unwrap()];
+    assert_ne!(url, "/static/style.css");
+    assert!(url.ends_with(".css"));
+    assert!(!body.contains("src='/static/script.js'"));
+
+    let ctx = server_context().await;
+    let req = Request::builder()
+        .uri(url)
+        .header("Accept-Encoding", "gzip, deflate")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers().clone();
+    let cache_control = headers.get("Cache-Control").unwrap().to_str().unwrap();
+    assert!(cache_control.contains("immutable"));
+    assert_eq!(headers.get("Content-Encoding").unwrap(), "gzip");
+    assert_eq!(headers.get("Vary").unwrap(), "Accept-Encoding");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let mut css = String::new();
+    let mut decoder = flate2::read::GzDecoder::new(&body[..]);
+    std::io::Read::read_to_string(&mut decoder, &mut css).unwrap();
+    assert!(css.contains("body {"));
+
+    let req = Request::builder()
+        .uri(url)
+        .header("Accept-Encoding", "br")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.headers().get("Content-Encoding").unwrap(), "br");
+
+    // The plain URL is not cached forever since the content can change.
+    let req = Request::builder()
+        .uri("/static/style.css")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let cache_control = response.headers().get("Cache-Control").unwrap();
+    assert!(!cache_control.to_str().unwrap().contains("immutable"));
+    assert!(response.headers().get("Content-Encoding").is_none());
+
+    let (status, _body) = request_body("/static/style.0000000000000000.css").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_highlight_style() {
+    let (status, body) = request_body("/static/highlight.css").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(".hl-keyword"));
+    assert!(body.contains("prefers-color-scheme: dark"));
+}
+
 #[tokio::test]
 async fn test_metadata() {
     let url = "/posts/1/lorem-ipsum-ut-enim-ad-minim-veniam-sit-amet-ipsum";
@@ -181,6 +286,80 @@ async fn test_login() {
     assert!(body.contains("Invalid username or password"));
 }
 
+async fn login_from(
+    ctx: &fx::serve::ServerContext,
+    peer: &str,
+    forwarded_for: Option<&str>,
+    password: &str,
+) -> (StatusCode, String) {
+    let form = LoginForm {
+        username: "test-admin".to_string(),
+        password: password.to_string(),
+    };
+    let form_data = serde_urlencoded::to_string(&form).unwrap();
+    let peer: SocketAddr = peer.parse().unwrap();
+    let mut req = Request::builder()
+        .method("POST")
+        .uri("/login")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .extension(ConnectInfo(peer));
+    if let Some(forwarded_for) = forwarded_for {
+        req = req.header("X-Forwarded-For", forwarded_for);
+    }
+    let req = req.body(Body::from(form_data)).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_login_lockout() {
+    let mut ctx = common::server_context().await;
+    ctx.args.trusted_proxies = vec!["10.0.0.1/32".parse().unwrap()];
+    let attacker = "198.51.100.1:1234";
+
+    for _ in 0..5 {
+        let (status, body) = login_from(&ctx, attacker, None, "wrong").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains("Invalid username or password"));
+    }
+    // Even the correct password is rejected during the lockout.
+    let (status, body) = login_from(&ctx, attacker, None, "test-password").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("Too many failed login attempts. Try again in 1 second."));
+
+    // Spoofing the header from an untrusted peer does not escape the lockout.
+    let spoofed = Some("203.0.113.9");
+    let (status, _body) = login_from(&ctx, attacker, spoofed, "test-password").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Other clients behind the trusted proxy can still log in.
+    let proxy = "10.0.0.1:443";
+    let client = Some("203.0.113.9");
+    let (status, _body) = login_from(&ctx, proxy, client, "test-password").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+
+    // The attacker behind the trusted proxy is still locked out.
+    let forwarded = Some("198.51.100.1");
+    let (status, _body) = login_from(&ctx, proxy, forwarded, "test-password").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_login_with_password_hash() {
+    let mut ctx = common::server_context().await;
+    ctx.args.password = None;
+    ctx.args.password_hash = Some(fx_auth::hash_password("test-password"));
+    let peer = "192.0.2.1:1234";
+
+    let (status, _body) = login_from(&ctx, peer, None, "wrong").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _body) = login_from(&ctx, peer, None, "test-password").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+}
+
 #[tokio::test]
 async fn test_delete_confirmation() {
     let (status, body) = request_body("/posts/delete/1").await;
@@ -199,20 +378,11 @@ async fn test_post_add() {
     let (ctx, auth) = request_cookie().await;
     let form = fx::serve::AddPostForm {
         content: "Lorem https://example.com".to_string(),
+        publish: None,
     };
     let form_data = serde_urlencoded::to_string(&form).unwrap();
-    let req = Request::builder()
-        .method("POST")
-        .uri("/posts/add")
-        .header("Cookie", format!("auth={auth}"))
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .body(Body::from(form_data))
-        .unwrap();
-    let response = app(ctx.clone()).oneshot(req).await.unwrap();
-    let status = response.status();
-    let body = response.into_body().collect().await.unwrap();
-    let body: Vec<u8> = body.to_bytes().into();
-    let body = String::from_utf8(body).unwrap();
+    let cookie = format!("auth={auth}");
+    let (status, _headers, body) = post_form(&ctx, "/posts/add", &cookie, &form_data).await;
     println!("body:\n{body}");
     assert_eq!(status, StatusCode::OK);
     assert!(body.contains("example.com"), "redirect to the new post");
@@ -242,20 +412,11 @@ async fn test_post_edit() {
     let (ctx, auth) = request_cookie().await;
     let form = fx::serve::EditPostForm {
         content: "Lorem https://example.com".to_string(),
+        publish: None,
     };
     let form_data = serde_urlencoded::to_string(&form).unwrap();
-    let req = Request::builder()
-        .method("POST")
-        .uri("/posts/edit/2")
-        .header("Cookie", format!("auth={auth}"))
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .body(Body::from(form_data))
-        .unwrap();
-    let response = app(ctx.clone()).oneshot(req).await.unwrap();
-    let status = response.status();
-    let body = response.into_body().collect().await.unwrap();
-    let body: Vec<u8> = body.to_bytes().into();
-    let body = String::from_utf8(body).unwrap();
+    let cookie = format!("auth={auth}");
+    let (status, _headers, body) = post_form(&ctx, "/posts/edit/2", &cookie, &form_data).await;
     println!("body:\n{body}");
     assert_eq!(status, StatusCode::OK);
     assert!(!body.contains("# Code"), "text not updated");
@@ -315,8 +476,496 @@ async fn test_rss() {
     assert_eq!(status, StatusCode::OK);
     println!("body:\n{body}");
     assert!(body.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
-    assert!(body.contains("<description><![CDATA[<p><a href='https://example.com"),);
+    assert!(body.contains("<description><![CDATA[<p><a href=\"https://example.com"));
     assert!(body.contains("<description><![CDATA[<h1>Code</h1>"));
+    assert!(body.contains("<math><semantics><mrow><mi>x</mi><mo>=</mo><mn>1</mn></mrow>"));
+}
+
+async fn feed_body(uri: &str) -> (String, String) {
+    let mut ctx = server_context().await;
+    ctx.args.domain = "example.com".to_string();
+    let response = conditional_get(&ctx, uri, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers().get("Content-Type").unwrap();
+    let content_type = content_type.to_str().unwrap().to_string();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (content_type, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// Checks the requirements of RFC 4287.
+#[tokio::test]
+async fn test_atom() {
+    let (content_type, body) = feed_body("/atom.xml").await;
+    assert_eq!(content_type, "application/atom+xml; charset=utf-8");
+    let feed = atom_syndication::Feed::read_from(body.as_bytes()).unwrap();
+    assert_eq!(feed.title().as_str(), "John's Weblog");
+    assert_eq!(feed.id(), "https://example.com/");
+    assert!(!feed.authors().is_empty());
+    let link = |links: &[atom_syndication::Link], rel: &str| {
+        links
+            .iter()
+            .find(|link| link.rel() == rel)
+            .map(|link| link.href().to_string())
+    };
+    assert_eq!(
+        link(feed.links(), "self").unwrap(),
+        "https://example.com/atom.xml"
+    );
+    assert!(!feed.entries().is_empty());
+    let mut ids = std::collections::HashSet::new();
+    for entry in feed.entries() {
+        assert!(ids.insert(entry.id().to_string()), "duplicate id");
+        assert!(entry.id().starts_with("https://example.com/posts/"));
+        assert!(!entry.title().is_empty());
+        assert!(entry.published().unwrap() <= entry.updated());
+        assert!(*entry.updated() <= *feed.updated());
+        assert_eq!(link(entry.links(), "alternate").unwrap(), entry.id());
+        let content = entry.content().unwrap();
+        assert_eq!(content.content_type(), Some("html"));
+        assert!(content.value().unwrap().starts_with('<'));
+    }
+    // The HTML is escaped instead of wrapped in CDATA.
+    assert!(body.contains("&lt;math&gt;&lt;semantics&gt;"));
+}
+
+/// Checks the requirements of <https://www.jsonfeed.org/version/1.1/>.
+#[tokio::test]
+async fn test_json_feed() {
+    let (content_type, body) = feed_body("/feed.json").await;
+    assert_eq!(content_type, "application/feed+json; charset=utf-8");
+    let feed: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(feed["version"], "https://jsonfeed.org/version/1.1");
+    assert_eq!(feed["title"], "John's Weblog");
+    assert_eq!(feed["home_page_url"], "https://example.com/");
+    assert_eq!(feed["feed_url"], "https://example.com/feed.json");
+    assert_eq!(feed["language"], "en");
+    assert!(feed["authors"][0]["name"].is_string());
+    let items = feed["items"].as_array().unwrap();
+    assert!(!items.is_empty());
+    let mut ids = std::collections::HashSet::new();
+    for item in items {
+        let id = item["id"].as_str().unwrap();
+        assert!(ids.insert(id.to_string()), "duplicate id");
+        assert_eq!(item["url"], id);
+        assert!(item["content_html"].is_string() || item["content_text"].is_string());
+        for date in ["date_published", "date_modified"] {
+            let date = item[date].as_str().unwrap();
+            chrono::DateTime::parse_from_rfc3339(date).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_feed_pages() {
+    let mut ctx = server_context().await;
+    ctx.args.domain = "example.com".to_string();
+    ctx.args.feed_size = 1;
+    let created = chrono::Utc::now() + chrono::Duration::hours(1);
+    let content = format!("# Long\n\n{}\n\nThe end.", "word ".repeat(500));
+    fx::data::Post::insert(&ctx.conn(), created, created, &content).unwrap();
+
+    let body = |response: axum::http::Response<Body>| async move {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    };
+    let response = conditional_get(&ctx, "/feed.xml", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let rss = body(response).await;
+    // The full post instead of a preview.
+    assert!(rss.contains("The end."));
+    assert_eq!(rss.matches("<item>").count(), 1);
+    assert!(
+        rss.contains(
+            "<atom:link rel=\"prev-archive\" href=\"https://example.com/feed.xml?page=2\""
+        )
+    );
+    assert!(!rss.contains("<fh:archive/>"));
+
+    let response = conditional_get(&ctx, "/atom.xml", &[]).await;
+    let feed = atom_syndication::Feed::read_from(body(response).await.as_bytes()).unwrap();
+    assert_eq!(feed.entries().len(), 1);
+    assert_eq!(feed.entries()[0].title().as_str(), "Long");
+    let link = |feed: &atom_syndication::Feed, rel: &str| {
+        feed.links()
+            .iter()
+            .find(|link| link.rel() == rel)
+            .map(|link| link.href().to_string())
+    };
+    let prev = link(&feed, "prev-archive").unwrap();
+    assert_eq!(prev, "https://example.com/atom.xml?page=2");
+
+    let response = conditional_get(&ctx, "/atom.xml?page=2", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let atom = body(response).await;
+    assert!(atom.contains("<fh:archive/>"));
+    let feed = atom_syndication::Feed::read_from(atom.as_bytes()).unwrap();
+    assert_eq!(feed.entries().len(), 1);
+    assert_eq!(
+        link(&feed, "self").unwrap(),
+        "https://example.com/atom.xml?page=2"
+    );
+    assert_eq!(
+        link(&feed, "current").unwrap(),
+        "https://example.com/atom.xml"
+    );
+    assert_eq!(
+        link(&feed, "prev-archive").unwrap(),
+        "https://example.com/atom.xml?page=1"
+    );
+    assert_eq!(link(&feed, "next-archive"), None);
+
+    let response = conditional_get(&ctx, "/atom.xml?page=1", &[]).await;
+    let feed = atom_syndication::Feed::read_from(body(response).await.as_bytes()).unwrap();
+    assert_eq!(link(&feed, "prev-archive"), None);
+    assert_eq!(
+        link(&feed, "next-archive").unwrap(),
+        "https://example.com/atom.xml?page=2"
+    );
+
+    let response = conditional_get(&ctx, "/atom.xml?page=3", &[]).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = conditional_get(&ctx, "/feed.json", &[]).await;
+    let json: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+    assert_eq!(json["next_url"], "https://example.com/feed.json?page=2");
+    let response = conditional_get(&ctx, "/feed.json?page=1", &[]).await;
+    let json: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+    assert_eq!(json["feed_url"], "https://example.com/feed.json?page=1");
+    assert!(json.get("next_url").is_none());
+}
+
+#[tokio::test]
+async fn test_feed_links() {
+    let (_, body) = request_body("/").await;
+    assert!(body.contains("<link rel='alternate' type='application/rss+xml' href='/feed.xml'>"));
+    assert!(body.contains("<link rel='alternate' type='application/atom+xml' href='/atom.xml'>"));
+    assert!(body.contains("<link rel='alternate' type='application/feed+json' href='/feed.json'>"));
+}
+
+#[tokio::test]
+async fn test_math() {
+    let (status, body) = request_body("/posts/2/code").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("<math display=\"block\">"));
+    assert!(body.contains("<annotation encoding=\"application/x-tex\">x = 1</annotation>"));
+    assert!(!body.contains("language-math"));
+    assert!(!body.contains("katex"));
+
+    let mut ctx = server_context().await;
+    ctx.args.katex = true;
+    let req = Request::builder()
+        .uri("/posts/2/code")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx).oneshot(req).await.unwrap();
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    assert!(body.contains("<math display=\"block\">"));
+    assert!(body.contains("/static/katex."));
+}
+
+async fn conditional_get(
+    ctx: &fx::serve::ServerContext,
+    uri: &str,
+    headers: &[(&str, &str)],
+) -> axum::response::Response {
+    let mut req = Request::builder().uri(uri);
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let req = req.body(Body::empty()).unwrap();
+    app(ctx.clone()).oneshot(req).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_conditional_get() {
+    let ctx = server_context().await;
+    let uri = "/posts/2/code";
+    let response = conditional_get(&ctx, uri, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers().get("ETag").unwrap().clone();
+    let etag = etag.to_str().unwrap();
+    assert!(etag.starts_with('"'));
+    // The page also depends on the settings, so the post date is no validator.
+    assert!(response.headers().get("Last-Modified").is_none());
+
+    // The nonce differs per request, but the page is the same.
+    let response = conditional_get(&ctx, uri, &[]).await;
+    assert_eq!(response.headers().get("ETag").unwrap(), etag);
+
+    let response = conditional_get(&ctx, uri, &[("If-None-Match", etag)]).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert!(response.headers().get("Content-Security-Policy").is_none());
+    assert_eq!(response.headers().get("ETag").unwrap(), etag);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.is_empty());
+
+    let response = conditional_get(&ctx, uri, &[("If-None-Match", "\"other\"")]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let later = "Fri, 01 Jan 2100 00:00:00 GMT";
+    let response = conditional_get(&ctx, uri, &[("If-Modified-Since", later)]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = conditional_get(&ctx, "/feed.xml", &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers().get("ETag").unwrap().clone();
+    assert!(response.headers().get("Last-Modified").is_none());
+    let headers = [("If-None-Match", etag.to_str().unwrap())];
+    let response = conditional_get(&ctx, "/feed.xml", &headers).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let uri = "/files/69b83ddf8f65695f/example.txt";
+    let response = conditional_get(&ctx, uri, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("ETag").unwrap(),
+        "\"69b83ddf8f65695f\""
+    );
+    let headers = [("If-None-Match", "\"69b83ddf8f65695f\"")];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // Other methods are not conditional.
+    let req = Request::builder()
+        .method("POST")
+        .uri("/csp-report")
+        .header("If-None-Match", "*")
+        .body(Body::from("{}"))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn test_compression() {
+    let ctx = server_context().await;
+    let uri = "/posts/2/code";
+    let response = conditional_get(&ctx, uri, &[]).await;
+    assert!(response.headers().get("Content-Encoding").is_none());
+    assert_eq!(response.headers().get("Vary").unwrap(), "Accept-Encoding");
+    let etag = response.headers().get("ETag").unwrap().clone();
+    let etag = etag.to_str().unwrap();
+    let plain = response.into_body().collect().await.unwrap().to_bytes();
+
+    let headers = [("Accept-Encoding", "gzip, deflate, br, zstd")];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("Content-Encoding").unwrap(), "br");
+    // Each encoding has its own entity tag.
+    let br_etag = format!("{}-br\"", etag.trim_end_matches('"'));
+    assert_eq!(response.headers().get("ETag").unwrap(), br_etag.as_str());
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.len() < plain.len());
+
+    let headers = [
+        ("Accept-Encoding", "gzip, deflate, br, zstd"),
+        ("If-None-Match", br_etag.as_str()),
+    ];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers().get("ETag").unwrap(), br_etag.as_str());
+
+    let headers = [("Accept-Encoding", "zstd;q=1, br;q=0")];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.headers().get("Content-Encoding").unwrap(), "zstd");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let decompressed = zstd::decode_all(&body[..]).unwrap();
+    // Only the nonce differs.
+    assert_eq!(decompressed.len(), plain.len());
+
+    // Small responses are not worth compressing.
+    let headers = [("Accept-Encoding", "gzip")];
+    let response = conditional_get(&ctx, "/robots.txt", &headers).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("Content-Encoding").is_none());
+
+    let mut ctx = server_context().await;
+    ctx.args.compression = vec![fx::compression::Encoding::Identity];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert!(response.headers().get("Content-Encoding").is_none());
+}
+
+#[tokio::test]
+async fn test_range() {
+    let ctx = server_context().await;
+    let uri = "/files/69b83ddf8f65695f/example.txt";
+    let response = conditional_get(&ctx, uri, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers.get("Accept-Ranges").unwrap(), "bytes");
+    assert_eq!(headers.get("Content-Length").unwrap(), "7");
+    assert_eq!(
+        headers.get("Content-Disposition").unwrap(),
+        "inline; filename=\"example.txt\"; filename*=UTF-8''example%2Etxt"
+    );
+
+    let headers = [("Range", "bytes=1-3")];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        response.headers().get("Content-Range").unwrap(),
+        "bytes 1-3/7"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, "xam");
+
+    let headers = [("Range", "bytes=0-0,-2")];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = response.headers().get("Content-Type").unwrap();
+    let content_type = content_type.to_str().unwrap().to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap();
+    let length = response.headers().get("Content-Length").unwrap().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(length, body.len().to_string().as_str());
+    let expected = format!(
+        "--{boundary}\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Range: bytes 0-0/7\r\n\r\n\
+        e\r\n\
+        --{boundary}\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Range: bytes 5-6/7\r\n\r\n\
+        le\r\n\
+        --{boundary}--\r\n"
+    );
+    assert_eq!(body, expected);
+
+    let headers = [("Range", "bytes=7-")];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        response.headers().get("Content-Range").unwrap(),
+        "bytes */7"
+    );
+
+    // A range of an older version would corrupt the download.
+    let headers = [("Range", "bytes=1-3"), ("If-Range", "\"0123456789abcdef\"")];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, "example");
+    let headers = [("Range", "bytes=1-3"), ("If-Range", "\"69b83ddf8f65695f\"")];
+    let response = conditional_get(&ctx, uri, &headers).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+}
+
+#[tokio::test]
+async fn test_describe_file() {
+    let (ctx, auth) = request_cookie().await;
+    let token = api_token(&ctx);
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/files?filename=circle.svg")
+        .header("Authorization", format!("Bearer {token}"))
+        .header("Content-Type", "image/svg+xml")
+        .body(Body::from("<svg xmlns='http://www.w3.org/2000/svg'></svg>"))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let sha = body["sha"].as_str().unwrap().to_string();
+
+    let cookie = format!("auth={auth}");
+    let form = "alt=A+%27red%27+circle&caption=Figure+1&title=Circle";
+    let uri = format!("/files/describe/{sha}");
+    let (status, _headers, _body) = post_form(&ctx, &uri, &cookie, form).await;
+    assert!(status.is_success() || status.is_redirection());
+    let (status, body) = get_with_cookie(&ctx, &uri, &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("value='A &#39;red&#39; circle'"));
+    let unknown = "/files/describe/0000000000000000";
+    let (status, _headers, _body) = post_form(&ctx, unknown, &cookie, form).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let req = Request::builder()
+        .uri("/files")
+        .header("Cookie", &cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("alt text: A 'red' circle"));
+    assert!(body.contains(&format!("![A 'red' circle](/files/{sha})")));
+
+    let form = fx::serve::AddPostForm {
+        content: format!("![](/files/{sha})\n\nSee ![own](/files/{sha})."),
+        publish: None,
+    };
+    let form = serde_urlencoded::to_string(&form).unwrap();
+    let (status, _headers, body) = post_form(&ctx, "/posts/add", &cookie, &form).await;
+    assert_eq!(status, StatusCode::OK);
+    let expected = format!(
+        "<figure><img src='/files/{sha}' loading='lazy' \
+        alt='A &#39;red&#39; circle' title='Circle' />\
+        <figcaption>Figure 1</figcaption></figure>"
+    );
+    assert!(body.contains(&expected), "{body}");
+    assert!(body.contains("alt=\"own\" title='Circle'"));
+}
+
+#[tokio::test]
+async fn test_file_usage() {
+    let (ctx, auth) = request_cookie().await;
+    let cookie = format!("auth={auth}");
+    let sha = "69b83ddf8f65695f";
+    let (status, body) = get_with_cookie(&ctx, "/files/orphaned", &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("value='69b83ddf8f65695f'"));
+
+    let form = fx::serve::AddPostForm {
+        content: format!("# Example\n\n[example](/files/{sha}/example.txt)"),
+        publish: Some("Publish".to_string()),
+    };
+    let form = serde_urlencoded::to_string(&form).unwrap();
+    let (status, _headers, _body) = post_form(&ctx, "/posts/add", &cookie, &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+
+    let (_status, body) = get_with_cookie(&ctx, "/files", &cookie).await;
+    assert!(body.contains("Used in: <a href='/posts/3'>Example</a>"));
+    let (_status, body) = get_with_cookie(&ctx, &format!("/files/delete/{sha}"), &cookie).await;
+    assert!(body.contains("will stop working: <a href='/posts/3'>Example</a>"));
+    let (_status, body) = get_with_cookie(&ctx, "/files/orphaned", &cookie).await;
+    assert!(body.contains("All files are used in posts."));
+
+    let token = api_token(&ctx);
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/files?filename=unused.txt")
+        .header("Authorization", format!("Bearer {token}"))
+        .header("Content-Type", "text/plain")
+        .body(Body::from("unused"))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let unused = body["sha"].as_str().unwrap().to_string();
+    let (_status, body) = get_with_cookie(&ctx, "/files/orphaned", &cookie).await;
+    assert!(body.contains(&format!("value='{unused}'")));
+
+    // Files that are used are kept even when selected.
+    let form = format!("sha={unused}&sha={sha}");
+    let (status, _headers, _body) = post_form(&ctx, "/files/orphaned", &cookie, &form).await;
+    assert!(status.is_success() || status.is_redirection());
+    let (status, _body) = get_with_cookie(&ctx, &format!("/files/{unused}"), &cookie).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _body) = get_with_cookie(&ctx, &format!("/files/{sha}"), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let form = fx::serve::EditPostForm {
+        content: "# Example\n\nNo link".to_string(),
+        publish: Some("Publish".to_string()),
+    };
+    let form = serde_urlencoded::to_string(&form).unwrap();
+    let (status, _headers, _body) = post_form(&ctx, "/posts/edit/3", &cookie, &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let (_status, body) = get_with_cookie(&ctx, "/files/orphaned", &cookie).await;
+    assert!(body.contains("value='69b83ddf8f65695f'"));
 }
 
 #[tokio::test]
@@ -324,3 +973,944 @@ async fn test_invalid_post_request() {
     let (status, _body) = request_body("/posts/foo").await;
     assert_eq!(status, StatusCode::NOT_FOUND);
 }
+
+#[tokio::test]
+async fn test_indieauth() {
+    let (status, body) = request_body("/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("<link rel='authorization_endpoint' href='/auth'/>"));
+    assert!(body.contains("<link rel='token_endpoint' href='/token'/>"));
+
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW-gFWFOEjXk";
+    let challenge = "90EpwHQr_xi9uDtjYyz5mq9Z4RekugHRqg5ijpXC3FQ";
+    let client_id = "https://app.example.com/";
+    let redirect_uri = "https://app.example.com/callback";
+    let query = serde_urlencoded::to_string([
+        ("response_type", "code"),
+        ("client_id", client_id),
+        ("redirect_uri", redirect_uri),
+        ("state", "1234"),
+        ("code_challenge", challenge),
+        ("code_challenge_method", "S256"),
+        ("scope", "create update"),
+    ])
+    .unwrap();
+    let uri = format!("/auth?{query}");
+    let (status, _body) = request_body(&uri).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = request_body_logged_in(&uri).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains("wants to sign in"));
+    assert!(body.contains("value='create' checked"));
+
+    let (ctx, auth) = request_cookie().await;
+    let csrf = csrf_token(&ctx, &auth).await;
+    let approve = |redirect_uri: &str| {
+        let form = serde_urlencoded::to_string([
+            ("csrf_token", csrf.as_str()),
+            ("client_id", client_id),
+            ("redirect_uri", redirect_uri),
+            ("state", "1234"),
+            ("code_challenge", challenge),
+            ("scope", "create"),
+        ])
+        .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/auth/approve")
+            .header("Cookie", format!("auth={auth}"))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(form))
+            .unwrap()
+    };
+    // The form is checked again, so a forged form cannot send the code to
+    // another origin.
+    let req = approve("https://evil.example.org/callback");
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let req = approve(redirect_uri);
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let location = response.headers().get("Location").unwrap();
+    let location = location.to_str().unwrap();
+    assert!(location.starts_with("https://app.example.com/callback?code="));
+    assert!(location.contains("&state=1234"));
+    let code = location.split("code=").nth(1).unwrap();
+    let code = code.split('&').next().unwrap();
+
+    let redeem = |verifier: &str| {
+        let form = serde_urlencoded::to_string([
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("client_id", client_id),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", verifier),
+        ])
+        .unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/token")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(form))
+            .unwrap()
+    };
+    let response = app(ctx.clone()).oneshot(redeem(verifier)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["token_type"], "Bearer");
+    assert_eq!(body["scope"], "create");
+    let token = body["access_token"].as_str().unwrap().to_string();
+
+    // Codes can only be used once.
+    let response = app(ctx.clone()).oneshot(redeem(verifier)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let verify = || {
+        Request::builder()
+            .uri("/token")
+            .header("Authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app(ctx.clone()).oneshot(verify()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let form = serde_urlencoded::to_string([("token", &token)]).unwrap();
+    let req = Request::builder()
+        .method("POST")
+        .uri("/revoke")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(form))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app(ctx.clone()).oneshot(verify()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+/// Return the value of the cookie that the response sets.
+fn set_cookie(headers: &axum::http::HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all("Set-Cookie")
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .find(|value| value.starts_with(&format!("{name}=")))
+        .map(|value| {
+            let value = value.split(';').next().unwrap();
+            value.split_once('=').unwrap().1.to_string()
+        })
+}
+
+/// Post a form like the browser does.
+///
+/// Adds the CSRF token when `cookie` is the `auth` cookie of a logged in user.
+async fn post_form(
+    ctx: &fx::serve::ServerContext,
+    uri: &str,
+    cookie: &str,
+    form: &str,
+) -> (StatusCode, axum::http::HeaderMap, String) {
+    let form = match cookie.strip_prefix("auth=") {
+        Some(auth) => {
+            let token = csrf_token(ctx, auth).await;
+            if form.is_empty() {
+                format!("csrf_token={token}")
+            } else {
+                format!("{form}&csrf_token={token}")
+            }
+        }
+        None => form.to_string(),
+    };
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Cookie", cookie)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(form))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    (status, headers, body)
+}
+
+#[tokio::test]
+async fn test_totp() {
+    let (ctx, auth) = request_cookie().await;
+    let auth = format!("auth={auth}");
+
+    let req = Request::builder()
+        .uri("/settings/totp")
+        .header("Cookie", &auth)
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    assert!(body.contains("<svg"));
+    let secret = body.split("<code id='totp-secret'>").nth(1).unwrap();
+    let secret = secret.split("</code>").next().unwrap();
+    let key = fx_auth::totp::decode_secret(secret).unwrap();
+    let code = fx_auth::totp::code(&key, chrono::Utc::now().timestamp());
+
+    let (status, _headers, body) = post_form(
+        &ctx,
+        "/settings/totp/enable",
+        &auth,
+        &format!("secret={secret}&code=000000"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("Invalid code"));
+    let (status, _headers, body) = post_form(
+        &ctx,
+        "/settings/totp/enable",
+        &auth,
+        &format!("secret={secret}&code={code}"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Two-factor authentication is enabled"));
+    let codes = body.split("<code id='recovery-codes'>").nth(1).unwrap();
+    let codes = codes.split("</code>").next().unwrap();
+    let recovery_code = codes.lines().next().unwrap().to_string();
+
+    // The password alone is not enough anymore.
+    let form = "username=test-admin&password=test-password";
+    let (status, headers, _body) = post_form(&ctx, "/login", "", form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(headers.get("Location").unwrap(), "/login/totp");
+    assert_eq!(set_cookie(&headers, "auth"), None);
+    let pending = format!("pending={}", set_cookie(&headers, "pending").unwrap());
+
+    // Without the password step, the second step redirects to the login.
+    let form = format!("code={code}");
+    let (status, headers, _body) = post_form(&ctx, "/login/totp", "", &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(headers.get("Location").unwrap(), "/login");
+
+    // The code that was used to enable TOTP cannot be replayed.
+    let (status, _headers, body) = post_form(&ctx, "/login/totp", &pending, &form).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body.contains("Invalid code"));
+
+    let form = format!("code={recovery_code}");
+    let (status, headers, _body) = post_form(&ctx, "/login/totp", &pending, &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(headers.get("Location").unwrap(), "/");
+    let new_auth = set_cookie(&headers, "auth").unwrap();
+    assert!(!new_auth.is_empty());
+
+    // Recovery codes are single-use.
+    let (status, _headers, _body) = post_form(&ctx, "/login/totp", &pending, &form).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+async fn post_json(
+    ctx: &fx::serve::ServerContext,
+    uri: &str,
+    cookie: &str,
+    body: serde_json::Value,
+) -> (StatusCode, axum::http::HeaderMap, serde_json::Value) {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Cookie", cookie)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap();
+    let body = serde_json::from_slice(&body.to_bytes()).unwrap();
+    (status, headers, body)
+}
+
+/// Authenticator that keeps its key in memory like a passkey would.
+struct SoftwareAuthenticator {
+    key: p256::ecdsa::SigningKey,
+    credential_id: Vec<u8>,
+    sign_count: u32,
+    user_verified: bool,
+}
+
+impl SoftwareAuthenticator {
+    fn new() -> Self {
+        let key = p256::ecdsa::SigningKey::from_slice(&[7u8; 32]).unwrap();
+        SoftwareAuthenticator {
+            key,
+            credential_id: b"software-authenticator".to_vec(),
+            sign_count: 0,
+            user_verified: true,
+        }
+    }
+    fn b64(data: &[u8]) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data)
+    }
+    fn client_data(kind: &str, options: &serde_json::Value, origin: &str) -> Vec<u8> {
+        let challenge = options["challenge"].as_str().unwrap();
+        serde_json::json!({"type": kind, "challenge": challenge, "origin": origin})
+            .to_string()
+            .into_bytes()
+    }
+    fn auth_data(&mut self, attested: bool) -> Vec<u8> {
+        use sha2::Digest;
+        self.sign_count += 1;
+        let mut data = sha2::Sha256::digest(b"localhost").to_vec();
+        // User present, optionally user verified, and attested credential.
+        let mut flags = 0x01;
+        if self.user_verified {
+            flags |= 0x04;
+        }
+        if attested {
+            flags |= 0x40;
+        }
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        if attested {
+            data.extend_from_slice(&[0u8; 16]);
+            data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(&self.credential_id);
+            let point = self.key.verifying_key().to_sec1_point(false);
+            let point = point.as_bytes();
+            use ciborium::Value;
+            let cose = Value::Map(vec![
+                (Value::from(1), Value::from(2)),
+                (Value::from(3), Value::from(-7)),
+                (Value::from(-1), Value::from(1)),
+                (Value::from(-2), Value::Bytes(point[1..33].to_vec())),
+                (Value::from(-3), Value::Bytes(point[33..65].to_vec())),
+            ]);
+            ciborium::ser::into_writer(&cose, &mut data).unwrap();
+        }
+        data
+    }
+    fn register(&mut self, options: &serde_json::Value, origin: &str) -> serde_json::Value {
+        use ciborium::Value;
+        let client_data = Self::client_data("webauthn.create", options, origin);
+        let attestation = Value::Map(vec![
+            (Value::from("fmt"), Value::from("none")),
+            (Value::from("attStmt"), Value::Map(vec![])),
+            (Value::from("authData"), Value::Bytes(self.auth_data(true))),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+        serde_json::json!({
+            "name": "Software",
+            "client_data_json": Self::b64(&client_data),
+            "attestation_object": Self::b64(&attestation_object),
+        })
+    }
+    fn login(&mut self, options: &serde_json::Value, origin: &str) -> serde_json::Value {
+        use p256::ecdsa::signature::Signer;
+        use sha2::Digest;
+        let client_data = Self::client_data("webauthn.get", options, origin);
+        let auth_data = self.auth_data(false);
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&sha2::Sha256::digest(&client_data));
+        let signature: p256::ecdsa::Signature = self.key.sign(&signed);
+        serde_json::json!({
+            "id": Self::b64(&self.credential_id),
+            "client_data_json": Self::b64(&client_data),
+            "authenticator_data": Self::b64(&auth_data),
+            "signature": Self::b64(&signature.to_der().to_bytes()),
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_passkeys() {
+    let (ctx, auth) = request_cookie().await;
+    let auth = format!("auth={auth}");
+    let origin = "http://localhost:3000";
+    let mut authenticator = SoftwareAuthenticator::new();
+
+    let (status, _headers, _body) = post_json(
+        &ctx,
+        "/webauthn/register/options",
+        "",
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _headers, options) = post_json(
+        &ctx,
+        "/webauthn/register/options",
+        &auth,
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(options["rp"]["id"], "localhost");
+
+    // The challenge is bound to the origin.
+    let registration = authenticator.register(&options, "https://evil.example");
+    let (status, _headers, _body) =
+        post_json(&ctx, "/webauthn/register", &auth, registration).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Authenticators without user verification cannot be registered.
+    let (_status, _headers, options) = post_json(
+        &ctx,
+        "/webauthn/register/options",
+        &auth,
+        serde_json::json!({}),
+    )
+    .await;
+    authenticator.user_verified = false;
+    let registration = authenticator.register(&options, origin);
+    authenticator.user_verified = true;
+    let (status, _headers, body) = post_json(&ctx, "/webauthn/register", &auth, registration).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["message"], "user was not verified");
+
+    let (_status, _headers, options) = post_json(
+        &ctx,
+        "/webauthn/register/options",
+        &auth,
+        serde_json::json!({}),
+    )
+    .await;
+    let registration = authenticator.register(&options, origin);
+    let (status, _headers, body) =
+        post_json(&ctx, "/webauthn/register", &auth, registration.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    // Challenges are single-use.
+    let (status, _headers, _body) =
+        post_json(&ctx, "/webauthn/register", &auth, registration).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let req = Request::builder()
+        .uri("/settings/passkeys")
+        .header("Cookie", &auth)
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    assert!(body.contains("Software"));
+    assert!(body.contains("never used"));
+
+    let (_status, _headers, options) =
+        post_json(&ctx, "/webauthn/login/options", "", serde_json::json!({})).await;
+    let assertion = authenticator.login(&options, origin);
+    let (status, headers, body) = post_json(&ctx, "/webauthn/login", "", assertion.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(body["redirect"], "/");
+    assert!(set_cookie(&headers, "auth").is_some());
+
+    // Replayed assertion.
+    let (status, headers, _body) = post_json(&ctx, "/webauthn/login", "", assertion).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(set_cookie(&headers, "auth").is_none());
+
+    // Signature by another key.
+    let (_status, _headers, options) =
+        post_json(&ctx, "/webauthn/login/options", "", serde_json::json!({})).await;
+    let mut other = SoftwareAuthenticator::new();
+    other.key = p256::ecdsa::SigningKey::from_slice(&[8u8; 32]).unwrap();
+    other.sign_count = 10;
+    let assertion = other.login(&options, origin);
+    let (status, _headers, _body) = post_json(&ctx, "/webauthn/login", "", assertion).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // As second factor, the password comes first. The failed attempts above
+    // would otherwise delay the next ones.
+    ctx.login_throttle.lock().await.record_success(None);
+    let form = "mode=second_factor";
+    let (status, _headers, _body) = post_form(&ctx, "/settings/passkeys/mode", &auth, form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let (_status, _headers, options) =
+        post_json(&ctx, "/webauthn/login/options", "", serde_json::json!({})).await;
+    let assertion = authenticator.login(&options, origin);
+    let (status, _headers, _body) = post_json(&ctx, "/webauthn/login", "", assertion).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let form = "username=test-admin&password=test-password";
+    let (status, headers, _body) = post_form(&ctx, "/login", "", form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(headers.get("Location").unwrap(), "/login/passkey");
+    let pending = format!("pending={}", set_cookie(&headers, "pending").unwrap());
+    let (_status, _headers, options) =
+        post_json(&ctx, "/webauthn/login/options", "", serde_json::json!({})).await;
+    let assertion = authenticator.login(&options, origin);
+    let (status, headers, _body) = post_json(&ctx, "/webauthn/login", &pending, assertion).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(set_cookie(&headers, "auth").is_some());
+
+    // Requesting challenges without logging in counts as failed attempts.
+    let mut statuses = Vec::new();
+    for _ in 0..6 {
+        let (status, _headers, _body) =
+            post_json(&ctx, "/webauthn/login/options", "", serde_json::json!({})).await;
+        statuses.push(status);
+    }
+    assert_eq!(statuses[0], StatusCode::OK);
+    assert_eq!(statuses[5], StatusCode::TOO_MANY_REQUESTS);
+}
+
+async fn get_with_cookie(
+    ctx: &fx::serve::ServerContext,
+    uri: &str,
+    cookie: &str,
+) -> (StatusCode, String) {
+    let req = Request::builder()
+        .uri(uri)
+        .header("Cookie", cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_sessions() {
+    let (ctx, first) = request_cookie().await;
+    let first = format!("auth={first}");
+    let form = "username=test-admin&password=test-password";
+    let req = Request::builder()
+        .method("POST")
+        .uri("/login")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header(
+            "User-Agent",
+            "Mozilla/5.0 (X11; Linux x86_64) Firefox/140.0",
+        )
+        .body(Body::from(form))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let second = format!("auth={}", set_cookie(response.headers(), "auth").unwrap());
+
+    let (status, body) = get_with_cookie(&ctx, "/settings/sessions", &first).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.matches("class='session'").count(), 2);
+    assert!(body.contains("Firefox/140.0"));
+    assert!(body.contains("This session"));
+    let id = body.split("/settings/sessions/revoke/").nth(1).unwrap();
+    let id = id.split('\'').next().unwrap();
+
+    // Revoking the other session logs out only that device.
+    let uri = format!("/settings/sessions/revoke/{id}");
+    let (status, _headers, _body) = post_form(&ctx, &uri, &first, "").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let (status, _body) = get_with_cookie(&ctx, "/settings", &second).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _body) = get_with_cookie(&ctx, "/settings", &first).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A copy of the cookie is useless after logging out.
+    let (status, _body) = get_with_cookie(&ctx, "/logout", &first).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let (status, _body) = get_with_cookie(&ctx, "/settings", &first).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let form = "username=test-admin&password=test-password";
+    let (_status, headers, _body) = post_form(&ctx, "/login", "", form).await;
+    let third = format!("auth={}", set_cookie(&headers, "auth").unwrap());
+    let uri = "/settings/sessions/revoke-all";
+    let (status, headers, _body) = post_form(&ctx, uri, &third, "").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(headers.get("Location").unwrap(), "/login");
+    let (status, _body) = get_with_cookie(&ctx, "/settings", &third).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_csrf() {
+    let (ctx, auth) = request_cookie().await;
+    let cookie = format!("auth={auth}");
+    let token = csrf_token(&ctx, &auth).await;
+    let delete = |form: String, site: &'static str| {
+        Request::builder()
+            .method("POST")
+            .uri("/posts/delete/1")
+            .header("Cookie", &cookie)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Sec-Fetch-Site", site)
+            .body(Body::from(form))
+            .unwrap()
+    };
+
+    let req = delete("".to_string(), "same-origin");
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    assert!(body.contains("The form could not be verified"));
+
+    let req = delete("csrf_token=wrong".to_string(), "same-origin");
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // A valid token does not help when the browser says that the request
+    // comes from another site.
+    let req = delete(format!("csrf_token={token}"), "cross-site");
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/login")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Origin", "https://evil.example")
+        .body(Body::from("username=test-admin&password=test-password"))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let req = delete(format!("csrf_token={token}"), "same-origin");
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    // The token is useless once the session has ended.
+    get_with_cookie(&ctx, "/logout", &cookie).await;
+    let req = delete(format!("csrf_token={token}"), "same-origin");
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_csp() {
+    let ctx = server_context().await;
+    let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let headers = response.headers().clone();
+    assert_eq!(headers.get("X-Content-Type-Options").unwrap(), "nosniff");
+    assert!(headers.get("Referrer-Policy").is_some());
+    assert!(headers.get("Permissions-Policy").is_some());
+    let policy = headers.get("Content-Security-Policy").unwrap();
+    let policy = policy.to_str().unwrap();
+    let nonce = policy.split("'nonce-").nth(1).unwrap();
+    let nonce = nonce.split('\'').next().unwrap();
+    assert!(!nonce.is_empty());
+    let body = response.into_body().collect().await.unwrap();
+    let body = String::from_utf8(body.to_bytes().into()).unwrap();
+    let scripts = body.matches("<script").count();
+    assert!(0 < scripts);
+    let with_nonce = format!("<script nonce='{nonce}'");
+    assert_eq!(body.matches(&with_nonce).count(), scripts);
+    assert!(!body.contains("onclick="));
+
+    // Each request gets a new nonce.
+    let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let other = response.headers().get("Content-Security-Policy").unwrap();
+    assert_ne!(other.to_str().unwrap(), policy);
+
+    // The policy is only sent for pages.
+    let req = Request::builder()
+        .uri("/static/style.css")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert!(response.headers().get("Content-Security-Policy").is_none());
+
+    let report =
+        r#"{"csp-report": {"document-uri": "http://localhost/", "blocked-uri": "inline"}}"#;
+    let req = Request::builder()
+        .method("POST")
+        .uri("/csp-report")
+        .header("Content-Type", "application/csp-report")
+        .body(Body::from(report))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let mut ctx = ctx;
+    ctx.args.csp = fx::csp::Mode::ReportOnly;
+    ctx.args.csp_report = false;
+    let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let headers = response.headers();
+    assert!(headers.get("Content-Security-Policy").is_none());
+    let policy = headers.get("Content-Security-Policy-Report-Only").unwrap();
+    assert!(!policy.to_str().unwrap().contains("report-uri"));
+    let req = Request::builder()
+        .method("POST")
+        .uri("/csp-report")
+        .body(Body::from(report))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+async fn post_upload_form(
+    ctx: &fx::serve::ServerContext,
+    auth: &str,
+    filename: &str,
+    data: &[u8],
+) -> axum::http::Response<Body> {
+    let token = csrf_token(ctx, auth).await;
+    let boundary = "upload-boundary";
+    let mut body = format!(
+        "--{boundary}\r\n\
+        Content-Disposition: form-data; name=\"csrf_token\"\r\n\r\n\
+        {token}\r\n\
+        --{boundary}\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(
+        format!(
+            "\r\n--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"prefix\"\r\n\r\n\
+            docs/\r\n\
+            --{boundary}--\r\n"
+        )
+        .as_bytes(),
+    );
+    let req = Request::builder()
+        .method("POST")
+        .uri("/files/add")
+        .header("Cookie", format!("auth={auth}"))
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    app(ctx.clone()).oneshot(req).await.unwrap()
+}
+
+fn short_sha(data: &[u8]) -> String {
+    use sha2::Digest;
+    hex::encode(&sha2::Sha256::digest(data)[..8])
+}
+
+#[tokio::test]
+async fn test_upload_form() {
+    let (ctx, auth) = request_cookie().await;
+    let data = b"%PDF-1.7 streamed upload";
+    let response = post_upload_form(&ctx, &auth, "notes.pdf", data).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let uri = format!("/files/{}", short_sha(data));
+    let response = conditional_get(&ctx, &uri, &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // The browser did not know the type, so it was sniffed.
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/pdf"
+    );
+    let disposition = response.headers().get("Content-Disposition").unwrap();
+    assert!(disposition.to_str().unwrap().contains("notes.pdf"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, &data[..]);
+
+    let (status, body) = get_with_cookie(&ctx, "/files", &format!("auth={auth}")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("docs/notes.pdf"));
+    assert!(body.contains("(max 100 MB)"));
+
+    // Uploads may be larger than the bodies of the other routes.
+    let data = vec![b'b'; 3 * 1024 * 1024];
+    let response = post_upload_form(&ctx, &auth, "medium.txt", &data).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let form = format!("sha={}", "a".repeat(3 * 1024 * 1024));
+    let (status, _, _) = post_form(&ctx, "/files/orphaned", &format!("auth={auth}"), &form).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let mut ctx = ctx;
+    ctx.args.max_upload_size = 1;
+    let data = vec![b'a'; 2 * 1024 * 1024];
+    let response = post_upload_form(&ctx, &auth, "large.txt", &data).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("The maximum is 1 MB."));
+}
+
+async fn upload_request(
+    ctx: &fx::serve::ServerContext,
+    auth: &str,
+    token: &str,
+    method: &str,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: Vec<u8>,
+) -> (StatusCode, serde_json::Value) {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Cookie", format!("auth={auth}"))
+        .header("X-CSRF-Token", token);
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let req = req.body(Body::from(body)).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_chunked_upload() {
+    let (ctx, auth) = request_cookie().await;
+    let token = csrf_token(&ctx, &auth).await;
+    let data = b"ID3\x04\0\0 chunked upload of an episode";
+    let start = serde_json::json!({
+        "filename": "episode.mp3",
+        "size": data.len(),
+        "key": format!("episode {}", short_sha(data)),
+    });
+    let start = serde_json::to_vec(&start).unwrap();
+    let uri = "/files/uploads";
+    let (status, _) = upload_request(&ctx, &auth, "", "POST", uri, &[], start.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, json) = upload_request(&ctx, &auth, &token, "POST", uri, &[], start.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["offset"], 0);
+    assert_eq!(json["chunk_size"], 4 * 1024 * 1024);
+    let id = json["id"].as_str().unwrap().to_string();
+
+    let uri = format!("/files/uploads/{id}");
+    let headers = [("Upload-Offset", "0")];
+    let first = data[..10].to_vec();
+    let (status, json) = upload_request(&ctx, &auth, &token, "PUT", &uri, &headers, first).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["offset"], 10);
+    assert!(json["sha"].is_null());
+
+    // The connection dropped and the client resumes.
+    let uri_start = "/files/uploads";
+    let (_, json) = upload_request(&ctx, &auth, &token, "POST", uri_start, &[], start).await;
+    assert_eq!(json["id"], id.as_str());
+    assert_eq!(json["offset"], 10);
+    let headers = [("Upload-Offset", "5")];
+    let rest = data[5..].to_vec();
+    let (status, json) = upload_request(&ctx, &auth, &token, "PUT", &uri, &headers, rest).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(json["offset"], 10);
+
+    let headers = [("Upload-Offset", "10")];
+    let rest = data[10..].to_vec();
+    let (status, json) = upload_request(&ctx, &auth, &token, "PUT", &uri, &headers, rest).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["offset"], data.len());
+    let sha = short_sha(data);
+    assert_eq!(json["sha"], sha.as_str());
+
+    let response = conditional_get(&ctx, &format!("/files/{sha}"), &[]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "audio/mpeg"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, &data[..]);
+
+    let (status, _) = upload_request(&ctx, &auth, &token, "PUT", &uri, &headers, vec![]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_concurrent_chunks() {
+    let (ctx, auth) = request_cookie().await;
+    let token = csrf_token(&ctx, &auth).await;
+    let data = b"ID3\x04\0\0 an episode that is sent twice";
+    let start = serde_json::json!({
+        "filename": "episode.mp3",
+        "size": data.len(),
+        "key": format!("episode {}", short_sha(data)),
+    });
+    let start = serde_json::to_vec(&start).unwrap();
+    let uri = "/files/uploads";
+    let (_, json) = upload_request(&ctx, &auth, &token, "POST", uri, &[], start).await;
+    let id = json["id"].as_str().unwrap().to_string();
+
+    // A client that retries before the first response arrived.
+    let uri = format!("/files/uploads/{id}");
+    let headers = [("Upload-Offset", "0")];
+    let (first, second) = tokio::join!(
+        upload_request(&ctx, &auth, &token, "PUT", &uri, &headers, data.to_vec()),
+        upload_request(&ctx, &auth, &token, "PUT", &uri, &headers, data.to_vec()),
+    );
+    let mut statuses = [first.0, second.0];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::NOT_FOUND]);
+
+    let sha = short_sha(data);
+    let response = conditional_get(&ctx, &format!("/files/{sha}"), &[]).await;
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body, &data[..]);
+}
+
+#[tokio::test]
+async fn test_podcast() {
+    let (mut ctx, auth) = request_cookie().await;
+    ctx.args.domain = "example.com".to_string();
+    let data = b"ID3\x04\0\0 the first episode";
+    let response = post_upload_form(&ctx, &auth, "episode 1.mp3", data).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let sha = short_sha(data);
+    let conn = ctx.conn();
+    let content = format!("# Episode 1\n\n[Listen](/files/{sha})\n\nShow notes.");
+    let created = chrono::Utc::now();
+    let id = fx::data::Post::insert(&conn, created, created, &content).unwrap();
+    fx::data::Kv::insert(&conn, "podcast_artwork", b"/files/0123456789abcdef").unwrap();
+    fx::data::Kv::insert(
+        &conn,
+        "podcast_category",
+        b"Society & Culture > Documentary",
+    )
+    .unwrap();
+    fx::data::Kv::insert(&conn, "podcast_explicit", b"on").unwrap();
+
+    let (status, body) = get_with_cookie(&ctx, &format!("/posts/{id}/episode-1"), "").await;
+    assert_eq!(status, StatusCode::OK);
+    let url = format!("/files/{sha}");
+    assert!(body.contains(&format!(
+        "<audio controls preload='metadata'><source src='{url}' type='audio/mpeg'>"
+    )));
+    assert!(body.contains(&format!("<figcaption><a href='{url}'>Listen</a>")));
+
+    let enclosure = format!(
+        "<enclosure url=\"https://example.com/files/{sha}/episode%201.mp3\" \
+        length=\"{}\" type=\"audio/mpeg\"/>",
+        data.len()
+    );
+    let (_status, body) = get_with_cookie(&ctx, "/feed.xml", "").await;
+    assert!(body.contains(&enclosure));
+    let (_status, body) = get_with_cookie(&ctx, "/atom.xml", "").await;
+    assert!(body.contains("<link rel=\"enclosure\" type=\"audio/mpeg\""));
+    let (_status, body) = get_with_cookie(&ctx, "/feed.json", "").await;
+    let feed: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let items = feed["items"].as_array().unwrap();
+    let url = format!("https://example.com/posts/{id}");
+    let item = items
+        .iter()
+        .find(|item| item["url"] == url.as_str())
+        .unwrap();
+    let attachment = &item["attachments"][0];
+    assert_eq!(attachment["mime_type"], "audio/mpeg");
+    assert_eq!(attachment["size_in_bytes"], data.len());
+
+    let (status, body) = get_with_cookie(&ctx, "/podcast.xml", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\""));
+    assert!(body.contains("xmlns:podcast=\"https://podcastindex.org/namespace/1.0\""));
+    assert!(body.contains("<itunes:image href=\"https://example.com/files/0123456789abcdef\"/>"));
+    assert!(body.contains(
+        "<itunes:category text=\"Society &amp; Culture\">\
+        <itunes:category text=\"Documentary\"/></itunes:category>"
+    ));
+    assert!(body.contains("<itunes:explicit>true</itunes:explicit>"));
+    assert!(body.contains("<podcast:guid>"));
+    assert!(body.contains(&enclosure));
+    assert!(body.contains("<itunes:episodeType>full</itunes:episodeType>"));
+    // Only the posts with audio are episodes.
+    assert_eq!(body.matches("<item>").count(), 1);
+}